@@ -0,0 +1,36 @@
+//! 同じ宛先に小さなペイロードを 1000 件暗号化する checked / unchecked の比較
+//!
+//! cargo bench --bench account_key_encrypt
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use takos_encrypt_ink_rs::{
+    encrypt_data_account_key, encrypt_data_account_key_unchecked, generate_account_key,
+    generate_master_key,
+};
+
+fn bench_account_key_encrypt(c: &mut Criterion) {
+    // 同じ宛先に小さなペイロードを 1000 件暗号化する (TOFU でキャッシュした鍵を使い回す想定)
+    let (master_pub, master_priv) = generate_master_key();
+    let (account_pub, _, _) = generate_account_key(&master_pub, &master_priv).unwrap();
+    let payloads: Vec<String> = (0..1000).map(|i| format!("message {}", i)).collect();
+
+    let mut group = c.benchmark_group("encrypt_data_account_key_x1000");
+    group.sample_size(10);
+    group.bench_function("checked", |b| {
+        b.iter(|| {
+            for p in &payloads {
+                black_box(encrypt_data_account_key(black_box(&account_pub), p));
+            }
+        })
+    });
+    group.bench_function("unchecked", |b| {
+        b.iter(|| {
+            for p in &payloads {
+                black_box(encrypt_data_account_key_unchecked(black_box(&account_pub), p));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_account_key_encrypt);
+criterion_main!(benches);
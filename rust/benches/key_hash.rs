@@ -0,0 +1,28 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use takos_encrypt_ink_rs::{generate_account_key, generate_master_key, key_hash_with, HashAlgo};
+
+fn bench_key_hash(c: &mut Criterion) {
+    // 実際の鍵 JSON のサイズで比較する (マスター公開鍵 ≒ 3.5KB, アカウント秘密鍵 ≒ 3.3KB)
+    let (master_pub, master_priv) = generate_master_key();
+    let (_, account_priv, _) = generate_account_key(&master_pub, &master_priv).unwrap();
+    // 鍵バンドル相当: 100 個分を連結した文字列
+    let bundle = vec![master_pub.as_str(); 100].join(",");
+
+    let mut group = c.benchmark_group("key_hash");
+    for (name, input) in [
+        ("masterKeyPublic", master_pub.as_str()),
+        ("accountKeyPrivate", account_priv.as_str()),
+        ("bundle100", bundle.as_str()),
+    ] {
+        group.bench_function(format!("sha256/{}", name), |b| {
+            b.iter(|| key_hash_with(black_box(input), HashAlgo::Sha256))
+        });
+        group.bench_function(format!("blake3/{}", name), |b| {
+            b.iter(|| key_hash_with(black_box(input), HashAlgo::Blake3))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_key_hash);
+criterion_main!(benches);
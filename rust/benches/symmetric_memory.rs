@@ -0,0 +1,73 @@
+//! 50MB ペイロードの対称復号でのピークメモリ計測
+//!
+//! cargo bench --bench symmetric_memory
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use takos_encrypt_ink_rs::{decrypt_into, encrypt_with_symmetric_key, generate_symmetric_key, try_decrypt_with_symmetric_key};
+
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let now = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(now, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
+const PAYLOAD_LEN: usize = 50 * 1024 * 1024;
+
+/// f の実行中に増えたピーク (入力として既に確保済みの分は含まない)
+fn measure<T>(f: impl FnOnce() -> T) -> usize {
+    let base = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(base, Ordering::Relaxed);
+    let out = f();
+    let peak = PEAK.load(Ordering::Relaxed) - base;
+    drop(out);
+    peak
+}
+
+/// 変更前の実装 (暗号文と平文を別々に確保)
+fn decrypt_allocating(encrypted_data_b64: &str, iv_b64: &str, key_b64: &str) -> Option<String> {
+    let key_bytes = BASE64.decode(key_b64).ok()?;
+    let iv = BASE64.decode(iv_b64).ok()?;
+    let encrypted = BASE64.decode(encrypted_data_b64).ok()?;
+    let cipher = Aes256Gcm::new_from_slice(&key_bytes).ok()?;
+    let plaintext = cipher.decrypt(Nonce::from_slice(&iv), encrypted.as_ref()).ok()?;
+    String::from_utf8(plaintext).ok()
+}
+
+fn main() {
+    let key = generate_symmetric_key();
+    let data = "a".repeat(PAYLOAD_LEN);
+    let enc = encrypt_with_symmetric_key(&data, &key);
+    drop(data);
+
+    let mb = |n: usize| n as f64 / (1024.0 * 1024.0);
+    let before = measure(|| decrypt_allocating(&enc.encrypted_data, &enc.iv, &key).unwrap());
+    let after = measure(|| try_decrypt_with_symmetric_key(&enc.encrypted_data, &enc.iv, &key).unwrap());
+    let mut buf = Vec::with_capacity(PAYLOAD_LEN + 16);
+    let reused = measure(|| decrypt_into(&enc.encrypted_data, &enc.iv, &key, &mut buf).unwrap());
+
+    println!("payload: {:.1} MB", mb(PAYLOAD_LEN));
+    println!("decrypt (allocating):         peak {:.1} MB", mb(before));
+    println!("try_decrypt_with_symmetric_key: peak {:.1} MB", mb(after));
+    println!("decrypt_into (reused buffer): peak {:.1} MB", mb(reused));
+}
@@ -1,13 +1,17 @@
+#[cfg(feature = "master-key")]
 use std::time::{SystemTime, UNIX_EPOCH};
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
-use serde_json;
-use crate::r#type::{AccountKey, EncryptedData};
-use crate::utils::key_hash;
-use crate::crypto::{encrypt, decrypt};
-use crate::master_key::sign_master_key;
-use crate::keyutils::is_valid_kem_key;
+use serde_json::{self, json, Value};
+use crate::r#type::AccountKey;
+use crate::utils::{decode_base64, key_hash_matches, key_material_hash};
+use crate::crypto::{encrypt, encrypt_with_symmetric_key, try_decrypt, try_decrypt_with_symmetric_key};
+use crate::key_pair::AsymmetricKeyPair;
+#[cfg(feature = "master-key")]
+use crate::master_key::sign_master_key_with_public;
+use crate::keyutils::{generate_symmetric_key, is_valid_key_pair_encrypt};
+use crate::identity_key::{sign_identity_key_with_public, verify_identity_key};
 
 /// アカウント鍵ペア生成 (JSON文字列＋署名)
+#[cfg(feature = "master-key")]
 pub fn generate_account_key(
     master_public_json: &str,
     master_private_json: &str,
@@ -38,31 +42,25 @@ pub fn generate_account_key(
     };
     let pub_json = serde_json::to_string(&pub_obj).ok()?;
     let priv_json = serde_json::to_string(&priv_obj).ok()?;
-    let mh = key_hash(master_public_json);
-    let sign = sign_master_key(master_private_json, &pub_json, &mh)?;
+    let sign = sign_master_key_with_public(master_private_json, master_public_json, &pub_json)?;
     Some((pub_json, priv_json, sign))
 }
 
 /// 公開鍵 JSON 検証
 pub fn is_valid_account_key_public(json: &str) -> bool {
-    if let Ok(ak) = serde_json::from_str::<AccountKey>(json) {
-        ak.key_type == "accountKeyPublic"
-            && ak.algorithm == "ML-KEM-768"
-            && is_valid_kem_key(&ak.key, true)
-    } else {
-        false
-    }
+    AccountKey::is_valid_public(json)
 }
 
 /// 秘密鍵 JSON 検証
 pub fn is_valid_account_key_private(json: &str) -> bool {
-    if let Ok(ak) = serde_json::from_str::<AccountKey>(json) {
-        ak.key_type == "accountKeyPrivate"
-            && ak.algorithm == "ML-KEM-768"
-            && is_valid_kem_key(&ak.key, false)
-    } else {
-        false
-    }
+    AccountKey::is_valid_private(json)
+}
+
+/// 公開鍵と秘密鍵が同じ AccountKey のペアか (keyType を確認した上で封入→復元を試す)
+pub fn account_key_pair_matches(pub_json: &str, priv_json: &str) -> bool {
+    is_valid_account_key_public(pub_json)
+        && is_valid_account_key_private(priv_json)
+        && is_valid_key_pair_encrypt(pub_json, priv_json)
 }
 
 /// アカウント鍵による暗号化 (EncryptedData JSON)
@@ -70,36 +68,24 @@ pub fn encrypt_data_account_key(
     key_json: &str,
     data: &str,
 ) -> Option<String> {
-    if !is_valid_account_key_public(key_json) {
-        return None;
-    }
-    let ak: AccountKey = serde_json::from_str(key_json).ok()?;
-    let enc = encrypt(data, &ak.key);
-    let ed = EncryptedData {
-        key_type: "accountKey".into(),
-        key_hash: key_hash(key_json),
-        encrypted_data: enc.encrypted_data,
-        iv: enc.iv,
-        algorithm: Some(enc.algorithm),
-        cipher_text: Some(enc.cipher_text),
-    };
-    serde_json::to_string(&ed).ok()
+    AccountKey::encrypt_data(key_json, data)
+}
+
+/// アカウント鍵による暗号化 (公開鍵の検証を省略)
+///
+/// 一度 is_valid_account_key_public で検証してキャッシュした鍵に繰り返し暗号化する
+/// ホットパス向け (TOFU)。key_json が正しい公開鍵であることは呼び出し側の責任で、
+/// 不正な鍵を渡した場合の結果は保証しない。通常は encrypt_data_account_key を使うこと
+pub fn encrypt_data_account_key_unchecked(
+    key_json: &str,
+    data: &str,
+) -> Option<String> {
+    AccountKey::encrypt_data_unchecked(key_json, data)
 }
 
 /// EncryptedData JSON 検証
 pub fn is_valid_encrypted_data_account_key(json: &str) -> bool {
-    if let Ok(ed) = serde_json::from_str::<EncryptedData>(json) {
-        ed.key_type == "accountKey"
-            && ed.algorithm.as_deref() == Some("AES-GCM")
-            && BASE64.decode(&ed.key_hash).map(|v| v.len() == 32).unwrap_or(false)
-            && BASE64.decode(&ed.iv).map(|v| v.len() == 12).unwrap_or(false)
-            && BASE64.decode(&ed.encrypted_data).is_ok()
-            && ed.cipher_text.as_ref()
-                .and_then(|ct| BASE64.decode(ct).ok())
-                .is_some()
-    } else {
-        false
-    }
+    AccountKey::is_valid_encrypted_data(json)
 }
 
 /// アカウント鍵による復号
@@ -107,18 +93,121 @@ pub fn decrypt_data_account_key(
     key_json: &str,
     encrypted_json: &str,
 ) -> Option<String> {
-    if !is_valid_account_key_private(key_json)
-        || !is_valid_encrypted_data_account_key(encrypted_json)
-    {
-        return None;
-    }
-    let ak: AccountKey = serde_json::from_str(key_json).ok()?;
-    let ed: EncryptedData = serde_json::from_str(encrypted_json).ok()?;
-    let ciphertext = ed.cipher_text.as_ref()?;
-    Some(decrypt(&ed.encrypted_data, ciphertext, &ed.iv, &ak.key))
+    AccountKey::decrypt_data(key_json, encrypted_json)
 }
 
 /// EncryptedAccountKey 検証 (エイリアス)
 pub fn is_valid_encrypted_account_key(json: &str) -> bool {
     is_valid_encrypted_data_account_key(json)
 }
+
+/// 複数のアカウント鍵宛てに暗号化 (データ本体は 1 つの AES 鍵で一度だけ暗号化)
+///
+/// AES 鍵を各受信者の公開鍵で KEM によりラップし、
+/// { encryptedData, iv, algorithm, recipients: [{keyHash, wrappedKey}] } を返す。keyHash は key_material_hash
+pub fn encrypt_to_recipients(recipient_pubkeys_json: &[&str], data: &str) -> Option<String> {
+    if recipient_pubkeys_json.is_empty() { return None; }
+    let aes_key = generate_symmetric_key();
+    let enc = encrypt_with_symmetric_key(data, &aes_key);
+    let mut recipients = Vec::new();
+    for pub_json in recipient_pubkeys_json {
+        if !is_valid_account_key_public(pub_json) { return None; }
+        let ak: AccountKey = serde_json::from_str(pub_json).ok()?;
+        let wrapped = encrypt(&aes_key, &ak.key).ok()?;
+        recipients.push(json!({
+            "keyHash": key_material_hash(pub_json)?,
+            "wrappedKey": {
+                "encryptedData": wrapped.encrypted_data,
+                "cipherText": wrapped.cipher_text,
+                "iv": wrapped.iv,
+            },
+        }));
+    }
+    let envelope = json!({
+        "encryptedData": enc.encrypted_data,
+        "iv": enc.iv,
+        "algorithm": enc.algorithm,
+        "recipients": recipients,
+    });
+    serde_json::to_string(&envelope).ok()
+}
+
+/// encrypt_to_recipients のエンベロープから自分宛ての鍵を選んで復号
+///
+/// my_key_hash と Base64 デコード後に一致する受信者、または秘密鍵から復元した公開鍵に
+/// key_hash_matches で一致する受信者 (旧形式の keyHash を含む) を自分宛てとみなす
+pub fn decrypt_from_envelope(priv_json: &str, envelope: &str, my_key_hash: &str) -> Option<String> {
+    if !is_valid_account_key_private(priv_json) { return None; }
+    let ak: AccountKey = serde_json::from_str(priv_json).ok()?;
+    let my_hash = decode_base64(my_key_hash);
+    let my_pub = AccountKey::public_json_from_private(priv_json);
+    let v: Value = serde_json::from_str(envelope).ok()?;
+    let wrapped = v.get("recipients")?
+        .as_array()?
+        .iter()
+        .find(|r| {
+            r.get("keyHash").and_then(Value::as_str).and_then(decode_base64).is_some_and(|h| {
+                my_hash.as_deref() == Some(&h[..])
+                    || my_pub.as_deref().is_some_and(|p| key_hash_matches(p, &h))
+            })
+        })?
+        .get("wrappedKey")?;
+    let aes_key = try_decrypt(
+        wrapped.get("encryptedData")?.as_str()?,
+        wrapped.get("cipherText")?.as_str()?,
+        wrapped.get("iv")?.as_str()?,
+        &ak.key,
+    )?;
+    try_decrypt_with_symmetric_key(
+        v.get("encryptedData")?.as_str()?,
+        v.get("iv")?.as_str()?,
+        &aes_key,
+    )
+}
+
+/// DM 用エンベロープ作成
+///
+/// 受信者のアカウント鍵で暗号化し、送信者自身のアカウント鍵のハッシュ (key_material_hash) と共に IdentityKey で署名する。
+/// 戻り値は { message, sign } (message は {encryptedData, senderAccountKeyHash} の JSON 文字列)
+pub fn encrypt_dm(
+    their_account_pub_json: &str,
+    my_account_pub_json: &str,
+    my_identity_priv_json: &str,
+    my_identity_pub_json: &str,
+    data: &str,
+) -> Option<String> {
+    if !is_valid_account_key_public(my_account_pub_json) { return None; }
+    let encrypted = encrypt_data_account_key(their_account_pub_json, data)?;
+    let message = json!({
+        "encryptedData": encrypted,
+        "senderAccountKeyHash": key_material_hash(my_account_pub_json)?,
+    });
+    let message_str = serde_json::to_string(&message).ok()?;
+    let sign = sign_identity_key_with_public(my_identity_priv_json, my_identity_pub_json, &message_str)?;
+    serde_json::to_string(&json!({"message": message_str, "sign": sign})).ok()
+}
+
+/// 送信者のアカウント鍵候補を順に試して DM を復号
+///
+/// 署名が送信者の IdentityKey で検証でき、署名対象に含まれる送信者アカウント鍵のハッシュが
+/// 候補のいずれかと一致し、かつ自分の秘密鍵で復号できた場合のみ平文を返す
+pub fn decrypt_dm_try_all(
+    my_account_priv_json: &str,
+    their_account_pub_jsons: &[&str],
+    their_identity_pub_json: &str,
+    envelope: &str,
+) -> Option<String> {
+    let env: Value = serde_json::from_str(envelope).ok()?;
+    let message_str = env.get("message")?.as_str()?;
+    let sign = env.get("sign")?.as_str()?;
+    if !verify_identity_key(their_identity_pub_json, sign, message_str) { return None; }
+    let message: Value = serde_json::from_str(message_str).ok()?;
+    // 旧形式 (JSON 文字列のハッシュ) の senderAccountKeyHash も key_hash_matches で受け付ける
+    let sender_hash = decode_base64(message.get("senderAccountKeyHash")?.as_str()?)?;
+    let encrypted = message.get("encryptedData")?.as_str()?;
+    // 暗号文は 1 つなので、一致する候補が見つかれば復号は一度だけ行う
+    their_account_pub_jsons
+        .iter()
+        .find(|pub_json| is_valid_account_key_public(pub_json) && key_hash_matches(pub_json, &sender_hash))?;
+    decrypt_data_account_key(my_account_priv_json, encrypted)
+}
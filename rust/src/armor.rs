@@ -0,0 +1,98 @@
+use crate::key_bundle::is_valid_key_json;
+use crate::utils::canonical_key_json;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde_json::{self, Map, Value};
+use sha2::{Digest, Sha256};
+
+const KEY_TYPE_HEADER: &str = "Key-Type";
+const LINE_WIDTH: usize = 64;
+
+/// keyType (camelCase) からアーマーのラベルを作る ("masterKeyPublic" -> "TAKOS MASTER KEY PUBLIC")
+fn armor_label(key_type: &str) -> String {
+    let mut label = String::from("TAKOS");
+    for c in key_type.chars() {
+        if c.is_ascii_uppercase() || label.len() == 5 {
+            label.push(' ');
+        }
+        label.push(c.to_ascii_uppercase());
+    }
+    label
+}
+
+/// 鍵バイト列の SHA-256 先頭 3 バイト (OpenPGP の CRC24 行と同じく "=" + 4 文字)
+fn armor_checksum(key_bytes: &[u8]) -> String {
+    BASE64.encode(&Sha256::digest(key_bytes)[..3])
+}
+
+/// 鍵 JSON を PEM 風のアーマー形式に変換
+///
+/// ```text
+/// -----BEGIN TAKOS MASTER KEY PUBLIC-----
+/// Key-Type: masterKeyPublic
+///
+/// <key の Base64 (64 文字で折り返し)>
+/// =<チェックサム>
+/// -----END TAKOS MASTER KEY PUBLIC-----
+/// ```
+///
+/// keyType と key 以外のフィールド (timestamp など) は "名前: JSON 値" のヘッダーとして保持する
+pub fn to_armored(key_json: &str) -> Option<String> {
+    if !is_valid_key_json(key_json) { return None; }
+    let v: Value = serde_json::from_str(key_json).ok()?;
+    let obj = v.as_object()?;
+    let key_type = obj.get("keyType")?.as_str()?;
+    let key_b64 = obj.get("key")?.as_str()?;
+    let key_bytes = BASE64.decode(key_b64).ok()?;
+    let label = armor_label(key_type);
+
+    let mut out = format!("-----BEGIN {label}-----\n{KEY_TYPE_HEADER}: {key_type}\n");
+    for (name, value) in obj {
+        if name == "keyType" || name == "key" { continue; }
+        out.push_str(&format!("{name}: {}\n", serde_json::to_string(value).ok()?));
+    }
+    out.push('\n');
+    for line in key_b64.as_bytes().chunks(LINE_WIDTH) {
+        out.push_str(std::str::from_utf8(line).ok()?);
+        out.push('\n');
+    }
+    out.push_str(&format!("={}\n-----END {label}-----\n", armor_checksum(&key_bytes)));
+    Some(out)
+}
+
+/// to_armored の逆変換。ラベル・チェックサム・鍵の妥当性がすべて一致した場合のみ JSON を返す
+pub fn from_armored(armored: &str) -> Option<String> {
+    let mut lines = armored.trim().lines().map(str::trim_end);
+    let label = lines.next()?.strip_prefix("-----BEGIN ")?.strip_suffix("-----")?;
+
+    let mut obj = Map::new();
+    for line in lines.by_ref() {
+        if line.is_empty() { break; }
+        let (name, value) = line.split_once(": ")?;
+        if name == KEY_TYPE_HEADER {
+            obj.insert("keyType".into(), Value::String(value.into()));
+        } else {
+            obj.insert(name.into(), serde_json::from_str(value).ok()?);
+        }
+    }
+    let key_type = obj.get("keyType")?.as_str()?;
+    if armor_label(key_type) != label { return None; }
+
+    let mut key_b64 = String::new();
+    let mut checksum = None;
+    for line in lines.by_ref() {
+        if let Some(sum) = line.strip_prefix('=') {
+            checksum = Some(sum);
+            break;
+        }
+        key_b64.push_str(line);
+    }
+    let end = lines.next()?.strip_prefix("-----END ")?.strip_suffix("-----")?;
+    if end != label || lines.next().is_some() { return None; }
+
+    let key_bytes = BASE64.decode(&key_b64).ok()?;
+    if checksum? != armor_checksum(&key_bytes) { return None; }
+    obj.insert("key".into(), Value::String(key_b64));
+
+    let json = canonical_key_json(Value::Object(obj))?;
+    is_valid_key_json(&json).then_some(json)
+}
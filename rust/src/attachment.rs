@@ -0,0 +1,63 @@
+//! E2EE 添付ファイル (画像・動画・音声・ファイルコンテンツの uri)
+//!
+//! 添付は使い捨ての対称鍵で暗号化し、uri に暗号化済みブロブのハッシュと鍵を埋め込む
+//! (`takos://<blobHash>#<key>`, いずれも URL-safe Base64)。
+//! uri はメッセージ本文 (RoomKey で暗号化される) にのみ入れ、ブロブだけをストレージに置く。
+//! 受信側は blobHash でブロブを取得し、ハッシュを確かめてから鍵で復号する
+
+use crate::crypto::{decrypt_into, encrypt_bytes_with_symmetric_key};
+use crate::keyutils::{generate_symmetric_key, is_valid_symmetric_key};
+use crate::utils::Base64Variant;
+use crate::verify::decode_base64;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use sha2::{Digest, Sha256};
+
+/// 添付 uri のスキーム
+pub const ATTACHMENT_URI_SCHEME: &str = "takos://";
+
+const IV_LEN: usize = 12;
+
+/// 暗号化済みブロブのハッシュ (uri とストレージのキーに使う)
+fn blob_hash(blob: &[u8]) -> String {
+    Base64Variant::UrlSafe.encode(&Sha256::digest(blob))
+}
+
+/// uri を (blobHash, 鍵 (標準 Base64)) に分解する
+fn parse_attachment_uri(uri: &str) -> Option<(&str, String)> {
+    let (hash, key) = uri.strip_prefix(ATTACHMENT_URI_SCHEME)?.split_once('#')?;
+    let key = BASE64.encode(decode_base64(key)?);
+    is_valid_symmetric_key(&key).then_some((hash, key))
+}
+
+/// 添付データを新しい対称鍵で暗号化し、(uri, 暗号化済みブロブ) を返す
+///
+/// ブロブは IV (12 バイト) || AES-GCM 暗号文 (タグ込み)。uri は create_image_content などの uri にそのまま使う
+pub fn create_encrypted_attachment(data: &[u8]) -> Option<(String, Vec<u8>)> {
+    let key = generate_symmetric_key();
+    let enc = encrypt_bytes_with_symmetric_key(data, &key);
+    let mut blob = decode_base64(&enc.iv)?;
+    blob.extend(decode_base64(&enc.encrypted_data)?);
+    let uri = format!(
+        "{ATTACHMENT_URI_SCHEME}{}#{}",
+        blob_hash(&blob),
+        Base64Variant::UrlSafe.encode(&decode_base64(&key)?),
+    );
+    Some((uri, blob))
+}
+
+/// uri のハッシュと一致するブロブだけを、uri の鍵で復号する
+pub fn decrypt_attachment(uri: &str, encrypted_blob: &[u8]) -> Option<Vec<u8>> {
+    let (hash, key) = parse_attachment_uri(uri)?;
+    if encrypted_blob.len() < IV_LEN || blob_hash(encrypted_blob) != hash {
+        return None;
+    }
+    let (iv, ciphertext) = encrypted_blob.split_at(IV_LEN);
+    let mut out = Vec::new();
+    decrypt_into(&BASE64.encode(ciphertext), &BASE64.encode(iv), &key, &mut out).ok()?;
+    Some(out)
+}
+
+/// uri から取得に使う blobHash を取り出す (鍵は返さない)
+pub fn attachment_blob_hash(uri: &str) -> Option<String> {
+    parse_attachment_uri(uri).map(|(hash, _)| hash.to_string())
+}
@@ -0,0 +1,192 @@
+use crate::r#type::{AccountKey, EncryptedData, EncryptedMessage, MasterKey};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// CBOR 形式での直列化 (JSON の代わりに鍵などを生バイトで保持するコンパクト表現)
+///
+/// JSON が既定であり、CBOR は帯域の限られた同期向けのオプション
+pub trait Cbor: Sized {
+    fn to_cbor(&self) -> Option<Vec<u8>>;
+    fn from_cbor(bytes: &[u8]) -> Option<Self>;
+}
+
+fn encode<T: Serialize>(v: &T) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    ciborium::ser::into_writer(v, &mut out).ok()?;
+    Some(out)
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    ciborium::de::from_reader(bytes).ok()
+}
+
+#[derive(Serialize, Deserialize)]
+struct MasterKeyCbor {
+    #[serde(rename = "keyType")]
+    key_type: String,
+    #[serde(with = "serde_bytes")]
+    key: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    algorithm: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountKeyCbor {
+    #[serde(rename = "keyType")]
+    key_type: String,
+    #[serde(with = "serde_bytes")]
+    key: Vec<u8>,
+    algorithm: String,
+    timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedDataCbor {
+    #[serde(rename = "keyType")]
+    key_type: String,
+    #[serde(rename = "keyHash", with = "serde_bytes")]
+    key_hash: Vec<u8>,
+    #[serde(rename = "encryptedData", with = "serde_bytes")]
+    encrypted_data: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    iv: Vec<u8>,
+    algorithm: Option<String>,
+    #[serde(rename = "cipherText", with = "serde_bytes")]
+    cipher_text: Option<Vec<u8>>,
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
+    commitment: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kem: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedMessageCbor {
+    encrypted: bool,
+    value: EncryptedDataCbor,
+    channel: String,
+    original: Option<String>,
+    timestamp: u64,
+    #[serde(rename = "isLarge")]
+    is_large: bool,
+    roomid: String,
+    #[serde(rename = "encryptedMetadata", default, skip_serializing_if = "Option::is_none")]
+    encrypted_metadata: Option<EncryptedDataCbor>,
+}
+
+impl EncryptedDataCbor {
+    fn from_data(ed: &EncryptedData) -> Option<Self> {
+        Some(EncryptedDataCbor {
+            key_type: ed.key_type.clone(),
+            key_hash: ed.key_hash_bytes()?,
+            encrypted_data: ed.encrypted_data_bytes()?,
+            iv: ed.iv_bytes()?,
+            algorithm: ed.algorithm.clone(),
+            cipher_text: ed.cipher_text_bytes()?,
+            commitment: ed.commitment_bytes()?,
+            kem: ed.kem.clone(),
+            compression: ed.compression.clone(),
+        })
+    }
+
+    fn into_data(self) -> EncryptedData {
+        let mut ed = EncryptedData::from_parts(
+            &self.key_type,
+            &self.key_hash,
+            &self.encrypted_data,
+            &self.iv,
+            self.cipher_text.as_deref(),
+            self.algorithm.as_deref(),
+        );
+        ed.commitment = self.commitment.map(|c| BASE64.encode(c));
+        ed.kem = self.kem;
+        ed.compression = self.compression;
+        ed
+    }
+}
+
+impl Cbor for MasterKey {
+    fn to_cbor(&self) -> Option<Vec<u8>> {
+        encode(&MasterKeyCbor {
+            key_type: self.key_type.clone(),
+            key: BASE64.decode(&self.key).ok()?,
+            algorithm: self.algorithm.clone(),
+        })
+    }
+    fn from_cbor(bytes: &[u8]) -> Option<Self> {
+        let c: MasterKeyCbor = decode(bytes)?;
+        Some(MasterKey { key_type: c.key_type, key: BASE64.encode(c.key), algorithm: c.algorithm })
+    }
+}
+
+impl Cbor for AccountKey {
+    fn to_cbor(&self) -> Option<Vec<u8>> {
+        encode(&AccountKeyCbor {
+            key_type: self.key_type.clone(),
+            key: BASE64.decode(&self.key).ok()?,
+            algorithm: self.algorithm.clone(),
+            timestamp: self.timestamp,
+        })
+    }
+    fn from_cbor(bytes: &[u8]) -> Option<Self> {
+        let c: AccountKeyCbor = decode(bytes)?;
+        Some(AccountKey { key_type: c.key_type, key: BASE64.encode(c.key), algorithm: c.algorithm, timestamp: c.timestamp })
+    }
+}
+
+impl Cbor for EncryptedData {
+    fn to_cbor(&self) -> Option<Vec<u8>> {
+        encode(&EncryptedDataCbor::from_data(self)?)
+    }
+    fn from_cbor(bytes: &[u8]) -> Option<Self> {
+        let c: EncryptedDataCbor = decode(bytes)?;
+        Some(c.into_data())
+    }
+}
+
+impl Cbor for EncryptedMessage {
+    fn to_cbor(&self) -> Option<Vec<u8>> {
+        let ed: EncryptedData = serde_json::from_str(&self.value).ok()?;
+        let encrypted_metadata = match &self.encrypted_metadata {
+            Some(m) => Some(EncryptedDataCbor::from_data(&serde_json::from_str(m).ok()?)?),
+            None => None,
+        };
+        encode(&EncryptedMessageCbor {
+            encrypted: self.encrypted,
+            value: EncryptedDataCbor::from_data(&ed)?,
+            channel: self.channel.clone(),
+            original: self.original.clone(),
+            timestamp: self.timestamp,
+            is_large: self.is_large,
+            roomid: self.roomid.clone(),
+            encrypted_metadata,
+        })
+    }
+    fn from_cbor(bytes: &[u8]) -> Option<Self> {
+        let c: EncryptedMessageCbor = decode(bytes)?;
+        Some(EncryptedMessage {
+            encrypted: c.encrypted,
+            value: serde_json::to_string(&c.value.into_data()).ok()?,
+            channel: c.channel,
+            original: c.original,
+            timestamp: c.timestamp,
+            is_large: c.is_large,
+            roomid: c.roomid,
+            encrypted_metadata: match c.encrypted_metadata {
+                Some(m) => Some(serde_json::to_string(&m.into_data()).ok()?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// JSON 文字列 → CBOR バイト列
+pub fn json_to_cbor<T: Cbor + DeserializeOwned>(json: &str) -> Option<Vec<u8>> {
+    serde_json::from_str::<T>(json).ok()?.to_cbor()
+}
+
+/// CBOR バイト列 → JSON 文字列
+pub fn cbor_to_json<T: Cbor + Serialize>(bytes: &[u8]) -> Option<String> {
+    serde_json::to_string(&T::from_cbor(bytes)?).ok()
+}
@@ -6,3 +6,20 @@ pub fn is_valid_uuid_v7(uuid: &str) -> bool {
     ).unwrap();
     re.is_match(uuid)
 }
+
+/// UUID v7 に埋め込まれたタイムスタンプ (先頭 48 ビット, Unix ミリ秒)。UUID v7 でなければ None
+pub fn uuid_v7_timestamp(uuid: &str) -> Option<u64> {
+    if !is_valid_uuid_v7(uuid) {
+        return None;
+    }
+    let hex: String = uuid[..13].chars().filter(|c| *c != '-').collect();
+    u64::from_str_radix(&hex, 16).ok()
+}
+
+/// UUID v7 で、かつ埋め込まれたタイムスタンプが min_ms 以上 max_ms 以下か
+///
+/// 形式だけの is_valid_uuid_v7 は 1970 年や遠い未来を指す UUID も通すため、
+/// セッション・ルームの UUID を受け入れる前に妥当な時刻の範囲に収まっているかを確かめる
+pub fn is_valid_uuid_v7_bounded(uuid: &str, min_ms: u64, max_ms: u64) -> bool {
+    uuid_v7_timestamp(uuid).is_some_and(|ts| (min_ms..=max_ms).contains(&ts))
+}
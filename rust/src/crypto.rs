@@ -3,9 +3,15 @@ use ml_kem::EncodedSizeUser;
 use ml_kem::kem::{Encapsulate, Decapsulate};
 use rand::rngs::OsRng;
 use rand::RngCore;
-use aes_gcm::{Aes256Gcm, Nonce, aead::{Aead, KeyInit}};
+use aes_gcm::{Aes256Gcm, Nonce, aead::{Aead, AeadInPlace, KeyInit}};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use hkdf::Hkdf;
+use std::collections::HashSet;
+use crate::error::{DecryptError, EncryptError};
+use crate::verify::decode_base64;
+use crate::keyutils::{is_valid_symmetric_key, KEM768_PUBLIC_KEY_LEN, SYMMETRIC_KEY_LEN};
 
 /// 非対称暗号化結果
 #[derive(Serialize)]
@@ -17,67 +23,112 @@ pub struct AsymmetricEncrypted {
 }
 
 /// 非対称暗号化（公開鍵 Base64 → データ文字列 → {encryptedData, cipherText, iv, algorithm}）
+///
+/// 公開鍵は封入前に長さを確認し、ML-KEM-768 の封入鍵でなければ WrongKeyLength を返す
 pub fn encrypt(
     data: &str,
     public_key_b64: &str,
-) -> AsymmetricEncrypted {
-    // 公開鍵復元
-    let pk_vec = BASE64.decode(public_key_b64).unwrap();
-    let pk_arr: Array<u8, <<MlKem768 as KemCore>::EncapsulationKey as EncodedSizeUser>::EncodedSize> =
-        Array::try_from(&pk_vec[..]).unwrap();
-    let ek = <MlKem768 as KemCore>::EncapsulationKey::from_bytes(&pk_arr);
-
+) -> Result<AsymmetricEncrypted, EncryptError> {
     // KEM 封入
-    let mut rng = OsRng;
-    let (ct_arr, shared_arr) = ek.encapsulate(&mut rng).unwrap();
-    let shared = shared_arr.as_slice();
+    let (ct, shared) = kem_encapsulate_bytes(public_key_b64)?;
 
     // IV 生成
     let mut iv = [0u8; 12];
-    rng.fill_bytes(&mut iv);
+    OsRng.fill_bytes(&mut iv);
 
     // AES-GCM 暗号化
-    let cipher = Aes256Gcm::new_from_slice(shared).unwrap();
+    let cipher = Aes256Gcm::new_from_slice(&shared).map_err(|_| EncryptError::EncryptionFailed)?;
     let nonce = Nonce::from_slice(&iv);
-    let ciphertext = cipher.encrypt(nonce, data.as_bytes()).unwrap();
+    let ciphertext = cipher.encrypt(nonce, data.as_bytes()).map_err(|_| EncryptError::EncryptionFailed)?;
 
-    AsymmetricEncrypted {
+    Ok(AsymmetricEncrypted {
         encrypted_data: BASE64.encode(ciphertext),
-        cipher_text: BASE64.encode(ct_arr.as_slice()),
+        cipher_text: BASE64.encode(ct),
         iv: BASE64.encode(iv),
         algorithm: "AES-GCM".into(),
-    }
+    })
+}
+
+/// ML-KEM-768 封入 (公開鍵 Base64 → (cipherText, 共有秘密) のバイト列)
+fn kem_encapsulate_bytes(public_key_b64: &str) -> Result<(Vec<u8>, Vec<u8>), EncryptError> {
+    let pk_vec = decode_base64(public_key_b64).ok_or(EncryptError::InvalidBase64)?;
+    if pk_vec.len() != KEM768_PUBLIC_KEY_LEN { return Err(EncryptError::WrongKeyLength); }
+    let pk_arr: Array<u8, <<MlKem768 as KemCore>::EncapsulationKey as EncodedSizeUser>::EncodedSize> =
+        Array::try_from(&pk_vec[..]).map_err(|_| EncryptError::WrongKeyLength)?;
+    let ek = <MlKem768 as KemCore>::EncapsulationKey::from_bytes(&pk_arr);
+    let (ct_arr, shared_arr) = ek.encapsulate(&mut OsRng).map_err(|_| EncryptError::EncryptionFailed)?;
+    Ok((ct_arr.to_vec(), shared_arr.to_vec()))
+}
+
+/// ML-KEM-768 復号 (秘密鍵 Base64, cipherText Base64 → 共有秘密のバイト列)
+fn kem_decapsulate_bytes(private_key_b64: &str, cipher_text_b64: &str) -> Result<Vec<u8>, DecryptError> {
+    let sk_vec = decode_base64(private_key_b64).ok_or(DecryptError::InvalidBase64)?;
+    let sk_arr: Array<u8, <<MlKem768 as KemCore>::DecapsulationKey as EncodedSizeUser>::EncodedSize> =
+        Array::try_from(&sk_vec[..]).map_err(|_| DecryptError::WrongKeyLength)?;
+    let dk = <MlKem768 as KemCore>::DecapsulationKey::from_bytes(&sk_arr);
+    let ct_vec = decode_base64(cipher_text_b64).ok_or(DecryptError::InvalidBase64)?;
+    let ct_arr: Array<u8, <MlKem768 as KemCore>::CiphertextSize> =
+        Array::try_from(&ct_vec[..]).map_err(|_| DecryptError::DecryptionFailed)?;
+    let shared_arr = dk.decapsulate(&ct_arr).map_err(|_| DecryptError::DecryptionFailed)?;
+    Ok(shared_arr.to_vec())
+}
+
+/// KEM の封入だけを行い (cipherText, 共有秘密) を Base64 で返す
+///
+/// 一度の封入で共有秘密 (32 バイト) を確立し、それを共通鍵として
+/// encrypt_with_symmetric_key などで複数のメッセージを送る用途向け。
+/// 共有秘密はそのまま共通鍵として使えるが、同じ鍵での暗号化回数の上限 (IV の誕生日限界) に注意すること
+pub fn kem_encapsulate(public_key_b64: &str) -> Option<(String, String)> {
+    let (ct, shared) = kem_encapsulate_bytes(public_key_b64).ok()?;
+    Some((BASE64.encode(ct), BASE64.encode(shared)))
+}
+
+/// kem_encapsulate の cipherText から共有秘密 (Base64) を復元する
+pub fn kem_decapsulate(private_key_b64: &str, cipher_text_b64: &str) -> Option<String> {
+    kem_decapsulate_bytes(private_key_b64, cipher_text_b64).ok().map(|s| BASE64.encode(s))
 }
 
 /// 非対称復号（encryptedData, cipherText, iv, 秘密鍵 Base64 → 平文文字列）
+///
+/// 平文が UTF-8 でない場合は InvalidUtf8 を返す (panic しない)
 pub fn decrypt(
     encrypted_data_b64: &str,
     cipher_text_b64: &str,
     iv_b64: &str,
     private_key_b64: &str,
-) -> String {
-    // 秘密鍵復元
-    let sk_vec = BASE64.decode(private_key_b64).unwrap();
-    let sk_arr: Array<u8, <<MlKem768 as KemCore>::DecapsulationKey as EncodedSizeUser>::EncodedSize> =
-        Array::try_from(&sk_vec[..]).unwrap();
-    let dk = <MlKem768 as KemCore>::DecapsulationKey::from_bytes(&sk_arr);
-
-    // データ復元
-    let ct_vec = BASE64.decode(cipher_text_b64).unwrap();
-    let ct_arr: Array<u8, <MlKem768 as KemCore>::CiphertextSize> =
-        Array::try_from(&ct_vec[..]).unwrap();
-    let iv = BASE64.decode(iv_b64).unwrap();
-    let encrypted = BASE64.decode(encrypted_data_b64).unwrap();
+) -> Result<String, DecryptError> {
+    let plaintext = decrypt_bytes(encrypted_data_b64, cipher_text_b64, iv_b64, private_key_b64)?;
+    String::from_utf8(plaintext).map_err(|_| DecryptError::InvalidUtf8)
+}
 
+/// 非対称復号（平文をバイト列のまま返す）
+pub fn decrypt_bytes(
+    encrypted_data_b64: &str,
+    cipher_text_b64: &str,
+    iv_b64: &str,
+    private_key_b64: &str,
+) -> Result<Vec<u8>, DecryptError> {
     // KEM 復号
-    let shared_arr = dk.decapsulate(&ct_arr).unwrap();
-    let shared = shared_arr.as_slice();
+    let shared = kem_decapsulate_bytes(private_key_b64, cipher_text_b64)?;
+    let iv = decode_base64(iv_b64).ok_or(DecryptError::InvalidBase64)?;
+    if iv.len() != 12 { return Err(DecryptError::WrongIvLength); }
 
     // AES-GCM 復号
-    let cipher = Aes256Gcm::new_from_slice(shared).unwrap();
+    let cipher = Aes256Gcm::new_from_slice(&shared).map_err(|_| DecryptError::DecryptionFailed)?;
     let nonce = Nonce::from_slice(&iv);
-    let plaintext = cipher.decrypt(nonce, encrypted.as_ref()).unwrap();
-    String::from_utf8(plaintext).unwrap()
+    let mut buf = decode_base64(encrypted_data_b64).ok_or(DecryptError::InvalidBase64)?;
+    cipher.decrypt_in_place(nonce, b"", &mut buf).map_err(|_| DecryptError::DecryptionFailed)?;
+    Ok(buf)
+}
+
+/// 非対称復号（失敗時に panic せず None を返す）
+pub fn try_decrypt(
+    encrypted_data_b64: &str,
+    cipher_text_b64: &str,
+    iv_b64: &str,
+    private_key_b64: &str,
+) -> Option<String> {
+    decrypt(encrypted_data_b64, cipher_text_b64, iv_b64, private_key_b64).ok()
 }
 
 /// 対称暗号化結果
@@ -86,40 +137,308 @@ pub struct SymmetricEncrypted {
     pub encrypted_data: String,
     pub iv: String,
     pub algorithm: String,
+    /// 鍵コミットメント (algorithm が "AES-GCM-commit" の場合のみ)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<String>,
 }
 
+/// AES-GCM の認証タグ長
+const AES_GCM_TAG_LEN: usize = 16;
+
+/// 鍵コミットメント付き AES-GCM のアルゴリズム名
+pub const ALGORITHM_AES_GCM_COMMIT: &str = "AES-GCM-commit";
+
+/// 公開鍵暗号で使う KEM のアルゴリズム名 (EncryptedData.kem / 鍵の algorithm)
+pub const ALGORITHM_ML_KEM_768: &str = "ML-KEM-768";
+
 /// 対称暗号化（共通鍵 Base64 → データ文字列 → {encryptedData, iv, algorithm}）
+///
+/// IV は毎回 96bit の乱数。誕生日限界により、同一鍵での暗号化はおよそ 2^32 回までに抑えること。
+/// それを超える大量送信には RoomKeyCipher (カウンタ方式の IV) を使う
 pub fn encrypt_with_symmetric_key(
     data: &str,
     key_b64: &str,
 ) -> SymmetricEncrypted {
-    let key_bytes = BASE64.decode(key_b64).unwrap();
     let mut iv = [0u8; 12];
     OsRng.fill_bytes(&mut iv);
+    encrypt_with_symmetric_key_nonce(data.as_bytes(), key_b64, iv)
+}
+
+/// 追加認証データ (AAD) 付きの対称暗号化
+///
+/// aad は暗号文に含まれないが認証タグに束縛され、復号時に同じ aad を渡さなければ失敗する
+pub fn encrypt_with_symmetric_key_aad(
+    data: &str,
+    key_b64: &str,
+    aad: &[u8],
+) -> SymmetricEncrypted {
+    let mut iv = [0u8; 12];
+    OsRng.fill_bytes(&mut iv);
+    encrypt_with_symmetric_key_nonce_aad(data.as_bytes(), key_b64, iv, aad)
+}
+
+/// 対称暗号化（任意のバイト列。UTF-8 である必要はない）
+pub fn encrypt_bytes_with_symmetric_key(
+    data: &[u8],
+    key_b64: &str,
+) -> SymmetricEncrypted {
+    let mut iv = [0u8; 12];
+    OsRng.fill_bytes(&mut iv);
+    encrypt_with_symmetric_key_nonce(data, key_b64, iv)
+}
+
+/// AAD 付きでバイト列を対称暗号化 (圧縮した平文など)
+pub(crate) fn encrypt_bytes_with_symmetric_key_aad(
+    data: &[u8],
+    key_b64: &str,
+    aad: &[u8],
+) -> SymmetricEncrypted {
+    let mut iv = [0u8; 12];
+    OsRng.fill_bytes(&mut iv);
+    encrypt_with_symmetric_key_nonce_aad(data, key_b64, iv, aad)
+}
+
+/// カウンタから IV を決定的に生成 (先頭 4 バイトの接頭辞 + 64bit カウンタ, ビッグエンディアン)
+pub fn counter_nonce(prefix: [u8; 4], counter: u64) -> [u8; 12] {
+    let mut iv = [0u8; 12];
+    iv[..4].copy_from_slice(&prefix);
+    iv[4..].copy_from_slice(&counter.to_be_bytes());
+    iv
+}
+
+/// カウンタ方式の対称暗号化
+///
+/// IV は prefix とカウンタから決定的に生成されるため、同一鍵で同じ (prefix, counter) を二度使ってはならない。
+/// prefix は同じ鍵で暗号化する端末ごとに一意な値を呼び出し側が割り当てて保持し、
+/// カウンタと共に永続化する (RoomKeyCipher)。それができない場合は encrypt_with_symmetric_key
+/// (96bit のランダム IV) を使う。
+/// カウンタの重複はここでは検出しないため、使用済みカウンタを管理する RoomKeyCipher からのみ呼ぶ
+pub(crate) fn encrypt_with_symmetric_key_counter(
+    data: &str,
+    key_b64: &str,
+    prefix: [u8; 4],
+    counter: u64,
+) -> SymmetricEncrypted {
+    encrypt_with_symmetric_key_nonce(data.as_bytes(), key_b64, counter_nonce(prefix, counter))
+}
 
+/// 指定した IV で対称暗号化
+///
+/// 平文のコピーを認証タグ分の余裕を持たせて確保し、その場で暗号化する (再確保なし)
+pub(crate) fn encrypt_with_symmetric_key_nonce(
+    data: &[u8],
+    key_b64: &str,
+    iv: [u8; 12],
+) -> SymmetricEncrypted {
+    encrypt_with_symmetric_key_nonce_aad(data, key_b64, iv, b"")
+}
+
+fn encrypt_with_symmetric_key_nonce_aad(
+    data: &[u8],
+    key_b64: &str,
+    iv: [u8; 12],
+    aad: &[u8],
+) -> SymmetricEncrypted {
+    let key_bytes = decode_base64(key_b64).unwrap();
     let cipher = Aes256Gcm::new_from_slice(&key_bytes).unwrap();
     let nonce = Nonce::from_slice(&iv);
-    let ciphertext = cipher.encrypt(nonce, data.as_bytes()).unwrap();
+    let mut ciphertext = Vec::with_capacity(data.len() + AES_GCM_TAG_LEN);
+    ciphertext.extend_from_slice(data);
+    cipher.encrypt_in_place(nonce, aad, &mut ciphertext).unwrap();
 
     SymmetricEncrypted {
         encrypted_data: BASE64.encode(ciphertext),
         iv: BASE64.encode(iv),
         algorithm: "AES-GCM".into(),
+        commitment: None,
     }
 }
 
 /// 対称復号（encryptedData, iv, 共通鍵 Base64 → 平文文字列）
+///
+/// IV・鍵は EncryptedData 経由で攻撃者が制御しうるため、長さを確認してから暗号器を作る
 pub fn decrypt_with_symmetric_key(
     encrypted_data_b64: &str,
     iv_b64: &str,
     key_b64: &str,
-) -> String {
-    let key_bytes = BASE64.decode(key_b64).unwrap();
-    let iv = BASE64.decode(iv_b64).unwrap();
-    let encrypted = BASE64.decode(encrypted_data_b64).unwrap();
+) -> Result<String, DecryptError> {
+    let mut plaintext = Vec::new();
+    decrypt_into(encrypted_data_b64, iv_b64, key_b64, &mut plaintext)?;
+    String::from_utf8(plaintext).map_err(|_| DecryptError::InvalidUtf8)
+}
 
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes).unwrap();
+/// AAD 付きの対称復号 (encrypt_with_symmetric_key_aad と同じ aad が必要)
+pub fn decrypt_with_symmetric_key_aad(
+    encrypted_data_b64: &str,
+    iv_b64: &str,
+    key_b64: &str,
+    aad: &[u8],
+) -> Result<String, DecryptError> {
+    let mut plaintext = Vec::new();
+    decrypt_into_aad(encrypted_data_b64, iv_b64, key_b64, aad, &mut plaintext)?;
+    String::from_utf8(plaintext).map_err(|_| DecryptError::InvalidUtf8)
+}
+
+/// 対称復号（失敗時に panic せず None を返す）
+pub fn try_decrypt_with_symmetric_key(
+    encrypted_data_b64: &str,
+    iv_b64: &str,
+    key_b64: &str,
+) -> Option<String> {
+    decrypt_with_symmetric_key(encrypted_data_b64, iv_b64, key_b64).ok()
+}
+
+/// 対称復号の結果を呼び出し側のバッファに書き込む
+///
+/// out はクリアされた上で encryptedData のデコード先として使われ、その場で復号される。
+/// 復号済みデータと暗号文を同時に保持しないため、isLarge なデータでもピークメモリは
+/// ほぼ暗号文 1 つ分で済む。多数のメッセージを処理する場合は同じ out を使い回すと再確保も避けられる。
+/// 失敗時は out をクリアしてエラーを返す (平文が残ることはない)
+pub fn decrypt_into(
+    encrypted_data_b64: &str,
+    iv_b64: &str,
+    key_b64: &str,
+    out: &mut Vec<u8>,
+) -> Result<(), DecryptError> {
+    decrypt_into_aad(encrypted_data_b64, iv_b64, key_b64, b"", out)
+}
+
+pub(crate) fn decrypt_into_aad(
+    encrypted_data_b64: &str,
+    iv_b64: &str,
+    key_b64: &str,
+    aad: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<(), DecryptError> {
+    let key_bytes = decode_base64(key_b64).ok_or(DecryptError::InvalidBase64)?;
+    let iv = decode_base64(iv_b64).ok_or(DecryptError::InvalidBase64)?;
+    if iv.len() != 12 { return Err(DecryptError::WrongIvLength); }
+    if key_bytes.len() != SYMMETRIC_KEY_LEN { return Err(DecryptError::WrongKeyLength); }
+    let cipher = Aes256Gcm::new_from_slice(&key_bytes).map_err(|_| DecryptError::WrongKeyLength)?;
     let nonce = Nonce::from_slice(&iv);
-    let plaintext = cipher.decrypt(nonce, encrypted.as_ref()).unwrap();
-    String::from_utf8(plaintext).unwrap()
+
+    out.clear();
+    if BASE64.decode_vec(encrypted_data_b64, out).is_err() {
+        // 標準 Base64 で読めなければ URL-safe として読み直す
+        out.clear();
+        match decode_base64(encrypted_data_b64) {
+            Some(b) => out.extend_from_slice(&b),
+            None => return Err(DecryptError::InvalidBase64),
+        }
+    }
+    if cipher.decrypt_in_place(nonce, aad, out).is_err() {
+        out.clear();
+        return Err(DecryptError::DecryptionFailed);
+    }
+    Ok(())
+}
+
+/// 共通鍵に対するコミットメント (HKDF-SHA256 で鍵から導出した 32 バイト)
+///
+/// AES-GCM 単体は鍵コミットではなく、2 つの異なる鍵で復号できる暗号文を意図的に作れる。
+/// グループでは同じ encryptedData が別々のラップ鍵と共に提示されうるため、
+/// 受信者ごとに異なる平文を見せる攻撃 (invisible salamanders) が成立する。
+/// 暗号文と共にコミットメントを送り、復号前に照合することでこれを防ぐ
+pub fn key_commitment(key_b64: &str) -> Option<String> {
+    let key_bytes = decode_base64(key_b64)?;
+    let hk = Hkdf::<Sha256>::new(None, &key_bytes);
+    let mut out = [0u8; 32];
+    hk.expand(b"takos aes-gcm key commitment", &mut out).ok()?;
+    Some(BASE64.encode(out))
+}
+
+pub(crate) fn commitment_matches(key_b64: &str, commitment_b64: &str) -> bool {
+    let expected = match key_commitment(key_b64).and_then(|c| BASE64.decode(c).ok()) {
+        Some(v) => v,
+        None => return false,
+    };
+    let given = match decode_base64(commitment_b64) {
+        Some(v) => v,
+        None => return false,
+    };
+    crate::utils::ct_eq(&expected, &given)
+}
+
+/// 鍵コミットメント付き対称暗号化 (algorithm = "AES-GCM-commit")
+pub fn encrypt_with_symmetric_key_committing(
+    data: &str,
+    key_b64: &str,
+) -> SymmetricEncrypted {
+    let mut enc = encrypt_with_symmetric_key(data, key_b64);
+    enc.algorithm = ALGORITHM_AES_GCM_COMMIT.into();
+    enc.commitment = key_commitment(key_b64);
+    enc
+}
+
+/// 鍵コミットメントを照合してから対称復号。コミットメント不一致や復号失敗時は None
+pub fn decrypt_with_symmetric_key_committing(
+    encrypted_data_b64: &str,
+    iv_b64: &str,
+    key_b64: &str,
+    commitment_b64: &str,
+) -> Option<String> {
+    if !commitment_matches(key_b64, commitment_b64) { return None; }
+    try_decrypt_with_symmetric_key(encrypted_data_b64, iv_b64, key_b64)
+}
+
+/// IV（nonce）の再利用を検出するためのトラッカー
+///
+/// 同一の共通鍵で使用済みの IV を記録する。永続化できるよう Serialize/Deserialize を実装する。
+/// key_hash は対象の共通鍵の key_hash_raw (デコードした鍵バイト列の SHA-256)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NonceGuard {
+    pub key_hash: String,
+    seen: HashSet<String>,
+}
+
+impl NonceGuard {
+    pub fn new(key_hash: &str) -> Self {
+        NonceGuard { key_hash: key_hash.to_string(), seen: HashSet::new() }
+    }
+
+    /// この guard が key_b64 の鍵のものか
+    ///
+    /// key_hash_raw に加え、鍵の Base64 文字列のハッシュ (key_hash) で作られた以前の guard も受け付ける
+    pub fn matches_key(&self, key_b64: &str) -> bool {
+        let Some(stored) = decode_base64(&self.key_hash) else { return false };
+        decode_base64(key_b64).is_some_and(|key| Sha256::digest(key).as_slice() == stored.as_slice())
+            || Sha256::digest(key_b64.as_bytes()).as_slice() == stored.as_slice()
+    }
+
+    /// IV を登録する。既に使用済みなら false
+    pub fn register(&mut self, iv_b64: &str) -> bool {
+        self.seen.insert(iv_b64.to_string())
+    }
+
+    /// 登録済み IV の件数
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+/// 対称暗号化（NonceGuard で IV の再利用を防止）
+///
+/// IV が衝突した場合は再生成し、使用済みの IV を出力しない。
+/// 鍵が 256bit の Base64 でない場合と、guard が別の鍵のものである場合は None
+pub fn encrypt_with_symmetric_key_guarded(
+    data: &str,
+    key_b64: &str,
+    guard: &mut NonceGuard,
+) -> Option<SymmetricEncrypted> {
+    if !is_valid_symmetric_key(key_b64) || !guard.matches_key(key_b64) {
+        return None;
+    }
+    let mut iv = [0u8; 12];
+    loop {
+        OsRng.fill_bytes(&mut iv);
+        if guard.register(&BASE64.encode(iv)) {
+            break;
+        }
+    }
+
+    Some(encrypt_with_symmetric_key_nonce(data.as_bytes(), key_b64, iv))
 }
@@ -1,9 +1,8 @@
 use crate::r#type::{DeviceKey, EncryptedData};
-use crate::keyutils::generate_symmetric_key;
-use crate::crypto::{encrypt_with_symmetric_key, decrypt_with_symmetric_key};
-use crate::utils::key_hash;
-use base64::engine::general_purpose::STANDARD as BASE64;
-use base64::Engine as _;
+use crate::keyutils::{generate_symmetric_key, is_valid_symmetric_key, key_byte_len, key_json_has_required_fields};
+use crate::crypto::{encrypt_with_symmetric_key, decrypt_with_symmetric_key, decrypt_with_symmetric_key_committing, ALGORITHM_AES_GCM_COMMIT};
+use crate::utils::{encrypted_data_key_matches, key_material_hash};
+use crate::verify::decode_base64;
 use serde_json;
 
 /// デバイス鍵生成
@@ -14,21 +13,41 @@ pub fn generate_device_key() -> String {
 }
 pub fn is_valid_device_key(json:&str)->bool {
     serde_json::from_str::<DeviceKey>(json)
-        .map(|d| BASE64.decode(&d.key).map_or(false,|b|b.len()==32))
+        .map(|d| d.key_type == "deviceKey" && key_json_has_required_fields(json) && decode_base64(&d.key).is_some_and(|b| Some(b.len())==key_byte_len("deviceKey",false)))
         .unwrap_or(false)
 }
 pub fn encrypt_data_device_key(json:&str,data:&str)->Option<String> {
     let dk:DeviceKey=serde_json::from_str(json).ok()?;
     if dk.key_type!="deviceKey" {return None}
+    // 壊れた DeviceKey JSON で暗号層の unwrap に到達しないよう、先に鍵長を確認する
+    if !is_valid_symmetric_key(&dk.key) {return None}
     let enc = encrypt_with_symmetric_key(data, &dk.key);
-    let ed=EncryptedData{ key_type:"deviceKey".into(), key_hash:key_hash(json), encrypted_data:enc.encrypted_data, iv:enc.iv, algorithm:Some(enc.algorithm), cipher_text:None };
+    let ed=EncryptedData{ key_type:"deviceKey".into(), key_hash:key_material_hash(json)?, encrypted_data:enc.encrypted_data, iv:enc.iv, algorithm:Some(enc.algorithm), cipher_text:None, commitment:None, kem:None, compression:None };
     serde_json::to_string(&ed).ok()
 }
 pub fn decrypt_data_device_key(json:&str,enc_json:&str)->Option<String> {
+    if !encrypted_data_key_matches(enc_json, json) {return None}
     let dk:DeviceKey=serde_json::from_str(json).ok()?;
+    if dk.key_type!="deviceKey" || !is_valid_symmetric_key(&dk.key) {return None}
     let ed:EncryptedData=serde_json::from_str(enc_json).ok()?;
-    Some(decrypt_with_symmetric_key(&ed.encrypted_data, &ed.iv, &dk.key))
+    if ed.algorithm.as_deref() == Some(ALGORITHM_AES_GCM_COMMIT) {
+        return decrypt_with_symmetric_key_committing(&ed.encrypted_data, &ed.iv, &dk.key, ed.commitment.as_ref()?);
+    }
+    decrypt_with_symmetric_key(&ed.encrypted_data, &ed.iv, &dk.key).ok()
+}
+/// デバイス鍵の更新に伴いローカルに保存した EncryptedData を新しい鍵で暗号化し直す
+///
+/// 各 blob を旧鍵で復号して新鍵で暗号化し、同じ順序で返す。
+/// keyHash が旧鍵と一致しない blob (別の鍵で暗号化されたもの) や復号に失敗する blob が
+/// 1 つでもあれば全体を None とする (一部だけ更新された状態を作らない)
+pub fn rotate_device_key(old_device_json:&str,new_device_json:&str,encrypted_blobs_json:&[&str])->Option<Vec<String>> {
+    if !is_valid_device_key(old_device_json) || !is_valid_device_key(new_device_json) {return None}
+    encrypted_blobs_json.iter().map(|blob| {
+        if !encrypted_data_key_matches(blob, old_device_json) {return None}
+        let plain = decrypt_data_device_key(old_device_json, blob)?;
+        encrypt_data_device_key(new_device_json, &plain)
+    }).collect()
 }
 pub fn is_valid_encrypted_data_device_key(json:&str)->bool {
-    serde_json::from_str::<EncryptedData>(json).map(|ed|ed.key_type=="deviceKey").unwrap_or(false)
+    serde_json::from_str::<EncryptedData>(json).map(|ed|ed.is_well_formed("deviceKey", false)).unwrap_or(false)
 }
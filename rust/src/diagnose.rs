@@ -0,0 +1,285 @@
+//! 鍵 JSON の検証に失敗した理由を説明する (is_valid_* の逆)
+//!
+//! 返す文字列はサポート対応用の人が読む説明で、有効な場合は "ok"。
+//! keyType・フィールドの有無・デコード後の長さなど構造上の事実だけを含め、鍵の値は含めない
+
+use serde_json::{json, Map, Value};
+use crate::core::is_valid_uuid_v7;
+use crate::crypto::ALGORITHM_ML_KEM_768;
+use crate::key_bundle::is_valid_key_json;
+use crate::keyutils::{is_valid_ed25519_key, key_byte_len};
+use crate::master_key::{is_valid_master_key_public, verify_key_signature, MASTER_KEY_ALGORITHM};
+use crate::signature::ALGORITHM_ED25519_MLDSA65;
+use crate::verify::decode_base64;
+
+/// 有効な鍵に対する診断結果
+pub const DIAGNOSIS_OK: &str = "ok";
+
+type Diagnosis = Result<(), String>;
+
+fn finish(res: Diagnosis) -> String {
+    res.err().unwrap_or_else(|| DIAGNOSIS_OK.to_string())
+}
+
+/// JSON オブジェクトとして解析する (serde のエラー文は値を含みうるため位置だけを返す)
+fn parse_object(json: &str) -> Result<Map<String, Value>, String> {
+    match serde_json::from_str::<Value>(json) {
+        Ok(Value::Object(m)) => Ok(m),
+        Ok(_) => Err("not a JSON object".into()),
+        Err(e) => Err(format!("not valid JSON (line {}, column {})", e.line(), e.column())),
+    }
+}
+
+fn str_field<'a>(obj: &'a Map<String, Value>, name: &str) -> Result<&'a str, String> {
+    match obj.get(name) {
+        None => Err(format!("missing field \"{name}\"")),
+        Some(Value::String(s)) => Ok(s),
+        Some(_) => Err(format!("field \"{name}\" is not a string")),
+    }
+}
+
+/// 省略・null を許すフィールド
+fn optional_str_field<'a>(obj: &'a Map<String, Value>, name: &str) -> Result<Option<&'a str>, String> {
+    match obj.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(format!("field \"{name}\" is not a string")),
+    }
+}
+
+fn timestamp_field(obj: &Map<String, Value>) -> Diagnosis {
+    match obj.get("timestamp") {
+        None => Err("missing field \"timestamp\"".into()),
+        Some(v) if v.as_u64().is_some() => Ok(()),
+        Some(_) => Err("field \"timestamp\" is not an unsigned integer".into()),
+    }
+}
+
+fn key_type_field(obj: &Map<String, Value>, expected: &[&str]) -> Result<String, String> {
+    let key_type = str_field(obj, "keyType")?;
+    if expected.contains(&key_type) {
+        Ok(key_type.to_string())
+    } else {
+        Err(format!("wrong keyType {key_type:?} (expected {})", expected.join(" or ")))
+    }
+}
+
+fn algorithm_field(obj: &Map<String, Value>, expected: &str) -> Diagnosis {
+    let algorithm = str_field(obj, "algorithm")?;
+    if algorithm == expected {
+        Ok(())
+    } else {
+        Err(format!("wrong algorithm {algorithm:?} (expected {expected:?})"))
+    }
+}
+
+/// Base64 の鍵フィールドをデコードし、バイト長を確認する
+fn key_bytes_field(obj: &Map<String, Value>, name: &str, expected_len: Option<usize>) -> Diagnosis {
+    let bytes = decode_base64(str_field(obj, name)?).ok_or_else(|| format!("field \"{name}\" is not valid Base64"))?;
+    match expected_len {
+        Some(len) if bytes.len() != len => {
+            Err(format!("field \"{name}\" decodes to {} bytes (expected {len})", bytes.len()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn session_uuid_field(obj: &Map<String, Value>) -> Diagnosis {
+    if is_valid_uuid_v7(str_field(obj, "sessionUuid")?) {
+        Ok(())
+    } else {
+        Err("field \"sessionUuid\" is not a UUID v7".into())
+    }
+}
+
+/// AccountKey (公開鍵・秘密鍵) の診断
+pub fn diagnose_account_key(json: &str) -> String {
+    finish(check_account_key(json))
+}
+
+fn check_account_key(json: &str) -> Diagnosis {
+    let obj = parse_object(json)?;
+    let key_type = key_type_field(&obj, &["accountKeyPublic", "accountKeyPrivate"])?;
+    algorithm_field(&obj, ALGORITHM_ML_KEM_768)?;
+    timestamp_field(&obj)?;
+    key_bytes_field(&obj, "key", key_byte_len("accountKey", key_type == "accountKeyPublic"))
+}
+
+/// MasterKey (公開鍵・秘密鍵) の診断
+pub fn diagnose_master_key(json: &str) -> String {
+    finish(check_master_key(json))
+}
+
+fn check_master_key(json: &str) -> Diagnosis {
+    let obj = parse_object(json)?;
+    let key_type = key_type_field(&obj, &["masterKeyPublic", "masterKeyPrivate"])?;
+    // 旧形式の鍵には algorithm が無い
+    if let Some(algorithm) = optional_str_field(&obj, "algorithm")?
+        && algorithm != MASTER_KEY_ALGORITHM
+    {
+        return Err(format!("wrong algorithm {algorithm:?} (expected {MASTER_KEY_ALGORITHM:?})"));
+    }
+    key_bytes_field(&obj, "key", key_byte_len("masterKey", key_type == "masterKeyPublic"))
+}
+
+/// IdentityKey (公開鍵・秘密鍵) の診断
+pub fn diagnose_identity_key(json: &str) -> String {
+    finish(check_identity_key(json))
+}
+
+fn check_identity_key(json: &str) -> Diagnosis {
+    let obj = parse_object(json)?;
+    let key_type = key_type_field(&obj, &["identityKeyPublic", "identityKeyPrivate"])?;
+    let is_public = key_type == "identityKeyPublic";
+    key_bytes_field(&obj, "key", key_byte_len("identityKey", is_public))?;
+    timestamp_field(&obj)?;
+    let algorithm = str_field(&obj, "algorithm")?;
+    match (algorithm, optional_str_field(&obj, "classicalKey")?) {
+        ("ML-DSA-65", None) => {}
+        ("ML-DSA-65", Some(_)) => return Err("field \"classicalKey\" must be absent for ML-DSA-65".into()),
+        (ALGORITHM_ED25519_MLDSA65, None) => {
+            return Err(format!("missing field \"classicalKey\" (required for {ALGORITHM_ED25519_MLDSA65})"));
+        }
+        (ALGORITHM_ED25519_MLDSA65, Some(classical)) => {
+            key_bytes_field(&obj, "classicalKey", Some(32))?;
+            if !is_valid_ed25519_key(classical, is_public) {
+                return Err("field \"classicalKey\" is not a valid Ed25519 public key".into());
+            }
+        }
+        (other, _) => {
+            return Err(format!("wrong algorithm {other:?} (expected \"ML-DSA-65\" or {ALGORITHM_ED25519_MLDSA65:?})"));
+        }
+    }
+    session_uuid_field(&obj)
+}
+
+/// RoomKey の診断
+pub fn diagnose_room_key(json: &str) -> String {
+    finish(check_room_key(json))
+}
+
+fn check_room_key(json: &str) -> Diagnosis {
+    let obj = parse_object(json)?;
+    key_type_field(&obj, &["roomKey"])?;
+    key_bytes_field(&obj, "key", key_byte_len("roomKey", true))?;
+    algorithm_field(&obj, "AES-GCM")?;
+    timestamp_field(&obj)?;
+    optional_str_field(&obj, "previousKeyHash")?;
+    session_uuid_field(&obj)
+}
+
+/// 鍵 JSON は文字列のままでもオブジェクトでも受け付ける
+fn key_json_of(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Object(_) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+/// 1 つの鍵についての監査結果
+///
+/// masterSignature は署名が無い・マスター鍵で署名されない鍵・マスター公開鍵が無効な場合 null
+fn audit_key(key: Option<&Value>, sign: Option<&Value>, master_pub: Option<&str>, now_ms: Option<u64>) -> Value {
+    let Some(key_json) = key.and_then(key_json_of) else {
+        return json!({ "valid": false, "reason": "missing or malformed key" });
+    };
+    let obj = serde_json::from_str::<Value>(&key_json).ok();
+    let field = |name: &str| obj.as_ref().and_then(|o| o.get(name)).cloned().unwrap_or(Value::Null);
+    let timestamp = field("timestamp").as_u64();
+    let age_ms = match (now_ms, timestamp) {
+        (Some(now), Some(ts)) => Some(now.saturating_sub(ts)),
+        _ => None,
+    };
+    let master_signature = match (sign.and_then(key_json_of), master_pub) {
+        (Some(sign_json), Some(master)) => Some(verify_key_signature(&key_json, &sign_json, master)),
+        _ => None,
+    };
+    json!({
+        "valid": is_valid_key_json(&key_json),
+        "keyType": field("keyType"),
+        "algorithm": field("algorithm"),
+        "timestamp": timestamp,
+        "ageMs": age_ms,
+        "masterSignature": master_signature,
+    })
+}
+
+/// {publicKey, sign} 形式の署名付き公開鍵エントリ
+fn audit_signed(entry: &Value, master_pub: Option<&str>, now_ms: Option<u64>) -> Value {
+    audit_key(entry.get("publicKey"), entry.get("sign"), master_pub, now_ms)
+}
+
+fn audit_signed_list(section: Option<&Value>, master_pub: Option<&str>, now_ms: Option<u64>) -> Value {
+    match section.and_then(Value::as_array) {
+        Some(list) => list.iter().map(|e| audit_signed(e, master_pub, now_ms)).collect(),
+        None => Value::Array(Vec::new()),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now_ms() -> Option<u64> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    Some(SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now_ms() -> Option<u64> {
+    None
+}
+
+/// アカウントのエクスポート全体を監査し、鍵ごとの状態を返す (「鍵の確認」画面用)
+///
+/// 入力は {masterKey, identityKeys: [{publicKey, sign}], accountKey: {publicKey, sign},
+/// deviceKey, shareKeys: [{publicKey, sign}]}。欠けたセクションは null または空配列として報告し、
+/// 不正な入力でも panic しない。wasm32 では現在時刻が取れないため ageMs は null。
+/// マスター鍵が無い・不正なまま署名付きの鍵がある場合、署名を検証できないため
+/// signaturesVerified と allValid は false になる
+pub fn audit_account_export(export_json: &str) -> Value {
+    audit_account_export_inner(export_json, now_ms())
+}
+
+/// audit_account_export の現在時刻 (ミリ秒) を指定する版
+pub fn audit_account_export_at(export_json: &str, now_ms: u64) -> Value {
+    audit_account_export_inner(export_json, Some(now_ms))
+}
+
+fn audit_account_export_inner(export_json: &str, now_ms: Option<u64>) -> Value {
+    // オブジェクトでない入力は全セクション欠落として扱い、allValid を false にする
+    let (export, parsed) = match serde_json::from_str::<Value>(export_json) {
+        Ok(v @ Value::Object(_)) => (v, true),
+        _ => (Value::Object(Map::new()), false),
+    };
+    let master = export.get("masterKey").and_then(key_json_of);
+    let master_pub = master.as_deref().filter(|m| is_valid_master_key_public(m));
+    let section = |name: &str| export.get(name).filter(|v| !v.is_null());
+
+    let master_report = section("masterKey").map(|m| audit_key(Some(m), None, None, now_ms));
+    let identity = audit_signed_list(section("identityKeys"), master_pub, now_ms);
+    let account = section("accountKey").map(|a| audit_signed(a, master_pub, now_ms));
+    let device = section("deviceKey").map(|d| audit_key(Some(d), None, None, now_ms));
+    let share = audit_signed_list(section("shareKeys"), master_pub, now_ms);
+
+    // 署名付きセクションの鍵がすべてマスター鍵で検証できたか。
+    // マスター鍵が欠けている・不正な場合は検証できていないので false (鍵が 1 つも無ければ true)
+    let signed = || account.iter()
+        .chain(identity.as_array().into_iter().flatten())
+        .chain(share.as_array().into_iter().flatten());
+    let signatures_verified = signed().all(|e| e.get("masterSignature") == Some(&Value::Bool(true)));
+
+    // 入力が解析でき、存在するすべての鍵が有効で、署名がすべて検証できれば true
+    let all_valid = parsed
+        && signatures_verified
+        && master_report.iter().chain(device.iter()).chain(signed())
+            .all(|e| e.get("valid") == Some(&Value::Bool(true)));
+
+    json!({
+        "allValid": all_valid,
+        "signaturesVerified": signatures_verified,
+        "masterKey": master_report,
+        "identityKeys": identity,
+        "accountKey": account,
+        "deviceKey": device,
+        "shareKeys": share,
+    })
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use crate::keyutils::{KEM768_PRIVATE_KEY_LEN, KEM768_PUBLIC_KEY_LEN, SYMMETRIC_KEY_LEN};
+
+/// 解析・検証の失敗理由
+///
+/// Option を返す既存 API では区別できない失敗を呼び出し側に伝えるために使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TakosError {
+    /// JSON として解析できない、または型が合わない
+    MalformedJson(String),
+    /// スキーマ検証 (validate_message など) に失敗
+    InvalidSchema(&'static str),
+    /// カウンタ方式の IV を使い切った (RoomKeyCipher)。RoomKey をローテーションする
+    NonceCounterExhausted,
+}
+
+impl TakosError {
+    /// 機械可読なエラーコード
+    pub fn code(&self) -> &'static str {
+        match self {
+            TakosError::MalformedJson(_) => "malformedJson",
+            TakosError::InvalidSchema(_) => "invalidSchema",
+            TakosError::NonceCounterExhausted => "nonceCounterExhausted",
+        }
+    }
+}
+
+impl fmt::Display for TakosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TakosError::MalformedJson(e) => write!(f, "malformed JSON: {}", e),
+            TakosError::InvalidSchema(s) => write!(f, "{} does not match its schema", s),
+            TakosError::NonceCounterExhausted => write!(f, "nonce counter exhausted; rotate the room key"),
+        }
+    }
+}
+
+impl std::error::Error for TakosError {}
+
+/// crypto::encrypt の失敗理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptError {
+    /// 公開鍵が Base64 として不正
+    InvalidBase64,
+    /// 公開鍵の長さが ML-KEM-768 の封入鍵 (KEM768_PUBLIC_KEY_LEN) と異なる
+    WrongKeyLength,
+    /// KEM 封入または AES-GCM 暗号化に失敗
+    EncryptionFailed,
+}
+
+impl EncryptError {
+    /// 機械可読なエラーコード
+    pub fn code(&self) -> &'static str {
+        match self {
+            EncryptError::InvalidBase64 => "invalidPublicKey",
+            EncryptError::WrongKeyLength => "wrongKeyLength",
+            EncryptError::EncryptionFailed => "encryptionFailed",
+        }
+    }
+}
+
+impl fmt::Display for EncryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptError::InvalidBase64 => write!(f, "public key is not valid base64"),
+            EncryptError::WrongKeyLength => write!(f, "public key must be {KEM768_PUBLIC_KEY_LEN} bytes (ML-KEM-768)"),
+            EncryptError::EncryptionFailed => write!(f, "encryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for EncryptError {}
+
+/// 対称復号の失敗理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptError {
+    /// 鍵・IV・暗号文のいずれかが Base64 として不正
+    InvalidBase64,
+    /// IV が 12 バイトでない
+    WrongIvLength,
+    /// 鍵の長さが不正 (共通鍵は SYMMETRIC_KEY_LEN, ML-KEM-768 秘密鍵は KEM768_PRIVATE_KEY_LEN)
+    WrongKeyLength,
+    /// 認証タグの検証に失敗 (鍵違い・改ざん)
+    DecryptionFailed,
+    /// 平文が UTF-8 として不正
+    InvalidUtf8,
+}
+
+impl DecryptError {
+    /// 機械可読なエラーコード
+    pub fn code(&self) -> &'static str {
+        match self {
+            DecryptError::InvalidBase64 => "invalidBase64",
+            DecryptError::WrongIvLength => "wrongIvLength",
+            DecryptError::WrongKeyLength => "wrongKeyLength",
+            DecryptError::DecryptionFailed => "decryptionFailed",
+            DecryptError::InvalidUtf8 => "invalidUtf8",
+        }
+    }
+}
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecryptError::InvalidBase64 => write!(f, "input is not valid base64"),
+            DecryptError::WrongIvLength => write!(f, "IV must be 12 bytes"),
+            DecryptError::WrongKeyLength => write!(f, "key has the wrong length (AES-256: {SYMMETRIC_KEY_LEN} bytes, ML-KEM-768: {KEM768_PRIVATE_KEY_LEN} bytes)"),
+            DecryptError::DecryptionFailed => write!(f, "decryption failed"),
+            DecryptError::InvalidUtf8 => write!(f, "plaintext is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for DecryptError {}
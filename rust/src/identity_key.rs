@@ -1,13 +1,36 @@
-use crate::r#type::IdentityKey;
-use crate::signature::{create_signature_object_mlds65, verify_with_mlds65};
-use crate::keyutils::generate_dsa65_key_pair;
+use crate::r#type::{IdentityKey, Sign};
+#[cfg(feature = "master-key")]
+use crate::r#type::GeneratedIdentityKey;
+use crate::signature::{
+    create_signature_object_hybrid,
+    create_signature_object_mlds65,
+    verify_hybrid_ed25519_mlds65,
+    verify_signature_object_with_algorithm,
+    ALGORITHM_ED25519_MLDSA65,
+};
+use crate::keyutils::{is_valid_dsa65_key, is_valid_ed25519_key, is_valid_key_pair_sign, key_json_has_required_fields};
+use crate::verify::verify_signature_object_with_algorithm_quiet;
 use crate::core::is_valid_uuid_v7;
-use crate::master_key::{is_valid_master_key_private, is_valid_master_key_public, sign_master_key};
-use crate::utils::key_hash;
+use crate::utils::{logged_validation, signer_key_hash};
+#[cfg(feature = "master-key")]
+use crate::keyutils::{generate_dsa65_key_pair, generate_dsa65_key_pair_from_rng, generate_ed25519_key_pair};
+#[cfg(feature = "master-key")]
+use crate::master_key::{is_valid_master_key_private, is_valid_master_key_public, sign_master_key_with_public};
+#[cfg(feature = "master-key")]
+use crate::utils::{key_fingerprint, sign_key_hash};
+#[cfg(feature = "master-key")]
 use chrono::Utc;
+#[cfg(feature = "master-key")]
+use rand::{rngs::OsRng, CryptoRng, RngCore, SeedableRng};
+#[cfg(feature = "master-key")]
+use rand_chacha::ChaCha20Rng;
 use serde_json;
 
-/// 秘密鍵で IdentityKey に署名
+/// 秘密鍵で IdentityKey に署名 (ハイブリッド鍵なら sign_identity_key_hybrid と同じ)
+///
+/// 非推奨: keyHash を渡す形式は次のリリースで削除する。sign_identity_key_with_public を使う
+#[deprecated(note = "pass the public key to sign_identity_key_with_public instead of a keyHash")]
+#[allow(deprecated)]
 pub fn sign_identity_key(
     key_json: &str,
     data: &str,
@@ -15,10 +38,22 @@ pub fn sign_identity_key(
 ) -> Option<String> {
     let ik: IdentityKey = serde_json::from_str(key_json).ok()?;
     if ik.key_type != "identityKeyPrivate" { return None; }
+    if ik.algorithm == ALGORITHM_ED25519_MLDSA65 {
+        return sign_identity_key_hybrid(key_json, data, key_hash);
+    }
     create_signature_object_mlds65(&ik.key, data.as_bytes(), key_hash, "identityKey").ok()
 }
 
+/// IdentityKey で署名 (keyHash は IdentityKey 公開鍵から計算する。ハイブリッド鍵にも対応)
+#[allow(deprecated)]
+pub fn sign_identity_key_with_public(key_json: &str, public_key_json: &str, data: &str) -> Option<String> {
+    let hash = signer_key_hash(public_key_json, "identityKeyPublic")?;
+    sign_identity_key(key_json, data, &hash)
+}
+
 /// 公開鍵で IdentityKey の署名検証
+///
+/// ハイブリッド鍵の場合は両方の署名を要求する (ML-DSA-65 のみの署名では通らない)
 pub fn verify_identity_key(
     key_json: &str,
     sign_json: &str,
@@ -29,24 +64,187 @@ pub fn verify_identity_key(
         Err(_) => return false,
     };
     if ik.key_type != "identityKeyPublic" { return false; }
-    let sign: crate::r#type::Sign = match serde_json::from_str(sign_json) {
+    verify_identity_key_bytes(&ik, sign_json, data.as_bytes(), true)
+}
+
+/// ログを出さない verify_identity_key
+///
+/// 同じ署名を複数の候補 (データの表記・公開鍵) で試し、失敗の記録は呼び出し側で一度だけ行う場合に使う
+pub(crate) fn verify_identity_key_quiet(key_json: &str, sign_json: &str, data: &str) -> bool {
+    match serde_json::from_str::<IdentityKey>(key_json) {
+        Ok(ik) if ik.key_type == "identityKeyPublic" => verify_identity_key_bytes(&ik, sign_json, data.as_bytes(), false),
+        _ => false,
+    }
+}
+
+/// 複数の候補公開鍵のうち、署名を検証できた最初の鍵のインデックスを返す
+///
+/// 鍵のローテーション中など、旧鍵・新鍵のどちらで署名されたか分からない場合に使う。
+/// 各鍵は順に到達した時点で解析し、最初に成功した時点で打ち切る
+pub fn verify_identity_key_any(pub_keys_json: &[&str], sign_json: &str, data: &str) -> Option<usize> {
+    pub_keys_json.iter().position(|key_json| verify_identity_key(key_json, sign_json, data))
+}
+
+fn verify_identity_key_bytes(ik: &IdentityKey, sign_json: &str, data: &[u8], log: bool) -> bool {
+    if ik.algorithm != ALGORITHM_ED25519_MLDSA65 {
+        return if log {
+            verify_signature_object_with_algorithm(&ik.key, sign_json, data, "identityKey", "ML-DSA-65")
+        } else {
+            verify_signature_object_with_algorithm_quiet(&ik.key, sign_json, data, "identityKey", "ML-DSA-65")
+        };
+    }
+    let sign: Sign = match serde_json::from_str(sign_json) {
+        Ok(v) => v,
+        Err(_) => {
+            if log {
+                log_debug!("malformed Sign JSON (expected keyType identityKey)");
+            }
+            return false;
+        }
+    };
+    match &ik.classical_key {
+        Some(classical) => {
+            let ok = sign.key_type == "identityKey"
+                && sign.algorithm.as_deref() == Some(ALGORITHM_ED25519_MLDSA65)
+                && verify_hybrid_ed25519_mlds65(classical, &ik.key, data, &sign.signature);
+            if !ok && log {
+                log_warn!("signature verification failed (keyType identityKey, algorithm {ALGORITHM_ED25519_MLDSA65})");
+            }
+            ok
+        }
+        None => false,
+    }
+}
+
+/// ハイブリッド IdentityKey (Ed25519 + ML-DSA-65) で署名
+///
+/// signature は Ed25519 署名 (64 バイト) と ML-DSA-65 署名を連結したもの
+///
+/// 非推奨: keyHash を渡す形式は次のリリースで削除する。sign_identity_key_with_public を使う
+#[deprecated(note = "pass the public key to sign_identity_key_with_public instead of a keyHash")]
+pub fn sign_identity_key_hybrid(
+    key_json: &str,
+    data: &str,
+    key_hash: &str,
+) -> Option<String> {
+    let ik: IdentityKey = serde_json::from_str(key_json).ok()?;
+    if ik.key_type != "identityKeyPrivate" || ik.algorithm != ALGORITHM_ED25519_MLDSA65 { return None; }
+    let classical = ik.classical_key.as_deref()?;
+    create_signature_object_hybrid(classical, &ik.key, data.as_bytes(), key_hash, "identityKey").ok()
+}
+
+/// ハイブリッド署名の検証。鍵がハイブリッドでない場合は false
+pub fn verify_identity_key_hybrid(
+    key_json: &str,
+    sign_json: &str,
+    data: &str,
+) -> bool {
+    let ik: IdentityKey = match serde_json::from_str(key_json) {
         Ok(v) => v,
         Err(_) => return false,
     };
-    if sign.key_type != "identityKey" { return false; }
-    verify_with_mlds65(&ik.key, data.as_bytes(), &sign.signature)
+    ik.key_type == "identityKeyPublic"
+        && ik.algorithm == ALGORITHM_ED25519_MLDSA65
+        && verify_identity_key_bytes(&ik, sign_json, data.as_bytes(), true)
+}
+
+/// IdentityKey 署名の逐次検証
+///
+/// isLarge なメッセージをディスクからストリームで読みながら検証するための API。
+/// ml_dsa は署名対象を一括で受け取るため現状は内部でバッファしているが、
+/// 呼び出し側は update / finalize の形で今のうちに移行しておける
+pub struct MessageVerifier {
+    identity_key: IdentityKey,
+    sign_json: String,
+    buffer: Vec<u8>,
+}
+
+impl MessageVerifier {
+    /// 公開鍵 JSON が IdentityKey の公開鍵でなければ None
+    pub fn new(identity_pub_json: &str, sign_json: &str) -> Option<Self> {
+        let ik: IdentityKey = serde_json::from_str(identity_pub_json).ok()?;
+        if ik.key_type != "identityKeyPublic" { return None; }
+        Some(Self {
+            identity_key: ik,
+            sign_json: sign_json.to_string(),
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// これまでに渡されたデータ全体に対する verify_identity_key と同じ結果を返す
+    pub fn finalize(self) -> bool {
+        verify_identity_key_bytes(&self.identity_key, &self.sign_json, &self.buffer, true)
+    }
 }
 
 /// IdentityKey を生成し、マスター鍵で署名
+#[cfg(feature = "master-key")]
 pub fn generate_identity_key(
     uuid: &str,
     master_public_json: &str,
     master_private_json: &str,
 ) -> Option<(String, String, String)> {
-    if !is_valid_uuid_v7(uuid) { return None; }
     if !is_valid_master_key_private(master_private_json) { return None; }
     if !is_valid_master_key_public(master_public_json)  { return None; }
-    let (pub_b64, priv_b64) = generate_dsa65_key_pair().ok()?;
+    generate_identity_key_from_rng(&mut OsRng, uuid, master_public_json, master_private_json)
+}
+
+/// 複数の IdentityKey をまとめて生成・署名する (アカウント作成時など)
+///
+/// uuids[i] が i 番目の鍵の sessionUuid になる (uuids の長さは count と一致すること)。
+/// 乱数生成器は 1 つを使い回し、native では 8MB スタックのワーカースレッド 1 本で全件を処理する。
+/// ML-DSA-65 の鍵生成もマスター鍵での署名 (sign_master_key_with_public) もスレッドを生成しないため、
+/// 生成されるスレッドはこの 1 本だけ。
+/// いずれかの生成・署名に失敗した場合は None
+#[cfg(feature = "master-key")]
+pub fn generate_identity_keys_batch(
+    count: usize,
+    master_public_json: &str,
+    master_private_json: &str,
+    uuids: &[&str],
+) -> Option<Vec<(String, String, String)>> {
+    if uuids.len() != count { return None; }
+    if !is_valid_master_key_private(master_private_json) { return None; }
+    if !is_valid_master_key_public(master_public_json)  { return None; }
+    let run = || {
+        let mut rng = ChaCha20Rng::from_rng(OsRng).ok()?;
+        uuids
+            .iter()
+            .map(|uuid| generate_identity_key_from_rng(&mut rng, uuid, master_public_json, master_private_json))
+            .collect::<Option<Vec<_>>>()
+    };
+    // wasm32 ではスレッドを使えないため直接生成
+    #[cfg(target_arch = "wasm32")]
+    {
+        run()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::thread::scope(|s| {
+            std::thread::Builder::new()
+                .stack_size(8 * 1024 * 1024)
+                .spawn_scoped(s, run)
+                .ok()?
+                .join()
+                .ok()?
+        })
+    }
+}
+
+/// generate_identity_key の本体 (マスター鍵の検証は呼び出し側で済ませる)
+#[cfg(feature = "master-key")]
+fn generate_identity_key_from_rng<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    uuid: &str,
+    master_public_json: &str,
+    master_private_json: &str,
+) -> Option<(String, String, String)> {
+    if !is_valid_uuid_v7(uuid) { return None; }
+    let (pub_b64, priv_b64) = generate_dsa65_key_pair_from_rng(rng).ok()?;
     #[cfg(target_arch = "wasm32")]
     let timestamp = 0u64;
     #[cfg(not(target_arch = "wasm32"))]
@@ -57,6 +255,7 @@ pub fn generate_identity_key(
         algorithm: "ML-DSA-65".into(),
         timestamp,
         session_uuid: uuid.into(),
+        classical_key: None,
     };
     let priv_obj = IdentityKey {
         key_type: "identityKeyPrivate".into(),
@@ -64,41 +263,137 @@ pub fn generate_identity_key(
         algorithm: "ML-DSA-65".into(),
         timestamp,
         session_uuid: uuid.into(),
+        classical_key: None,
     };
     let pub_json = serde_json::to_string(&pub_obj).ok()?;
     let priv_json = serde_json::to_string(&priv_obj).ok()?;
-    let mk: crate::r#type::MasterKey = serde_json::from_str(master_public_json).ok()?;
-    let mh = key_hash(&mk.key);
-    let sign = crate::master_key::sign_master_key(
-        master_private_json,
-        &pub_json,
-        &mh,
-    )?;
+    let sign = sign_master_key_with_public(master_private_json, master_public_json, &pub_json)?;
     Some((pub_json, priv_json, sign))
 }
 
+/// IdentityKey を生成・署名し、keyHash とフィンガープリントも併せて返す
+#[cfg(feature = "master-key")]
+pub fn generate_identity_key_full(
+    uuid: &str,
+    master_public_json: &str,
+    master_private_json: &str,
+) -> Option<GeneratedIdentityKey> {
+    let (public_key, private_key, sign) = generate_identity_key(uuid, master_public_json, master_private_json)?;
+    Some(GeneratedIdentityKey {
+        key_hash: sign_key_hash(&public_key)?,
+        fingerprint: key_fingerprint(&public_key),
+        public_key,
+        private_key,
+        sign,
+    })
+}
+
+/// ハイブリッド IdentityKey (Ed25519 + ML-DSA-65) を生成し、マスター鍵で署名
+///
+/// 移行期間中、どちらか一方のアルゴリズムが破られても署名を偽造できないようにする
+#[cfg(feature = "master-key")]
+pub fn generate_identity_key_hybrid(
+    uuid: &str,
+    master_public_json: &str,
+    master_private_json: &str,
+) -> Option<(String, String, String)> {
+    if !is_valid_uuid_v7(uuid) { return None; }
+    if !is_valid_master_key_private(master_private_json) { return None; }
+    if !is_valid_master_key_public(master_public_json)  { return None; }
+    let (pub_b64, priv_b64) = generate_dsa65_key_pair().ok()?;
+    let (ed_pub_b64, ed_priv_b64) = generate_ed25519_key_pair().ok()?;
+    let timestamp = Utc::now().timestamp_millis() as u64;
+    let pub_obj = IdentityKey {
+        key_type: "identityKeyPublic".into(),
+        key: pub_b64,
+        algorithm: ALGORITHM_ED25519_MLDSA65.into(),
+        timestamp,
+        session_uuid: uuid.into(),
+        classical_key: Some(ed_pub_b64),
+    };
+    let priv_obj = IdentityKey {
+        key_type: "identityKeyPrivate".into(),
+        key: priv_b64,
+        algorithm: ALGORITHM_ED25519_MLDSA65.into(),
+        timestamp,
+        session_uuid: uuid.into(),
+        classical_key: Some(ed_priv_b64),
+    };
+    let pub_json = serde_json::to_string(&pub_obj).ok()?;
+    let priv_json = serde_json::to_string(&priv_obj).ok()?;
+    let sign = sign_master_key_with_public(master_private_json, master_public_json, &pub_json)?;
+    Some((pub_json, priv_json, sign))
+}
+
+/// algorithm と classicalKey の組み合わせが妥当か
+fn is_valid_identity_key_algorithm(ik: &IdentityKey, is_public: bool) -> bool {
+    match (ik.algorithm.as_str(), ik.classical_key.as_deref()) {
+        ("ML-DSA-65", None) => true,
+        (ALGORITHM_ED25519_MLDSA65, Some(classical)) => is_valid_ed25519_key(classical, is_public),
+        _ => false,
+    }
+}
+
 /// 秘密鍵 JSON の妥当性チェック
 pub fn is_valid_identity_key_private(key_json: &str) -> bool {
-    if let Ok(ik) = serde_json::from_str::<IdentityKey>(key_json) {
+    let ok = if let Ok(ik) = serde_json::from_str::<IdentityKey>(key_json) {
         ik.key_type == "identityKeyPrivate"
-            && ik.algorithm == "ML-DSA-65"
+            && key_json_has_required_fields(key_json)
+            && is_valid_dsa65_key(&ik.key, false)
+            && is_valid_identity_key_algorithm(&ik, false)
             && is_valid_uuid_v7(&ik.session_uuid)
-    } else { false }
+    } else { false };
+    logged_validation("identityKeyPrivate", ok)
 }
 
 /// 公開鍵 JSON の妥当性チェック
 pub fn is_valid_identity_key_public(key_json: &str) -> bool {
-    if let Ok(ik) = serde_json::from_str::<IdentityKey>(key_json) {
+    let ok = if let Ok(ik) = serde_json::from_str::<IdentityKey>(key_json) {
         ik.key_type == "identityKeyPublic"
-            && ik.algorithm == "ML-DSA-65"
+            && key_json_has_required_fields(key_json)
+            && is_valid_dsa65_key(&ik.key, true)
+            && is_valid_identity_key_algorithm(&ik, true)
             && is_valid_uuid_v7(&ik.session_uuid)
-    } else { false }
+    } else { false };
+    logged_validation("identityKeyPublic", ok)
+}
+
+/// 公開鍵と秘密鍵が同じ IdentityKey のペアか (keyType を確認した上で試し署名→検証)
+///
+/// ハイブリッド鍵は Ed25519 側も対になっているか確かめるため、両方の署名で試す
+pub fn identity_key_pair_matches(pub_json: &str, priv_json: &str) -> bool {
+    if !is_valid_identity_key_public(pub_json) || !is_valid_identity_key_private(priv_json) {
+        return false;
+    }
+    match serde_json::from_str::<IdentityKey>(priv_json) {
+        Ok(ik) if ik.algorithm == ALGORITHM_ED25519_MLDSA65 => {
+            sign_identity_key_with_public(priv_json, pub_json, "test").is_some_and(|sign| verify_identity_key(pub_json, &sign, "test"))
+        }
+        Ok(_) => is_valid_key_pair_sign(pub_json, priv_json),
+        Err(_) => false,
+    }
+}
+
+/// candidate が current と同じセッションの、厳密に新しい IdentityKey 公開鍵か
+///
+/// クライアントは同じ sessionUuid 内で厳密に新しくない置き換え鍵を拒否すること
+/// (古い鍵を現在の鍵として提示されるのを防ぐ)。timestamp が等しい場合は新しいとみなさない
+pub fn is_newer_identity_key(candidate_json: &str, current_json: &str) -> bool {
+    if !is_valid_identity_key_public(candidate_json) || !is_valid_identity_key_public(current_json) {
+        return false;
+    }
+    match (
+        serde_json::from_str::<IdentityKey>(candidate_json),
+        serde_json::from_str::<IdentityKey>(current_json),
+    ) {
+        (Ok(candidate), Ok(current)) => {
+            candidate.session_uuid == current.session_uuid && candidate.timestamp > current.timestamp
+        }
+        _ => false,
+    }
 }
 
 /// Sign JSON の妥当性チェック
 pub fn is_valid_sign_identity_key(sign_json: &str) -> bool {
-    if let Ok(sign) = serde_json::from_str::<crate::r#type::Sign>(sign_json) {
-        sign.key_type == "identityKey"
-            && sign.algorithm.as_deref() == Some("ML-DSA-65")
-    } else { false }
+    crate::signature::is_valid_sign_for(sign_json, "identityKey")
 }
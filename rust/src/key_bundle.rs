@@ -0,0 +1,117 @@
+use crate::crypto::{encrypt_with_symmetric_key, try_decrypt_with_symmetric_key};
+use crate::utils::canonicalize_key_json;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rand::{rngs::OsRng, RngCore};
+use serde_json::{self, json, Value};
+
+/// キーバンドルの識別子とフォーマットバージョン
+pub const KEY_BUNDLE_MAGIC: &str = "takosKeyBundle";
+pub const KEY_BUNDLE_VERSION: u64 = 1;
+/// バンドルの鍵導出と暗号化のアルゴリズム (ヘッダの kdf / algorithm)
+const KEY_BUNDLE_KDF: &str = "Argon2id";
+const KEY_BUNDLE_ALGORITHM: &str = "AES-GCM";
+/// 取り込むバンドルに許す Argon2 のメモリコスト上限 (KiB)。ヘッダの値で過大なメモリを確保させない
+const KEY_BUNDLE_MAX_M_COST: u32 = 1024 * 1024;
+
+/// パスフレーズから AES-256 鍵を導出 (Argon2id)
+fn derive_bundle_key(passphrase: &str, salt: &[u8], params: Params) -> Option<String> {
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .ok()?;
+    Some(BASE64.encode(key))
+}
+
+/// ヘッダの kdfParams から Argon2 のパラメータを復元する
+///
+/// kdfParams を持たない書き出し (パラメータを記録する前の形式) は既定値で導出されている
+fn bundle_kdf_params(v: &Value) -> Option<Params> {
+    let Some(p) = v.get("kdfParams") else { return Some(Params::default()) };
+    let field = |name: &str| -> Option<u32> { u32::try_from(p.get(name)?.as_u64()?).ok() };
+    let m_cost = field("memoryCost")?;
+    if m_cost > KEY_BUNDLE_MAX_M_COST { return None; }
+    Params::new(m_cost, field("timeCost")?, field("parallelism")?, None).ok()
+}
+
+/// keyType に応じた鍵 JSON の妥当性チェック
+pub fn is_valid_key_json(key_json: &str) -> bool {
+    let v: Value = match serde_json::from_str(key_json) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    match v.get("keyType").and_then(Value::as_str) {
+        Some("masterKeyPublic") => crate::master_key::is_valid_master_key_public(key_json),
+        Some("masterKeyPrivate") => crate::master_key::is_valid_master_key_private(key_json),
+        Some("identityKeyPublic") => crate::identity_key::is_valid_identity_key_public(key_json),
+        Some("identityKeyPrivate") => crate::identity_key::is_valid_identity_key_private(key_json),
+        Some("accountKeyPublic") => crate::account_key::is_valid_account_key_public(key_json),
+        Some("accountKeyPrivate") => crate::account_key::is_valid_account_key_private(key_json),
+        Some("accountPrekeyPublic") => crate::prekey::is_valid_account_prekey_public(key_json),
+        Some("accountPrekeyPrivate") => crate::prekey::is_valid_account_prekey_private(key_json),
+        Some("serverKeyPublic") => crate::server_key::is_valid_server_key_public(key_json),
+        Some("serverKeyPrivate") => crate::server_key::is_valid_server_key_private(key_json),
+        Some("roomKey") => crate::room_key::is_valid_room_key(key_json),
+        Some("shareKeyPublic") => crate::share_key::is_valid_share_key_public(key_json),
+        Some("shareKeyPrivate") => crate::share_key::is_valid_share_key_private(key_json),
+        Some("shareSignKeyPublic") => crate::share_key::is_valid_share_sign_key_public(key_json),
+        Some("shareSignKeyPrivate") => crate::share_key::is_valid_share_sign_key_private(key_json),
+        Some("migrateKeyPublic") => crate::migrate_key::is_valid_migrate_key_public(key_json),
+        Some("migrateKeyPrivate") => crate::migrate_key::is_valid_migrate_key_private(key_json),
+        Some("migrateSignKeyPublic") => crate::migrate_key::is_valid_migrate_sign_key_public(key_json),
+        Some("migrateSignKeyPrivate") => crate::migrate_key::is_valid_migrate_sign_key_private(key_json),
+        Some("deviceKey") => crate::device_key::is_valid_device_key(key_json),
+        _ => false,
+    }
+}
+
+/// 複数の鍵をまとめてパスフレーズで暗号化 (端末移行用)
+///
+/// keys_json は鍵 JSON 文字列の配列。すべての鍵が妥当な場合のみバンドルを作成する
+pub fn export_key_bundle(keys_json: &str, passphrase: &str) -> Option<String> {
+    let keys: Vec<String> = serde_json::from_str(keys_json).ok()?;
+    if !keys.iter().all(|k| is_valid_key_json(k)) { return None; }
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let params = Params::default();
+    let bundle_key = derive_bundle_key(passphrase, &salt, params.clone())?;
+    let plain = serde_json::to_string(&keys).ok()?;
+    let enc = encrypt_with_symmetric_key(&plain, &bundle_key);
+    let bundle = json!({
+        "magic": KEY_BUNDLE_MAGIC,
+        "version": KEY_BUNDLE_VERSION,
+        "kdf": KEY_BUNDLE_KDF,
+        "kdfParams": {
+            "memoryCost": params.m_cost(),
+            "timeCost": params.t_cost(),
+            "parallelism": params.p_cost(),
+        },
+        "salt": BASE64.encode(salt),
+        "iv": enc.iv,
+        "algorithm": enc.algorithm,
+        "encryptedData": enc.encrypted_data,
+    });
+    serde_json::to_string(&bundle).ok()
+}
+
+/// キーバンドルの復号 (鍵 JSON 文字列の配列を返す)
+///
+/// パスフレーズ誤りや不正な形式、妥当でない鍵を含む場合は None
+pub fn import_key_bundle(bundle: &str, passphrase: &str) -> Option<String> {
+    let v: Value = serde_json::from_str(bundle).ok()?;
+    if v.get("magic")?.as_str()? != KEY_BUNDLE_MAGIC { return None; }
+    if v.get("version")?.as_u64()? != KEY_BUNDLE_VERSION { return None; }
+    if v.get("kdf")?.as_str()? != KEY_BUNDLE_KDF { return None; }
+    if v.get("algorithm")?.as_str()? != KEY_BUNDLE_ALGORITHM { return None; }
+    let params = bundle_kdf_params(&v)?;
+    let salt = BASE64.decode(v.get("salt")?.as_str()?).ok()?;
+    let iv = v.get("iv")?.as_str()?;
+    let encrypted = v.get("encryptedData")?.as_str()?;
+    let bundle_key = derive_bundle_key(passphrase, &salt, params)?;
+    let plain = try_decrypt_with_symmetric_key(encrypted, iv, &bundle_key)?;
+    let keys: Vec<String> = serde_json::from_str(&plain).ok()?;
+    if !keys.iter().all(|k| is_valid_key_json(k)) { return None; }
+    // 取り込む鍵は正規形に揃える (古い端末が URL-safe などで書き出していても同じ文字列になる)
+    let keys: Vec<String> = keys.iter().map(|k| canonicalize_key_json(k)).collect::<Option<_>>()?;
+    serde_json::to_string(&keys).ok()
+}
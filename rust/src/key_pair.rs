@@ -0,0 +1,188 @@
+use crate::r#type::{AccountKey, AccountPrekey, EncryptedData, MigrateKey, ShareKey};
+use crate::crypto::{encrypt, try_decrypt, ALGORITHM_ML_KEM_768};
+use crate::keyutils::{is_valid_kem_key, kem_public_key_from_private, key_json_has_required_fields};
+use crate::utils::{encrypted_data_key_matches, key_material_hash, logged_validation};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json;
+
+/// ML-KEM-768 ベースの鍵ペア (AccountKey / ShareKey / MigrateKey) 共通の処理
+///
+/// 鍵種別ごとの keyType 文字列を関連定数で持ち、検証・暗号化・復号の本体をここに集約する
+pub trait AsymmetricKeyPair: Serialize + DeserializeOwned {
+    /// 公開鍵の keyType
+    const PUBLIC_KEY_TYPE: &'static str;
+    /// 秘密鍵の keyType
+    const PRIVATE_KEY_TYPE: &'static str;
+    /// 暗号化データ (EncryptedData) の keyType
+    const ENCRYPTED_KEY_TYPE: &'static str;
+
+    fn key_type(&self) -> &str;
+    fn key(&self) -> &str;
+    /// keyType と key だけを差し替えた複製 (秘密鍵から公開鍵 JSON を復元するため)
+    fn with_key(&self, key_type: &str, key: String) -> Self;
+
+    /// 鍵種別固有の追加チェック (algorithm など)
+    fn is_valid_fields(&self) -> bool {
+        true
+    }
+
+    /// この鍵の KEM アルゴリズム (algorithm フィールドを持たない鍵は ML-KEM-768)
+    fn kem_algorithm(&self) -> &str {
+        ALGORITHM_ML_KEM_768
+    }
+
+    /// 公開鍵 JSON 検証
+    fn is_valid_public(json: &str) -> bool {
+        let ok = serde_json::from_str::<Self>(json)
+            .map(|k| {
+                k.key_type() == Self::PUBLIC_KEY_TYPE
+                    && key_json_has_required_fields(json)
+                    && k.is_valid_fields()
+                    && is_valid_kem_key(k.key(), true)
+            })
+            .unwrap_or(false);
+        logged_validation(Self::PUBLIC_KEY_TYPE, ok)
+    }
+
+    /// 秘密鍵 JSON 検証
+    fn is_valid_private(json: &str) -> bool {
+        let ok = serde_json::from_str::<Self>(json)
+            .map(|k| {
+                k.key_type() == Self::PRIVATE_KEY_TYPE
+                    && key_json_has_required_fields(json)
+                    && k.is_valid_fields()
+                    && is_valid_kem_key(k.key(), false)
+            })
+            .unwrap_or(false);
+        logged_validation(Self::PRIVATE_KEY_TYPE, ok)
+    }
+
+    /// EncryptedData JSON 検証
+    fn is_valid_encrypted_data(json: &str) -> bool {
+        serde_json::from_str::<EncryptedData>(json)
+            .map(|ed| ed.is_well_formed(Self::ENCRYPTED_KEY_TYPE, true))
+            .unwrap_or(false)
+    }
+
+    /// 秘密鍵 JSON から対応する公開鍵 JSON を復元
+    ///
+    /// 生成時と同じフィールド順で直列化されるため、keyHash の照合に使える
+    fn public_json_from_private(priv_json: &str) -> Option<String> {
+        if !Self::is_valid_private(priv_json) {
+            return None;
+        }
+        let k: Self = serde_json::from_str(priv_json).ok()?;
+        let pub_key = kem_public_key_from_private(k.key())?;
+        serde_json::to_string(&k.with_key(Self::PUBLIC_KEY_TYPE, pub_key)).ok()
+    }
+
+    /// 公開鍵による暗号化 (EncryptedData JSON)
+    fn encrypt_data(pub_json: &str, data: &str) -> Option<String> {
+        if !Self::is_valid_public(pub_json) {
+            return None;
+        }
+        Self::encrypt_data_unchecked(pub_json, data)
+    }
+
+    /// 公開鍵の検証を省略して暗号化 (encrypt_data から検証だけを除いたもの)
+    ///
+    /// pub_json が検証済みの公開鍵であることは呼び出し側の責任
+    fn encrypt_data_unchecked(pub_json: &str, data: &str) -> Option<String> {
+        let k: Self = serde_json::from_str(pub_json).ok()?;
+        let enc = encrypt(data, k.key()).ok()?;
+        let ed = EncryptedData {
+            key_type: Self::ENCRYPTED_KEY_TYPE.into(),
+            key_hash: key_material_hash(pub_json)?,
+            encrypted_data: enc.encrypted_data,
+            iv: enc.iv,
+            algorithm: Some(enc.algorithm),
+            cipher_text: Some(enc.cipher_text),
+            commitment: None,
+            kem: Some(k.kem_algorithm().into()),
+            compression: None,
+        };
+        serde_json::to_string(&ed).ok()
+    }
+
+    /// 秘密鍵による復号
+    fn decrypt_data(priv_json: &str, encrypted_json: &str) -> Option<String> {
+        if !Self::is_valid_private(priv_json) || !Self::is_valid_encrypted_data(encrypted_json) {
+            return None;
+        }
+        if !encrypted_data_key_matches(encrypted_json, priv_json) {
+            return None;
+        }
+        let k: Self = serde_json::from_str(priv_json).ok()?;
+        let ed: EncryptedData = serde_json::from_str(encrypted_json).ok()?;
+        // 暗号文を作った KEM と復号する鍵の KEM が一致しなければ拒否 (ダウングレード防止)
+        if ed.kem.as_deref().unwrap_or(ALGORITHM_ML_KEM_768) != k.kem_algorithm() {
+            return None;
+        }
+        let ciphertext = ed.cipher_text.as_ref()?;
+        try_decrypt(&ed.encrypted_data, ciphertext, &ed.iv, k.key())
+    }
+}
+
+impl AsymmetricKeyPair for AccountKey {
+    const PUBLIC_KEY_TYPE: &'static str = "accountKeyPublic";
+    const PRIVATE_KEY_TYPE: &'static str = "accountKeyPrivate";
+    const ENCRYPTED_KEY_TYPE: &'static str = "accountKey";
+
+    fn key_type(&self) -> &str { &self.key_type }
+    fn key(&self) -> &str { &self.key }
+    fn with_key(&self, key_type: &str, key: String) -> Self {
+        Self { key_type: key_type.into(), key, ..self.clone() }
+    }
+
+    fn is_valid_fields(&self) -> bool {
+        self.algorithm == ALGORITHM_ML_KEM_768
+    }
+
+    fn kem_algorithm(&self) -> &str {
+        &self.algorithm
+    }
+}
+
+impl AsymmetricKeyPair for ShareKey {
+    const PUBLIC_KEY_TYPE: &'static str = "shareKeyPublic";
+    const PRIVATE_KEY_TYPE: &'static str = "shareKeyPrivate";
+    const ENCRYPTED_KEY_TYPE: &'static str = "shareKey";
+
+    fn key_type(&self) -> &str { &self.key_type }
+    fn key(&self) -> &str { &self.key }
+    fn with_key(&self, key_type: &str, key: String) -> Self {
+        Self { key_type: key_type.into(), key, ..self.clone() }
+    }
+}
+
+impl AsymmetricKeyPair for MigrateKey {
+    const PUBLIC_KEY_TYPE: &'static str = "migrateKeyPublic";
+    const PRIVATE_KEY_TYPE: &'static str = "migrateKeyPrivate";
+    const ENCRYPTED_KEY_TYPE: &'static str = "migrateKey";
+
+    fn key_type(&self) -> &str { &self.key_type }
+    fn key(&self) -> &str { &self.key }
+    fn with_key(&self, key_type: &str, key: String) -> Self {
+        Self { key_type: key_type.into(), key, ..self.clone() }
+    }
+}
+
+impl AsymmetricKeyPair for AccountPrekey {
+    const PUBLIC_KEY_TYPE: &'static str = "accountPrekeyPublic";
+    const PRIVATE_KEY_TYPE: &'static str = "accountPrekeyPrivate";
+    const ENCRYPTED_KEY_TYPE: &'static str = "accountPrekey";
+
+    fn key_type(&self) -> &str { &self.key_type }
+    fn key(&self) -> &str { &self.key }
+    fn with_key(&self, key_type: &str, key: String) -> Self {
+        Self { key_type: key_type.into(), key, ..self.clone() }
+    }
+
+    fn is_valid_fields(&self) -> bool {
+        self.algorithm == ALGORITHM_ML_KEM_768
+    }
+
+    fn kem_algorithm(&self) -> &str {
+        &self.algorithm
+    }
+}
@@ -1,15 +1,107 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use crate::verify::decode_base64;
 use ml_kem::{array::Array, EncodedSizeUser, KemCore, MlKem768};
 use ml_kem::kem::{Encapsulate, Decapsulate};
-use ml_dsa::{EncodedSigningKey, MlDsa65, MlDsa87, SigningKey, KeyGen};
-use ml_dsa::signature::{Signer, SignatureEncoding};
-use rand::{rngs::OsRng, RngCore};
+use ml_dsa::{EncodedSigningKey, MlDsa65, MlDsa87, KeyGen};
+use rand::{rngs::OsRng, CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
 use serde_json;
+use crate::r#type::KeyTypeInfo;
+
+/// アルゴリズムごとの鍵バイト長 (Base64 デコード後)。鍵長の定数はここにだけ置く
+pub(crate) const KEM768_PUBLIC_KEY_LEN: usize = 1184;
+pub(crate) const KEM768_PRIVATE_KEY_LEN: usize = 2400;
+pub(crate) const DSA65_PUBLIC_KEY_LEN: usize = 1952;
+pub(crate) const DSA65_PRIVATE_KEY_LEN: usize = 4032;
+pub(crate) const DSA87_PUBLIC_KEY_LEN: usize = 2592;
+pub(crate) const DSA87_PRIVATE_KEY_LEN: usize = 4896;
+pub(crate) const SYMMETRIC_KEY_LEN: usize = 32;
+
+/// 鍵種別のレジストリ: (名前, アルゴリズム, 公開鍵長, 秘密鍵長, 必須フィールド)
+///
+/// 鍵種別の定義はここにだけ置き、key_byte_len・署名アルゴリズムの確認・スキーマ出力はすべてここを引く
+const KEY_TYPES: &[(&str, &str, usize, usize, &[&str])] = &[
+    ("masterKey", "ML-DSA-87", DSA87_PUBLIC_KEY_LEN, DSA87_PRIVATE_KEY_LEN, &["keyType", "key"]),
+    ("identityKey", "ML-DSA-65", DSA65_PUBLIC_KEY_LEN, DSA65_PRIVATE_KEY_LEN, &["keyType", "key", "algorithm", "timestamp", "sessionUuid"]),
+    ("serverKey", "ML-DSA-65", DSA65_PUBLIC_KEY_LEN, DSA65_PRIVATE_KEY_LEN, &["keyType", "key", "timestamp"]),
+    ("shareSignKey", "ML-DSA-65", DSA65_PUBLIC_KEY_LEN, DSA65_PRIVATE_KEY_LEN, &["keyType", "key", "algorithm", "timestamp", "sessionUuid"]),
+    ("migrateSignKey", "ML-DSA-65", DSA65_PUBLIC_KEY_LEN, DSA65_PRIVATE_KEY_LEN, &["keyType", "key"]),
+    ("accountKey", "ML-KEM-768", KEM768_PUBLIC_KEY_LEN, KEM768_PRIVATE_KEY_LEN, &["keyType", "key", "algorithm", "timestamp"]),
+    ("accountPrekey", "ML-KEM-768", KEM768_PUBLIC_KEY_LEN, KEM768_PRIVATE_KEY_LEN, &["keyType", "key", "algorithm", "timestamp"]),
+    ("shareKey", "ML-KEM-768", KEM768_PUBLIC_KEY_LEN, KEM768_PRIVATE_KEY_LEN, &["keyType", "key", "algorithm", "timestamp", "sessionUuid"]),
+    ("migrateKey", "ML-KEM-768", KEM768_PUBLIC_KEY_LEN, KEM768_PRIVATE_KEY_LEN, &["keyType", "key"]),
+    ("roomKey", "AES-GCM", SYMMETRIC_KEY_LEN, SYMMETRIC_KEY_LEN, &["keyType", "key", "algorithm", "timestamp", "sessionUuid"]),
+    ("deviceKey", "AES-GCM", SYMMETRIC_KEY_LEN, SYMMETRIC_KEY_LEN, &["keyType", "key"]),
+];
+
+/// 既知の鍵種別をすべて列挙する (ツール・テスト・他言語実装向け)
+pub fn describe_key_types() -> Vec<KeyTypeInfo> {
+    KEY_TYPES
+        .iter()
+        .map(|&(name, algorithm, public_len, private_len, fields)| KeyTypeInfo {
+            name: name.into(),
+            algorithm: algorithm.into(),
+            public_len,
+            private_len,
+            required_fields: fields.iter().map(|f| f.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// 鍵種別のアルゴリズム (レジストリの値)。未知の種別は None
+pub(crate) fn key_type_algorithm(key_type: &str) -> Option<&'static str> {
+    KEY_TYPES.iter().find(|e| e.0 == key_type).map(|e| e.1)
+}
+
+/// 鍵種別の必須フィールド (レジストリの値)。未知の種別は None
+pub(crate) fn key_type_required_fields(key_type: &str) -> Option<&'static [&'static str]> {
+    KEY_TYPES.iter().find(|e| e.0 == key_type).map(|e| e.4)
+}
+
+/// レジストリの名前をすべて列挙する (スキーマ出力用)
+pub(crate) fn key_type_names() -> impl Iterator<Item = &'static str> {
+    KEY_TYPES.iter().map(|e| e.0)
+}
+
+/// 鍵 JSON がレジストリの必須フィールドをすべて持つか
+///
+/// 種別は keyType から Public / Private を除いて引く。null は欠落として扱い、未知の種別は false
+pub(crate) fn has_required_fields(v: &serde_json::Value) -> bool {
+    let Some(key_type) = v.get("keyType").and_then(serde_json::Value::as_str) else {
+        return false;
+    };
+    let name = key_type
+        .strip_suffix("Public")
+        .or_else(|| key_type.strip_suffix("Private"))
+        .unwrap_or(key_type);
+    key_type_required_fields(name)
+        .is_some_and(|fields| fields.iter().all(|f| v.get(*f).is_some_and(|x| !x.is_null())))
+}
+
+/// 鍵 JSON 文字列版の has_required_fields
+pub(crate) fn key_json_has_required_fields(key_json: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(key_json).is_ok_and(|v| has_required_fields(&v))
+}
+
+/// 鍵種別ごとの正しい鍵バイト長 (Base64 デコード後)
+///
+/// key_type は "masterKey" のように Public / Private を除いた名前。
+/// 対称鍵 (roomKey, deviceKey) は is_public に関わらず同じ長さを返す。未知の種別は None
+pub fn key_byte_len(key_type: &str, is_public: bool) -> Option<usize> {
+    let &(_, _, public, private, _) = KEY_TYPES.iter().find(|e| e.0 == key_type)?;
+    Some(if is_public { public } else { private })
+}
 
 /// ML‑KEM‑768 鍵ペア生成 (Base64)
 pub fn generate_kem_key_pair() -> Result<(String, String), Box<dyn std::error::Error>> {
-    let mut rng = OsRng;
-    let (dec, enc) = MlKem768::generate(&mut rng);
+    generate_kem_key_pair_from_rng(&mut OsRng)
+}
+
+/// ML‑KEM‑768 鍵ペア生成 (任意の乱数生成器を使用)
+pub fn generate_kem_key_pair_from_rng<R: RngCore + CryptoRng>(
+    rng: &mut R,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let (dec, enc) = MlKem768::generate(rng);
     let pk = BASE64.encode(enc.as_bytes().as_slice());
     let sk = BASE64.encode(dec.as_bytes().as_slice());
     Ok((pk, sk))
@@ -17,20 +109,40 @@ pub fn generate_kem_key_pair() -> Result<(String, String), Box<dyn std::error::E
 
 /// ML‑DSA‑65 鍵ペア生成 (Base64)
 pub fn generate_dsa65_key_pair() -> Result<(String, String), Box<dyn std::error::Error>> {
-    let mut rng = OsRng;
-    let kp = MlDsa65::key_gen(&mut rng);
+    generate_dsa65_key_pair_from_rng(&mut OsRng)
+}
+
+/// ML‑DSA‑65 鍵ペア生成 (任意の乱数生成器を使用)
+pub fn generate_dsa65_key_pair_from_rng<R: RngCore + CryptoRng>(
+    rng: &mut R,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let kp = MlDsa65::key_gen(rng);
     let sk = BASE64.encode(kp.signing_key().encode());
     let pk = BASE64.encode(kp.verifying_key().encode());
     Ok((pk, sk))
 }
 
+/// Ed25519 鍵ペア生成 (Base64, 秘密鍵は 32 バイトのシード)
+pub fn generate_ed25519_key_pair() -> Result<(String, String), Box<dyn std::error::Error>> {
+    let sk = ed25519_dalek::SigningKey::generate(&mut OsRng);
+    Ok((BASE64.encode(sk.verifying_key().as_bytes()), BASE64.encode(sk.to_bytes())))
+}
+
 /// ML‑DSA‑87 鍵ペア生成 (Base64)
+#[cfg(feature = "master-key")]
 pub fn generate_dsa87_key_pair() -> Result<(String, String), Box<dyn std::error::Error>> {
-    let mut rng = OsRng;
+    generate_dsa87_key_pair_from_rng(&mut OsRng)
+}
+
+/// ML‑DSA‑87 鍵ペア生成 (任意の乱数生成器を使用)
+#[cfg(feature = "master-key")]
+pub fn generate_dsa87_key_pair_from_rng<R: RngCore + CryptoRng + Send>(
+    rng: &mut R,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
     // wasm32ではスレッド生成がサポートされないため、直接生成
     #[cfg(target_arch = "wasm32")]
     {
-        let kp = MlDsa87::key_gen(&mut rng);
+        let kp = MlDsa87::key_gen(rng);
         let sk = BASE64.encode(kp.signing_key().encode());
         let pk = BASE64.encode(kp.verifying_key().encode());
         return Ok((pk, sk));
@@ -38,26 +150,38 @@ pub fn generate_dsa87_key_pair() -> Result<(String, String), Box<dyn std::error:
     // それ以外では既存のスレッド生成版を利用
     #[cfg(not(target_arch = "wasm32"))]
     {
-        let handle = std::thread::Builder::new()
-            .stack_size(8 * 1024 * 1024)
-            .spawn(move || {
-                let kp = MlDsa87::key_gen(&mut rng);
-                let sk = BASE64.encode(kp.signing_key().encode());
-                let pk = BASE64.encode(kp.verifying_key().encode());
-                (pk, sk)
+        std::thread::scope(|s| {
+            let handle = std::thread::Builder::new()
+                .stack_size(8 * 1024 * 1024)
+                .spawn_scoped(s, move || {
+                    let kp = MlDsa87::key_gen(rng);
+                    let sk = BASE64.encode(kp.signing_key().encode());
+                    let pk = BASE64.encode(kp.verifying_key().encode());
+                    (pk, sk)
+                })?;
+            let (pk, sk) = handle.join().map_err(|e| {
+                Box::<dyn std::error::Error>::from(format!("Thread panicked: {:?}", e))
             })?;
-        let (pk, sk) = handle.join().map_err(|e| {
-            Box::<dyn std::error::Error>::from(format!("Thread panicked: {:?}", e))
-        })?;
-        Ok((pk, sk))
+            Ok((pk, sk))
+        })
     }
 }
 
 /// 対称鍵生成 (256bit → Base64)
 pub fn generate_symmetric_key() -> String {
+    generate_symmetric_key_from_rng(&mut OsRng)
+}
+
+/// 対称鍵生成 (任意の乱数生成器を使用)
+pub fn generate_symmetric_key_from_rng<R: RngCore + CryptoRng>(rng: &mut R) -> String {
     let mut key = [0u8; 32];
-    OsRng.fill_bytes(&mut key);
-    BASE64.encode(&key)
+    rng.fill_bytes(&mut key);
+    BASE64.encode(key)
+}
+
+/// シードから決定的に生成する乱数生成器 (テスト・再現用)
+pub fn seeded_rng(seed: [u8; 32]) -> ChaCha20Rng {
+    ChaCha20Rng::from_seed(seed)
 }
 
 /// 署名鍵ペア検証 (秘密鍵で試し署名→公開鍵で検証)
@@ -77,8 +201,9 @@ pub fn is_valid_key_pair_sign(pub_json: &str, priv_json: &str) -> bool {
     let stype = priv_val.get("keyType").and_then(|v| v.as_str()).unwrap_or("");
     let pkey = pub_val.get("key").and_then(|v| v.as_str()).unwrap_or("");
     let skey = priv_val.get("key").and_then(|v| v.as_str()).unwrap_or("");
-    // マスターキーの場合
+    // マスターキーの場合 (master-key feature が無効なら常に false)
     if ptype == "masterKeyPublic" && stype == "masterKeyPrivate" {
+        #[cfg(feature = "master-key")]
         if let Ok(sig) = crate::signature::sign_with_mlds87(skey, data) {
             return crate::signature::verify_with_mlds87(pkey, data, &sig);
         }
@@ -100,8 +225,8 @@ pub fn is_valid_key_pair_encrypt(pub_json: &str, priv_json: &str) -> bool {
     let pkey = match pub_val.get("key").and_then(|v| v.as_str()) { Some(k) => k, None => return false };
     let skey = match priv_val.get("key").and_then(|v| v.as_str()) { Some(k) => k, None => return false };
     // Base64デコード
-    let pkb = match BASE64.decode(pkey) { Ok(b) => b, Err(_) => return false };
-    let skb = match BASE64.decode(skey) { Ok(b) => b, Err(_) => return false };
+    let pkb = match decode_base64(pkey) { Some(b) => b, None => return false };
+    let skb = match decode_base64(skey) { Some(b) => b, None => return false };
     // EncapsulationKey生成・封入
     let pk_arr: Array<u8, <<MlKem768 as KemCore>::EncapsulationKey as EncodedSizeUser>::EncodedSize> = match Array::try_from(&pkb[..]) { Ok(a) => a, Err(_) => return false };
     let ek = <MlKem768 as KemCore>::EncapsulationKey::from_bytes(&pk_arr);
@@ -116,42 +241,58 @@ pub fn is_valid_key_pair_encrypt(pub_json: &str, priv_json: &str) -> bool {
 
 /// ML‑DSA‑65 鍵検証
 pub fn is_valid_dsa65_key(key_b64: &str, is_public: bool) -> bool {
-    if let Ok(bytes) = BASE64.decode(key_b64) {
-        if is_public { return bytes.len() == 1952; }
-        if let Ok(arr) = <EncodedSigningKey<MlDsa65>>::try_from(&bytes[..]) {
-            let sk = SigningKey::<MlDsa65>::decode(&arr);
-            let sig_bytes = sk.sign(b"test").to_bytes();
-            return sig_bytes.len() > 0;
-        }
+    if let Some(bytes) = decode_base64(key_b64) {
+        if is_public { return bytes.len() == DSA65_PUBLIC_KEY_LEN; }
+        // 試し署名は行わず、エンコード長のみを検証
+        return bytes.len() == DSA65_PRIVATE_KEY_LEN
+            && <EncodedSigningKey<MlDsa65>>::try_from(&bytes[..]).is_ok();
     }
     false
 }
 
 /// ML‑DSA‑87 鍵検証
 pub fn is_valid_dsa87_key(key_b64: &str, is_public: bool) -> bool {
-    if let Ok(bytes) = BASE64.decode(key_b64) {
-        if is_public { return bytes.len() == 2592; }
-        if let Ok(arr) = <EncodedSigningKey<MlDsa87>>::try_from(&bytes[..]) {
-            let sk = SigningKey::<MlDsa87>::decode(&arr);
-            let sig_bytes = sk.sign(b"test").to_bytes();
-            return sig_bytes.len() > 0;
-        }
+    if let Some(bytes) = decode_base64(key_b64) {
+        if is_public { return bytes.len() == DSA87_PUBLIC_KEY_LEN; }
+        // 試し署名は行わず、エンコード長のみを検証
+        return bytes.len() == DSA87_PRIVATE_KEY_LEN
+            && <EncodedSigningKey<MlDsa87>>::try_from(&bytes[..]).is_ok();
     }
     false
 }
 
 /// ML‑KEM‑768 鍵検証 (長さチェック)
 pub fn is_valid_kem_key(key_b64: &str, is_public: bool) -> bool {
-    if let Ok(bytes) = BASE64.decode(key_b64) {
-        return if is_public { bytes.len() == 1184 } else { bytes.len() == 2400 };
+    if let Some(bytes) = decode_base64(key_b64) {
+        return bytes.len() == if is_public { KEM768_PUBLIC_KEY_LEN } else { KEM768_PRIVATE_KEY_LEN };
     }
     false
 }
 
+/// ML‑KEM‑768 秘密鍵 (Base64) に埋め込まれた公開鍵 (Base64) を取り出す
+pub fn kem_public_key_from_private(private_key_b64: &str) -> Option<String> {
+    let sk_vec = decode_base64(private_key_b64)?;
+    let sk_arr: Array<u8, <<MlKem768 as KemCore>::DecapsulationKey as EncodedSizeUser>::EncodedSize> =
+        Array::try_from(&sk_vec[..]).ok()?;
+    let dk = <MlKem768 as KemCore>::DecapsulationKey::from_bytes(&sk_arr);
+    Some(BASE64.encode(dk.encapsulation_key().as_bytes().as_slice()))
+}
+
+/// Ed25519 鍵検証 (32 バイトで、公開鍵は曲線上の点として復元できること)
+pub fn is_valid_ed25519_key(key_b64: &str, is_public: bool) -> bool {
+    match decode_base64(key_b64) {
+        Some(bytes) if bytes.len() == 32 => {
+            // 公開鍵は曲線上の点として復元できること
+            !is_public || ed25519_dalek::VerifyingKey::from_bytes(&bytes.try_into().unwrap()).is_ok()
+        }
+        _ => false,
+    }
+}
+
 /// 対称鍵検証 (長さチェックのみ)
 pub fn is_valid_symmetric_key(key_b64: &str) -> bool {
-    if let Ok(bytes) = BASE64.decode(key_b64) {
-        bytes.len() == 32
+    if let Some(bytes) = decode_base64(key_b64) {
+        bytes.len() == SYMMETRIC_KEY_LEN
     } else {
         false
     }
@@ -159,14 +300,26 @@ pub fn is_valid_symmetric_key(key_b64: &str) -> bool {
 
 /// ランダム文字列生成
 pub fn generate_random_string(len: usize) -> String {
+    generate_random_string_from_rng(&mut OsRng, len)
+}
+
+/// ランダム文字列生成 (任意の乱数生成器を使用)
+///
+/// 62 は 2^32 を割り切らないため、単純な剰余では一部の文字が出やすくなる。
+/// 62 の倍数に収まらない値を捨てる棄却サンプリングで偏りをなくす
+/// (usize の幅に依存しないよう u32 で行い、同じシードならどの環境でも同じ文字列になる)
+pub fn generate_random_string_from_rng<R: RngCore + CryptoRng>(rng: &mut R, len: usize) -> String {
     const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                           abcdefghijklmnopqrstuvwxyz\
                           0123456789";
-    let mut rng = OsRng;
+    let n = CHARS.len() as u32;
+    let zone = u32::MAX - u32::MAX % n;
     (0..len)
-        .map(|_| {
-            let idx = (rng.next_u32() as usize) % CHARS.len();
-            CHARS[idx] as char
+        .map(|_| loop {
+            let v = rng.next_u32();
+            if v < zone {
+                break CHARS[(v % n) as usize] as char;
+            }
         })
         .collect()
 }
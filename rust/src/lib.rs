@@ -1,137 +1,450 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+// export_validation_rules の json! リテラルが既定の再帰上限 (128) を超えるため
+#![recursion_limit = "256"]
+
+extern crate alloc;
+
+// logging feature が有効なときだけ log クレートに出力する (無効時は何もしない)。
+// 鍵・平文・署名の値は渡さず、keyType やアルゴリズム名・失敗理由だけを出すこと
+#[allow(unused_macros)]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "logging")]
+        ::log::warn!($($arg)*);
+        #[cfg(not(feature = "logging"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+#[allow(unused_macros)]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "logging")]
+        ::log::debug!($($arg)*);
+        #[cfg(not(feature = "logging"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
 
 // モジュール公開
+// std なしでビルドした場合は verify (署名検証のみ) だけが使える
+pub mod verify;
+#[cfg(feature = "std")]
 pub mod utils;
+#[cfg(feature = "std")]
 pub mod core;
+#[cfg(feature = "std")]
 pub mod crypto;
+#[cfg(feature = "std")]
 pub mod r#type;
+#[cfg(feature = "std")]
 pub mod signature;
+#[cfg(feature = "std")]
 pub mod keyutils;
+#[cfg(feature = "std")]
 pub mod master_key;
+#[cfg(feature = "std")]
 pub mod identity_key;
+#[cfg(feature = "std")]
 pub mod account_key;
+#[cfg(feature = "std")]
 pub mod server_key;
+#[cfg(feature = "std")]
 pub mod room_key;
+#[cfg(feature = "std")]
 pub mod share_key;
+#[cfg(feature = "std")]
 pub mod migrate_key;
+#[cfg(feature = "std")]
 pub mod device_key;
+#[cfg(feature = "std")]
 pub mod schema;
+#[cfg(feature = "std")]
 pub mod message;
+#[cfg(feature = "std")]
+pub mod key_bundle;
+#[cfg(feature = "std")]
+pub mod cbor;
+#[cfg(feature = "std")]
+pub mod key_pair;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod prekey;
+#[cfg(feature = "std")]
+pub mod armor;
+#[cfg(feature = "std")]
+pub mod diagnose;
+#[cfg(feature = "std")]
+pub mod attachment;
 
 
 // 外部公開用 re-export
-pub use utils::key_hash;
-pub use core::is_valid_uuid_v7;
+#[cfg(not(feature = "std"))]
+pub use verify::{
+    verify_with_mlds65,
+    prehash_sha256,
+    verify_prehash_mlds65,
+    verify_with_ed25519,
+    verify_hybrid_ed25519_mlds65,
+    verify_signature_object,
+    verify_signature_object_with_algorithm,
+    verify_any,
+    verify_data_server_key,
+    decode_base64,
+    ALGORITHM_ED25519_MLDSA65,
+    ALGORITHM_MLDSA65_PREHASH_SHA256,
+    ALGORITHM_MLDSA87_PREHASH_SHA256,
+};
+#[cfg(all(not(feature = "std"), feature = "master-key"))]
+pub use verify::{
+    verify_with_mlds87,
+    verify_prehash_mlds87,
+};
+#[cfg(feature = "std")]
+pub use utils::{key_hash, key_hash_with, HashAlgo, key_hash_bytes, key_hash_raw, key_material_hash, Base64Variant, key_hash_with_variant, key_hash_bytes_with_variant, reencode_base64, canonicalize_key_json, is_canonical_key_json, decode_base64, sign_key_hash, compute_sign_key_hash, key_fingerprint, encrypted_data_key_matches, find_iv_collisions, group_by_key_hash, canonicalize_json};
+#[cfg(feature = "std")]
+pub use core::{is_valid_uuid_v7, uuid_v7_timestamp, is_valid_uuid_v7_bounded};
+#[cfg(feature = "std")]
 pub use crypto::{
     AsymmetricEncrypted,
     SymmetricEncrypted,
+    NonceGuard,
     encrypt,
     decrypt,
+    decrypt_bytes,
+    try_decrypt,
+    kem_encapsulate,
+    kem_decapsulate,
     encrypt_with_symmetric_key,
+    encrypt_bytes_with_symmetric_key,
+    encrypt_with_symmetric_key_aad,
     decrypt_with_symmetric_key,
+    decrypt_with_symmetric_key_aad,
+    try_decrypt_with_symmetric_key,
+    decrypt_into,
+    encrypt_with_symmetric_key_guarded,
+    counter_nonce,
+    key_commitment,
+    encrypt_with_symmetric_key_committing,
+    decrypt_with_symmetric_key_committing,
+    ALGORITHM_AES_GCM_COMMIT,
+    ALGORITHM_ML_KEM_768,
 };
+#[cfg(feature = "std")]
 pub use r#type::*;
+#[cfg(feature = "std")]
 pub use signature::{
-    sign_with_mlds87,
-    verify_with_mlds87,
     sign_with_mlds65,
     verify_with_mlds65,
-    create_signature_object_mlds87,
     create_signature_object_mlds65,
     verify_signature_object,
+    verify_signature_object_with_algorithm,
+    verify_any,
+    extract_signature,
+    wrap_signature,
+    sign_algorithm_for_key_type,
+    is_sign_algorithm_allowed,
+    signature_raw_len_mlds65,
+    signature_raw_len_mlds87,
+    normalize_signature_mlds65,
+    prehash_sha256,
+    sign_prehash_mlds65,
+    verify_prehash_mlds65,
+    create_signature_object_prehash_mlds65,
+    sign_with_ed25519,
+    verify_with_ed25519,
+    sign_hybrid_ed25519_mlds65,
+    verify_hybrid_ed25519_mlds65,
+    create_signature_object_hybrid,
+    ALGORITHM_ED25519_MLDSA65,
+    ALGORITHM_MLDSA65_PREHASH_SHA256,
+    ALGORITHM_MLDSA87_PREHASH_SHA256,
+};
+#[cfg(all(feature = "std", feature = "master-key"))]
+pub use signature::{
+    sign_with_mlds87,
+    verify_with_mlds87,
+    create_signature_object_mlds87,
+    normalize_signature_mlds87,
+    sign_prehash_mlds87,
+    verify_prehash_mlds87,
+    create_signature_object_prehash_mlds87,
 };
+#[cfg(feature = "std")]
 pub use keyutils::{
     generate_kem_key_pair,
     generate_dsa65_key_pair,
-    generate_dsa87_key_pair,
+    generate_ed25519_key_pair,
     generate_symmetric_key,
+    generate_kem_key_pair_from_rng,
+    generate_dsa65_key_pair_from_rng,
+    generate_symmetric_key_from_rng,
+    seeded_rng,
     is_valid_key_pair_sign,
     is_valid_key_pair_encrypt,
     is_valid_dsa65_key,
     is_valid_dsa87_key,
+    is_valid_ed25519_key,
     is_valid_kem_key,
     is_valid_symmetric_key,
+    key_byte_len,
+    describe_key_types,
+    kem_public_key_from_private,
     generate_random_string,
+    generate_random_string_from_rng,
+};
+#[cfg(all(feature = "std", feature = "master-key"))]
+pub use keyutils::{
+    generate_dsa87_key_pair,
+    generate_dsa87_key_pair_from_rng,
 };
+#[cfg(feature = "std")]
 pub use master_key::{
-    generate_master_key,
-    sign_master_key,
+    MASTER_KEY_ALGORITHM,
     verify_master_key,
     is_valid_master_key_private,
     is_valid_master_key_public,
+    master_key_pair_matches,
     is_valid_sign_master_key,
+    verify_key_signature,
+    validate_key_set,
 };
+#[cfg(all(feature = "std", feature = "master-key"))]
+pub use master_key::{
+    generate_master_key,
+    sign_master_key_with_public,
+    resign_key,
+};
+#[cfg(all(feature = "std", feature = "master-key"))]
+#[allow(deprecated)]
+pub use master_key::sign_master_key;
+#[cfg(feature = "std")]
 pub use identity_key::{
-    sign_identity_key,
+    sign_identity_key_with_public,
     verify_identity_key,
-    generate_identity_key,
+    verify_identity_key_any,
     is_valid_identity_key_private,
     is_valid_identity_key_public,
+    identity_key_pair_matches,
+    is_newer_identity_key,
     is_valid_sign_identity_key,
+    verify_identity_key_hybrid,
+    MessageVerifier,
 };
+#[cfg(feature = "std")]
+#[allow(deprecated)]
+pub use identity_key::sign_identity_key;
+#[cfg(feature = "std")]
+#[allow(deprecated)]
+pub use identity_key::sign_identity_key_hybrid;
+#[cfg(all(feature = "std", feature = "master-key"))]
+pub use identity_key::{
+    generate_identity_key,
+    generate_identity_key_full,
+    generate_identity_key_hybrid,
+    generate_identity_keys_batch,
+};
+#[cfg(feature = "std")]
 pub use account_key::{
-    generate_account_key,
     is_valid_account_key_public,
     is_valid_account_key_private,
+    account_key_pair_matches,
     encrypt_data_account_key,
+    encrypt_data_account_key_unchecked,
     is_valid_encrypted_data_account_key,
     decrypt_data_account_key,
     is_valid_encrypted_account_key,
+    encrypt_to_recipients,
+    decrypt_from_envelope,
+    encrypt_dm,
+    decrypt_dm_try_all,
 };
+#[cfg(all(feature = "std", feature = "master-key"))]
+pub use account_key::generate_account_key;
+#[cfg(feature = "std")]
 pub use server_key::{
     generate_server_key,
     is_valid_server_key_public,
     is_valid_server_key_private,
-    sign_data_server_key,
+    sign_data_server_key_with_public,
     verify_data_server_key,
+    is_valid_sign_server_key,
+    create_inclusion_proof,
+    verify_inclusion_proof,
 };
+#[cfg(feature = "std")]
+#[allow(deprecated)]
+pub use server_key::sign_data_server_key;
+#[cfg(feature = "std")]
 pub use room_key::{
     generate_room_key,
     is_valid_room_key,
     encrypt_data_room_key,
+    encrypt_data_room_key_aad,
+    encrypt_data_room_key_committing,
+    encrypt_data_room_key_compressed,
+    COMPRESSION_DEFLATE,
+    MAX_DECOMPRESSED_BYTES,
     decrypt_data_room_key,
+    decrypt_data_room_key_aad,
+    encrypt_bytes_room_key,
+    decrypt_bytes_room_key,
     is_valid_encrypted_data_room_key,
+    deterministic_room_uuid,
+    rotate_room_key,
+    derive_room_key,
+    RoomKeyCipher,
+    reencrypt_data_room_key,
+    rewrap_account_to_room,
+    sign_room_settings,
+    verify_room_settings,
+    verify_room_settings_with_version,
 };
+#[cfg(feature = "std")]
 pub use share_key::{
-    generate_share_key,
     is_valid_share_key_public,
     is_valid_share_key_private,
     encrypt_data_share_key,
     decrypt_data_share_key,
     is_valid_encrypted_data_share_key,
-    generate_share_sign_key,
+    transfer_share_to_account,
     is_valid_share_sign_key_public,
     is_valid_share_sign_key_private,
-    sign_data_share_sign_key,
+    sign_data_share_sign_key_with_public,
     verify_data_share_sign_key,
     is_valid_sign_share_sign_key,
 };
+#[cfg(feature = "std")]
+#[allow(deprecated)]
+pub use share_key::sign_data_share_sign_key;
+#[cfg(all(feature = "std", feature = "master-key"))]
+pub use share_key::{
+    generate_share_key,
+    generate_share_sign_key,
+};
+#[cfg(feature = "std")]
 pub use migrate_key::{
     generate_migrate_key,
+    generate_migrate_key_with_ttl,
+    is_migrate_key_expired,
+    DEFAULT_MIGRATE_KEY_TTL_MS,
     is_valid_migrate_key_public,
     is_valid_migrate_key_private,
     encrypt_data_migrate_key,
     decrypt_data_migrate_key,
     is_valid_encrypted_data_migrate_key,
     generate_migrate_sign_key,
-    sign_data_migrate_sign_key,
+    generate_migrate_sign_key_with_ttl,
+    is_valid_migrate_sign_key_public,
+    is_valid_migrate_sign_key_private,
+    sign_data_migrate_sign_key_with_public,
     verify_data_migrate_sign_key,
     is_valid_sign_migrate_sign_key,
 };
+#[cfg(feature = "std")]
+#[allow(deprecated)]
+pub use migrate_key::sign_data_migrate_sign_key;
+#[cfg(feature = "std")]
 pub use device_key::{
     generate_device_key,
     is_valid_device_key,
     encrypt_data_device_key,
     decrypt_data_device_key,
     is_valid_encrypted_data_device_key,
+    rotate_device_key,
 };
+#[cfg(feature = "std")]
 pub use message::{
     encrypt_message,
+    encrypt_message_private_metadata,
+    send_text_message,
+    should_be_large,
+    should_be_large_with_threshold,
+    LARGE_THRESHOLD_BYTES,
     decrypt_message,
+    decrypt_message_with_reason,
+    decrypt_message_guarded,
+    verify_message_only,
+    ReplayGuard,
+    ReplayCheck,
+    MESSAGE_TIMESTAMP_SKEW_MS,
+    DEFAULT_REPLAY_GUARD_CAPACITY,
+    decrypt_batch,
+    parse_message,
+    stamp_message,
+    verify_server_stamp,
+    verify_server_stamp_matches,
+    decrypt_stamped_message,
+    normalize_stored_message,
+    encode_message_wire,
+    decode_message_wire,
+    MESSAGE_WIRE_VERSION,
+    verify_against_stored,
+    extract_index_metadata,
     is_valid_message,
+    validate_message_original,
     create_text_content,
     create_image_content,
     create_video_content,
     create_audio_content,
     create_file_content,
+    create_thumbnail_content,
+    content_id,
+    validate_thumbnail_link,
+    validate_content_limits,
     encrypt_room_key_with_account_keys,
-};
\ No newline at end of file
+    encrypt_room_key_with_account_keys_partial,
+    distribute_room_key_to_share_keys,
+    wrap_room_key_for_recipients,
+    bulk_rotate_and_rewrap,
+    select_and_decrypt_room_key,
+    merge_distributions,
+    merge_distributions_with_room_keys,
+};
+#[cfg(feature = "std")]
+pub use error::{DecryptError, EncryptError, TakosError};
+#[cfg(feature = "std")]
+pub use prekey::{
+    is_valid_account_prekey_public,
+    is_valid_account_prekey_private,
+    encrypt_to_prekey,
+    decrypt_from_prekey,
+    is_valid_encrypted_data_prekey,
+};
+#[cfg(all(feature = "std", feature = "master-key"))]
+pub use prekey::generate_account_prekeys;
+#[cfg(feature = "std")]
+pub use key_bundle::{
+    export_key_bundle,
+    import_key_bundle,
+    is_valid_key_json,
+};
+#[cfg(feature = "std")]
+pub use armor::{to_armored, from_armored};
+#[cfg(feature = "std")]
+pub use schema::export_validation_rules;
+#[cfg(feature = "std")]
+pub use diagnose::{
+    diagnose_account_key,
+    diagnose_master_key,
+    diagnose_identity_key,
+    diagnose_room_key,
+    DIAGNOSIS_OK,
+    audit_account_export,
+    audit_account_export_at,
+};
+#[cfg(feature = "std")]
+pub use attachment::{
+    create_encrypted_attachment,
+    decrypt_attachment,
+    attachment_blob_hash,
+    ATTACHMENT_URI_SCHEME,
+};
+#[cfg(feature = "std")]
+pub use cbor::{
+    Cbor,
+    json_to_cbor,
+    cbor_to_json,
+};
+#[cfg(feature = "std")]
+pub use key_pair::AsymmetricKeyPair;
\ No newline at end of file
@@ -1,7 +1,3 @@
-use ml_kem::{array::Array, kem::{Decapsulate, Encapsulate}, KemCore, MlKem768};
-use ml_kem::EncodedSizeUser;
-use ml_dsa::{MlDsa65, KeyGen, signature::{Signer, Verifier, SignatureEncoding}};
-use rand::{thread_rng, Rng, CryptoRng};
 use takos_encrypt_ink_rs::generate_master_key;
 
 
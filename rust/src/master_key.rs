@@ -1,13 +1,29 @@
-use crate::r#type::MasterKey;
-use crate::signature::{create_signature_object_mlds87, verify_with_mlds87};
-use crate::keyutils::{generate_dsa87_key_pair, is_valid_dsa87_key};
+use crate::r#type::{KeySetFailure, KeySetReport, MasterKey};
+use crate::signature::{is_valid_sign_for, verify_signature_object_with_algorithm};
+#[cfg(feature = "master-key")]
+use crate::signature::create_signature_object_mlds87;
+use crate::keyutils::{is_valid_dsa87_key, is_valid_key_pair_sign, key_json_has_required_fields};
+#[cfg(feature = "master-key")]
+use crate::keyutils::generate_dsa87_key_pair;
+use crate::utils::logged_validation;
+#[cfg(feature = "master-key")]
+use crate::utils::signer_key_hash;
 use serde_json;
 
+/// マスター鍵の署名アルゴリズム
+pub const MASTER_KEY_ALGORITHM: &str = "ML-DSA-87";
+
+/// algorithm が無い (旧形式) か、ML-DSA-87 であること
+fn is_valid_master_key_algorithm(mk: &MasterKey) -> bool {
+    mk.algorithm.as_deref().is_none_or(|a| a == MASTER_KEY_ALGORITHM)
+}
+
 /// マスター鍵ペア生成 (Base64 JSON文字列)
+#[cfg(feature = "master-key")]
 pub fn generate_master_key() -> (String, String) {
     let (pub_b64, priv_b64) = generate_dsa87_key_pair().unwrap();
-    let pub_obj = MasterKey { key_type: "masterKeyPublic".into(), key: pub_b64 };
-    let priv_obj = MasterKey { key_type: "masterKeyPrivate".into(), key: priv_b64 };
+    let pub_obj = MasterKey { key_type: "masterKeyPublic".into(), key: pub_b64, algorithm: Some(MASTER_KEY_ALGORITHM.into()) };
+    let priv_obj = MasterKey { key_type: "masterKeyPrivate".into(), key: priv_b64, algorithm: Some(MASTER_KEY_ALGORITHM.into()) };
     (
         serde_json::to_string(&pub_obj).unwrap(),
         serde_json::to_string(&priv_obj).unwrap(),
@@ -15,6 +31,10 @@ pub fn generate_master_key() -> (String, String) {
 }
 
 /// マスター鍵署名作成
+///
+/// 非推奨: keyHash を渡す形式は次のリリースで削除する。sign_master_key_with_public を使う
+#[cfg(feature = "master-key")]
+#[deprecated(note = "pass the public key to sign_master_key_with_public instead of a keyHash")]
 pub fn sign_master_key(
     key_json: &str,
     data: &str,
@@ -25,6 +45,21 @@ pub fn sign_master_key(
     create_signature_object_mlds87(&mk.key, data.as_bytes(), pub_key_hash, "masterKey").ok()
 }
 
+/// マスター鍵で署名 (keyHash はマスター公開鍵から計算する)
+///
+/// 秘密鍵と公開鍵が対であることは確認しない (対でなければ検証に失敗する)。
+/// スレッドは生成せず、呼び出し元のスレッドで署名する
+#[cfg(feature = "master-key")]
+#[allow(deprecated)]
+pub fn sign_master_key_with_public(
+    key_json: &str,
+    public_key_json: &str,
+    data: &str,
+) -> Option<String> {
+    let hash = signer_key_hash(public_key_json, "masterKeyPublic")?;
+    sign_master_key(key_json, data, &hash)
+}
+
 /// マスター鍵署名検証
 pub fn verify_master_key(
     key_json: &str,
@@ -36,34 +71,131 @@ pub fn verify_master_key(
         Err(_) => return false,
     };
     if mk.key_type != "masterKeyPublic" { return false; }
-    let sign: crate::r#type::Sign = match serde_json::from_str(sign_json) {
-        Ok(s) => s,
-        Err(_) => return false,
-    };
-    if sign.key_type != "masterKey" { return false; }
-    verify_with_mlds87(&mk.key, data.as_bytes(), &sign.signature)
+    verify_signature_object_with_algorithm(&mk.key, sign_json, data.as_bytes(), "masterKey", "ML-DSA-87")
 }
 
 /// マスター鍵バリデーション (秘密鍵)
 pub fn is_valid_master_key_private(key_json: &str) -> bool {
-    if let Ok(mk) = serde_json::from_str::<MasterKey>(key_json) {
+    let ok = if let Ok(mk) = serde_json::from_str::<MasterKey>(key_json) {
         mk.key_type == "masterKeyPrivate"
+            && key_json_has_required_fields(key_json)
+            && is_valid_master_key_algorithm(&mk)
             && is_valid_dsa87_key(&mk.key, false)
-    } else { false }
+    } else { false };
+    logged_validation("masterKeyPrivate", ok)
 }
 
 /// マスター鍵バリデーション (公開鍵)
 pub fn is_valid_master_key_public(key_json: &str) -> bool {
-    if let Ok(mk) = serde_json::from_str::<MasterKey>(key_json) {
+    let ok = if let Ok(mk) = serde_json::from_str::<MasterKey>(key_json) {
         mk.key_type == "masterKeyPublic"
+            && key_json_has_required_fields(key_json)
+            && is_valid_master_key_algorithm(&mk)
             && is_valid_dsa87_key(&mk.key, true)
-    } else { false }
+    } else { false };
+    logged_validation("masterKeyPublic", ok)
+}
+
+/// 公開鍵と秘密鍵が同じマスター鍵のペアか (keyType を確認した上で試し署名→検証)
+///
+/// master-key feature が無効な場合は常に false
+pub fn master_key_pair_matches(pub_json: &str, priv_json: &str) -> bool {
+    is_valid_master_key_public(pub_json)
+        && is_valid_master_key_private(priv_json)
+        && is_valid_key_pair_sign(pub_json, priv_json)
 }
 
 /// 署名オブジェクト形式のバリデーション
 pub fn is_valid_sign_master_key(sign_json: &str) -> bool {
-    if let Ok(obj) = serde_json::from_str::<crate::r#type::Sign>(sign_json) {
-        obj.key_type == "masterKey"
-            && obj.algorithm.as_deref() == Some("ML-DSA-87")
-    } else { false }
+    is_valid_sign_for(sign_json, "masterKey")
+}
+
+/// マスター鍵で署名される鍵の種類
+const MASTER_SIGNED_KEY_TYPES: [&str; 5] = [
+    "identityKeyPublic",
+    "accountKeyPublic",
+    "accountPrekeyPublic",
+    "shareKeyPublic",
+    "shareSignKeyPublic",
+];
+
+fn master_signed_key_type(key_public_json: &str) -> Option<String> {
+    let v: serde_json::Value = serde_json::from_str(key_public_json).ok()?;
+    let key_type = v.get("keyType")?.as_str()?;
+    if !MASTER_SIGNED_KEY_TYPES.contains(&key_type) { return None; }
+    if !crate::key_bundle::is_valid_key_json(key_public_json) { return None; }
+    Some(key_type.to_string())
+}
+
+/// マスター鍵ローテーション後、既存の公開鍵に新しいマスター鍵で署名し直す
+///
+/// 公開鍵 JSON はそのまま署名対象とし、keyHash は新しいマスター公開鍵から計算する
+#[cfg(feature = "master-key")]
+pub fn resign_key(
+    key_public_json: &str,
+    new_master_priv_json: &str,
+    new_master_pub_json: &str,
+) -> Option<String> {
+    if !is_valid_master_key_private(new_master_priv_json)
+        || !is_valid_master_key_public(new_master_pub_json)
+    {
+        return None;
+    }
+    master_signed_key_type(key_public_json)?;
+    sign_master_key_with_public(new_master_priv_json, new_master_pub_json, key_public_json)
+}
+
+/// 公開鍵に付いたマスター鍵の署名を鍵の種類に応じて検証
+///
+/// 署名の keyHash が master_pub_json を指していない場合も false
+pub fn verify_key_signature(
+    key_public_json: &str,
+    sign_json: &str,
+    master_pub_json: &str,
+) -> bool {
+    if master_signed_key_type(key_public_json).is_none() { return false; }
+    let sign: crate::r#type::Sign = match serde_json::from_str(sign_json) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    match crate::utils::sign_key_hash(master_pub_json) {
+        Some(h) if h == sign.key_hash => {}
+        _ => return false,
+    }
+    verify_master_key(master_pub_json, sign_json, key_public_json)
+}
+
+/// 公開済みの鍵セット全体について、各鍵がマスター鍵で正しく署名されているかを検証
+///
+/// entries_json は [{keyType, keyPublic, sign}] の配列。
+/// マスター公開鍵が不正、または entries_json が配列でない場合は None
+pub fn validate_key_set(master_pub_json: &str, entries_json: &str) -> Option<KeySetReport> {
+    if !is_valid_master_key_public(master_pub_json) { return None; }
+    let entries: Vec<serde_json::Value> = serde_json::from_str(entries_json).ok()?;
+    let mut report = KeySetReport::default();
+    for (index, entry) in entries.iter().enumerate() {
+        let key_type = entry.get("keyType").and_then(|v| v.as_str());
+        let fail = |reason: &str| KeySetFailure {
+            index,
+            key_type: key_type.map(String::from),
+            reason: reason.into(),
+        };
+        let (Some(key_type), Some(key_public), Some(sign)) = (
+            key_type,
+            entry.get("keyPublic").and_then(|v| v.as_str()),
+            entry.get("sign").and_then(|v| v.as_str()),
+        ) else {
+            report.failed.push(fail("malformedEntry"));
+            continue;
+        };
+        match master_signed_key_type(key_public) {
+            None => report.failed.push(fail("notMasterSigned")),
+            Some(t) if t != key_type => report.failed.push(fail("keyTypeMismatch")),
+            Some(_) if !verify_key_signature(key_public, sign, master_pub_json) => {
+                report.failed.push(fail("invalidSignature"))
+            }
+            Some(_) => report.valid.push(index),
+        }
+    }
+    Some(report)
 }
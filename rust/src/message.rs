@@ -1,34 +1,195 @@
-use crate::r#type::{NotEncryptMessageValue, NotEncryptMessage, EncryptedMessage, TextContent, ImageContent};
+use crate::r#type::{AccountKey, EncryptedMessage, EncryptedData, IdentityKey, Message, RoomKey, TextContent, ImageContent, ThumbnailContent};
+use crate::error::TakosError;
+use crate::utils::{canonicalize_json, decode_base64, key_hash, key_hash_matches, key_material_hash};
 use crate::schema::validate_message;
-use crate::room_key::{encrypt_data_room_key, decrypt_data_room_key, is_valid_room_key, is_valid_encrypted_data_room_key};
-use crate::identity_key::{is_valid_identity_key_private, is_valid_identity_key_public, sign_identity_key, verify_identity_key};
-use crate::account_key::encrypt_data_account_key;
+use crate::room_key::{encrypt_data_room_key_aad, rotate_room_key, decrypt_data_room_key, decrypt_data_room_key_aad, is_valid_room_key, is_valid_encrypted_data_room_key};
+use crate::identity_key::{is_valid_identity_key_private, is_valid_identity_key_public, sign_identity_key_with_public, verify_identity_key, verify_identity_key_quiet};
+use crate::account_key::{decrypt_data_account_key, encrypt_data_account_key, is_valid_account_key_public};
+use crate::key_pair::AsymmetricKeyPair;
+use crate::share_key::{encrypt_data_share_key, is_valid_share_key_public};
+use crate::server_key::{is_valid_server_key_private, sign_data_server_key_with_public, verify_data_server_key};
 use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use chrono::Utc;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 
+/// メッセージの暗号化と署名
+///
+/// original は編集・転送で置き換える元メッセージの ID (extract_index_metadata の messageId)。
+/// 指定した場合はメタデータの original より優先する。空文字列は拒否する。
+/// 本文の暗号文には channel / roomid / timestamp を AAD として束縛する (message_aad)。
+/// isLarge の本文も圧縮しない (圧縮後の長さから内容が推測されうるうえ、
+/// encrypt_data_room_key_compressed の暗号文は AAD と併用できない)。
+/// Sign.keyHash は identity_pub_json (IdentityKey 公開鍵) から計算する
 pub fn encrypt_message(
     message_value_json: &str,
     metadata_json: &str,
     room_key_json: &str,
     identity_priv_json: &str,
-    identity_pubhash: &str,
+    identity_pub_json: &str,
     roomid: &str,
+    original: Option<&str>,
+) -> Option<String> {
+    encrypt_message_with(message_value_json, metadata_json, room_key_json, identity_priv_json, identity_pub_json, roomid, original, None)
+}
+
+/// encrypt_message のメタデータ秘匿版
+///
+/// channel を本文と同じ RoomKey で暗号化して encryptedMetadata に入れ、平文の channel は空文字列にする。
+/// hide_original が true なら original も encryptedMetadata に入れる。
+/// roomid / timestamp / isLarge はルーティングのため平文のまま (署名対象であることは変わらない)。
+/// decrypt_message は encryptedMetadata を復号して channel / original を復元する
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_message_private_metadata(
+    message_value_json: &str,
+    metadata_json: &str,
+    room_key_json: &str,
+    identity_priv_json: &str,
+    identity_pub_json: &str,
+    roomid: &str,
+    original: Option<&str>,
+    hide_original: bool,
+) -> Option<String> {
+    encrypt_message_with(message_value_json, metadata_json, room_key_json, identity_priv_json, identity_pub_json, roomid, original, Some(hide_original))
+}
+
+/// encrypt_message の本体。private_metadata が Some(hide_original) ならメタデータを秘匿する
+#[allow(clippy::too_many_arguments)]
+fn encrypt_message_with(
+    message_value_json: &str,
+    metadata_json: &str,
+    room_key_json: &str,
+    identity_priv_json: &str,
+    identity_pub_json: &str,
+    roomid: &str,
+    original: Option<&str>,
+    private_metadata: Option<bool>,
 ) -> Option<String> {
     if !is_valid_room_key(room_key_json) { return None; }
     if !is_valid_identity_key_private(identity_priv_json) { return None; }
-    println!("debug1");
-    let encrypted_val = encrypt_data_room_key(room_key_json, message_value_json)?;
     let meta: Value = serde_json::from_str(metadata_json).ok()?;
     let channel = meta.get("channel")?.as_str()?.to_string();
     let timestamp = meta.get("timestamp")?.as_u64()?;
+    // 本文の AAD には (秘匿する場合も) 本当の channel を使う
+    let aad = message_aad(&channel, roomid, timestamp);
+    // 別ルームの RoomKey で暗号化するのを防ぐ (roomid は sessionUuid と同じ表記である必要がある)
+    let encrypted_val = encrypt_data_room_key_aad(room_key_json, message_value_json, Some(roomid), &aad)?;
     let is_large = meta.get("isLarge")?.as_bool()?;
-    let original = meta.get("original").and_then(|v| v.as_str()).map(String::from);
-    let msg = EncryptedMessage { encrypted: true, value: encrypted_val.clone(), channel: channel.clone(), original: original.clone(), timestamp, is_large, roomid: roomid.to_string() };
-    let msg_str = serde_json::to_string(&msg).ok()?;
-    let sign = sign_identity_key(identity_priv_json, &msg_str, identity_pubhash)?;
+    let mut original = original
+        .or_else(|| meta.get("original").and_then(|v| v.as_str()))
+        .map(String::from);
+    if original.as_deref() == Some("") { return None; }
+    let (channel, encrypted_metadata) = match private_metadata {
+        None => (channel, None),
+        Some(hide_original) => {
+            let mut hidden = json!({ "channel": channel });
+            if hide_original && let Some(o) = original.take() {
+                hidden["original"] = json!(o);
+            }
+            let enc = encrypt_data_room_key_aad(room_key_json, &hidden.to_string(), Some(roomid), &metadata_aad(roomid, timestamp))?;
+            (String::new(), Some(enc))
+        }
+    };
+    let msg = EncryptedMessage { encrypted: true, value: encrypted_val, channel, original, timestamp, is_large, roomid: roomid.to_string(), encrypted_metadata };
+    // 署名は正規形 (canonicalize_json) に対して行い、送信するのも正規形そのもの
+    let msg_str = canonicalize_json(&serde_json::to_string(&msg).ok()?)?;
+    let sign = sign_identity_key_with_public(identity_priv_json, identity_pub_json, &msg_str)?;
     let res = json!({"message": msg_str, "sign": sign});
     serde_json::to_string(&res).ok()
 }
 
+/// メッセージ本文の暗号化に使う AAD
+///
+/// 署名付きの封筒だけでなく暗号文そのものを文脈に束縛し、RoomKey を持つ者が
+/// 暗号文を別の channel / roomid / timestamp のメッセージへ移し替えても復号できないようにする
+fn message_aad(channel: &str, roomid: &str, timestamp: u64) -> Vec<u8> {
+    // serde_json::Map はキー順に並ぶため、送受信で同じバイト列になる
+    json!({"channel": channel, "roomid": roomid, "timestamp": timestamp}).to_string().into_bytes()
+}
+
+/// encryptedMetadata の暗号化に使う AAD (本文の AAD とは別のバイト列にする)
+fn metadata_aad(roomid: &str, timestamp: u64) -> Vec<u8> {
+    json!({"encryptedMetadata": true, "roomid": roomid, "timestamp": timestamp}).to_string().into_bytes()
+}
+
+/// encryptedMetadata を復号して (channel, original) を返す
+///
+/// 秘匿したメタデータと平文のメタデータが両方ある (channel が空でない・original が二重) ものは拒否する
+fn open_private_metadata(m: &EncryptedMessage, room_key_json: &str) -> Result<(String, Option<String>), &'static str> {
+    let enc = match &m.encrypted_metadata {
+        Some(enc) => enc,
+        None => return Ok((m.channel.clone(), m.original.clone())),
+    };
+    if !m.channel.is_empty() { return Err("malformedMessage"); }
+    if !is_valid_encrypted_data_room_key(enc) { return Err("invalidEncryptedData"); }
+    let hidden = decrypt_data_room_key_aad(room_key_json, enc, &metadata_aad(&m.roomid, m.timestamp))
+        .ok_or("decryptionFailed")?;
+    let hidden: Value = serde_json::from_str(&hidden).map_err(|_| "malformedContent")?;
+    let channel = hidden.get("channel").and_then(Value::as_str).ok_or("malformedContent")?;
+    let original = match hidden.get("original") {
+        None => m.original.clone(),
+        Some(_) if m.original.is_some() => return Err("malformedMessage"),
+        Some(o) => Some(o.as_str().filter(|s| !s.is_empty()).ok_or("malformedContent")?.to_string()),
+    };
+    Ok((channel.to_string(), original))
+}
+
+/// 平文コンテンツ JSON がこのバイト数を超えると isLarge とみなす
+pub const LARGE_THRESHOLD_BYTES: usize = 64 * 1024;
+
+/// コンテンツ JSON が isLarge として扱うべき大きさか (LARGE_THRESHOLD_BYTES を使用)
+pub fn should_be_large(content_json: &str) -> bool {
+    should_be_large_with_threshold(content_json, LARGE_THRESHOLD_BYTES)
+}
+
+/// 閾値を指定して isLarge を判定
+///
+/// 文字数ではなく UTF-8 のバイト数で比較する (マルチバイト文字は 1 文字で複数バイト)
+pub fn should_be_large_with_threshold(content_json: &str, threshold: usize) -> bool {
+    content_json.len() > threshold
+}
+
+/// テキストメッセージ送信の一括ヘルパー
+///
+/// create_text_content → メタデータ (現在時刻・isLarge) の組み立て → encrypt_message を行い、
+/// encrypt_message と同じ {message, sign} を返す。細かく制御したい場合は個別の関数を使う。
+/// large_threshold が None の場合は LARGE_THRESHOLD_BYTES で isLarge を判定する
+pub fn send_text_message(
+    text: &str,
+    room_key_json: &str,
+    identity_priv_json: &str,
+    identity_pub_json: &str,
+    roomid: &str,
+    channel: &str,
+    large_threshold: Option<usize>,
+) -> Option<String> {
+    let content = create_text_content(text, None, None, None, None)?;
+    let threshold = large_threshold.unwrap_or(LARGE_THRESHOLD_BYTES);
+    let metadata = json!({
+        "channel": channel,
+        "timestamp": Utc::now().timestamp_millis() as u64,
+        "isLarge": should_be_large_with_threshold(&content, threshold),
+    });
+    encrypt_message(&content, &metadata.to_string(), room_key_json, identity_priv_json, identity_pub_json, roomid, None)
+}
+
+/// メッセージ JSON を Message に解析し、validate_message で検証する
+pub fn parse_message(message_str: &str) -> Result<Message, TakosError> {
+    let v: Value = serde_json::from_str(message_str)
+        .map_err(|e| TakosError::MalformedJson(e.to_string()))?;
+    if !validate_message(&v) {
+        return Err(TakosError::InvalidSchema("message"));
+    }
+    serde_json::from_value(v).map_err(|e| TakosError::MalformedJson(e.to_string()))
+}
+
+/// メッセージの検証と復号
+///
+/// expected_session_uuid を指定した場合、署名した IdentityKey の sessionUuid が
+/// それと一致しなければ拒否する (IdentityKey はセッションに紐づくため)。
+/// server_timestamp は呼び出し側が信頼する時刻で、サーバー刻印 (stamp_message) を
+/// サーバー鍵で検証してから使う場合は decrypt_stamped_message を使う
 pub fn decrypt_message(
     message_str: &str,
     sign_str: &str,
@@ -36,35 +197,191 @@ pub fn decrypt_message(
     room_key_json: &str,
     identity_pub_json: &str,
     roomid: &str,
+    expected_session_uuid: Option<&str>,
 ) -> Option<String> {
-    if !is_valid_identity_key_public(identity_pub_json) { return None; }
-    if !verify_identity_key(identity_pub_json, sign_str, message_str) { return None; }
-    let v: Value = serde_json::from_str(message_str).ok()?;
-    let encrypted = v.get("encrypted")?.as_bool()?;
-    let timestamp = v.get("timestamp")?.as_u64()?;
-    let channel = v.get("channel")?.as_str()?.to_string();
-    let is_large = v.get("isLarge")?.as_bool()?;
-    let original = v.get("original").and_then(|v| v.as_str()).map(String::from);
-    let rid = v.get("roomid")?.as_str()?;
-    if rid != roomid || (timestamp as i64 - server_timestamp as i64).abs() as u64 > 60000 { return None; }
-    if !encrypted {
-        let val_json = v.get("value")?.clone();
-        let res = json!({
-            "encrypted": false,
-            "value": val_json,
-            "channel": channel,
-            "original": original,
-            "timestamp": timestamp,
-            "isLarge": is_large,
-            "roomid": roomid
-        });
-        return serde_json::to_string(&res).ok();
+    decrypt_message_with_reason(message_str, sign_str, server_timestamp, room_key_json, identity_pub_json, roomid, expected_session_uuid)
+        .inspect_err(|&reason| {
+            if reason == "invalidSignature" {
+                log_warn!("decrypt_message rejected: message signature verification failed");
+            } else {
+                log_debug!("decrypt_message rejected: {reason}");
+            }
+        })
+        .ok()
+}
+
+/// decrypt_message に ReplayGuard を組み合わせたもの
+///
+/// 検証・復号にすべて成功した後で署名を記録し、既に見た署名またはガードが上限に達していれば None を返す
+#[allow(clippy::too_many_arguments)]
+pub fn decrypt_message_guarded(
+    message_str: &str,
+    sign_str: &str,
+    server_timestamp: u64,
+    room_key_json: &str,
+    identity_pub_json: &str,
+    roomid: &str,
+    expected_session_uuid: Option<&str>,
+    guard: &mut ReplayGuard,
+) -> Option<String> {
+    let res = decrypt_message(message_str, sign_str, server_timestamp, room_key_json, identity_pub_json, roomid, expected_session_uuid)?;
+    guard.check_and_record(sign_str, server_timestamp).then_some(res)
+}
+
+/// メッセージ timestamp とサーバー時刻の許容差 (ミリ秒)
+pub const MESSAGE_TIMESTAMP_SKEW_MS: u64 = 60_000;
+
+/// ReplayGuard が保持する署名数の既定の上限
+pub const DEFAULT_REPLAY_GUARD_CAPACITY: usize = 100_000;
+
+/// ReplayGuard::check の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayCheck {
+    /// 未知の署名で、記録した
+    Accepted,
+    /// 期限内に同じ署名を既に受理している
+    Replayed,
+    /// 期限内の記録が上限に達しているため受理できない (記録はしていない)
+    Saturated,
+    /// 署名を Base64 としてデコードできない
+    Malformed,
+}
+
+/// 署名済みメッセージの再送 (リプレイ) を検出する
+///
+/// decrypt_message の timestamp 検査だけでは、許容範囲内に同じメッセージを再送されると受理してしまう。
+/// デコードした署名バイト列の SHA-256 を記録しておき、2 度目を拒否する
+/// (Base64 の表記を変えただけの再送も同じ署名として扱うため、文字列ではなくバイト列で識別する)。
+/// 許容範囲の端で受理したメッセージは受理時刻から最大で許容差の 2 倍まで再送されうるため、
+/// 記録はその間だけ保持する。期限内の記録が capacity に達した場合は新しい署名を拒否する
+/// (古い記録を捨てると、その署名の再送が受理されてしまうため)
+pub struct ReplayGuard {
+    window_ms: u64,
+    capacity: usize,
+    seen: HashSet<[u8; 32]>,
+    order: VecDeque<(u64, [u8; 32])>,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::new()
     }
-    if !is_valid_room_key(room_key_json) { return None; }
-    let enc_val = v.get("value")?.as_str()?;
-    if !is_valid_encrypted_data_room_key(enc_val) { return None; }
-    let decrypted_str = decrypt_data_room_key(room_key_json, enc_val)?;
-    let val_json: Value = serde_json::from_str(&decrypted_str).ok()?;
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::with_window(MESSAGE_TIMESTAMP_SKEW_MS, DEFAULT_REPLAY_GUARD_CAPACITY)
+    }
+
+    pub fn with_window(window_ms: u64, capacity: usize) -> Self {
+        Self { window_ms, capacity, seen: HashSet::new(), order: VecDeque::new() }
+    }
+
+    /// 未知の署名なら記録して true、既に記録済み・上限に達している・デコードできなければ false
+    ///
+    /// sign は Sign JSON (signature フィールドで識別) または生の署名文字列。
+    /// timestamp は検証時のサーバー時刻 (ミリ秒)
+    pub fn check_and_record(&mut self, sign: &str, timestamp: u64) -> bool {
+        self.check(sign, timestamp) == ReplayCheck::Accepted
+    }
+
+    /// check_and_record と同じだが、拒否の理由 (再送 / 上限 / デコード不可) を区別して返す
+    pub fn check(&mut self, sign: &str, timestamp: u64) -> ReplayCheck {
+        self.evict(timestamp);
+        let signature = serde_json::from_str::<crate::r#type::Sign>(sign)
+            .map(|s| s.signature)
+            .unwrap_or_else(|_| sign.to_string());
+        let Some(signature_bytes) = decode_base64(&signature) else { return ReplayCheck::Malformed };
+        let id: [u8; 32] = Sha256::digest(&signature_bytes).into();
+        if self.seen.contains(&id) { return ReplayCheck::Replayed; }
+        if self.order.len() >= self.capacity {
+            log_warn!("replay guard saturated ({} entries within the window)", self.capacity);
+            return ReplayCheck::Saturated;
+        }
+        self.seen.insert(id);
+        self.order.push_back((timestamp, id));
+        ReplayCheck::Accepted
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    fn evict(&mut self, now: u64) {
+        let horizon = now.saturating_sub(self.window_ms.saturating_mul(2));
+        while let Some(&(recorded, id)) = self.order.front() {
+            if recorded >= horizon { break; }
+            self.order.pop_front();
+            self.seen.remove(&id);
+        }
+    }
+}
+
+/// メッセージ署名の検証
+///
+/// 署名は正規形に対して行われるため、途中で再整形されたメッセージも正規形に戻してから検証する。
+/// 正規形で署名していなかった以前のメッセージのために、受け取った文字列そのものでも検証する。
+/// 候補ごとの失敗はログに出さず、失敗の記録は呼び出し側 (decrypt_message など) で一度だけ行う
+fn verify_message_signature(identity_pub_json: &str, sign_str: &str, message_str: &str) -> bool {
+    // 正規形が受信文字列と同じなら同じ検証を二度行わない
+    match canonicalize_json(message_str) {
+        Some(c) if c != message_str => {
+            verify_identity_key_quiet(identity_pub_json, sign_str, &c)
+                || verify_identity_key_quiet(identity_pub_json, sign_str, message_str)
+        }
+        _ => verify_identity_key_quiet(identity_pub_json, sign_str, message_str),
+    }
+}
+
+/// decrypt_message の本体。失敗時はその理由を返す
+///
+/// 理由は "invalidIdentityKey" / "sessionMismatch" / "invalidSignature" / "malformedMessage" /
+/// "roomidMismatch" / "timestampOutOfRange" / "invalidRoomKey" / "invalidEncryptedData" /
+/// "decryptionFailed" / "malformedContent"。
+/// 署名の検証は RoomKey と暗号文に触れる前に必ず行う (後述の不変条件)
+pub fn decrypt_message_with_reason(
+    message_str: &str,
+    sign_str: &str,
+    server_timestamp: u64,
+    room_key_json: &str,
+    identity_pub_json: &str,
+    roomid: &str,
+    expected_session_uuid: Option<&str>,
+) -> Result<String, &'static str> {
+    if !is_valid_identity_key_public(identity_pub_json) { return Err("invalidIdentityKey"); }
+    if let Some(expected) = expected_session_uuid {
+        let ik: IdentityKey = serde_json::from_str(identity_pub_json).map_err(|_| "invalidIdentityKey")?;
+        if ik.session_uuid != expected { return Err("sessionMismatch"); }
+    }
+    // 不変条件: 署名検証 → 解析 → 復号 の順を崩さない。
+    // 署名の無い・不正な入力で RoomKey の検証や AES-GCM 復号を行うと、
+    // 復号失敗の違いが攻撃者への手がかりになる (verify-then-decrypt)
+    if !verify_message_signature(identity_pub_json, sign_str, message_str) { return Err("invalidSignature"); }
+    let msg = parse_message(message_str).map_err(|_| "malformedMessage")?;
+    let (timestamp, rid) = match &msg {
+        Message::NotEncrypted(m) => (m.timestamp, m.roomid.as_str()),
+        Message::Encrypted(m) => (m.timestamp, m.roomid.as_str()),
+    };
+    if rid != roomid { return Err("roomidMismatch"); }
+    if timestamp.abs_diff(server_timestamp) > MESSAGE_TIMESTAMP_SKEW_MS { return Err("timestampOutOfRange"); }
+    let m = match msg {
+        Message::NotEncrypted(m) => return serde_json::to_string(&m).map_err(|_| "malformedMessage"),
+        Message::Encrypted(m) => m,
+    };
+    if !is_valid_room_key(room_key_json) { return Err("invalidRoomKey"); }
+    if !is_valid_encrypted_data_room_key(&m.value) { return Err("invalidEncryptedData"); }
+    let (channel, original) = open_private_metadata(&m, room_key_json)?;
+    // AAD を使う前に暗号化されたメッセージは AAD なしで復号する
+    // (AAD 付きの暗号文は AAD なしでは復号できないため、移し替えの防御は弱まらない)
+    let aad = message_aad(&channel, &m.roomid, m.timestamp);
+    let decrypted_str = decrypt_data_room_key_aad(room_key_json, &m.value, &aad)
+        .or_else(|| decrypt_data_room_key(room_key_json, &m.value))
+        .ok_or("decryptionFailed")?;
+    let val_json: Value = serde_json::from_str(&decrypted_str).map_err(|_| "malformedContent")?;
     // Wrap decrypted content into NotEncryptMessageValue struct
     let content_type = if val_json.get("text").is_some() {
         "text"
@@ -82,17 +399,292 @@ pub fn decrypt_message(
         "value": value_obj,
         "channel": channel,
         "original": original,
-        "timestamp": timestamp,
-        "isLarge": is_large,
-        "roomid": roomid
+        "timestamp": m.timestamp,
+        "isLarge": m.is_large,
+        "roomid": m.roomid
+    });
+    serde_json::to_string(&res).map_err(|_| "malformedMessage")
+}
+
+/// サーバーのタイムスタンプ刻印に署名する対象
+fn server_stamp_payload(message_hash: &str, server_timestamp: u64) -> String {
+    json!({"messageHash": message_hash, "serverTimestamp": server_timestamp}).to_string()
+}
+
+/// 復号せずにメッセージの署名と構造だけを検証する
+///
+/// RoomKey を持たないモデレーターが、通報されたメッセージが主張する IdentityKey によって
+/// 送られたものかを確認するためのもの。IdentityKey による署名、メッセージの形式、
+/// roomid が空でないことのみを確認し、本文は復号しない (受信時刻との比較も行わない)
+pub fn verify_message_only(message_str: &str, sign_str: &str, identity_pub_json: &str) -> bool {
+    if !is_valid_identity_key_public(identity_pub_json) { return false; }
+    if !verify_message_signature(identity_pub_json, sign_str, message_str) {
+        log_warn!("verify_message_only: message signature verification failed");
+        return false;
+    }
+    match parse_message(message_str) {
+        Ok(Message::Encrypted(m)) => !m.roomid.is_empty(),
+        Ok(Message::NotEncrypted(m)) => !m.roomid.is_empty(),
+        Err(_) => false,
+    }
+}
+
+/// サーバーが受信時刻を刻印する
+///
+/// (messageHash, serverTimestamp) にサーバー鍵で署名し、
+/// { messageHash, serverTimestamp, sign } を返す
+pub fn stamp_message(
+    message_str: &str,
+    server_key_priv_json: &str,
+    server_key_pub_json: &str,
+    server_timestamp: u64,
+) -> Option<String> {
+    if !is_valid_server_key_private(server_key_priv_json) { return None; }
+    let message_hash = key_hash(message_str);
+    let payload = server_stamp_payload(&message_hash, server_timestamp);
+    let sign = sign_data_server_key_with_public(server_key_priv_json, server_key_pub_json, &payload)?;
+    let res = json!({
+        "messageHash": message_hash,
+        "serverTimestamp": server_timestamp,
+        "sign": sign,
+    });
+    serde_json::to_string(&res).ok()
+}
+
+/// サーバー刻印を検証し、刻印されたタイムスタンプを返す
+pub fn verify_server_stamp(
+    message_str: &str,
+    stamp_json: &str,
+    server_key_pub_json: &str,
+) -> Option<u64> {
+    let stamp: Value = serde_json::from_str(stamp_json).ok()?;
+    let message_hash = stamp.get("messageHash")?.as_str()?;
+    let server_timestamp = stamp.get("serverTimestamp")?.as_u64()?;
+    let sign = stamp.get("sign")?.as_str()?;
+    if message_hash != key_hash(message_str) { return None; }
+    let payload = server_stamp_payload(message_hash, server_timestamp);
+    verify_data_server_key(server_key_pub_json, sign, &payload).then_some(server_timestamp)
+}
+
+/// サーバー刻印を検証し、刻印時刻が expected_timestamp と一致するかを返す
+///
+/// (messageHash, serverTimestamp) の署名をサーバー公開鍵で検証したうえで時刻を比較する。
+/// クライアントがサーバーの示す時刻を信頼してよいかを 1 回の呼び出しで判定するためのもの
+pub fn verify_server_stamp_matches(
+    message_str: &str,
+    stamp_json: &str,
+    server_key_pub_json: &str,
+    expected_timestamp: u64,
+) -> bool {
+    verify_server_stamp(message_str, stamp_json, server_key_pub_json) == Some(expected_timestamp)
+}
+
+/// サーバー刻印付きメッセージの復号
+///
+/// 送信者の時計ではなく、サーバー鍵で検証した刻印時刻を server_timestamp として用いる
+pub fn decrypt_stamped_message(
+    message_str: &str,
+    sign_str: &str,
+    stamp_json: &str,
+    server_key_pub_json: &str,
+    room_key_json: &str,
+    identity_pub_json: &str,
+    roomid: &str,
+) -> Option<String> {
+    let server_timestamp = verify_server_stamp(message_str, stamp_json, server_key_pub_json)?;
+    decrypt_message(message_str, sign_str, server_timestamp, room_key_json, identity_pub_json, roomid, None)
+}
+
+/// 同期時のメッセージ一括復号
+///
+/// envelopes は encrypt_message の出力 ({"message", "sign"}) の配列。
+/// room_keys_json / identity_keys_json は各鍵 JSON 文字列の配列で、
+/// RoomKey は EncryptedData の keyHash で、IdentityKey は署名検証に成功したもので選択する。
+/// 一部が壊れていても処理を続け、{ decrypted: [{index, value}], failed: [{index, reason}] } を返す
+pub fn decrypt_batch(
+    envelopes: &[&str],
+    room_keys_json: &str,
+    identity_keys_json: &str,
+    roomid: &str,
+    server_timestamp: u64,
+) -> String {
+    let room_keys: Vec<String> = serde_json::from_str(room_keys_json).unwrap_or_default();
+    let identity_keys: Vec<String> = serde_json::from_str(identity_keys_json).unwrap_or_default();
+    let mut decrypted = Vec::new();
+    let mut failed = Vec::new();
+    for (index, envelope) in envelopes.iter().enumerate() {
+        match decrypt_envelope(envelope, &room_keys, &identity_keys, roomid, server_timestamp) {
+            Ok(value) => decrypted.push(json!({"index": index, "value": value})),
+            Err(reason) => failed.push(json!({"index": index, "reason": reason})),
+        }
+    }
+    json!({"decrypted": decrypted, "failed": failed}).to_string()
+}
+
+fn decrypt_envelope(
+    envelope: &str,
+    room_keys: &[String],
+    identity_keys: &[String],
+    roomid: &str,
+    server_timestamp: u64,
+) -> Result<String, &'static str> {
+    let env: Value = serde_json::from_str(envelope).map_err(|_| "malformedEnvelope")?;
+    let message_str = env.get("message").and_then(Value::as_str).ok_or("malformedEnvelope")?;
+    let sign_str = env.get("sign").and_then(Value::as_str).ok_or("malformedEnvelope")?;
+    let identity_pub = identity_keys
+        .iter()
+        .find(|ik| verify_message_signature(ik, sign_str, message_str))
+        .ok_or("identityKeyNotFound")?;
+    // 暗号化されたメッセージなら keyHash から RoomKey を選ぶ
+    let room_key = match parse_message(message_str).map_err(|_| "malformedMessage")? {
+        Message::Encrypted(m) => {
+            let ed: EncryptedData = serde_json::from_str(&m.value).map_err(|_| "invalidEncryptedData")?;
+            let stored = ed.key_hash_bytes().ok_or("invalidEncryptedData")?;
+            room_keys
+                .iter()
+                .find(|rk| key_hash_matches(rk, &stored))
+                .map(String::as_str)
+                .ok_or("roomKeyNotFound")?
+        }
+        Message::NotEncrypted(_) => "",
+    };
+    decrypt_message_with_reason(message_str, sign_str, server_timestamp, room_key, identity_pub, roomid, None)
+}
+
+/// サーバーが保存すべき正規形 (署名対象と同じバイト列) を返す
+///
+/// encrypt_message は EncryptedMessage を canonicalize_json で正規化した文字列に署名するため、
+/// サーバーはこの正規形のバイト列を保存・配信すること
+pub fn normalize_stored_message(message_str: &str) -> Option<String> {
+    let msg: EncryptedMessage = serde_json::from_str(message_str).ok()?;
+    canonicalize_json(&serde_json::to_string(&msg).ok()?)
+}
+
+/// バイナリ封筒形式のバージョン (先頭 1 バイト)
+pub const MESSAGE_WIRE_VERSION: u8 = 1;
+
+/// {message, sign} をコンパクトなバイナリ形式にする (JSON 封筒の代わりに使えるオプション)
+///
+/// JSON 封筒ではメッセージ JSON を文字列として埋め込むため、引用符などがエスケープされて膨らみ、
+/// 受信側も二重に解析する必要がある。こちらは
+/// [バージョン 1 バイト][message 長 u32 BE][message][sign 長 u32 BE][sign] の長さ接頭辞形式で、
+/// 署名対象の message はバイト列としてそのまま運ぶ (署名検証に影響しない)。
+/// どちらかが u32 で表せない長さ (4GiB 以上) なら None
+pub fn encode_message_wire(message_str: &str, sign_str: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(1 + 4 + message_str.len() + 4 + sign_str.len());
+    out.push(MESSAGE_WIRE_VERSION);
+    for part in [message_str, sign_str] {
+        out.extend_from_slice(&u32::try_from(part.len()).ok()?.to_be_bytes());
+        out.extend_from_slice(part.as_bytes());
+    }
+    Some(out)
+}
+
+/// encode_message_wire の逆。バージョン・長さ・UTF-8 のいずれかが不正なら None
+pub fn decode_message_wire(bytes: &[u8]) -> Option<(String, String)> {
+    let (&version, mut rest) = bytes.split_first()?;
+    if version != MESSAGE_WIRE_VERSION { return None; }
+    let mut parts = Vec::with_capacity(2);
+    for _ in 0..2 {
+        let (len, tail) = rest.split_first_chunk::<4>()?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if tail.len() < len { return None; }
+        let (part, tail) = tail.split_at(len);
+        parts.push(String::from_utf8(part.to_vec()).ok()?);
+        rest = tail;
+    }
+    // 後ろに余分なバイトがあるものは受け付けない
+    if !rest.is_empty() { return None; }
+    let sign = parts.pop()?;
+    let message = parts.pop()?;
+    Some((message, sign))
+}
+
+/// 保存形式に対する厳密な署名検証
+///
+/// stored_bytes が正規形であり、かつそのバイト列に対する署名が有効な場合のみ true
+pub fn verify_against_stored(
+    identity_pub_json: &str,
+    stored_bytes: &[u8],
+    sign_str: &str,
+) -> bool {
+    let stored = match std::str::from_utf8(stored_bytes) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    if normalize_stored_message(stored).as_deref() != Some(stored) { return false; }
+    is_valid_identity_key_public(identity_pub_json)
+        && verify_identity_key(identity_pub_json, sign_str, stored)
+}
+
+/// サーバーのインデックス用メタデータを封筒 ({message, sign}) から抽出
+///
+/// 復号は行わず、{ messageId, roomid, channel, timestamp, senderKeyHash } のみを返す。
+/// messageId は署名対象のメッセージ文字列の key_hash。
+/// encryptedMetadata 付きのメッセージでは channel は空文字列になる
+pub fn extract_index_metadata(envelope_json: &str) -> Option<String> {
+    let env: Value = serde_json::from_str(envelope_json).ok()?;
+    let message_str = env.get("message")?.as_str()?;
+    let sign: crate::r#type::Sign = serde_json::from_str(env.get("sign")?.as_str()?).ok()?;
+    let msg: EncryptedMessage = serde_json::from_str(message_str).ok()?;
+    let res = json!({
+        "messageId": key_hash(message_str),
+        "roomid": msg.roomid,
+        "channel": msg.channel,
+        "timestamp": msg.timestamp,
+        "senderKeyHash": sign.key_hash,
     });
     serde_json::to_string(&res).ok()
 }
 
+/// original (置き換え元メッセージの ID) の妥当性を検証
+///
+/// message_id はこのメッセージ自身の ID (extract_index_metadata の messageId)。
+/// original が無ければ true、あれば空でなく自分自身を指していない場合のみ true
+pub fn validate_message_original(message_str: &str, message_id: &str) -> bool {
+    let original = match parse_message(message_str) {
+        Ok(Message::Encrypted(m)) => m.original,
+        Ok(Message::NotEncrypted(m)) => m.original,
+        Err(_) => return false,
+    };
+    original.is_none_or(|o| !o.is_empty() && o != message_id)
+}
+
 pub fn is_valid_message(message_str: &str) -> bool {
     serde_json::from_str::<Value>(message_str)
         .ok()
-        .map_or(false, |v| validate_message(&v))
+        .is_some_and(|v| validate_message(&v))
+}
+
+/// コンテンツ各フィールドの既定の最大長 (文字数)
+pub const DEFAULT_MAX_TEXT_LENGTH: usize = 100_000;
+pub const DEFAULT_MAX_FILENAME_LENGTH: usize = 255;
+pub const DEFAULT_MAX_URI_LENGTH: usize = 2048;
+pub const DEFAULT_MAX_MIME_TYPE_LENGTH: usize = 255;
+
+/// コンテンツのフィールド長制限を検証
+///
+/// limits_json は {text, filename, uri, mimeType} の最大長。省略したものは既定値を使う
+pub fn validate_content_limits(content_json: &str, limits_json: &str) -> bool {
+    let content: Value = match serde_json::from_str(content_json) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let limits: Value = match serde_json::from_str(limits_json) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let limit = |name: &str, default: usize| {
+        limits.get(name).and_then(Value::as_u64).map_or(default, |n| n as usize)
+    };
+    let within = |v: Option<&Value>, max: usize| {
+        v.and_then(Value::as_str).is_none_or(|s| s.chars().count() <= max)
+    };
+    let metadata = content.get("metadata");
+    within(content.get("text"), limit("text", DEFAULT_MAX_TEXT_LENGTH))
+        && within(content.get("uri"), limit("uri", DEFAULT_MAX_URI_LENGTH))
+        && within(metadata.and_then(|m| m.get("filename")), limit("filename", DEFAULT_MAX_FILENAME_LENGTH))
+        && within(metadata.and_then(|m| m.get("mimeType")), limit("mimeType", DEFAULT_MAX_MIME_TYPE_LENGTH))
 }
 
 pub fn create_text_content(
@@ -103,7 +695,8 @@ pub fn create_text_content(
     original_size: Option<u64>,
 ) -> Option<String> {
     let content = TextContent { text: text.to_string(), format: format.map(String::from), is_thumbnail, thumbnail_of: thumbnail_of.map(String::from), original_size };
-    serde_json::to_string(&content).ok()
+    let json = serde_json::to_string(&content).ok()?;
+    validate_content_limits(&json, "{}").then_some(json)
 }
 
 pub fn create_image_content(
@@ -116,7 +709,8 @@ pub fn create_image_content(
 ) -> Option<String> {
     let metadata = crate::r#type::MediaMetadata { filename: filename.to_string(), mime_type: mime_type.to_string() };
     let content = ImageContent { uri: uri.to_string(), metadata, is_thumbnail, thumbnail_of: thumbnail_of.map(String::from), original_size };
-    serde_json::to_string(&content).ok()
+    let json = serde_json::to_string(&content).ok()?;
+    validate_content_limits(&json, "{}").then_some(json)
 }
 
 pub fn create_video_content(
@@ -152,18 +746,316 @@ pub fn create_file_content(
     create_image_content(uri, filename, mime_type, is_thumbnail, thumbnail_of, original_size)
 }
 
+/// ThumbnailContent を生成
+///
+/// original_type が image / video の場合 thumbnail はサムネイルの URI で thumbnail_mime_type が必須、
+/// text / file / audio の場合 thumbnail はサムネイルのテキストで thumbnail_mime_type は指定しない
+pub fn create_thumbnail_content(
+    original_type: &str,
+    thumbnail: &str,
+    thumbnail_mime_type: Option<&str>,
+    size: Option<u64>,
+) -> Option<String> {
+    let thumbnail_text = thumbnail.to_string();
+    let content = match (original_type, thumbnail_mime_type) {
+        ("text", None) => ThumbnailContent::Text { thumbnail_text, size },
+        ("file", None) => ThumbnailContent::File { thumbnail_text, size },
+        ("audio", None) => ThumbnailContent::Audio { thumbnail_text, size },
+        ("image", Some(mime)) => ThumbnailContent::Image { thumbnail_uri: thumbnail.to_string(), thumbnail_mime_type: mime.to_string(), size },
+        ("video", Some(mime)) => ThumbnailContent::Video { thumbnail_uri: thumbnail.to_string(), thumbnail_mime_type: mime.to_string(), size },
+        _ => return None,
+    };
+    serde_json::to_string(&content).ok()
+}
+
+/// サムネイルの thumbnailOf に入れる元コンテンツの識別子 (コンテンツ JSON の key_hash)
+pub fn content_id(content_json: &str) -> String {
+    key_hash(content_json)
+}
+
+/// サムネイルが元コンテンツを正しく参照しているか検証
+///
+/// isThumbnail が true で、thumbnailOf が content_id(original_json) と一致し、
+/// originalSize が指定されていれば original_json のバイト長と一致する必要がある
+pub fn validate_thumbnail_link(thumbnail_json: &str, original_json: &str) -> bool {
+    let thumbnail: Value = match serde_json::from_str(thumbnail_json) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let original: Value = match serde_json::from_str(original_json) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    if !original.is_object() || original.get("isThumbnail").and_then(Value::as_bool) == Some(true) {
+        return false;
+    }
+    thumbnail.get("isThumbnail").and_then(Value::as_bool) == Some(true)
+        && thumbnail.get("thumbnailOf").and_then(Value::as_str) == Some(content_id(original_json).as_str())
+        && thumbnail.get("originalSize").is_none_or(|s| s.as_u64() == Some(original_json.len() as u64))
+}
+
+/// 各ユーザーの AccountKey 宛てに RoomKey を暗号化する
+///
+/// roomKeyHash は RoomKey の key_material_hash で、merge_distributions が衝突の検出と解決に使う
 pub fn encrypt_room_key_with_account_keys(
     users_json: &str,
     room_key_json: &str,
 ) -> Option<String> {
     let users: Vec<Value> = serde_json::from_str(users_json).ok()?;
+    let room_key_hash = key_material_hash(room_key_json)?;
     let mut res = Vec::new();
     for u in users {
         let account_key = u.get("accountKey")?.as_str()?;
         let user_id = u.get("userId")?.as_str()?;
         if let Some(enc) = encrypt_data_account_key(account_key, room_key_json) {
-            res.push(json!({"userId": user_id, "encryptedData": enc}));
+            res.push(json!({"userId": user_id, "encryptedData": enc, "roomKeyHash": room_key_hash}));
         }
     }
     serde_json::to_string(&res).ok()
-}
\ No newline at end of file
+}
+
+/// 一部の受信者が不正でも中断しない encrypt_room_key_with_account_keys
+///
+/// 有効な受信者すべてに RoomKey を包み、失敗したものは個別に報告する。
+/// { wrapped: [{userId, encryptedData, roomKeyHash}], failed: [{index, userId, reason}] } を返し、
+/// reason は "missingUserId" / "missingAccountKey" / "invalidAccountKey" / "encryptionFailed"。
+/// userId が取り出せない要素は userId を null とする。users_json が配列でない場合のみ None
+pub fn encrypt_room_key_with_account_keys_partial(
+    users_json: &str,
+    room_key_json: &str,
+) -> Option<String> {
+    let users: Vec<Value> = serde_json::from_str(users_json).ok()?;
+    let room_key_hash = key_material_hash(room_key_json)?;
+    let mut wrapped = Vec::new();
+    let mut failed = Vec::new();
+    for (index, u) in users.iter().enumerate() {
+        let user_id = u.get("userId").and_then(Value::as_str);
+        let res = user_id.ok_or("missingUserId").and_then(|user_id| {
+            let account_key = u.get("accountKey").and_then(Value::as_str).ok_or("missingAccountKey")?;
+            if !is_valid_account_key_public(account_key) { return Err("invalidAccountKey"); }
+            let enc = encrypt_data_account_key(account_key, room_key_json).ok_or("encryptionFailed")?;
+            Ok(json!({"userId": user_id, "encryptedData": enc, "roomKeyHash": room_key_hash}))
+        });
+        match res {
+            Ok(entry) => wrapped.push(entry),
+            Err(reason) => failed.push(json!({"index": index, "userId": user_id, "reason": reason})),
+        }
+    }
+    serde_json::to_string(&json!({"wrapped": wrapped, "failed": failed})).ok()
+}
+
+/// encrypt_room_key_with_account_keys の出力から自分宛てのエントリを選んで RoomKey を復号
+///
+/// 秘密鍵から公開鍵 JSON を導出して keyHash を計算し、encryptedData の keyHash が一致するものを使う。
+/// 一致するエントリが無い、または復号結果が RoomKey として不正な場合は None
+pub fn select_and_decrypt_room_key(
+    wrapped_list_json: &str,
+    account_priv_json: &str,
+) -> Option<String> {
+    let my_pub = AccountKey::public_json_from_private(account_priv_json)?;
+    let list: Vec<Value> = serde_json::from_str(wrapped_list_json).ok()?;
+    let enc = list.iter().find_map(|entry| {
+        let enc = entry.get("encryptedData")?.as_str()?;
+        let ed: EncryptedData = serde_json::from_str(enc).ok()?;
+        key_hash_matches(&my_pub, &ed.key_hash_bytes()?).then_some(enc)
+    })?;
+    let room_key = decrypt_data_account_key(account_priv_json, enc)?;
+    is_valid_room_key(&room_key).then_some(room_key)
+}
+
+/// 自分の各端末の ShareKey 宛てに RoomKey を配布
+///
+/// encrypt_room_key_with_account_keys の自端末版。不正な ShareKey は飛ばし、そのインデックスを報告する。
+/// 戻り値は { distribution: [{keyHash, encryptedData}], skipped: [index] } (keyHash は key_material_hash)
+pub fn distribute_room_key_to_share_keys(
+    share_pubkeys_json: &[&str],
+    room_key_json: &str,
+) -> Option<String> {
+    if !is_valid_room_key(room_key_json) { return None; }
+    let mut distribution = Vec::new();
+    let mut skipped = Vec::new();
+    for (index, share_pub) in share_pubkeys_json.iter().enumerate() {
+        if !is_valid_share_key_public(share_pub) {
+            skipped.push(index);
+            continue;
+        }
+        match (encrypt_data_share_key(share_pub, room_key_json), key_material_hash(share_pub)) {
+            (Some(enc), Some(hash)) => distribution.push(json!({"keyHash": hash, "encryptedData": enc})),
+            _ => skipped.push(index),
+        }
+    }
+    serde_json::to_string(&json!({"distribution": distribution, "skipped": skipped})).ok()
+}
+
+/// AccountKey (他メンバー) と ShareKey (自端末) が混在する宛先に RoomKey をまとめて配布
+///
+/// recipients_json は [{kind: "account" | "share", key: 公開鍵 JSON, id}]。
+/// 不正なエントリは飛ばし、そのインデックスを報告する。
+/// 戻り値は { distribution: [{id, kind, encryptedData}], skipped: [index] }
+pub fn wrap_room_key_for_recipients(room_key_json: &str, recipients_json: &str) -> Option<String> {
+    if !is_valid_room_key(room_key_json) { return None; }
+    let recipients: Vec<Value> = serde_json::from_str(recipients_json).ok()?;
+    let (distribution, skipped) = wrap_room_key_for_parsed_recipients(room_key_json, &recipients);
+    serde_json::to_string(&json!({"distribution": distribution, "skipped": skipped})).ok()
+}
+
+fn wrap_room_key_for_parsed_recipients(room_key_json: &str, recipients: &[Value]) -> (Vec<Value>, Vec<usize>) {
+    let mut distribution = Vec::new();
+    let mut skipped = Vec::new();
+    for (index, r) in recipients.iter().enumerate() {
+        let fields = (|| {
+            Some((r.get("kind")?.as_str()?, r.get("key")?.as_str()?, r.get("id")?.as_str()?))
+        })();
+        let (kind, key, id) = match fields {
+            Some(f) => f,
+            None => {
+                skipped.push(index);
+                continue;
+            }
+        };
+        let enc = match kind {
+            "account" => encrypt_data_account_key(key, room_key_json),
+            "share" => encrypt_data_share_key(key, room_key_json),
+            _ => None,
+        };
+        match enc {
+            Some(enc) => distribution.push(json!({"id": id, "kind": kind, "encryptedData": enc})),
+            None => skipped.push(index),
+        }
+    }
+    (distribution, skipped)
+}
+
+/// 端末の侵害時に、その端末が持っていた全ルームの RoomKey をローテーションし残りの宛先へ配布し直す
+///
+/// rooms_json は [{roomKey, roomUuid}]、remaining_recipients_json は wrap_room_key_for_recipients と同じ形式。
+/// 戻り値は { rooms: [{roomUuid, roomKey, rotation, distribution, skipped}], failed: [index] }。
+/// roomKey は新しい RoomKey、rotation は rotate_room_key のローテーション記録。
+/// `parallel` feature を有効にするとルームごとの処理を rayon で並列に行う
+pub fn bulk_rotate_and_rewrap(rooms_json: &str, remaining_recipients_json: &str) -> Option<String> {
+    let rooms: Vec<Value> = serde_json::from_str(rooms_json).ok()?;
+    let recipients: Vec<Value> = serde_json::from_str(remaining_recipients_json).ok()?;
+    let rotate_one = |room: &Value| -> Option<Value> {
+        let old_key = room.get("roomKey")?.as_str()?;
+        let room_uuid = room.get("roomUuid")?.as_str()?;
+        let (new_key, rotation) = rotate_room_key(old_key, room_uuid)?;
+        let (distribution, skipped) = wrap_room_key_for_parsed_recipients(&new_key, &recipients);
+        Some(json!({
+            "roomUuid": room_uuid,
+            "roomKey": new_key,
+            "rotation": rotation,
+            "distribution": distribution,
+            "skipped": skipped,
+        }))
+    };
+    #[cfg(feature = "parallel")]
+    let results: Vec<Option<Value>> = {
+        use rayon::prelude::*;
+        rooms.par_iter().map(rotate_one).collect()
+    };
+    #[cfg(not(feature = "parallel"))]
+    let results: Vec<Option<Value>> = rooms.iter().map(rotate_one).collect();
+
+    let mut rotated = Vec::new();
+    let mut failed = Vec::new();
+    for (index, r) in results.into_iter().enumerate() {
+        match r {
+            Some(v) => rotated.push(v),
+            None => failed.push(index),
+        }
+    }
+    serde_json::to_string(&json!({"rooms": rotated, "failed": failed})).ok()
+}
+
+/// merge_distributions が保持する配布エントリ
+struct DistributionEntry {
+    entry: Value,
+    user_id: String,
+    /// 宛先 AccountKey のハッシュ (デコード済み)
+    recipient: Vec<u8>,
+    /// 配布している RoomKey のハッシュ (デコード済み、無ければ None)
+    room_key_hash: Option<Vec<u8>>,
+}
+
+/// 2 つの RoomKey 配布 ([{userId, encryptedData, roomKeyHash}]) を統合 (ルームのマージ／連合用)
+///
+/// 同じ宛先・同じ RoomKey のエントリは一つにまとめ、同一ユーザーが異なる RoomKey のエントリを持つ場合は
+/// 衝突として報告する。同じ RoomKey を別の宛先鍵 (別デバイス等) に配ったエントリは衝突ではないので両方残す。
+/// roomKeyHash が無いエントリは鍵を比べられないため衝突にしない。
+/// エントリの timestamp は署名されていないので、どちらが新しいかはここでは判断せず先に現れたエントリを残す。
+/// 解決するには merge_distributions_with_room_keys を使う。
+/// 戻り値は { distribution: [...], conflicts: [{userId, roomKeyHashes, resolved}], malformed: [{source, index}] }
+pub fn merge_distributions(a_json: &str, b_json: &str) -> Option<String> {
+    merge_distributions_with_room_keys(a_json, b_json, &[])
+}
+
+/// merge_distributions と同じだが、衝突を room_keys_json (統合する側が保持する RoomKey) で解決する
+///
+/// roomKeyHash が一致する鍵自身の timestamp で新しい方を採用する。
+/// どちらの鍵も手元に無ければ先に現れたエントリを残し resolved を false にする。
+/// userId・encryptedData が取り出せないエントリは中断せずに飛ばし malformed に報告する。
+pub fn merge_distributions_with_room_keys(a_json: &str, b_json: &str, room_keys_json: &[&str]) -> Option<String> {
+    let a: Vec<Value> = serde_json::from_str(a_json).ok()?;
+    let b: Vec<Value> = serde_json::from_str(b_json).ok()?;
+    // ハッシュはデコードしたバイト列で比較する (Base64 の表記揺れを同一視する)
+    let parse_entry = |e: Value| -> Option<DistributionEntry> {
+        let user_id = e.get("userId")?.as_str()?.to_string();
+        let ed: EncryptedData = serde_json::from_str(e.get("encryptedData")?.as_str()?).ok()?;
+        let recipient = ed.key_hash_bytes()?;
+        let room_key_hash = match e.get("roomKeyHash") {
+            None => None,
+            Some(v) => Some(decode_base64(v.as_str()?)?),
+        };
+        Some(DistributionEntry { entry: e, user_id, recipient, room_key_hash })
+    };
+    let room_key_timestamp = |hash: &Option<Vec<u8>>| -> Option<u64> {
+        let hash = hash.as_deref()?;
+        let rk = room_keys_json
+            .iter()
+            .find(|rk| is_valid_room_key(rk) && key_hash_matches(rk, hash))?;
+        Some(serde_json::from_str::<RoomKey>(rk).ok()?.timestamp)
+    };
+    let hash_str = |h: &Option<Vec<u8>>| h.as_ref().map(|h| BASE64.encode(h));
+    let mut merged: Vec<DistributionEntry> = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut malformed = Vec::new();
+    let entries = a.into_iter().enumerate().map(|(i, e)| ("a", i, e))
+        .chain(b.into_iter().enumerate().map(|(i, e)| ("b", i, e)));
+    for (source, index, entry) in entries {
+        let Some(new) = parse_entry(entry) else {
+            malformed.push(json!({"source": source, "index": index}));
+            continue;
+        };
+        let same_user = |m: &DistributionEntry| m.user_id == new.user_id;
+        if merged.iter().any(|m| same_user(m) && m.recipient == new.recipient && m.room_key_hash == new.room_key_hash) {
+            continue;
+        }
+        // 両方が roomKeyHash を持ち、それが異なるときだけ別の RoomKey と判断できる
+        let rival = new.room_key_hash.as_ref().and_then(|h| {
+            merged.iter().position(|m| same_user(m) && m.room_key_hash.as_ref().is_some_and(|o| o != h))
+        });
+        let Some(i) = rival else {
+            merged.push(new);
+            continue;
+        };
+        let rival_hash = merged[i].room_key_hash.clone();
+        let prefer_new = match (room_key_timestamp(&rival_hash), room_key_timestamp(&new.room_key_hash)) {
+            (Some(old), Some(newer)) => Some(newer > old),
+            (None, Some(_)) => Some(true),
+            (Some(_), None) => Some(false),
+            (None, None) => None,
+        };
+        conflicts.push(json!({
+            "userId": new.user_id,
+            "roomKeyHashes": [hash_str(&rival_hash), hash_str(&new.room_key_hash)],
+            "resolved": prefer_new.is_some(),
+        }));
+        if prefer_new == Some(true) {
+            // 古い RoomKey のエントリは宛先ごとすべて外し、最初の位置に新しいエントリを置く
+            merged.retain(|m| !(m.user_id == new.user_id && m.room_key_hash == rival_hash));
+            merged.insert(i, new);
+        }
+    }
+    let distribution: Vec<Value> = merged.into_iter().map(|m| m.entry).collect();
+    serde_json::to_string(&json!({"distribution": distribution, "conflicts": conflicts, "malformed": malformed})).ok()
+}
@@ -1,67 +1,95 @@
-use crate::r#type::{MigrateKey, MigrateSignKey, EncryptedData, Sign};
-use crate::keyutils::{generate_kem_key_pair, generate_dsa65_key_pair};
-use crate::crypto::{encrypt, decrypt};
-use crate::utils::key_hash;
-use crate::signature::{create_signature_object_mlds65, verify_with_mlds65};
-use base64::engine::general_purpose::STANDARD as BASE64;
-use base64::Engine as _;
+use crate::r#type::{MigrateKey, MigrateSignKey};
+use crate::keyutils::{generate_kem_key_pair, generate_dsa65_key_pair, is_valid_dsa65_key, key_json_has_required_fields};
+use crate::key_pair::AsymmetricKeyPair;
+use crate::signature::{create_signature_object_mlds65, verify_signature_object_with_algorithm};
+use crate::utils::signer_key_hash;
+use chrono::Utc;
 use serde_json;
 
-/// MigrateKey 生成
+/// 移行用鍵の既定の有効期間 (10 分)
+pub const DEFAULT_MIGRATE_KEY_TTL_MS: u64 = 10 * 60 * 1000;
+
+/// MigrateKey 生成 (有効期間は DEFAULT_MIGRATE_KEY_TTL_MS)
 pub fn generate_migrate_key() -> (String,String) {
+    generate_migrate_key_with_ttl(DEFAULT_MIGRATE_KEY_TTL_MS)
+}
+
+/// 有効期間を指定して MigrateKey 生成
+pub fn generate_migrate_key_with_ttl(ttl_ms: u64) -> (String,String) {
     let (pub_b64, priv_b64)=generate_kem_key_pair().unwrap();
-    let pk=MigrateKey{key_type:"migrateKeyPublic".into(),key:pub_b64, timestamp:None};
-    let sk=MigrateKey{key_type:"migrateKeyPrivate".into(),key:priv_b64,timestamp:None};
+    let ts = Utc::now().timestamp_millis() as u64;
+    let exp = Some(ts.saturating_add(ttl_ms));
+    let pk=MigrateKey{key_type:"migrateKeyPublic".into(),key:pub_b64, timestamp:Some(ts), expires_at:exp};
+    let sk=MigrateKey{key_type:"migrateKeyPrivate".into(),key:priv_b64,timestamp:Some(ts), expires_at:exp};
     (serde_json::to_string(&pk).unwrap(),serde_json::to_string(&sk).unwrap())
 }
+
+/// MigrateKey / MigrateSignKey の期限切れ判定
+///
+/// expiresAt を持たない旧形式の鍵は期限なしとして false。解析できない JSON は true
+pub fn is_migrate_key_expired(json:&str, now_ms:u64)->bool {
+    match serde_json::from_str::<MigrateKey>(json) {
+        Ok(k) => k.expires_at.is_some_and(|exp| now_ms >= exp),
+        Err(_) => true,
+    }
+}
 pub fn is_valid_migrate_key_public(json:&str)->bool {
-    serde_json::from_str::<MigrateKey>(json).map(|k|k.key_type=="migrateKeyPublic"&&BASE64.decode(&k.key).map_or(false,|b|b.len()==1184)).unwrap_or(false)
+    MigrateKey::is_valid_public(json)
 }
 pub fn is_valid_migrate_key_private(json:&str)->bool {
-    serde_json::from_str::<MigrateKey>(json).map(|k|k.key_type=="migrateKeyPrivate"&&BASE64.decode(&k.key).map_or(false,|b|b.len()==2400)).unwrap_or(false)
+    MigrateKey::is_valid_private(json)
 }
 pub fn encrypt_data_migrate_key(pub_json:&str, data:&str)->Option<String> {
-    let mk: MigrateKey = match serde_json::from_str(pub_json) { Ok(v) => v, Err(_) => return None };
-    if mk.key_type!="migrateKeyPublic" {return None}
-    let enc = encrypt(data, &mk.key);
-    let ed=EncryptedData{ key_type:"migrateKey".into(), key_hash:key_hash(pub_json), encrypted_data:enc.encrypted_data, iv:enc.iv, algorithm:Some(enc.algorithm), cipher_text:Some(enc.cipher_text) };
-    serde_json::to_string(&ed).ok()
+    MigrateKey::encrypt_data(pub_json, data)
 }
 pub fn decrypt_data_migrate_key(priv_json:&str, json:&str)->Option<String> {
-    let mk: MigrateKey=serde_json::from_str(priv_json).ok()?;
-    if mk.key_type!="migrateKeyPrivate"{return None}
-    let ed:EncryptedData=serde_json::from_str(json).ok()?;
-    let ciphertext = ed.cipher_text.as_ref()?;
-    Some(decrypt(&ed.encrypted_data, ciphertext, &ed.iv, &mk.key))
+    if is_migrate_key_expired(priv_json, Utc::now().timestamp_millis() as u64) { return None }
+    MigrateKey::decrypt_data(priv_json, json)
 }
 pub fn is_valid_encrypted_data_migrate_key(json:&str)->bool {
-    serde_json::from_str::<EncryptedData>(json).map(|ed|ed.key_type=="migrateKey").unwrap_or(false)
+    MigrateKey::is_valid_encrypted_data(json)
 }
 
-/// MigrateSignKey 生成／署名／検証
+/// MigrateSignKey 生成／署名／検証 (有効期間は DEFAULT_MIGRATE_KEY_TTL_MS)
 pub fn generate_migrate_sign_key()->(String,String) {
+    generate_migrate_sign_key_with_ttl(DEFAULT_MIGRATE_KEY_TTL_MS)
+}
+
+/// 有効期間を指定して MigrateSignKey 生成
+pub fn generate_migrate_sign_key_with_ttl(ttl_ms: u64)->(String,String) {
     let (pub_b64, priv_b64)=generate_dsa65_key_pair().unwrap();
-    let pk=MigrateSignKey{key_type:"migrateSignKeyPublic".into(),key:pub_b64,timestamp:None};
-    let sk=MigrateSignKey{key_type:"migrateSignKeyPrivate".into(),key:priv_b64,timestamp:None};
+    let ts = Utc::now().timestamp_millis() as u64;
+    let exp = Some(ts.saturating_add(ttl_ms));
+    let pk=MigrateSignKey{key_type:"migrateSignKeyPublic".into(),key:pub_b64,timestamp:Some(ts),expires_at:exp};
+    let sk=MigrateSignKey{key_type:"migrateSignKeyPrivate".into(),key:priv_b64,timestamp:Some(ts),expires_at:exp};
     (serde_json::to_string(&pk).unwrap(),serde_json::to_string(&sk).unwrap())
 }
+pub fn is_valid_migrate_sign_key_public(json:&str)->bool {
+    serde_json::from_str::<MigrateSignKey>(json).map(|k| k.key_type=="migrateSignKeyPublic" && key_json_has_required_fields(json) && is_valid_dsa65_key(&k.key, true)).unwrap_or(false)
+}
+pub fn is_valid_migrate_sign_key_private(json:&str)->bool {
+    serde_json::from_str::<MigrateSignKey>(json).map(|k| k.key_type=="migrateSignKeyPrivate" && key_json_has_required_fields(json) && is_valid_dsa65_key(&k.key, false)).unwrap_or(false)
+}
+/// 非推奨: keyHash を渡す形式は次のリリースで削除する。sign_data_migrate_sign_key_with_public を使う
+#[deprecated(note = "pass the public key to sign_data_migrate_sign_key_with_public instead of a keyHash")]
 pub fn sign_data_migrate_sign_key(priv_json:&str, data:&str, key_hash:&str)->Option<String> {
     let sk:MigrateSignKey=serde_json::from_str(priv_json).ok()?;
     if sk.key_type!="migrateSignKeyPrivate"{return None}
     create_signature_object_mlds65(&sk.key,data.as_bytes(),key_hash,"migrateSignKey").ok()
 }
+/// MigrateSignKey で署名 (keyHash は公開鍵から計算する)
+#[allow(deprecated)]
+pub fn sign_data_migrate_sign_key_with_public(priv_json:&str, pub_json:&str, data:&str)->Option<String> {
+    let hash = signer_key_hash(pub_json, "migrateSignKeyPublic")?;
+    sign_data_migrate_sign_key(priv_json, data, &hash)
+}
 pub fn verify_data_migrate_sign_key(pub_json:&str, sign_json:&str, data:&str)->bool {
     let pk = match serde_json::from_str::<MigrateSignKey>(pub_json) {
         Ok(v) => v,
         Err(_) => return false,
     };
-    let s = match serde_json::from_str::<Sign>(sign_json) {
-        Ok(v) => v,
-        Err(_) => return false,
-    };
-    if s.key_type != "migrateSignKey" { return false; }
-    verify_with_mlds65(&pk.key, data.as_bytes(), &s.signature)
+    verify_signature_object_with_algorithm(&pk.key, sign_json, data.as_bytes(), "migrateSignKey", "ML-DSA-65")
 }
 pub fn is_valid_sign_migrate_sign_key(json:&str)->bool {
-    serde_json::from_str::<Sign>(json).map(|s|s.key_type=="migrateSignKey").unwrap_or(false)
+    crate::signature::is_valid_sign_for(json, "migrateSignKey")
 }
@@ -0,0 +1,70 @@
+use crate::r#type::AccountPrekey;
+use crate::key_pair::AsymmetricKeyPair;
+#[cfg(feature = "master-key")]
+use crate::keyutils::generate_kem_key_pair;
+#[cfg(feature = "master-key")]
+use crate::master_key::{is_valid_master_key_private, is_valid_master_key_public, sign_master_key_with_public};
+#[cfg(feature = "master-key")]
+use chrono::Utc;
+#[cfg(feature = "master-key")]
+use serde_json;
+
+/// 使い捨てプリキーをまとめて生成し、それぞれマスター鍵で署名
+///
+/// アカウント鍵は長期間使われるため、漏洩すると過去に送られたラップをすべて復号されてしまう。
+/// サーバーは受信メッセージごとに未使用のプリキーを 1 つ配布し、
+/// 受信者は decrypt_from_prekey で復号した後そのプリキーの秘密鍵を破棄する
+#[cfg(feature = "master-key")]
+pub fn generate_account_prekeys(
+    count: usize,
+    master_public_json: &str,
+    master_private_json: &str,
+) -> Option<Vec<(String, String, String)>> {
+    if !is_valid_master_key_public(master_public_json) || !is_valid_master_key_private(master_private_json) {
+        return None;
+    }
+    let timestamp = Utc::now().timestamp_millis() as u64;
+    (0..count)
+        .map(|_| {
+            let (pub_b64, priv_b64) = generate_kem_key_pair().ok()?;
+            let pk = AccountPrekey {
+                key_type: AccountPrekey::PUBLIC_KEY_TYPE.into(),
+                key: pub_b64,
+                algorithm: "ML-KEM-768".into(),
+                timestamp,
+            };
+            let sk = AccountPrekey {
+                key_type: AccountPrekey::PRIVATE_KEY_TYPE.into(),
+                key: priv_b64,
+                algorithm: "ML-KEM-768".into(),
+                timestamp,
+            };
+            let pkj = serde_json::to_string(&pk).ok()?;
+            let skj = serde_json::to_string(&sk).ok()?;
+            let sign = sign_master_key_with_public(master_private_json, master_public_json, &pkj)?;
+            Some((pkj, skj, sign))
+        })
+        .collect()
+}
+
+pub fn is_valid_account_prekey_public(json: &str) -> bool {
+    AccountPrekey::is_valid_public(json)
+}
+
+pub fn is_valid_account_prekey_private(json: &str) -> bool {
+    AccountPrekey::is_valid_private(json)
+}
+
+/// プリキー宛ての暗号化 (EncryptedData JSON)
+pub fn encrypt_to_prekey(pub_json: &str, data: &str) -> Option<String> {
+    AccountPrekey::encrypt_data(pub_json, data)
+}
+
+/// プリキーによる復号。成功後、呼び出し側はこの秘密鍵を破棄すること
+pub fn decrypt_from_prekey(priv_json: &str, encrypted_json: &str) -> Option<String> {
+    AccountPrekey::decrypt_data(priv_json, encrypted_json)
+}
+
+pub fn is_valid_encrypted_data_prekey(json: &str) -> bool {
+    AccountPrekey::is_valid_encrypted_data(json)
+}
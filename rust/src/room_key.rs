@@ -1,10 +1,29 @@
 use crate::r#type::{RoomKey, EncryptedData};
+use crate::error::TakosError;
 use crate::core::is_valid_uuid_v7;
-use crate::keyutils::generate_symmetric_key;
-use crate::crypto::{encrypt_with_symmetric_key, decrypt_with_symmetric_key};
-use crate::utils::key_hash;
+use crate::keyutils::{generate_symmetric_key, key_byte_len, key_json_has_required_fields};
+use crate::crypto::{
+    encrypt_with_symmetric_key_aad, encrypt_with_symmetric_key_committing, encrypt_with_symmetric_key_counter,
+    encrypt_bytes_with_symmetric_key, encrypt_bytes_with_symmetric_key_aad, decrypt_with_symmetric_key_aad, decrypt_with_symmetric_key_committing,
+    decrypt_into_aad, commitment_matches, ALGORITHM_AES_GCM_COMMIT,
+};
+use crate::utils::{encrypted_data_key_matches, key_hash, key_material_hash, logged_validation};
+use crate::identity_key::{is_valid_identity_key_private, sign_identity_key_with_public, verify_identity_key};
+use crate::account_key::{decrypt_data_account_key, is_valid_encrypted_data_account_key};
 use chrono::Utc;
-use serde_json;
+use sha2::{Digest, Sha256};
+use hkdf::Hkdf;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use crate::verify::decode_base64;
+use serde_json::{self, json, Value};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::io::{Read, Write};
+
+/// EncryptedData.compression に記録する圧縮方式
+pub const COMPRESSION_DEFLATE: &str = "deflate";
+
+/// 展開後の平文の上限 (圧縮爆弾対策)
+pub const MAX_DECOMPRESSED_BYTES: usize = 64 * 1024 * 1024;
 
 /// RoomKey生成
 pub fn generate_room_key(room_uuid: &str) -> Option<String> {
@@ -13,52 +32,443 @@ pub fn generate_room_key(room_uuid: &str) -> Option<String> {
     }
     let key = generate_symmetric_key();
     let ts = Utc::now().timestamp_millis() as u64;
-    let rk = RoomKey { key_type: "roomKey".into(), key: key.clone(), algorithm: "AES-GCM".into(), timestamp: ts, session_uuid: room_uuid.into() };
+    let rk = RoomKey { key_type: "roomKey".into(), key: key.clone(), algorithm: "AES-GCM".into(), timestamp: ts, session_uuid: room_uuid.into(), previous_key_hash: None };
     serde_json::to_string(&rk).ok()
 }
 
 /// RoomKey検証
 pub fn is_valid_room_key(key_json: &str) -> bool {
-    if let Ok(rk) = serde_json::from_str::<RoomKey>(key_json) {
+    let ok = if let Ok(rk) = serde_json::from_str::<RoomKey>(key_json) {
         rk.key_type == "roomKey" &&
+        key_json_has_required_fields(key_json) &&
         rk.algorithm == "AES-GCM" &&
+        decode_base64(&rk.key).is_some_and(|b| Some(b.len()) == key_byte_len("roomKey", true)) &&
         is_valid_uuid_v7(&rk.session_uuid)
     } else {
         false
-    }
+    };
+    logged_validation("roomKey", ok)
 }
 
 /// RoomKeyを使ったデータ暗号化
-pub fn encrypt_data_room_key(key_json: &str, data: &str) -> Option<String> {
+///
+/// expected_uuid を指定すると RoomKey の sessionUuid と完全一致する場合のみ暗号化する。
+/// UUID の表記 (大文字・ハイフン有無など) の正規化は行わないため、形式が異なれば拒否される
+pub fn encrypt_data_room_key(key_json: &str, data: &str, expected_uuid: Option<&str>) -> Option<String> {
+    encrypt_data_room_key_aad(key_json, data, expected_uuid, b"")
+}
+
+/// RoomKey による AAD 付き暗号化
+///
+/// aad (メッセージの channel / roomid / timestamp など) を認証タグに束縛し、
+/// 暗号文を別の文脈へ移すと復号できなくなるようにする。復号には decrypt_data_room_key_aad を使う
+pub fn encrypt_data_room_key_aad(key_json: &str, data: &str, expected_uuid: Option<&str>, aad: &[u8]) -> Option<String> {
+    if !is_valid_room_key(key_json) {
+        return None;
+    }
+    let rk = serde_json::from_str::<RoomKey>(key_json).ok()?;
+    if expected_uuid.is_some_and(|uuid| uuid != rk.session_uuid) {
+        return None;
+    }
+    let enc = encrypt_with_symmetric_key_aad(data, &rk.key, aad);
+    let ed = EncryptedData {
+        key_type: "roomKey".into(),
+        key_hash: key_material_hash(key_json)?,
+        encrypted_data: enc.encrypted_data,
+        iv: enc.iv,
+        algorithm: Some(enc.algorithm),
+        cipher_text: None,
+        commitment: None,
+        kem: None,
+        compression: None,
+    };
+    serde_json::to_string(&ed).ok()
+}
+
+/// RoomKey による鍵コミットメント付き暗号化 (algorithm = "AES-GCM-commit")
+///
+/// 同じ encryptedData が受信者ごとに異なる鍵と共に配られても、
+/// コミットメントと一致しない鍵では復号を拒否する
+pub fn encrypt_data_room_key_committing(key_json: &str, data: &str) -> Option<String> {
+    if !is_valid_room_key(key_json) {
+        return None;
+    }
+    let rk = serde_json::from_str::<RoomKey>(key_json).ok()?;
+    let enc = encrypt_with_symmetric_key_committing(data, &rk.key);
+    let ed = EncryptedData {
+        key_type: "roomKey".into(),
+        key_hash: key_material_hash(key_json)?,
+        encrypted_data: enc.encrypted_data,
+        iv: enc.iv,
+        algorithm: Some(enc.algorithm),
+        cipher_text: None,
+        commitment: enc.commitment,
+        kem: None,
+        compression: None,
+    };
+    serde_json::to_string(&ed).ok()
+}
+
+/// 平文を deflate で圧縮してから RoomKey で暗号化 (compression = "deflate")
+///
+/// isLarge の JSON など圧縮の効く大きな平文向け。暗号文は後から圧縮できないため暗号化前に行う。
+/// 注意: 圧縮後の長さは平文の内容に依存する (CRIME/BREACH 型の攻撃)。
+/// 秘密の値と攻撃者が制御できる値を 1 つの平文に混ぜる場合は使わないこと。
+/// compression は AAD (compression_aad) として認証タグに束縛するため、付け外しすると復号できない。
+/// 復号は decrypt_data_room_key / decrypt_bytes_room_key がそのまま扱う
+pub fn encrypt_data_room_key_compressed(key_json: &str, data: &str) -> Option<String> {
     if !is_valid_room_key(key_json) {
         return None;
     }
     let rk = serde_json::from_str::<RoomKey>(key_json).ok()?;
-    let enc = encrypt_with_symmetric_key(data, &rk.key);
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data.as_bytes()).ok()?;
+    let compressed = encoder.finish().ok()?;
+    let enc = encrypt_bytes_with_symmetric_key_aad(&compressed, &rk.key, &compression_aad(COMPRESSION_DEFLATE));
     let ed = EncryptedData {
         key_type: "roomKey".into(),
-        key_hash: key_hash(key_json),
+        key_hash: key_material_hash(key_json)?,
         encrypted_data: enc.encrypted_data,
         iv: enc.iv,
         algorithm: Some(enc.algorithm),
         cipher_text: None,
+        commitment: None,
+        kem: None,
+        compression: Some(COMPRESSION_DEFLATE.into()),
     };
     serde_json::to_string(&ed).ok()
 }
 
-/// RoomKeyを使ったデータ復号
+/// 圧縮付きの暗号文に使う AAD (compression フィールドを暗号文に束縛する)
+fn compression_aad(compression: &str) -> Vec<u8> {
+    json!({"compression": compression}).to_string().into_bytes()
+}
+
+/// compression に従って復号後のバイト列を展開する (未知の方式・上限超過は None)
+fn decompress(compression: Option<&str>, data: Vec<u8>) -> Option<Vec<u8>> {
+    match compression {
+        None => Some(data),
+        Some(COMPRESSION_DEFLATE) => {
+            let mut out = Vec::new();
+            DeflateDecoder::new(&data[..])
+                .take(MAX_DECOMPRESSED_BYTES as u64 + 1)
+                .read_to_end(&mut out)
+                .ok()?;
+            (out.len() <= MAX_DECOMPRESSED_BYTES).then_some(out)
+        }
+        Some(_) => None,
+    }
+}
+
+/// RoomKeyを使ったデータ復号 (compression が記録されていれば展開する)
 pub fn decrypt_data_room_key(key_json: &str, data_json: &str) -> Option<String> {
+    decrypt_data_room_key_aad(key_json, data_json, b"")
+}
+
+/// RoomKey による AAD 付き復号 (暗号化時と同じ aad が必要)
+///
+/// 鍵コミットメント付き (AES-GCM-commit) の暗号文と圧縮付きの暗号文は呼び出し側の AAD を持たないため、
+/// aad が空の場合のみ復号する
+pub fn decrypt_data_room_key_aad(key_json: &str, data_json: &str, aad: &[u8]) -> Option<String> {
+    if !is_valid_room_key(key_json) {
+        return None;
+    }
+    if !encrypted_data_key_matches(data_json, key_json) {
+        return None;
+    }
+    let rk = serde_json::from_str::<RoomKey>(key_json).ok()?;
+    let ed: EncryptedData = serde_json::from_str(data_json).ok()?;
+    if ed.algorithm.as_deref() == Some(ALGORITHM_AES_GCM_COMMIT) {
+        if !aad.is_empty() { return None; }
+        let commitment = ed.commitment.as_ref()?;
+        return decrypt_with_symmetric_key_committing(&ed.encrypted_data, &ed.iv, &rk.key, commitment);
+    }
+    if let Some(compression) = ed.compression.as_deref() {
+        // 圧縮付きの暗号文の AAD は compression_aad だけで、呼び出し側の aad とは併用できない
+        if !aad.is_empty() { return None; }
+        let mut out = Vec::new();
+        decrypt_into_aad(&ed.encrypted_data, &ed.iv, &rk.key, &compression_aad(compression), &mut out).ok()?;
+        return String::from_utf8(decompress(Some(compression), out)?).ok();
+    }
+    decrypt_with_symmetric_key_aad(&ed.encrypted_data, &ed.iv, &rk.key, aad).ok()
+}
+
+/// RoomKey によるバイト列の暗号化 (UTF-8 でないバイナリをそのまま扱う)
+pub fn encrypt_bytes_room_key(key_json: &str, data: &[u8]) -> Option<String> {
+    if !is_valid_room_key(key_json) {
+        return None;
+    }
+    let rk = serde_json::from_str::<RoomKey>(key_json).ok()?;
+    let enc = encrypt_bytes_with_symmetric_key(data, &rk.key);
+    let ed = EncryptedData {
+        key_type: "roomKey".into(),
+        key_hash: key_material_hash(key_json)?,
+        encrypted_data: enc.encrypted_data,
+        iv: enc.iv,
+        algorithm: Some(enc.algorithm),
+        cipher_text: None,
+        commitment: None,
+        kem: None,
+        compression: None,
+    };
+    serde_json::to_string(&ed).ok()
+}
+
+/// RoomKey によるバイト列の復号 (平文の UTF-8 検証を行わない)
+///
+/// encrypt_data_room_key / encrypt_data_room_key_compressed で暗号化したものも復号できる
+pub fn decrypt_bytes_room_key(key_json: &str, data_json: &str) -> Option<Vec<u8>> {
     if !is_valid_room_key(key_json) {
         return None;
     }
+    if !encrypted_data_key_matches(data_json, key_json) {
+        return None;
+    }
     let rk = serde_json::from_str::<RoomKey>(key_json).ok()?;
     let ed: EncryptedData = serde_json::from_str(data_json).ok()?;
-    Some(decrypt_with_symmetric_key(&ed.encrypted_data, &ed.iv, &rk.key))
+    if ed.algorithm.as_deref() == Some(ALGORITHM_AES_GCM_COMMIT)
+        && !commitment_matches(&rk.key, ed.commitment.as_ref()?)
+    {
+        return None;
+    }
+    let aad = ed.compression.as_deref().map(compression_aad).unwrap_or_default();
+    let mut out = Vec::new();
+    decrypt_into_aad(&ed.encrypted_data, &ed.iv, &rk.key, &aad, &mut out).ok()?;
+    decompress(ed.compression.as_deref(), out)
 }
 
 /// 暗号化RoomKeyデータ検証
 pub fn is_valid_encrypted_data_room_key(data: &str) -> bool {
     serde_json::from_str::<EncryptedData>(data)
-        .map(|ed| ed.key_type == "roomKey")
+        .map(|ed| ed.is_well_formed("roomKey", false))
         .unwrap_or(false)
-}
\ No newline at end of file
+}
+
+/// カウンタ方式の IV で RoomKey 暗号化を行う状態付きラッパー
+///
+/// IV は端末ごとの 4 バイトの接頭辞と 64bit カウンタからなる。接頭辞は同じ RoomKey を使う端末の間で
+/// 一意になるよう呼び出し側が割り当て、prefix() と counter() を永続化して再開時に with_counter に渡す。
+/// 接頭辞とカウンタを永続化できない環境ではランダム IV の encrypt_data_room_key を使うこと。
+/// カウンタが一周する前にエラーを返し、IV の再利用を防ぐ
+pub struct RoomKeyCipher {
+    key_hash: String,
+    key: String,
+    prefix: [u8; 4],
+    counter: u64,
+}
+
+impl RoomKeyCipher {
+    /// 新しい接頭辞でカウンタ 0 から始める
+    pub fn new(room_key_json: &str, prefix: [u8; 4]) -> Option<Self> {
+        Self::with_counter(room_key_json, prefix, 0)
+    }
+
+    /// 永続化した接頭辞と次のカウンタから再開
+    pub fn with_counter(room_key_json: &str, prefix: [u8; 4], counter: u64) -> Option<Self> {
+        if !is_valid_room_key(room_key_json) {
+            return None;
+        }
+        let rk = serde_json::from_str::<RoomKey>(room_key_json).ok()?;
+        let key_hash = key_material_hash(room_key_json)?;
+        Some(RoomKeyCipher { key_hash, key: rk.key, prefix, counter })
+    }
+
+    /// IV の接頭辞 (カウンタと共に永続化する)
+    pub fn prefix(&self) -> [u8; 4] {
+        self.prefix
+    }
+
+    /// 次に使用するカウンタ
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// 暗号化 (EncryptedData JSON)。カウンタを使い切った場合は TakosError::NonceCounterExhausted
+    pub fn encrypt(&mut self, data: &str) -> Result<String, TakosError> {
+        if self.counter == u64::MAX {
+            return Err(TakosError::NonceCounterExhausted);
+        }
+        let enc = encrypt_with_symmetric_key_counter(data, &self.key, self.prefix, self.counter);
+        self.counter += 1;
+        let ed = EncryptedData {
+            key_type: "roomKey".into(),
+            key_hash: self.key_hash.clone(),
+            encrypted_data: enc.encrypted_data,
+            iv: enc.iv,
+            algorithm: Some(enc.algorithm),
+            cipher_text: None,
+            commitment: None,
+            kem: None,
+            compression: None,
+        };
+        serde_json::to_string(&ed).map_err(|e| TakosError::MalformedJson(e.to_string()))
+    }
+}
+
+/// ルームごとのルート秘密から RoomKey を決定的に導出 (複数端末で同じ鍵を再計算するため)
+///
+/// HKDF-SHA256 (salt = room_uuid) で 32 バイトの鍵を導出する。
+/// timestamp は UUID v7 に埋め込まれた時刻を用い、同じ入力なら同一の JSON を返す
+pub fn derive_room_key(root_secret_b64: &str, room_uuid: &str) -> Option<String> {
+    if !is_valid_uuid_v7(room_uuid) {
+        return None;
+    }
+    let secret = decode_base64(root_secret_b64)?;
+    if secret.len() < 32 {
+        return None;
+    }
+    let hk = Hkdf::<Sha256>::new(Some(room_uuid.as_bytes()), &secret);
+    let mut okm = [0u8; 32];
+    hk.expand(b"takos roomKey", &mut okm).ok()?;
+    let ts = u64::from_str_radix(&room_uuid.replace('-', "")[..12], 16).ok()?;
+    let rk = RoomKey {
+        key_type: "roomKey".into(),
+        key: BASE64.encode(okm),
+        algorithm: "AES-GCM".into(),
+        timestamp: ts,
+        session_uuid: room_uuid.into(),
+        previous_key_hash: None,
+    };
+    serde_json::to_string(&rk).ok()
+}
+
+/// RoomKeyのローテーション
+///
+/// 新しい RoomKey を生成し、previousKeyHash に旧鍵のハッシュ (key_material_hash) を記録する。
+/// 戻り値は (新しい RoomKey JSON, ローテーション記録 JSON)
+pub fn rotate_room_key(old_key_json: &str, room_uuid: &str) -> Option<(String, String)> {
+    if !is_valid_room_key(old_key_json) || !is_valid_uuid_v7(room_uuid) {
+        return None;
+    }
+    let key = generate_symmetric_key();
+    let ts = Utc::now().timestamp_millis() as u64;
+    let previous_key_hash = key_material_hash(old_key_json)?;
+    let rk = RoomKey {
+        key_type: "roomKey".into(),
+        key,
+        algorithm: "AES-GCM".into(),
+        timestamp: ts,
+        session_uuid: room_uuid.into(),
+        previous_key_hash: Some(previous_key_hash.clone()),
+    };
+    let new_json = serde_json::to_string(&rk).ok()?;
+    let proof = json!({
+        "previousKeyHash": previous_key_hash,
+        "newKeyHash": key_material_hash(&new_json)?,
+        "sessionUuid": room_uuid,
+        "timestamp": ts,
+    });
+    Some((new_json, proof.to_string()))
+}
+
+/// 旧 RoomKey で復号し、新 RoomKey で再暗号化
+pub fn reencrypt_data_room_key(
+    old_key_json: &str,
+    new_key_json: &str,
+    encrypted_json: &str,
+) -> Option<String> {
+    if !is_valid_room_key(new_key_json) || !is_valid_encrypted_data_room_key(encrypted_json) {
+        return None;
+    }
+    let plain = decrypt_data_room_key(old_key_json, encrypted_json)?;
+    encrypt_data_room_key(new_key_json, &plain, None)
+}
+
+/// アカウント鍵宛ての EncryptedData を復号し、RoomKey で再暗号化
+///
+/// 平文は呼び出し側に返さず、この関数内でのみ保持される
+pub fn rewrap_account_to_room(
+    account_priv_json: &str,
+    encrypted_account_json: &str,
+    room_key_json: &str,
+) -> Option<String> {
+    if !is_valid_room_key(room_key_json) || !is_valid_encrypted_data_account_key(encrypted_account_json) {
+        return None;
+    }
+    let plain = decrypt_data_account_key(account_priv_json, encrypted_account_json)?;
+    encrypt_data_room_key(room_key_json, &plain, None)
+}
+
+/// メンバーの IdentityKey 公開鍵から決定的なルーム ID を算出 (アドホック DM 用)
+///
+/// 各メンバー鍵の key_material_hash をソートして連結し、SHA-256 から UUID v7 形式の ID を生成する。
+/// key を取り出せない入力は JSON 文字列のハッシュ (key_hash) で代用する。
+/// 入力順に依存しないため、参加者全員が同じ ID を得られる
+pub fn deterministic_room_uuid(member_identity_pub_jsons: &[&str]) -> String {
+    let mut hashes: Vec<String> = member_identity_pub_jsons
+        .iter()
+        .map(|j| key_material_hash(j).unwrap_or_else(|| key_hash(j)))
+        .collect();
+    hashes.sort();
+    hashes.dedup();
+    let digest = Sha256::digest(hashes.join(",").as_bytes());
+    let mut b = [0u8; 16];
+    b.copy_from_slice(&digest[..16]);
+    // version 7 / variant 10xx
+    b[6] = 0x70 | (b[6] & 0x0f);
+    b[8] = 0x80 | (b[8] & 0x3f);
+    let h = hex::encode(b);
+    format!("{}-{}-{}-{}-{}", &h[0..8], &h[8..12], &h[12..16], &h[16..20], &h[20..32])
+}
+
+/// ルーム設定 (名前・トピック・権限など) に管理者の IdentityKey で署名
+///
+/// 戻り値は { settings: 署名対象の JSON 文字列, sign } 。
+/// settings には roomUuid / version / timestamp が含まれ、version はロールバック検出に使う
+pub fn sign_room_settings(
+    admin_identity_priv_json: &str,
+    admin_identity_pub_json: &str,
+    room_uuid: &str,
+    settings_json: &str,
+    version: u64,
+    now_ms: u64,
+) -> Option<String> {
+    if !is_valid_uuid_v7(room_uuid) || !is_valid_identity_key_private(admin_identity_priv_json) {
+        return None;
+    }
+    let settings: Value = serde_json::from_str(settings_json).ok()?;
+    let payload = json!({
+        "roomUuid": room_uuid,
+        "settings": settings,
+        "version": version,
+        "timestamp": now_ms,
+    });
+    let payload_str = serde_json::to_string(&payload).ok()?;
+    let sign = sign_identity_key_with_public(admin_identity_priv_json, admin_identity_pub_json, &payload_str)?;
+    serde_json::to_string(&json!({"settings": payload_str, "sign": sign})).ok()
+}
+
+/// 署名済みルーム設定の検証
+pub fn verify_room_settings(
+    admin_identity_pub_json: &str,
+    signed_settings_json: &str,
+    room_uuid: &str,
+) -> bool {
+    signed_room_settings_version(admin_identity_pub_json, signed_settings_json, room_uuid).is_some()
+}
+
+/// 署名済みルーム設定の検証 (current_version より古い設定へのロールバックを拒否)
+pub fn verify_room_settings_with_version(
+    admin_identity_pub_json: &str,
+    signed_settings_json: &str,
+    room_uuid: &str,
+    current_version: u64,
+) -> bool {
+    signed_room_settings_version(admin_identity_pub_json, signed_settings_json, room_uuid)
+        .is_some_and(|v| v >= current_version)
+}
+
+/// 署名と roomUuid を検証し、設定の version を返す
+fn signed_room_settings_version(
+    admin_identity_pub_json: &str,
+    signed_settings_json: &str,
+    room_uuid: &str,
+) -> Option<u64> {
+    let signed: Value = serde_json::from_str(signed_settings_json).ok()?;
+    let payload_str = signed.get("settings")?.as_str()?;
+    let sign = signed.get("sign")?.as_str()?;
+    if !verify_identity_key(admin_identity_pub_json, sign, payload_str) { return None; }
+    let payload: Value = serde_json::from_str(payload_str).ok()?;
+    if payload.get("roomUuid")?.as_str()? != room_uuid { return None; }
+    payload.get("version")?.as_u64()
+}
@@ -1,27 +1,31 @@
-use serde_json::Value;
-use crate::core::is_valid_uuid_v7;
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde_json::{Value, json};
+use crate::keyutils::{has_required_fields, key_byte_len, key_type_algorithm, key_type_names, key_type_required_fields};
+use crate::verify::decode_base64;
 
 fn decode_b64(src: &str) -> Option<Vec<u8>> {
-    BASE64.decode(src).ok()
+    decode_base64(src)
 }
 
 /// MasterKeyPrivateSchema に相当
 pub fn validate_master_key_private(v: &Value) -> bool {
     v.get("keyType").and_then(Value::as_str) == Some("masterKeyPrivate")
+        && has_required_fields(v)
+        && v.get("algorithm").is_none_or(|a| a.as_str() == key_type_algorithm("masterKey"))
         && v.get("key")
             .and_then(Value::as_str)
             .and_then(decode_b64)
-            .map_or(false, |b| b.len() == 4896)
+            .is_some_and(|b| Some(b.len()) == key_byte_len("masterKey", false))
 }
 
 /// MasterKeyPublicSchema に相当
 pub fn validate_master_key_public(v: &Value) -> bool {
     v.get("keyType").and_then(Value::as_str) == Some("masterKeyPublic")
+        && has_required_fields(v)
+        && v.get("algorithm").is_none_or(|a| a.as_str() == key_type_algorithm("masterKey"))
         && v.get("key")
             .and_then(Value::as_str)
             .and_then(decode_b64)
-            .map_or(false, |b| b.len() == 2592)
+            .is_some_and(|b| Some(b.len()) == key_byte_len("masterKey", true))
 }
 
 /// SignMasterKeySchema に相当
@@ -30,7 +34,7 @@ pub fn validate_sign_master_key(v: &Value) -> bool {
         && v.get("keyHash")
             .and_then(Value::as_str)
             .and_then(decode_b64)
-            .map_or(false, |b| b.len() == 32)
+            .is_some_and(|b| b.len() == 32)
         && v.get("signature")
             .and_then(Value::as_str)
             .and_then(decode_b64)
@@ -49,6 +53,8 @@ pub fn validate_message(v: &Value) -> bool {
             && o.get("timestamp").and_then(Value::as_u64).is_some()
             && o.get("isLarge").and_then(Value::as_bool).is_some()
             && o.get("roomid").and_then(Value::as_str).is_some()
+            // original は省略・null、または空でない文字列
+            && o.get("original").is_none_or(|x| x.is_null() || x.as_str().is_some_and(|s| !s.is_empty()))
     };
     match v.get("encrypted") {
         Some(Value::Bool(false)) => {
@@ -56,10 +62,222 @@ pub fn validate_message(v: &Value) -> bool {
                 && v.get("value").and_then(|x| {
                     let t = x.get("type").and_then(Value::as_str)?;
                     ["text","image","video","audio","file","thumbnail"].contains(&t)
-                        .then(|| ())
+                        .then_some(())
                 }).is_some()
         }
-        Some(Value::Bool(true)) => base(v) && v.get("value").and_then(Value::as_str).is_some(),
+        Some(Value::Bool(true)) => {
+            base(v)
+                && v.get("value").and_then(Value::as_str).is_some()
+                && v.get("encryptedMetadata").is_none_or(|x| x.as_str().is_some())
+        }
         _ => false,
     }
 }
+
+
+/// 各 is_valid_* が行う検証ルールを機械可読な JSON で出力
+///
+/// 他言語で検証を実装するサーバー向け。フィールドごとに期待値・デコード後の長さ・形式を記述する。
+/// 鍵のルールには鍵種別レジストリの必須フィールドを requiredFields として付ける
+pub fn export_validation_rules() -> String {
+    let mut rules = json!({
+        "masterKeyPublic": {
+            "keyType": { "equals": "masterKeyPublic" },
+            "algorithm": { "equals": key_type_algorithm("masterKey"), "required": false },
+            "key": { "decode": "base64", "length": key_byte_len("masterKey", true) }
+        },
+        "masterKeyPrivate": {
+            "keyType": { "equals": "masterKeyPrivate" },
+            "algorithm": { "equals": key_type_algorithm("masterKey"), "required": false },
+            "key": { "decode": "base64", "length": key_byte_len("masterKey", false) }
+        },
+        "signMasterKey": {
+            "keyType": { "equals": "masterKey" },
+            "keyHash": { "decode": "base64", "length": 32 },
+            "signature": { "decode": "base64" },
+            "algorithm": { "equals": "ML-DSA-87" }
+        },
+        "identityKeyPublic": {
+            "keyType": { "equals": "identityKeyPublic" },
+            "algorithm": { "oneOf": ["ML-DSA-65", "Ed25519+ML-DSA-65"] },
+            "classicalKey": { "decode": "base64", "length": 32, "requiredWhen": { "algorithm": "Ed25519+ML-DSA-65" } },
+            "timestamp": { "type": "u64" },
+            "sessionUuid": { "format": "uuid-v7" }
+        },
+        "identityKeyPrivate": {
+            "keyType": { "equals": "identityKeyPrivate" },
+            "algorithm": { "oneOf": ["ML-DSA-65", "Ed25519+ML-DSA-65"] },
+            "classicalKey": { "decode": "base64", "length": 32, "requiredWhen": { "algorithm": "Ed25519+ML-DSA-65" } },
+            "timestamp": { "type": "u64" },
+            "sessionUuid": { "format": "uuid-v7" }
+        },
+        "signIdentityKey": {
+            "keyType": { "equals": "identityKey" },
+            "algorithm": { "oneOf": ["ML-DSA-65", "Ed25519+ML-DSA-65"] }
+        },
+        "accountKeyPublic": {
+            "keyType": { "equals": "accountKeyPublic" },
+            "algorithm": { "equals": key_type_algorithm("accountKey") },
+            "key": { "decode": "base64", "length": key_byte_len("accountKey", true) },
+            "timestamp": { "type": "u64" }
+        },
+        "accountKeyPrivate": {
+            "keyType": { "equals": "accountKeyPrivate" },
+            "algorithm": { "equals": key_type_algorithm("accountKey") },
+            "key": { "decode": "base64", "length": key_byte_len("accountKey", false) },
+            "timestamp": { "type": "u64" }
+        },
+        "accountPrekeyPublic": {
+            "keyType": { "equals": "accountPrekeyPublic" },
+            "algorithm": { "equals": key_type_algorithm("accountPrekey") },
+            "key": { "decode": "base64", "length": key_byte_len("accountPrekey", true) },
+            "timestamp": { "type": "u64" }
+        },
+        "accountPrekeyPrivate": {
+            "keyType": { "equals": "accountPrekeyPrivate" },
+            "algorithm": { "equals": key_type_algorithm("accountPrekey") },
+            "key": { "decode": "base64", "length": key_byte_len("accountPrekey", false) },
+            "timestamp": { "type": "u64" }
+        },
+        "serverKeyPublic": {
+            "keyType": { "equals": "serverKeyPublic" },
+            "key": { "decode": "base64", "length": key_byte_len("serverKey", true) },
+            "timestamp": { "type": "u64" }
+        },
+        "serverKeyPrivate": {
+            "keyType": { "equals": "serverKeyPrivate" },
+            "key": { "decode": "base64", "length": key_byte_len("serverKey", false) },
+            "timestamp": { "type": "u64" }
+        },
+        "roomKey": {
+            "keyType": { "equals": "roomKey" },
+            "algorithm": { "equals": key_type_algorithm("roomKey") },
+            "key": { "decode": "base64", "length": key_byte_len("roomKey", true) },
+            "timestamp": { "type": "u64" },
+            "sessionUuid": { "format": "uuid-v7" }
+        },
+        "shareKeyPublic": {
+            "keyType": { "equals": "shareKeyPublic" },
+            "key": { "decode": "base64", "length": key_byte_len("shareKey", true) }
+        },
+        "shareKeyPrivate": {
+            "keyType": { "equals": "shareKeyPrivate" },
+            "key": { "decode": "base64", "length": key_byte_len("shareKey", false) }
+        },
+        "shareSignKeyPublic": {
+            "keyType": { "equals": "shareSignKeyPublic" }
+        },
+        "shareSignKeyPrivate": {
+            "keyType": { "equals": "shareSignKeyPrivate" }
+        },
+        "migrateKeyPublic": {
+            "keyType": { "equals": "migrateKeyPublic" },
+            "key": { "decode": "base64", "length": key_byte_len("migrateKey", true) },
+            "expiresAt": { "type": "u64", "required": false }
+        },
+        "migrateKeyPrivate": {
+            "keyType": { "equals": "migrateKeyPrivate" },
+            "key": { "decode": "base64", "length": key_byte_len("migrateKey", false) },
+            "expiresAt": { "type": "u64", "required": false }
+        },
+        "deviceKey": {
+            "key": { "decode": "base64", "length": key_byte_len("deviceKey", false) }
+        },
+        "encryptedDataAccountKey": {
+            "keyType": { "equals": "accountKey" },
+            "algorithm": { "equals": "AES-GCM" },
+            "keyHash": { "decode": "base64", "length": 32 },
+            "iv": { "decode": "base64", "length": 12 },
+            "encryptedData": { "decode": "base64" },
+            "cipherText": { "decode": "base64", "required": true },
+            "kem": { "equals": "ML-KEM-768", "required": false }
+        },
+        "encryptedDataAccountPrekey": {
+            "keyType": { "equals": "accountPrekey" },
+            "algorithm": { "equals": "AES-GCM" },
+            "keyHash": { "decode": "base64", "length": 32 },
+            "iv": { "decode": "base64", "length": 12 },
+            "encryptedData": { "decode": "base64" },
+            "cipherText": { "decode": "base64", "required": true },
+            "kem": { "equals": "ML-KEM-768", "required": false }
+        },
+        "encryptedDataRoomKey": {
+            "keyType": { "equals": "roomKey" },
+            "algorithm": { "oneOf": ["AES-GCM", "AES-GCM-commit"] },
+            "keyHash": { "decode": "base64", "length": 32 },
+            "iv": { "decode": "base64", "length": 12 },
+            "encryptedData": { "decode": "base64" },
+            "cipherText": { "absent": true },
+            "kem": { "absent": true },
+            "commitment": { "decode": "base64", "length": 32, "requiredWhen": { "algorithm": "AES-GCM-commit" } },
+            "compression": { "equals": "deflate", "required": false }
+        },
+        "encryptedDataShareKey": {
+            "keyType": { "equals": "shareKey" },
+            "algorithm": { "equals": "AES-GCM" },
+            "keyHash": { "decode": "base64", "length": 32 },
+            "iv": { "decode": "base64", "length": 12 },
+            "encryptedData": { "decode": "base64" },
+            "cipherText": { "decode": "base64", "required": true },
+            "kem": { "equals": "ML-KEM-768", "required": false }
+        },
+        "encryptedDataMigrateKey": {
+            "keyType": { "equals": "migrateKey" },
+            "algorithm": { "equals": "AES-GCM" },
+            "keyHash": { "decode": "base64", "length": 32 },
+            "iv": { "decode": "base64", "length": 12 },
+            "encryptedData": { "decode": "base64" },
+            "cipherText": { "decode": "base64", "required": true },
+            "kem": { "equals": "ML-KEM-768", "required": false }
+        },
+        "encryptedDataDeviceKey": {
+            "keyType": { "equals": "deviceKey" },
+            "algorithm": { "oneOf": ["AES-GCM", "AES-GCM-commit"] },
+            "keyHash": { "decode": "base64", "length": 32 },
+            "iv": { "decode": "base64", "length": 12 },
+            "encryptedData": { "decode": "base64" },
+            "cipherText": { "absent": true },
+            "kem": { "absent": true },
+            "commitment": { "decode": "base64", "length": 32, "requiredWhen": { "algorithm": "AES-GCM-commit" } }
+        },
+        "message": {
+            "encrypted": { "type": "bool" },
+            "channel": { "type": "string" },
+            "original": { "type": "string", "nonEmpty": true, "required": false, "notEquals": "messageId" },
+            "timestamp": { "type": "u64" },
+            "isLarge": { "type": "bool" },
+            "roomid": { "type": "string" },
+            "value": {
+                "whenEncrypted": { "type": "string" },
+                "whenNotEncrypted": {
+                    "type": { "oneOf": ["text", "image", "video", "audio", "file", "thumbnail"] }
+                }
+            },
+            "encryptedMetadata": { "type": "string", "required": false }
+        }
+    });
+    for name in key_type_names() {
+        let Some(fields) = key_type_required_fields(name) else { continue };
+        // 対称鍵は Public / Private の区別がなく、keyType は種別名そのもの
+        let variants: Vec<(String, bool)> = if matches!(name, "roomKey" | "deviceKey") {
+            vec![(name.to_string(), false)]
+        } else {
+            vec![(format!("{name}Public"), true), (format!("{name}Private"), false)]
+        };
+        for (rule_name, is_public) in variants {
+            let Some(rule) = rules
+                .as_object_mut()
+                .and_then(|o| o.entry(rule_name.clone()).or_insert_with(|| json!({})).as_object_mut())
+            else { continue };
+            for &field in fields {
+                rule.entry(field).or_insert_with(|| match field {
+                    "keyType" => json!({ "equals": rule_name }),
+                    "key" => json!({ "decode": "base64", "length": key_byte_len(name, is_public) }),
+                    _ => json!({ "required": true }),
+                });
+            }
+            rule.insert("requiredFields".into(), json!(fields));
+        }
+    }
+    rules.to_string()
+}
\ No newline at end of file
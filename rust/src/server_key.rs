@@ -1,10 +1,13 @@
-use crate::r#type::{ServerKey, Sign};
-use crate::signature::{create_signature_object_mlds65, verify_with_mlds65};
-use crate::keyutils::generate_dsa65_key_pair;
+use crate::r#type::ServerKey;
+use crate::signature::create_signature_object_mlds65;
+use crate::keyutils::{generate_dsa65_key_pair, key_byte_len, key_json_has_required_fields};
 use chrono::Utc;
 use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::Engine as _;
-use serde_json;
+use crate::verify::decode_base64;
+use serde_json::{self, json, Value};
+use sha2::{Digest, Sha256};
+use crate::utils::signer_key_hash;
 
 /// 秘密鍵 / 公開鍵生成 (ML‑DSA‑65)
 pub fn generate_server_key() -> (String, String) {
@@ -17,30 +20,115 @@ pub fn generate_server_key() -> (String, String) {
 
 pub fn is_valid_server_key_public(json: &str) -> bool {
     serde_json::from_str::<ServerKey>(json)
-        .map(|k| k.key_type=="serverKeyPublic" && BASE64.decode(&k.key).map_or(false, |b| b.len()==1952))
+        .map(|k| k.key_type=="serverKeyPublic" && key_json_has_required_fields(json) && decode_base64(&k.key).is_some_and(|b| Some(b.len())==key_byte_len("serverKey", true)))
         .unwrap_or(false)
 }
 pub fn is_valid_server_key_private(json: &str) -> bool {
     serde_json::from_str::<ServerKey>(json)
-        .map(|k| k.key_type=="serverKeyPrivate" && BASE64.decode(&k.key).map_or(false, |b| b.len()==4032))
+        .map(|k| k.key_type=="serverKeyPrivate" && key_json_has_required_fields(json) && decode_base64(&k.key).is_some_and(|b| Some(b.len())==key_byte_len("serverKey", false)))
         .unwrap_or(false)
 }
 
+/// 非推奨: keyHash を渡す形式は次のリリースで削除する。sign_data_server_key_with_public を使う
+#[deprecated(note = "pass the public key to sign_data_server_key_with_public instead of a keyHash")]
 pub fn sign_data_server_key(priv_json: &str, data: &str, key_hash: &str) -> Option<String> {
     let sk: ServerKey = serde_json::from_str(priv_json).ok()?;
     if sk.key_type!="serverKeyPrivate" { return None }
     create_signature_object_mlds65(&sk.key, data.as_bytes(), key_hash, "serverKey").ok()
 }
-pub fn verify_data_server_key(pub_json: &str, sign_json: &str, data: &str) -> bool {
-    let pk = match serde_json::from_str::<ServerKey>(pub_json) {
+/// サーバー鍵で署名 (keyHash はサーバー公開鍵から計算する)
+#[allow(deprecated)]
+pub fn sign_data_server_key_with_public(priv_json: &str, pub_json: &str, data: &str) -> Option<String> {
+    let hash = signer_key_hash(pub_json, "serverKeyPublic")?;
+    sign_data_server_key(priv_json, data, &hash)
+}
+/// サーバー鍵の署名オブジェクトの形式検証 (keyType と algorithm の対応)
+pub fn is_valid_sign_server_key(sign_json: &str) -> bool {
+    crate::signature::is_valid_sign_for(sign_json, "serverKey")
+}
+// 検証は no_std でも使えるよう verify モジュールにある
+pub use crate::verify::verify_data_server_key;
+
+/// Merkle 葉ノード (0x00 || keyHash)
+fn merkle_leaf(key_hash: &str) -> Vec<u8> {
+    let mut h = Sha256::new();
+    h.update([0u8]);
+    h.update(key_hash.as_bytes());
+    h.finalize().to_vec()
+}
+
+/// Merkle 内部ノード (0x01 || left || right)
+fn merkle_node(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut h = Sha256::new();
+    h.update([1u8]);
+    h.update(left);
+    h.update(right);
+    h.finalize().to_vec()
+}
+
+/// 葉から Merkle パス ([{hash, position: "left"|"right"}]) を辿りルートを再構成
+fn merkle_root_from_path(key_hash: &str, path: &Value) -> Option<String> {
+    let mut cur = merkle_leaf(key_hash);
+    for step in path.as_array()? {
+        let sibling = decode_base64(step.get("hash")?.as_str()?)?;
+        if sibling.len() != 32 { return None; }
+        cur = match step.get("position")?.as_str()? {
+            "left" => merkle_node(&sibling, &cur),
+            "right" => merkle_node(&cur, &sibling),
+            _ => return None,
+        };
+    }
+    Some(BASE64.encode(cur))
+}
+
+/// 署名対象となるルート情報
+fn inclusion_root_payload(merkle_root: &str, timestamp: u64) -> String {
+    json!({ "merkleRoot": merkle_root, "timestamp": timestamp }).to_string()
+}
+
+/// 鍵透明性ログへの包含証明を作成 (サーバー鍵でルートに署名)
+pub fn create_inclusion_proof(
+    server_priv_json: &str,
+    server_pub_json: &str,
+    key_hash_b64: &str,
+    merkle_root: &str,
+    merkle_path_json: &str,
+    now_ms: u64,
+) -> Option<String> {
+    if !is_valid_server_key_private(server_priv_json) { return None; }
+    let path: Value = serde_json::from_str(merkle_path_json).ok()?;
+    if merkle_root_from_path(key_hash_b64, &path)? != merkle_root { return None; }
+    let payload = inclusion_root_payload(merkle_root, now_ms);
+    let sign = sign_data_server_key_with_public(server_priv_json, server_pub_json, &payload)?;
+    let proof = json!({
+        "keyHash": key_hash_b64,
+        "merkleRoot": merkle_root,
+        "merklePath": path,
+        "timestamp": now_ms,
+        "sign": sign,
+    });
+    serde_json::to_string(&proof).ok()
+}
+
+/// 包含証明の検証 (Merkle パスがサーバー署名済みルートを再構成するか)
+pub fn verify_inclusion_proof(server_pub_json: &str, key_hash_b64: &str, proof_json: &str) -> bool {
+    let proof: Value = match serde_json::from_str(proof_json) {
         Ok(v) => v,
         Err(_) => return false,
     };
-    if pk.key_type != "serverKeyPublic" { return false; }
-    let sig_obj = match serde_json::from_str::<Sign>(sign_json) {
-        Ok(v) => v,
-        Err(_) => return false,
+    let fields = (|| {
+        let kh = proof.get("keyHash")?.as_str()?;
+        let root = proof.get("merkleRoot")?.as_str()?;
+        let timestamp = proof.get("timestamp")?.as_u64()?;
+        let sign = proof.get("sign")?.as_str()?;
+        let computed = merkle_root_from_path(kh, proof.get("merklePath")?)?;
+        Some((kh, root, timestamp, sign, computed))
+    })();
+    let (kh, root, timestamp, sign, computed) = match fields {
+        Some(f) => f,
+        None => return false,
     };
-    if sig_obj.key_type != "serverKey" { return false; }
-    crate::signature::verify_with_mlds65(&pk.key, data.as_bytes(), &sig_obj.signature)
+    kh == key_hash_b64
+        && computed == root
+        && verify_data_server_key(server_pub_json, sign, &inclusion_root_payload(root, timestamp))
 }
@@ -1,97 +1,105 @@
-use crate::r#type::{ShareKey, ShareSignKey, EncryptedData, Sign};
+use crate::r#type::{ShareKey, ShareSignKey};
+use crate::key_pair::AsymmetricKeyPair;
+#[cfg(feature = "master-key")]
 use crate::keyutils::{generate_kem_key_pair, generate_dsa65_key_pair};
-use crate::crypto::{encrypt, decrypt};
-use crate::master_key::{is_valid_master_key_private, sign_master_key};
+#[cfg(feature = "master-key")]
+use crate::master_key::{is_valid_master_key_private, is_valid_master_key_public, sign_master_key_with_public};
+#[cfg(feature = "master-key")]
 use crate::core::is_valid_uuid_v7;
-use crate::utils::key_hash;
-use crate::signature::verify_with_mlds65;
-use base64::engine::general_purpose::STANDARD as BASE64;
-use base64::Engine as _;
+use crate::account_key::{encrypt_data_account_key, is_valid_account_key_public};
+use crate::utils::signer_key_hash;
+use crate::keyutils::key_json_has_required_fields;
+use crate::signature::verify_signature_object_with_algorithm;
+#[cfg(feature = "master-key")]
 use chrono::Utc;
 use serde_json;
 
 /// ShareKey生成
-pub fn generate_share_key(master_priv: &str, session_uuid: &str) -> Option<(String,String,String)> {
-    if !is_valid_master_key_private(master_priv) || !is_valid_uuid_v7(session_uuid) { return None }
+#[cfg(feature = "master-key")]
+pub fn generate_share_key(master_pub: &str, master_priv: &str, session_uuid: &str) -> Option<(String,String,String)> {
+    if !is_valid_master_key_public(master_pub) || !is_valid_master_key_private(master_priv) || !is_valid_uuid_v7(session_uuid) { return None }
     let (pub_b64, priv_b64) = generate_kem_key_pair().ok()?;
     let ts = Utc::now().timestamp_millis() as u64;
     let pk = ShareKey{ key_type:"shareKeyPublic".into(), key:pub_b64.clone(), algorithm:"ML-KEM-768".into(), timestamp:ts, session_uuid:session_uuid.into() };
     let sk = ShareKey{ key_type:"shareKeyPrivate".into(), key:priv_b64.clone(), algorithm:"ML-KEM-768".into(), timestamp:ts, session_uuid:session_uuid.into() };
     let pkj = serde_json::to_string(&pk).ok()?;
     let skj = serde_json::to_string(&sk).ok()?;
-    let mh = key_hash(master_priv);
-    let sign = sign_master_key(master_priv, &pkj, &mh)?;
+    let sign = sign_master_key_with_public(master_priv, master_pub, &pkj)?;
     Some((pkj,skj,sign))
 }
 pub fn is_valid_share_key_public(json: &str)->bool {
-    serde_json::from_str::<ShareKey>(json).map(|k| k.key_type=="shareKeyPublic" && BASE64.decode(&k.key).map_or(false,|b|b.len()==1184)).unwrap_or(false)
+    ShareKey::is_valid_public(json)
 }
 pub fn is_valid_share_key_private(json: &str)->bool {
-    serde_json::from_str::<ShareKey>(json).map(|k| k.key_type=="shareKeyPrivate"&&BASE64.decode(&k.key).map_or(false,|b|b.len()==2400)).unwrap_or(false)
+    ShareKey::is_valid_private(json)
 }
 pub fn encrypt_data_share_key(pub_json: &str, data: &str) -> Option<String> {
-    let sk = serde_json::from_str::<ShareKey>(pub_json).ok()?;
-    if sk.key_type != "shareKeyPublic" { return None; }
-    let enc = encrypt(data, &sk.key);
-    let ed = EncryptedData {
-        key_type: "shareKey".into(),
-        key_hash: key_hash(pub_json),
-        encrypted_data: enc.encrypted_data,
-        iv: enc.iv,
-        algorithm: Some(enc.algorithm),
-        cipher_text: Some(enc.cipher_text),
-    };
-    serde_json::to_string(&ed).ok()
+    ShareKey::encrypt_data(pub_json, data)
 }
 
 pub fn decrypt_data_share_key(priv_json: &str, json: &str) -> Option<String> {
-    let sk = serde_json::from_str::<ShareKey>(priv_json).ok()?;
-    if sk.key_type != "shareKeyPrivate" { return None; }
-    let ed: EncryptedData = serde_json::from_str(json).ok()?;
-    let ciphertext = ed.cipher_text.as_ref()?;
-    Some(decrypt(&ed.encrypted_data, ciphertext, &ed.iv, &sk.key))
+    ShareKey::decrypt_data(priv_json, json)
 }
 
 pub fn is_valid_encrypted_data_share_key(json:&str)->bool {
-    serde_json::from_str::<EncryptedData>(json).map(|ed|ed.key_type=="shareKey").unwrap_or(false)
+    ShareKey::is_valid_encrypted_data(json)
+}
+
+/// ShareKey 宛ての EncryptedData を復号し、AccountKey 宛てに再暗号化 (端末移行用)
+///
+/// 宛先の AccountKey を先に検証し、平文は呼び出し側に返さずこの関数内でのみ保持する。
+/// 戻り値は keyType が "accountKey" の EncryptedData
+pub fn transfer_share_to_account(
+    share_priv_json: &str,
+    encrypted_share_json: &str,
+    account_pub_json: &str,
+) -> Option<String> {
+    if !is_valid_account_key_public(account_pub_json) || !is_valid_encrypted_data_share_key(encrypted_share_json) {
+        return None;
+    }
+    let plain = decrypt_data_share_key(share_priv_json, encrypted_share_json)?;
+    encrypt_data_account_key(account_pub_json, &plain)
 }
 
 /// ShareSignKey生成／検証
-pub fn generate_share_sign_key(master_priv:&str, session_uuid:&str)->Option<(String,String,String)> {
-    if !is_valid_master_key_private(master_priv) || !is_valid_uuid_v7(session_uuid) { return None }
+#[cfg(feature = "master-key")]
+pub fn generate_share_sign_key(master_pub:&str, master_priv:&str, session_uuid:&str)->Option<(String,String,String)> {
+    if !is_valid_master_key_public(master_pub) || !is_valid_master_key_private(master_priv) || !is_valid_uuid_v7(session_uuid) { return None }
     let (pub_b64, priv_b64) = generate_dsa65_key_pair().ok()?;
     let ts = Utc::now().timestamp_millis() as u64;
     let pk = ShareSignKey{ key_type:"shareSignKeyPublic".into(), key:pub_b64.clone(), algorithm:"ML-DSA-65".into(), timestamp:ts, session_uuid:session_uuid.into() };
     let sk = ShareSignKey{ key_type:"shareSignKeyPrivate".into(), key:priv_b64.clone(), algorithm:"ML-DSA-65".into(), timestamp:ts, session_uuid:session_uuid.into() };
     let pkj=serde_json::to_string(&pk).ok()?;
     let skj=serde_json::to_string(&sk).ok()?;
-    let mh = key_hash(master_priv);
-    let sign = sign_master_key(master_priv, &pkj, &mh)?;
+    let sign = sign_master_key_with_public(master_priv, master_pub, &pkj)?;
     Some((pkj,skj,sign))
 }
 pub fn is_valid_share_sign_key_public(json:&str)->bool {
-    serde_json::from_str::<ShareSignKey>(json).map(|k| k.key_type=="shareSignKeyPublic").unwrap_or(false)
+    serde_json::from_str::<ShareSignKey>(json).map(|k| k.key_type=="shareSignKeyPublic" && key_json_has_required_fields(json)).unwrap_or(false)
 }
 pub fn is_valid_share_sign_key_private(json:&str)->bool {
-    serde_json::from_str::<ShareSignKey>(json).map(|k| k.key_type=="shareSignKeyPrivate").unwrap_or(false)
+    serde_json::from_str::<ShareSignKey>(json).map(|k| k.key_type=="shareSignKeyPrivate" && key_json_has_required_fields(json)).unwrap_or(false)
 }
+/// 非推奨: keyHash を渡す形式は次のリリースで削除する。sign_data_share_sign_key_with_public を使う
+#[deprecated(note = "pass the public key to sign_data_share_sign_key_with_public instead of a keyHash")]
 pub fn sign_data_share_sign_key(priv_json:&str, data:&str, key_hash:&str)->Option<String> {
     let sk: ShareSignKey = serde_json::from_str(priv_json).ok()?;
     if sk.key_type != "shareSignKeyPrivate" { return None; }
     crate::signature::create_signature_object_mlds65(&sk.key, data.as_bytes(), key_hash, "shareSignKey").ok()
 }
+/// ShareSignKey で署名 (keyHash は公開鍵から計算する)
+#[allow(deprecated)]
+pub fn sign_data_share_sign_key_with_public(priv_json:&str, pub_json:&str, data:&str)->Option<String> {
+    let hash = signer_key_hash(pub_json, "shareSignKeyPublic")?;
+    sign_data_share_sign_key(priv_json, data, &hash)
+}
 pub fn verify_data_share_sign_key(pub_json: &str, sign_json: &str, data: &str) -> bool {
     let sk = match serde_json::from_str::<ShareSignKey>(pub_json) {
         Ok(v) => v,
         Err(_) => return false,
     };
-    let sig = match serde_json::from_str::<Sign>(sign_json) {
-        Ok(v) => v,
-        Err(_) => return false,
-    };
-    if sig.key_type != "shareSignKey" { return false; }
-    verify_with_mlds65(&sk.key, data.as_bytes(), &sig.signature)
+    verify_signature_object_with_algorithm(&sk.key, sign_json, data.as_bytes(), "shareSignKey", "ML-DSA-65")
 }
 pub fn is_valid_sign_share_sign_key(json:&str)->bool {
-    serde_json::from_str::<Sign>(json).map(|s|s.key_type=="shareSignKey").unwrap_or(false)
+    crate::signature::is_valid_sign_for(json, "shareSignKey")
 }
@@ -1,86 +1,68 @@
 use crate::r#type::Sign;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use ml_dsa::{
-    EncodedSigningKey, EncodedVerifyingKey, EncodedSignature,
-    MlDsa65, MlDsa87,
-    SigningKey, VerifyingKey, Signature
+    EncodedSigningKey, EncodedSignature,
+    MlDsa65,
+    SigningKey, Signature
 };
-use ml_dsa::signature::{Signer, Verifier, SignatureEncoding};
+#[cfg(feature = "master-key")]
+use ml_dsa::MlDsa87;
+use ml_dsa::signature::{Signer, SignatureEncoding};
 use serde_json;
+use crate::verify::{decode_base64, prehash_message};
+use crate::keyutils::key_type_algorithm;
+
+// 検証関数は no_std でも使えるよう verify モジュールにある
+pub use crate::verify::{
+    verify_with_mlds65,
+    prehash_sha256,
+    verify_prehash_mlds65,
+    verify_with_ed25519,
+    verify_hybrid_ed25519_mlds65,
+    verify_signature_object,
+    verify_signature_object_with_algorithm,
+    verify_any,
+    ALGORITHM_ED25519_MLDSA65,
+    ALGORITHM_MLDSA65_PREHASH_SHA256,
+    ALGORITHM_MLDSA87_PREHASH_SHA256,
+};
+#[cfg(feature = "master-key")]
+pub use crate::verify::{verify_with_mlds87, verify_prehash_mlds87};
 
 /// ML‑DSA‑87 署名 (Base64 出力)
+#[cfg(feature = "master-key")]
 pub fn sign_with_mlds87(private_key_b64: &str, data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
-    let sk_bytes = BASE64.decode(private_key_b64)?;
+    let sk_bytes = decode_base64(private_key_b64).ok_or("invalid base64")?;
     let sk_arr = <EncodedSigningKey<MlDsa87>>::try_from(&sk_bytes[..])?;
     let sk = SigningKey::<MlDsa87>::decode(&sk_arr);
     let sig: Signature<MlDsa87> = sk.sign(data);
     Ok(BASE64.encode(sig.to_bytes()))
 }
 
-/// ML‑DSA‑87 検証
-pub fn verify_with_mlds87(public_key_b64: &str, data: &[u8], signature_b64: &str) -> bool {
-    let pk_bytes = match BASE64.decode(public_key_b64) {
-        Ok(b) => b,
-        Err(_) => return false,
-    };
-    let pk_arr = match <EncodedVerifyingKey<MlDsa87>>::try_from(&pk_bytes[..]) {
-        Ok(a) => a,
-        Err(_) => return false,
-    };
-    let pk = VerifyingKey::<MlDsa87>::decode(&pk_arr);
-    let sig_bytes = match BASE64.decode(signature_b64) {
-        Ok(b) => b,
-        Err(_) => return false,
-    };
-    let sig_arr = match <EncodedSignature<MlDsa87>>::try_from(&sig_bytes[..]) {
-        Ok(a) => a,
-        Err(_) => return false,
-    };
-    let sig_opt = Signature::<MlDsa87>::decode(&sig_arr);
-    let sig = match sig_opt {
-        Some(s) => s,
-        None => return false,
-    };
-    pk.verify(data, &sig).is_ok()
-}
-
 /// ML‑DSA‑65 署名 (Base64 出力)
 pub fn sign_with_mlds65(private_key_b64: &str, data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
-    let sk_bytes = BASE64.decode(private_key_b64)?;
+    let sk_bytes = decode_base64(private_key_b64).ok_or("invalid base64")?;
     let sk_arr = <EncodedSigningKey<MlDsa65>>::try_from(&sk_bytes[..])?;
     let sk = SigningKey::<MlDsa65>::decode(&sk_arr);
     let sig: Signature<MlDsa65> = sk.sign(data);
     Ok(BASE64.encode(sig.to_bytes()))
 }
 
-/// ML‑DSA‑65 検証
-pub fn verify_with_mlds65(public_key_b64: &str, data: &[u8], signature_b64: &str) -> bool {
-    let pk_bytes = match BASE64.decode(public_key_b64) {
-        Ok(b) => b,
-        Err(_) => return false,
-    };
-    let pk_arr = match <EncodedVerifyingKey<MlDsa65>>::try_from(&pk_bytes[..]) {
-        Ok(a) => a,
-        Err(_) => return false,
-    };
-    let pk = VerifyingKey::<MlDsa65>::decode(&pk_arr);
-    let sig_bytes = match BASE64.decode(signature_b64) {
-        Ok(b) => b,
-        Err(_) => return false,
-    };
-    let sig_arr = match <EncodedSignature<MlDsa65>>::try_from(&sig_bytes[..]) {
-        Ok(a) => a,
-        Err(_) => return false,
-    };
-    let sig_opt = Signature::<MlDsa65>::decode(&sig_arr);
-    let sig = match sig_opt {
-        Some(s) => s,
-        None => return false,
-    };
-    pk.verify(data, &sig).is_ok()
+/// ML‑DSA‑65 事前ハッシュ署名 (digest は SHA-256, 32 バイト)
+pub fn sign_prehash_mlds65(private_key_b64: &str, digest: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let m = prehash_message(digest).ok_or("digest must be 32 bytes (SHA-256)")?;
+    sign_with_mlds65(private_key_b64, &m)
+}
+
+/// ML‑DSA‑87 事前ハッシュ署名 (digest は SHA-256, 32 バイト)
+#[cfg(feature = "master-key")]
+pub fn sign_prehash_mlds87(private_key_b64: &str, digest: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let m = prehash_message(digest).ok_or("digest must be 32 bytes (SHA-256)")?;
+    sign_with_mlds87(private_key_b64, &m)
 }
 
 /// ML‑DSA‑87 署名オブジェクト作成
+#[cfg(feature = "master-key")]
 pub fn create_signature_object_mlds87(
     private_key_b64: &str,
     data: &[u8],
@@ -114,23 +96,154 @@ pub fn create_signature_object_mlds65(
     Ok(serde_json::to_string(&obj)?)
 }
 
-/// 署名オブジェクト検証
-pub fn verify_signature_object(
-    public_key_b64: &str,
-    signature_obj: &str,
-    data: &[u8],
-    expected_key_type: &str,
-) -> bool {
-    let obj: Sign = match serde_json::from_str(signature_obj) {
-        Ok(v) => v,
-        Err(_) => return false,
+/// ML‑DSA‑65 事前ハッシュ署名オブジェクト作成
+pub fn create_signature_object_prehash_mlds65(
+    private_key_b64: &str,
+    digest: &[u8],
+    key_hash: &str,
+    key_type: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let signature = sign_prehash_mlds65(private_key_b64, digest)?;
+    let obj = Sign {
+        signature,
+        key_hash: key_hash.to_string(),
+        key_type: key_type.to_string(),
+        algorithm: Some(ALGORITHM_MLDSA65_PREHASH_SHA256.to_string()),
     };
-    if obj.key_type != expected_key_type {
-        return false;
-    }
-    match obj.algorithm.as_deref() {
-        Some("ML-DSA-87") => verify_with_mlds87(public_key_b64, data, &obj.signature),
-        Some("ML-DSA-65") | None  => verify_with_mlds65(public_key_b64, data, &obj.signature),
+    Ok(serde_json::to_string(&obj)?)
+}
+
+/// ML‑DSA‑87 事前ハッシュ署名オブジェクト作成
+#[cfg(feature = "master-key")]
+pub fn create_signature_object_prehash_mlds87(
+    private_key_b64: &str,
+    digest: &[u8],
+    key_hash: &str,
+    key_type: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let signature = sign_prehash_mlds87(private_key_b64, digest)?;
+    let obj = Sign {
+        signature,
+        key_hash: key_hash.to_string(),
+        key_type: key_type.to_string(),
+        algorithm: Some(ALGORITHM_MLDSA87_PREHASH_SHA256.to_string()),
+    };
+    Ok(serde_json::to_string(&obj)?)
+}
+
+/// Sign.keyType ごとに必須の署名アルゴリズム
+///
+/// is_valid_sign_* はすべてこの対応で algorithm を確認する。未知の keyType は None
+pub fn sign_algorithm_for_key_type(key_type: &str) -> Option<&'static str> {
+    key_type_algorithm(key_type).filter(|a| a.starts_with("ML-DSA-"))
+}
+
+/// Sign の keyType と algorithm の組が許されるか
+///
+/// sign_algorithm_for_key_type の対応に加え、identityKey のみハイブリッド署名 (Ed25519+ML-DSA-65) も許す
+pub fn is_sign_algorithm_allowed(key_type: &str, algorithm: Option<&str>) -> bool {
+    match (sign_algorithm_for_key_type(key_type), algorithm) {
+        (Some(expected), Some(a)) if a == expected => true,
+        (Some(_), Some(ALGORITHM_ED25519_MLDSA65)) => key_type == "identityKey",
         _ => false,
     }
 }
+
+/// Sign JSON の keyType が expected_key_type で、algorithm がその keyType に対応するか (is_valid_sign_* の共通部分)
+pub(crate) fn is_valid_sign_for(sign_json: &str, expected_key_type: &str) -> bool {
+    serde_json::from_str::<Sign>(sign_json)
+        .map(|s| s.key_type == expected_key_type && is_sign_algorithm_allowed(&s.key_type, s.algorithm.as_deref()))
+        .unwrap_or(false)
+}
+
+/// Sign オブジェクトから生の署名 (Base64) を取り出す
+///
+/// sign_with_mlds65 などの出力 (detached 形式) と相互に変換するためのもの
+pub fn extract_signature(sign_json: &str) -> Option<String> {
+    let obj: Sign = serde_json::from_str(sign_json).ok()?;
+    Some(obj.signature)
+}
+
+/// 生の署名 (Base64) を Sign オブジェクトに包む
+pub fn wrap_signature(signature_b64: &str, key_hash: &str, key_type: &str, algorithm: &str) -> String {
+    let obj = Sign {
+        signature: signature_b64.to_string(),
+        key_hash: key_hash.to_string(),
+        key_type: key_type.to_string(),
+        algorithm: Some(algorithm.to_string()),
+    };
+    serde_json::to_string(&obj).unwrap()
+}
+
+/// FIPS 204 の署名バイト長 (ML-DSA-65: λ/4 + l·32·(1+bitlen(γ1−1)) + ω + k)
+const MLDSA65_SIGNATURE_LEN: usize = 3309;
+const MLDSA87_SIGNATURE_LEN: usize = 4627;
+
+/// ML-DSA-65 署名の生バイト長
+pub fn signature_raw_len_mlds65() -> usize {
+    MLDSA65_SIGNATURE_LEN
+}
+
+/// ML-DSA-87 署名の生バイト長
+pub fn signature_raw_len_mlds87() -> usize {
+    MLDSA87_SIGNATURE_LEN
+}
+
+/// ML-DSA-65 署名 (Base64) を Signature::decode / to_bytes で再エンコードして正規化
+///
+/// 長さが違うもの、ヒント部が不正なものなどデコードできない署名は None。
+/// 他実装 (NIST リファレンス形式) の署名と相互運用できるかの確認に使う
+pub fn normalize_signature_mlds65(signature_b64: &str) -> Option<String> {
+    let bytes = decode_base64(signature_b64)?;
+    if bytes.len() != MLDSA65_SIGNATURE_LEN { return None; }
+    let arr = <EncodedSignature<MlDsa65>>::try_from(&bytes[..]).ok()?;
+    let sig = Signature::<MlDsa65>::decode(&arr)?;
+    Some(BASE64.encode(sig.to_bytes()))
+}
+
+/// ML-DSA-87 署名 (Base64) を正規化 (normalize_signature_mlds65 の ML-DSA-87 版)
+#[cfg(feature = "master-key")]
+pub fn normalize_signature_mlds87(signature_b64: &str) -> Option<String> {
+    let bytes = decode_base64(signature_b64)?;
+    if bytes.len() != MLDSA87_SIGNATURE_LEN { return None; }
+    let arr = <EncodedSignature<MlDsa87>>::try_from(&bytes[..]).ok()?;
+    let sig = Signature::<MlDsa87>::decode(&arr)?;
+    Some(BASE64.encode(sig.to_bytes()))
+}
+
+/// Ed25519 署名 (Base64 出力, 秘密鍵は 32 バイトのシード)
+pub fn sign_with_ed25519(private_key_b64: &str, data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    use ed25519_dalek::Signer as _;
+    let seed: [u8; 32] = decode_base64(private_key_b64).ok_or("invalid base64")?.try_into().map_err(|_| "Ed25519 private key must be 32 bytes")?;
+    let sk = ed25519_dalek::SigningKey::from_bytes(&seed);
+    Ok(BASE64.encode(sk.sign(data).to_bytes()))
+}
+
+/// ハイブリッド署名 (Base64(Ed25519 署名 64 バイト || ML-DSA-65 署名))
+pub fn sign_hybrid_ed25519_mlds65(
+    ed25519_private_b64: &str,
+    mlds65_private_b64: &str,
+    data: &[u8],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut sig = BASE64.decode(sign_with_ed25519(ed25519_private_b64, data)?)?;
+    sig.extend(BASE64.decode(sign_with_mlds65(mlds65_private_b64, data)?)?);
+    Ok(BASE64.encode(sig))
+}
+
+/// ハイブリッド署名オブジェクト作成
+pub fn create_signature_object_hybrid(
+    ed25519_private_b64: &str,
+    mlds65_private_b64: &str,
+    data: &[u8],
+    key_hash: &str,
+    key_type: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let signature = sign_hybrid_ed25519_mlds65(ed25519_private_b64, mlds65_private_b64, data)?;
+    let obj = Sign {
+        signature,
+        key_hash: key_hash.to_string(),
+        key_type: key_type.to_string(),
+        algorithm: Some(ALGORITHM_ED25519_MLDSA65.to_string()),
+    };
+    Ok(serde_json::to_string(&obj)?)
+}
@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use crate::verify::decode_base64;
 
 /// 基本的な鍵の種類
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -6,6 +8,9 @@ pub struct MasterKey {
     #[serde(rename = "keyType")]
     pub key_type: String,
     pub key: String,
+    /// 署名アルゴリズム。旧形式の鍵には無く、その場合は ML-DSA-87 とみなす
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -17,6 +22,9 @@ pub struct IdentityKey {
     pub timestamp: u64,
     #[serde(rename = "sessionUuid")]
     pub session_uuid: String,
+    /// ハイブリッド鍵 (algorithm が "Ed25519+ML-DSA-65") の Ed25519 鍵。key は ML-DSA-65 側
+    #[serde(rename = "classicalKey", default, skip_serializing_if = "Option::is_none")]
+    pub classical_key: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -45,6 +53,9 @@ pub struct RoomKey {
     pub timestamp: u64,
     #[serde(rename = "sessionUuid")]
     pub session_uuid: String,
+    /// ローテーション前の RoomKey のハッシュ
+    #[serde(rename = "previousKeyHash", default, skip_serializing_if = "Option::is_none")]
+    pub previous_key_hash: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -58,6 +69,16 @@ pub struct ShareKey {
     pub session_uuid: String,
 }
 
+/// 使い捨てのプリキー (アカウント鍵と同じ ML-KEM-768、1 メッセージにつき 1 つ)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountPrekey {
+    #[serde(rename = "keyType")]
+    pub key_type: String,
+    pub key: String,
+    pub algorithm: String,
+    pub timestamp: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ShareSignKey {
     #[serde(rename = "keyType")]
@@ -75,6 +96,9 @@ pub struct MigrateKey {
     pub key_type: String,
     pub key: String,
     pub timestamp: Option<u64>,
+    /// 有効期限 (ミリ秒)。旧形式の鍵には無く、その場合は期限なしとして扱う
+    #[serde(rename = "expiresAt", default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -83,6 +107,9 @@ pub struct MigrateSignKey {
     pub key_type: String,
     pub key: String,
     pub timestamp: Option<u64>,
+    /// 有効期限 (ミリ秒)。旧形式の鍵には無く、その場合は期限なしとして扱う
+    #[serde(rename = "expiresAt", default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -92,6 +119,60 @@ pub struct DeviceKey {
     pub key: String,
 }
 
+/// validate_key_set の結果
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct KeySetReport {
+    /// マスター鍵の署名が正しいエントリのインデックス
+    pub valid: Vec<usize>,
+    pub failed: Vec<KeySetFailure>,
+}
+
+impl KeySetReport {
+    pub fn is_all_valid(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeySetFailure {
+    pub index: usize,
+    #[serde(rename = "keyType")]
+    pub key_type: Option<String>,
+    /// malformedEntry / keyTypeMismatch / notMasterSigned / invalidSignature
+    pub reason: String,
+}
+
+/// generate_identity_key_full の結果 (保存・索引・表示に必要なものをまとめたもの)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratedIdentityKey {
+    #[serde(rename = "publicKey")]
+    pub public_key: String,
+    #[serde(rename = "privateKey")]
+    pub private_key: String,
+    pub sign: String,
+    /// 公開鍵の keyHash (compute_sign_key_hash と同じ定義)
+    #[serde(rename = "keyHash")]
+    pub key_hash: String,
+    /// 人が比較するためのフィンガープリント (key_fingerprint)
+    pub fingerprint: String,
+}
+
+/// describe_key_types の 1 エントリ (鍵種別ごとのアルゴリズム・鍵長・必須フィールド)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyTypeInfo {
+    /// "masterKey" のように Public / Private を除いた名前 (Sign / EncryptedData の keyType と同じ)
+    pub name: String,
+    pub algorithm: String,
+    /// Base64 デコード後のバイト長
+    #[serde(rename = "publicLen")]
+    pub public_len: usize,
+    #[serde(rename = "privateLen")]
+    pub private_len: usize,
+    /// 鍵 JSON に必須のフィールド
+    #[serde(rename = "requiredFields")]
+    pub required_fields: Vec<String>,
+}
+
 /// 署名情報
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Sign {
@@ -116,6 +197,103 @@ pub struct EncryptedData {
     pub algorithm: Option<String>,
     #[serde(rename = "cipherText")]
     pub cipher_text: Option<String>,
+    /// 鍵コミットメント (algorithm が "AES-GCM-commit" の場合のみ)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<String>,
+    /// cipherText を作った KEM のアルゴリズム (公開鍵暗号のみ)。旧形式には無く、その場合は ML-KEM-768 とみなす
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kem: Option<String>,
+    /// 暗号化前に平文を圧縮した方式 ("deflate")。RoomKey のみ。無ければ非圧縮
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+}
+
+impl EncryptedData {
+    /// 生バイト列から EncryptedData を構築 (各フィールドは Base64 で保持)
+    pub fn from_parts(
+        key_type: &str,
+        key_hash: &[u8],
+        encrypted: &[u8],
+        iv: &[u8],
+        cipher_text: Option<&[u8]>,
+        algorithm: Option<&str>,
+    ) -> Self {
+        EncryptedData {
+            key_type: key_type.to_string(),
+            key_hash: BASE64.encode(key_hash),
+            encrypted_data: BASE64.encode(encrypted),
+            iv: BASE64.encode(iv),
+            algorithm: algorithm.map(String::from),
+            cipher_text: cipher_text.map(|ct| BASE64.encode(ct)),
+            commitment: None,
+            kem: None,
+            compression: None,
+        }
+    }
+
+    pub fn key_hash_bytes(&self) -> Option<Vec<u8>> {
+        decode_base64(&self.key_hash)
+    }
+
+    pub fn encrypted_data_bytes(&self) -> Option<Vec<u8>> {
+        decode_base64(&self.encrypted_data)
+    }
+
+    pub fn iv_bytes(&self) -> Option<Vec<u8>> {
+        decode_base64(&self.iv)
+    }
+
+    /// cipherText を持たない (共通鍵暗号) 場合は Some(None)、デコード失敗時は None
+    pub fn cipher_text_bytes(&self) -> Option<Option<Vec<u8>>> {
+        match &self.cipher_text {
+            Some(ct) => decode_base64(ct).map(Some),
+            None => Some(None),
+        }
+    }
+
+    pub fn commitment_bytes(&self) -> Option<Option<Vec<u8>>> {
+        match &self.commitment {
+            Some(c) => decode_base64(c).map(Some),
+            None => Some(None),
+        }
+    }
+
+    /// 形式検証の共通部分
+    ///
+    /// 公開鍵暗号 (account/share/migrate) は cipherText 必須、共通鍵暗号 (room/device) は cipherText 禁止。
+    /// algorithm が "AES-GCM-commit" の場合は 32 バイトの commitment が必要。
+    /// kem は公開鍵暗号でのみ許し、既知のアルゴリズム (ML-KEM-768) でなければならない。
+    /// compression は RoomKey でのみ許し、"deflate" でなければならない
+    pub fn is_well_formed(&self, key_type: &str, with_cipher_text: bool) -> bool {
+        let algorithm_ok = match (self.algorithm.as_deref(), &self.commitment) {
+            (Some("AES-GCM"), None) => true,
+            (Some("AES-GCM-commit"), Some(c)) => {
+                // コミットメントは共通鍵暗号 (room/device) のみ
+                !with_cipher_text && decode_base64(c).is_some_and(|v| v.len() == 32)
+            }
+            _ => false,
+        };
+        let kem_ok = match self.kem.as_deref() {
+            None => true,
+            Some(kem) => with_cipher_text && kem == "ML-KEM-768",
+        };
+        let compression_ok = match self.compression.as_deref() {
+            None => true,
+            Some(c) => key_type == "roomKey" && c == "deflate",
+        };
+        self.key_type == key_type
+            && algorithm_ok
+            && kem_ok
+            && compression_ok
+            && self.key_hash_bytes().is_some_and(|v| v.len() == 32)
+            && self.iv_bytes().is_some_and(|v| v.len() == 12)
+            && self.encrypted_data_bytes().is_some()
+            && match self.cipher_text_bytes() {
+                Some(Some(_)) => with_cipher_text,
+                Some(None) => !with_cipher_text,
+                None => false,
+            }
+    }
 }
 
 pub type UserIdentifier = String;
@@ -226,6 +404,7 @@ pub struct NotEncryptMessage {
     pub encrypted: bool,
     pub value: NotEncryptMessageValue,
     pub channel: String,
+    /// このメッセージが置き換える (編集・転送元の) メッセージの ID。空文字列や自分自身の ID は不可
     pub original: Option<String>,
     pub timestamp: u64,
     #[serde(rename = "isLarge")]
@@ -237,12 +416,17 @@ pub struct NotEncryptMessage {
 pub struct EncryptedMessage {
     pub encrypted: bool,
     pub value: String,
+    /// encryptedMetadata がある場合は空文字列 (本当の channel は encryptedMetadata の中)
     pub channel: String,
+    /// このメッセージが置き換える (編集・転送元の) メッセージの ID。空文字列や自分自身の ID は不可
     pub original: Option<String>,
     pub timestamp: u64,
     #[serde(rename = "isLarge")]
     pub is_large: bool,
     pub roomid: String,
+    /// channel (と original) を RoomKey で暗号化した EncryptedData JSON (encrypt_message_private_metadata)
+    #[serde(rename = "encryptedMetadata", default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_metadata: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
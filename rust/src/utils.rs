@@ -1,5 +1,12 @@
+use std::collections::{BTreeMap, HashMap};
 use sha2::{Sha256, Digest};
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use base64::{engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD}, Engine as _};
+use crate::r#type::{
+    AccountKey, AccountPrekey, DeviceKey, EncryptedData, IdentityKey, MasterKey, MigrateKey, MigrateSignKey, RoomKey, ServerKey,
+    ShareKey, ShareSignKey,
+};
+use crate::key_pair::AsymmetricKeyPair;
+pub use crate::verify::decode_base64;
 
 /// 入力文字列の SHA-256 ハッシュを Base64 文字列で返す
 pub fn key_hash(input: &str) -> String {
@@ -8,3 +15,295 @@ pub fn key_hash(input: &str) -> String {
     let result = hasher.finalize();
     BASE64.encode(result)
 }
+
+/// key_hash_with で使うハッシュアルゴリズム
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+/// 指定したアルゴリズムで入力文字列をハッシュし Base64 文字列で返す
+///
+/// 互換性のルール: Sign.keyHash と EncryptedData.keyHash は他の端末やサーバーが検証するため、
+/// アルゴリズムを記録するフィールドを持たず常に SHA-256 (key_material_hash) に固定する。
+/// BLAKE3 は同期中の重複検出やローカルキャッシュの索引など、
+/// ハッシュを計算した本人だけが比較する用途に限って使うこと
+pub fn key_hash_with(input: &str, algo: HashAlgo) -> String {
+    match algo {
+        HashAlgo::Sha256 => key_hash(input),
+        HashAlgo::Blake3 => BASE64.encode(blake3::hash(input.as_bytes()).as_bytes()),
+    }
+}
+
+/// バイト列の SHA-256 ハッシュを Base64 文字列で返す
+pub fn key_hash_bytes(input: &[u8]) -> String {
+    BASE64.encode(Sha256::digest(input))
+}
+
+/// Base64 の出力形式
+///
+/// 既定は標準 Base64 (+ / と = パディング)。UrlSafe は - _ を使いパディングなしで、
+/// URL のパスやファイル名にそのまま埋め込める。入力側はどちらの形式も受け付ける (decode_base64)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Base64Variant {
+    #[default]
+    Standard,
+    UrlSafe,
+}
+
+impl Base64Variant {
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Base64Variant::Standard => BASE64.encode(bytes),
+            Base64Variant::UrlSafe => URL_SAFE_NO_PAD.encode(bytes),
+        }
+    }
+}
+
+/// Base64 の鍵 (標準 / URL-safe どちらでも可) をデコードした生バイトの SHA-256 (Base64)
+///
+/// key_hash は JSON 文字列を対象にするため、空白やフィールド順が違うだけで値が変わる。
+/// こちらは鍵の値だけに依存するので、シリアライザをまたいだ識別子に使える。デコードできない場合は None
+pub fn key_hash_raw(b64_key: &str) -> Option<String> {
+    decode_base64(b64_key).map(|b| key_hash_bytes(&b))
+}
+
+/// key_hash を指定した Base64 形式で返す
+pub fn key_hash_with_variant(input: &str, variant: Base64Variant) -> String {
+    variant.encode(&Sha256::digest(input.as_bytes()))
+}
+
+/// key_hash_bytes を指定した Base64 形式で返す
+pub fn key_hash_bytes_with_variant(input: &[u8], variant: Base64Variant) -> String {
+    variant.encode(&Sha256::digest(input))
+}
+
+/// Base64 文字列 (標準 / URL-safe どちらでも可) を指定した形式に書き直す
+pub fn reencode_base64(b64: &str, variant: Base64Variant) -> Option<String> {
+    decode_base64(b64).map(|b| variant.encode(&b))
+}
+
+/// 鍵 JSON のうち Base64 で鍵の値を持つフィールド
+const KEY_BASE64_FIELDS: [&str; 2] = ["key", "classicalKey"];
+
+/// 鍵 JSON の Base64 フィールドを標準 Base64 (パディングあり) の正規形に揃える
+///
+/// 入力側は URL-safe やパディングなしも受け付ける (decode_base64) ため、同じ鍵バイト列に
+/// 複数の文字列表現がありうる。key_hash は JSON 文字列そのものを対象にするので、
+/// 鍵を保存・索引する前にこれを通して表現を 1 つにする (取り込み時に正規化する方針。
+/// 生成関数の出力は既に正規形で、import_key_bundle / from_armored も正規形を返す)。
+/// 標準 Base64 のデコーダは末尾の余りビットが 0 でない非正規な符号化を拒否する。
+/// serde_json::Value として読み、keyType に対応する構造体を経由して書き直すため、
+/// 文字列のエスケープ (\/ など)・空白・フィールド順も生成時と同じ形になる (未知のフィールドは落ちる)。
+/// JSON でない・未知の keyType・Base64 として読めない場合は None
+pub fn canonicalize_key_json(key_json: &str) -> Option<String> {
+    let mut v: serde_json::Value = serde_json::from_str(key_json).ok()?;
+    for field in KEY_BASE64_FIELDS {
+        let Some(value) = v.get_mut(field) else { continue };
+        let canonical = BASE64.encode(decode_base64(value.as_str()?)?);
+        *value = serde_json::Value::String(canonical);
+    }
+    canonical_key_json(v)
+}
+
+/// keyType に対応する構造体を経由して、ライブラリが生成するのと同じフィールド順の JSON に戻す
+///
+/// key_hash は JSON 文字列そのもののハッシュなので、順序が変わると別の鍵として扱われてしまう
+pub(crate) fn canonical_key_json(v: serde_json::Value) -> Option<String> {
+    match v.get("keyType")?.as_str()? {
+        "masterKeyPublic" | "masterKeyPrivate" => serde_json::to_string(&serde_json::from_value::<MasterKey>(v).ok()?).ok(),
+        "identityKeyPublic" | "identityKeyPrivate" => serde_json::to_string(&serde_json::from_value::<IdentityKey>(v).ok()?).ok(),
+        "accountKeyPublic" | "accountKeyPrivate" => serde_json::to_string(&serde_json::from_value::<AccountKey>(v).ok()?).ok(),
+        "accountPrekeyPublic" | "accountPrekeyPrivate" => serde_json::to_string(&serde_json::from_value::<AccountPrekey>(v).ok()?).ok(),
+        "serverKeyPublic" | "serverKeyPrivate" => serde_json::to_string(&serde_json::from_value::<ServerKey>(v).ok()?).ok(),
+        "roomKey" => serde_json::to_string(&serde_json::from_value::<RoomKey>(v).ok()?).ok(),
+        "shareKeyPublic" | "shareKeyPrivate" => serde_json::to_string(&serde_json::from_value::<ShareKey>(v).ok()?).ok(),
+        "shareSignKeyPublic" | "shareSignKeyPrivate" => serde_json::to_string(&serde_json::from_value::<ShareSignKey>(v).ok()?).ok(),
+        "migrateKeyPublic" | "migrateKeyPrivate" => serde_json::to_string(&serde_json::from_value::<MigrateKey>(v).ok()?).ok(),
+        "migrateSignKeyPublic" | "migrateSignKeyPrivate" => serde_json::to_string(&serde_json::from_value::<MigrateSignKey>(v).ok()?).ok(),
+        "deviceKey" => serde_json::to_string(&serde_json::from_value::<DeviceKey>(v).ok()?).ok(),
+        _ => None,
+    }
+}
+
+/// 鍵 JSON の Base64 フィールドがすべて正規形か
+pub fn is_canonical_key_json(key_json: &str) -> bool {
+    canonicalize_key_json(key_json).is_some_and(|c| c == key_json)
+}
+
+/// Sign.keyHash / EncryptedData.keyHash に用いる鍵のハッシュ
+///
+/// keyHash は常に「鍵 JSON の key フィールドを Base64 デコードした生バイト」の SHA-256 (Base64)
+/// (key_hash_raw) とする。署名・暗号化で keyHash を埋め込む箇所はすべてこの定義に従う
+pub fn key_material_hash(key_json: &str) -> Option<String> {
+    let v: serde_json::Value = serde_json::from_str(key_json).ok()?;
+    key_hash_raw(v.get("key")?.as_str()?)
+}
+
+/// Sign.keyHash に用いる署名鍵のハッシュ (key_material_hash と同じ定義)
+pub fn sign_key_hash(public_key_json: &str) -> Option<String> {
+    key_material_hash(public_key_json)
+}
+
+/// 埋め込まれた keyHash (Base64 デコード済み) が key_json のものか (定数時間比較)
+///
+/// 生バイトのハッシュ (key_material_hash) に加え、JSON 文字列のハッシュ (key_hash) で
+/// keyHash を付けていた旧形式の EncryptedData も受け付ける
+pub(crate) fn key_hash_matches(key_json: &str, stored: &[u8]) -> bool {
+    let raw = serde_json::from_str::<serde_json::Value>(key_json)
+        .ok()
+        .and_then(|v| decode_base64(v.get("key")?.as_str()?))
+        .is_some_and(|key| ct_eq(&Sha256::digest(key), stored));
+    let legacy = ct_eq(&Sha256::digest(key_json.as_bytes()), stored);
+    raw | legacy
+}
+
+/// 公開鍵 JSON から Sign.keyHash を計算する (定義は sign_key_hash と同じ)
+///
+/// 署名関数の *_with_public 版は内部でこれを使うため、呼び出し側が keyHash を渡す必要はない。
+/// key が取り出せない場合は空文字列
+pub fn compute_sign_key_hash(public_key_json: &str) -> String {
+    sign_key_hash(public_key_json).unwrap_or_default()
+}
+
+/// keyType が expected_key_type の公開鍵から keyHash を計算する (署名関数の内部用)
+pub(crate) fn signer_key_hash(public_key_json: &str, expected_key_type: &str) -> Option<String> {
+    let v: serde_json::Value = serde_json::from_str(public_key_json).ok()?;
+    if v.get("keyType")?.as_str()? != expected_key_type { return None; }
+    sign_key_hash(public_key_json)
+}
+
+/// 鍵 JSON から人が比較しやすい短いフィンガープリントを生成
+///
+/// key フィールドの生バイトの SHA-256 先頭 16 バイトを 4 桁ずつ 8 グループの hex で表す
+/// (例: "1a2b 3c4d ...")。key が取り出せない場合は入力文字列全体を対象にする
+pub fn key_fingerprint(key_json: &str) -> String {
+    let key_bytes = serde_json::from_str::<serde_json::Value>(key_json)
+        .ok()
+        .and_then(|v| v.get("key").and_then(|k| k.as_str()).map(String::from))
+        .and_then(|k| decode_base64(&k))
+        .unwrap_or_else(|| key_json.as_bytes().to_vec());
+    let digest = Sha256::digest(&key_bytes);
+    digest[..16]
+        .chunks(2)
+        .map(hex::encode)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 鍵 JSON の検証結果を返す。不合格なら keyType だけを debug ログに出す (logging feature)
+pub(crate) fn logged_validation(key_type: &str, ok: bool) -> bool {
+    if !ok {
+        log_debug!("{key_type} validation failed");
+    }
+    ok
+}
+
+/// 定数時間でのバイト列比較
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// EncryptedData の keyHash が与えられた鍵のものか照合 (定数時間比較)
+///
+/// key_json は暗号化に使った鍵 JSON (RoomKey / DeviceKey / 公開鍵)。
+/// account/share/migrate の秘密鍵を渡した場合は埋め込まれた公開鍵から公開鍵 JSON を復元して比較する
+pub fn encrypted_data_key_matches(encrypted_json: &str, key_json: &str) -> bool {
+    let ed: EncryptedData = match serde_json::from_str(encrypted_json) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let key_type = serde_json::from_str::<serde_json::Value>(key_json)
+        .ok()
+        .and_then(|v| v.get("keyType").and_then(|t| t.as_str()).map(String::from));
+    let hashed = match key_type.as_deref() {
+        Some("accountKeyPrivate") => AccountKey::public_json_from_private(key_json),
+        Some("shareKeyPrivate") => ShareKey::public_json_from_private(key_json),
+        Some("migrateKeyPrivate") => MigrateKey::public_json_from_private(key_json),
+        Some("accountPrekeyPrivate") => AccountPrekey::public_json_from_private(key_json),
+        _ => Some(key_json.to_string()),
+    };
+    match (hashed, ed.key_hash_bytes()) {
+        (Some(hashed), Some(stored)) => key_hash_matches(&hashed, &stored),
+        _ => false,
+    }
+}
+
+/// EncryptedData の集合から IV の再利用を検出し、衝突しているインデックスの組 (i < j) を返す
+///
+/// 同じ鍵で IV が重複すると AES-GCM の機密性・完全性が失われる。
+/// 異なる鍵同士の IV 一致は問題にならないため keyHash も一致する場合のみ衝突とみなす。
+/// keyHash と IV はデコードしたバイト列で比較する (標準 / URL-safe の表記違いも同じとみなす)。
+/// パースまたはデコードできない要素は無視する
+pub fn find_iv_collisions(encrypted_jsons: &[&str]) -> Vec<(usize, usize)> {
+    let mut seen: HashMap<(Vec<u8>, Vec<u8>), Vec<usize>> = HashMap::new();
+    let mut collisions = Vec::new();
+    for (j, json) in encrypted_jsons.iter().enumerate() {
+        let Some(id) = serde_json::from_str::<EncryptedData>(json)
+            .ok()
+            .and_then(|ed| Some((ed.key_hash_bytes()?, ed.iv_bytes()?)))
+        else {
+            continue;
+        };
+        let earlier = seen.entry(id).or_default();
+        collisions.extend(earlier.iter().map(|&i| (i, j)));
+        earlier.push(j);
+    }
+    collisions
+}
+
+/// EncryptedData の集合を keyHash ごとにまとめ、各グループのインデックスを返す
+///
+/// 受信箱ごとに配られた同じ鍵によるラップをストレージ側でまとめるためのもの。
+/// keyHash はデコードしたバイト列でまとめ、キーは標準 Base64 で表す (URL-safe の表記違いも同じグループ)。
+/// 旧形式 (JSON 文字列のハッシュ, key_hash) と生バイトのハッシュは鍵そのものが無いと対応が分からないため、
+/// 同じ鍵でも別のグループになる。パースまたはデコードできない要素は無視する
+pub fn group_by_key_hash(encrypted_jsons: &[&str]) -> BTreeMap<String, Vec<usize>> {
+    let mut groups: BTreeMap<Vec<u8>, Vec<usize>> = BTreeMap::new();
+    for (i, json) in encrypted_jsons.iter().enumerate() {
+        if let Some(hash) = serde_json::from_str::<EncryptedData>(json).ok().and_then(|ed| ed.key_hash_bytes()) {
+            groups.entry(hash).or_default().push(i);
+        }
+    }
+    groups.into_iter().map(|(hash, indices)| (BASE64.encode(hash), indices)).collect()
+}
+
+/// JSON を正規形に変換 (オブジェクトのキーを辞書順 (UTF-8 バイト順) に並べ、不要な空白を除く)
+///
+/// 署名対象の JSON はこの形で署名・検証する。途中で再整形されても同じ正規形に戻るが、
+/// 送信側は正規形そのものを送ること (key_hash などは文字列そのものを対象にするため)
+pub fn canonicalize_json(s: &str) -> Option<String> {
+    let v: serde_json::Value = serde_json::from_str(s).ok()?;
+    let mut out = String::with_capacity(s.len());
+    write_canonical_json(&v, &mut out)?;
+    Some(out)
+}
+
+fn write_canonical_json(v: &serde_json::Value, out: &mut String) -> Option<()> {
+    match v {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, k) in keys.into_iter().enumerate() {
+                if i > 0 { out.push(','); }
+                out.push_str(&serde_json::to_string(k).ok()?);
+                out.push(':');
+                write_canonical_json(&map[k], out)?;
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 { out.push(','); }
+                write_canonical_json(item, out)?;
+            }
+            out.push(']');
+        }
+        _ => out.push_str(&serde_json::to_string(v).ok()?),
+    }
+    Some(())
+}
@@ -0,0 +1,302 @@
+//! 署名検証のみのサブセット (no_std + alloc で利用可能)
+//!
+//! `default-features = false` でビルドした場合、このモジュールだけが公開される。
+//! 組み込みゲートウェイなど std を使えない環境でサーバー鍵の署名を検証するためのもの。
+//!
+//! no_std で使える関数:
+//! - `verify_with_mlds65` / `verify_with_mlds87`
+//! - `verify_with_ed25519` / `verify_hybrid_ed25519_mlds65`
+//! - `prehash_sha256` / `verify_prehash_mlds65` / `verify_prehash_mlds87`
+//! - `verify_signature_object` / `verify_signature_object_with_algorithm`
+//! - `verify_any`
+//! - `verify_data_server_key`
+//! - `decode_base64`
+//!
+//! 鍵生成・署名・暗号化 (乱数・スレッド・時刻を使うもの) は `std` feature が必要。
+//! ML-DSA-87 の検証は `master-key` feature が必要 (無効時は ML-DSA-87 の署名を常に false とする)
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use base64::{engine::general_purpose::{STANDARD as BASE64, URL_SAFE, URL_SAFE_NO_PAD}, Engine as _};
+use ml_dsa::{EncodedSignature, EncodedVerifyingKey, MlDsa65, Signature, VerifyingKey};
+#[cfg(feature = "master-key")]
+use ml_dsa::MlDsa87;
+use ml_dsa::signature::Verifier;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// 事前ハッシュ署名のアルゴリズム名 (Sign.algorithm に記録)
+pub const ALGORITHM_MLDSA65_PREHASH_SHA256: &str = "ML-DSA-65+SHA-256";
+pub const ALGORITHM_MLDSA87_PREHASH_SHA256: &str = "ML-DSA-87+SHA-256";
+
+/// Ed25519 + ML-DSA-65 ハイブリッド署名のアルゴリズム名
+pub const ALGORITHM_ED25519_MLDSA65: &str = "Ed25519+ML-DSA-65";
+
+const ED25519_SIGNATURE_LEN: usize = 64;
+
+/// 標準 Base64 と URL-safe Base64 (パディング有無どちらも) を受け付けるデコード
+///
+/// 出力は常に標準 Base64 のままで、入力側だけ両方を許容するために使う
+pub fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    BASE64.decode(s)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(s))
+        .or_else(|_| URL_SAFE.decode(s))
+        .ok()
+}
+
+/// Sign の検証に必要なフィールドだけを読む (r#type は std 前提のため)
+#[derive(Deserialize)]
+struct SignFields {
+    signature: String,
+    #[serde(rename = "keyType")]
+    key_type: String,
+    algorithm: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ServerKeyFields {
+    #[serde(rename = "keyType")]
+    key_type: String,
+    key: String,
+    #[allow(dead_code)]
+    timestamp: u64,
+}
+
+/// ML‑DSA‑87 検証
+#[cfg(feature = "master-key")]
+pub fn verify_with_mlds87(public_key_b64: &str, data: &[u8], signature_b64: &str) -> bool {
+    let pk_bytes = match decode_base64(public_key_b64) {
+        Some(b) => b,
+        None => return false,
+    };
+    let pk_arr = match <EncodedVerifyingKey<MlDsa87>>::try_from(&pk_bytes[..]) {
+        Ok(a) => a,
+        Err(_) => return false,
+    };
+    let pk = VerifyingKey::<MlDsa87>::decode(&pk_arr);
+    let sig_bytes = match decode_base64(signature_b64) {
+        Some(b) => b,
+        None => return false,
+    };
+    let sig_arr = match <EncodedSignature<MlDsa87>>::try_from(&sig_bytes[..]) {
+        Ok(a) => a,
+        Err(_) => return false,
+    };
+    let sig_opt = Signature::<MlDsa87>::decode(&sig_arr);
+    let sig = match sig_opt {
+        Some(s) => s,
+        None => return false,
+    };
+    pk.verify(data, &sig).is_ok()
+}
+
+/// ML‑DSA‑65 検証
+pub fn verify_with_mlds65(public_key_b64: &str, data: &[u8], signature_b64: &str) -> bool {
+    let pk_bytes = match decode_base64(public_key_b64) {
+        Some(b) => b,
+        None => return false,
+    };
+    let pk_arr = match <EncodedVerifyingKey<MlDsa65>>::try_from(&pk_bytes[..]) {
+        Ok(a) => a,
+        Err(_) => return false,
+    };
+    let pk = VerifyingKey::<MlDsa65>::decode(&pk_arr);
+    let sig_bytes = match decode_base64(signature_b64) {
+        Some(b) => b,
+        None => return false,
+    };
+    let sig_arr = match <EncodedSignature<MlDsa65>>::try_from(&sig_bytes[..]) {
+        Ok(a) => a,
+        Err(_) => return false,
+    };
+    let sig_opt = Signature::<MlDsa65>::decode(&sig_arr);
+    let sig = match sig_opt {
+        Some(s) => s,
+        None => return false,
+    };
+    pk.verify(data, &sig).is_ok()
+}
+
+/// 事前ハッシュ署名で署名対象とするバイト列 (ドメイン分離用の接頭辞 + ダイジェスト)
+///
+/// ml_dsa 0.0.4 は HashML-DSA / external-mu を公開していないため、
+/// 接頭辞付きのダイジェストを通常の ML-DSA で署名する
+pub(crate) fn prehash_message(digest: &[u8]) -> Option<Vec<u8>> {
+    if digest.len() != 32 { return None; }
+    let mut m = b"takos-prehash:SHA-256:".to_vec();
+    m.extend_from_slice(digest);
+    Some(m)
+}
+
+/// SHA-256 ダイジェスト計算 (事前ハッシュ署名用)
+pub fn prehash_sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// ML‑DSA‑65 事前ハッシュ検証
+pub fn verify_prehash_mlds65(public_key_b64: &str, digest: &[u8], signature_b64: &str) -> bool {
+    match prehash_message(digest) {
+        Some(m) => verify_with_mlds65(public_key_b64, &m, signature_b64),
+        None => false,
+    }
+}
+
+/// ML‑DSA‑87 事前ハッシュ検証
+#[cfg(feature = "master-key")]
+pub fn verify_prehash_mlds87(public_key_b64: &str, digest: &[u8], signature_b64: &str) -> bool {
+    match prehash_message(digest) {
+        Some(m) => verify_with_mlds87(public_key_b64, &m, signature_b64),
+        None => false,
+    }
+}
+
+/// Ed25519 検証 (verify_strict: 非正規な署名・小位数の公開鍵を拒否)
+pub fn verify_with_ed25519(public_key_b64: &str, data: &[u8], signature_b64: &str) -> bool {
+    let pk: [u8; 32] = match decode_base64(public_key_b64).and_then(|b| b.try_into().ok()) {
+        Some(b) => b,
+        None => return false,
+    };
+    let sig: [u8; ED25519_SIGNATURE_LEN] = match decode_base64(signature_b64).and_then(|b| b.try_into().ok()) {
+        Some(b) => b,
+        None => return false,
+    };
+    match ed25519_dalek::VerifyingKey::from_bytes(&pk) {
+        Ok(vk) => vk.verify_strict(data, &ed25519_dalek::Signature::from_bytes(&sig)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// ハイブリッド署名の検証。どちらか一方でも失敗すれば false
+pub fn verify_hybrid_ed25519_mlds65(
+    ed25519_public_b64: &str,
+    mlds65_public_b64: &str,
+    data: &[u8],
+    signature_b64: &str,
+) -> bool {
+    let sig = match decode_base64(signature_b64) {
+        Some(b) if b.len() > ED25519_SIGNATURE_LEN => b,
+        _ => return false,
+    };
+    let (classical, pq) = sig.split_at(ED25519_SIGNATURE_LEN);
+    let classical_ok = verify_with_ed25519(ed25519_public_b64, data, &BASE64.encode(classical));
+    let pq_ok = verify_with_mlds65(mlds65_public_b64, data, &BASE64.encode(pq));
+    classical_ok && pq_ok
+}
+
+/// 署名オブジェクト検証
+pub fn verify_signature_object(
+    public_key_b64: &str,
+    signature_obj: &str,
+    data: &[u8],
+    expected_key_type: &str,
+) -> bool {
+    verify_signature_object_inner(public_key_b64, signature_obj, data, expected_key_type, None, true)
+}
+
+/// 署名オブジェクト検証 (鍵種別ごとにアルゴリズムを固定)
+///
+/// Sign.algorithm を無視し、鍵の種類から決まる algorithm で検証する
+pub fn verify_signature_object_with_algorithm(
+    public_key_b64: &str,
+    signature_obj: &str,
+    data: &[u8],
+    expected_key_type: &str,
+    algorithm: &str,
+) -> bool {
+    verify_signature_object_inner(public_key_b64, signature_obj, data, expected_key_type, Some(algorithm), true)
+}
+
+/// ログを出さない verify_signature_object_with_algorithm
+///
+/// 同じ署名を複数の候補で試し、失敗の記録は呼び出し側で一度だけ行う場合に使う
+#[cfg(feature = "std")]
+pub(crate) fn verify_signature_object_with_algorithm_quiet(
+    public_key_b64: &str,
+    signature_obj: &str,
+    data: &[u8],
+    expected_key_type: &str,
+    algorithm: &str,
+) -> bool {
+    verify_signature_object_inner(public_key_b64, signature_obj, data, expected_key_type, Some(algorithm), false)
+}
+
+fn verify_signature_object_inner(
+    public_key_b64: &str,
+    signature_obj: &str,
+    data: &[u8],
+    expected_key_type: &str,
+    algorithm: Option<&str>,
+    log: bool,
+) -> bool {
+    let obj: SignFields = match serde_json::from_str(signature_obj) {
+        Ok(v) => v,
+        Err(_) => {
+            if log {
+                log_debug!("malformed Sign JSON (expected keyType {expected_key_type})");
+            }
+            return false;
+        }
+    };
+    if obj.key_type != expected_key_type {
+        if log {
+            log_debug!("Sign keyType {:?} does not match expected {expected_key_type}", obj.key_type);
+        }
+        return false;
+    }
+    let algorithm = algorithm.or(obj.algorithm.as_deref());
+    let ok = match algorithm {
+        #[cfg(feature = "master-key")]
+        Some("ML-DSA-87") => verify_with_mlds87(public_key_b64, data, &obj.signature),
+        Some("ML-DSA-65") | None  => verify_with_mlds65(public_key_b64, data, &obj.signature),
+        // 事前ハッシュ署名は data を SHA-256 してから検証
+        #[cfg(feature = "master-key")]
+        Some(ALGORITHM_MLDSA87_PREHASH_SHA256) => verify_prehash_mlds87(public_key_b64, &prehash_sha256(data), &obj.signature),
+        Some(ALGORITHM_MLDSA65_PREHASH_SHA256) => verify_prehash_mlds65(public_key_b64, &prehash_sha256(data), &obj.signature),
+        _ => false,
+    };
+    if !ok && log {
+        log_warn!("signature verification failed (keyType {expected_key_type}, algorithm {algorithm:?})");
+    }
+    ok
+}
+
+/// Sign.algorithm に従って検証器を選ぶ署名検証
+///
+/// verify_signature_object が期待する鍵種別で検証するのに対し、こちらは鍵種別を問わず
+/// 署名自身が記録したアルゴリズムで検証する。algorithm が無い・未知の場合は false。
+/// 新しいアルゴリズムはここに分岐を追加する (ハイブリッド署名は公開鍵が 2 つ必要なため対象外)
+pub fn verify_any(public_key_b64: &str, sign_json: &str, data: &[u8]) -> bool {
+    let obj: SignFields = match serde_json::from_str(sign_json) {
+        Ok(v) => v,
+        Err(_) => {
+            log_debug!("malformed Sign JSON");
+            return false;
+        }
+    };
+    let ok = match obj.algorithm.as_deref() {
+        Some("ML-DSA-65") => verify_with_mlds65(public_key_b64, data, &obj.signature),
+        #[cfg(feature = "master-key")]
+        Some("ML-DSA-87") => verify_with_mlds87(public_key_b64, data, &obj.signature),
+        Some(ALGORITHM_MLDSA65_PREHASH_SHA256) => verify_prehash_mlds65(public_key_b64, &prehash_sha256(data), &obj.signature),
+        #[cfg(feature = "master-key")]
+        Some(ALGORITHM_MLDSA87_PREHASH_SHA256) => verify_prehash_mlds87(public_key_b64, &prehash_sha256(data), &obj.signature),
+        _ => false,
+    };
+    if !ok {
+        log_warn!("signature verification failed (keyType {}, algorithm {:?})", obj.key_type, obj.algorithm);
+    }
+    ok
+}
+
+/// サーバー鍵による署名の検証
+pub fn verify_data_server_key(pub_json: &str, sign_json: &str, data: &str) -> bool {
+    let pk = match serde_json::from_str::<ServerKeyFields>(pub_json) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    if pk.key_type != "serverKeyPublic" {
+        log_debug!("verify_data_server_key: keyType {:?} is not serverKeyPublic", pk.key_type);
+        return false;
+    }
+    verify_signature_object_with_algorithm(&pk.key, sign_json, data.as_bytes(), "serverKey", "ML-DSA-65")
+}
@@ -0,0 +1,74 @@
+//! logging feature の出力を log クレートの logger で確かめる
+//!
+//! cargo test --features logging で実行する
+#![cfg(all(feature = "logging", feature = "master-key"))]
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+use takos_encrypt_ink_rs::{
+    create_text_content, decrypt_message, encrypt_message, generate_identity_key, generate_master_key,
+    generate_room_key, verify_message_only,
+};
+
+const SESSION_UUID: &str = "018fdb31-0798-78a2-b4c9-e145d5b5b88e";
+
+/// 出力された warn 以上のレコードを溜めるだけの logger
+struct CaptureLogger {
+    warnings: Mutex<Vec<String>>,
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.warnings.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger { warnings: Mutex::new(Vec::new()) };
+
+fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *LOGGER.warnings.lock().unwrap())
+}
+
+// logger はプロセス全体で一つなので、ケースはすべて一つのテストにまとめる
+#[test]
+fn signature_failure_is_logged_once() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Warn);
+
+    let (master_pub, master_priv) = generate_master_key();
+    let (identity_pub, identity_priv, _) = generate_identity_key(SESSION_UUID, &master_pub, &master_priv).unwrap();
+    let (other_pub, _, _) = generate_identity_key(SESSION_UUID, &master_pub, &master_priv).unwrap();
+    let room_key = generate_room_key(SESSION_UUID).unwrap();
+
+    let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+    let content = create_text_content("hello", None, None, None, None).unwrap();
+    let metadata = serde_json::json!({ "channel": "general", "timestamp": timestamp, "isLarge": false }).to_string();
+    let sent: serde_json::Value = serde_json::from_str(
+        &encrypt_message(&content, &metadata, &room_key, &identity_priv, &identity_pub, SESSION_UUID, None).unwrap(),
+    )
+    .unwrap();
+    let message = sent["message"].as_str().unwrap();
+    let sign = sent["sign"].as_str().unwrap();
+    // 正規形と異なる表記にして、正規形と受信文字列の両方で検証させる
+    let reformatted = serde_json::to_string_pretty(&serde_json::from_str::<serde_json::Value>(message).unwrap()).unwrap();
+    take_warnings();
+
+    assert!(decrypt_message(&reformatted, sign, timestamp, &room_key, &identity_pub, SESSION_UUID, None).is_some());
+    assert!(take_warnings().is_empty(), "a successful decrypt logs no warnings");
+
+    assert!(decrypt_message(&reformatted, sign, timestamp, &room_key, &other_pub, SESSION_UUID, None).is_none());
+    let warnings = take_warnings();
+    assert_eq!(warnings.len(), 1, "one warning per rejected message: {warnings:?}");
+    assert!(warnings[0].contains("signature verification failed"));
+
+    assert!(!verify_message_only(&reformatted, sign, &other_pub));
+    assert_eq!(take_warnings().len(), 1);
+}
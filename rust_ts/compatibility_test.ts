@@ -29,7 +29,6 @@ import {
   decrypt_message as wasmDecryptMessage,
   generate_master_key as wasmGenerateMasterKey,
   generate_identity_key as wasmGenerateIdentityKey,
-  key_hash as wasmKeyHash,
 } from "takos_encrypt_ink_wasm";
 
 const TEST_DATA = "compatibility-test";
@@ -95,11 +94,10 @@ Deno.test("Message: wasm encrypt -> ts decrypt", async () => {
   const ikW = wasmGenerateIdentityKey(SESSION, mp, ms)!;
   const rkW = wasmGenerateRoomKey(SESSION)!;
   const txtW = wasmCreateTextContent("hello");
-  const hashW = wasmKeyHash(ikW.publicKey);
   const msgWjson = wasmEncryptMessage(
     txtW!,
     JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false }),
-    rkW, ikW.privateKey, hashW, SESSION,
+    rkW, ikW.privateKey, ikW.publicKey, SESSION,
   )!;
   const out = await decryptMessage(
     JSON.parse(msgWjson),
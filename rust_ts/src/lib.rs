@@ -1,8 +1,72 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsValue;
+use wasm_bindgen::JsCast;
 use takos_encrypt_ink_rs as core;
 use console_error_panic_hook;
 use serde_json::json;
+use std::convert::TryInto;
+
+// ---- TypeScript 型定義 ----
+// 戻り値は従来通りプレーンな JS オブジェクト/配列のまま、.d.ts にだけ形を載せる
+#[wasm_bindgen(typescript_custom_section)]
+const TS_KEY_TYPES: &'static str = r#"
+export interface KeyTriple {
+  publicKey: string;
+  privateKey: string;
+  sign: string;
+}
+export interface KeyPair {
+  publicKey: string;
+  privateKey: string;
+}
+export type KeyTuple = [publicKey: string, privateKey: string];
+"#;
+
+// wasm_bindgen の extern 型は展開先で core::ops を参照するため、
+// takos_encrypt_ink_rs を core と呼んでいるクレートルートではなく別モジュールで宣言する
+mod ts_types {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(typescript_type = "KeyTriple")]
+        pub type KeyTriple;
+        #[wasm_bindgen(typescript_type = "KeyTriple | null")]
+        pub type OptionalKeyTriple;
+        #[wasm_bindgen(typescript_type = "KeyTriple[] | null")]
+        pub type OptionalKeyTripleArray;
+        #[wasm_bindgen(typescript_type = "KeyPair")]
+        pub type KeyPair;
+        #[wasm_bindgen(typescript_type = "KeyTuple")]
+        pub type KeyTuple;
+    }
+}
+pub use ts_types::*;
+
+// serde_json::Value のマップは Map ではなくプレーンオブジェクトとして、
+// u64 (timestamp) は bigint ではなく number として渡す
+fn to_value<T: serde::Serialize + ?Sized>(value: &T) -> Result<JsValue, serde_wasm_bindgen::Error> {
+    value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+}
+
+#[cfg(feature = "master-key")]
+fn key_triple_value(res: Option<(String, String, String)>) -> JsValue {
+    match res {
+        Some((pk, sk, sign)) => to_value(&json!({
+            "publicKey": pk,
+            "privateKey": sk,
+            "sign": sign
+        })).unwrap(),
+        None => JsValue::NULL,
+    }
+}
+
+fn key_pair_value(pk: String, sk: String) -> JsValue {
+    to_value(&json!({
+        "publicKey": pk,
+        "privateKey": sk
+    })).unwrap()
+}
 
 #[wasm_bindgen(start)]
 pub fn init() {
@@ -14,175 +78,639 @@ pub fn key_hash(input: &str) -> String {
     core::key_hash(input)
 }
 
+/// Base64 の鍵の値をデコードした生バイトのハッシュ (EncryptedData.keyHash / Sign.keyHash と同じ定義)
+#[wasm_bindgen]
+pub fn key_hash_raw(b64_key: &str) -> Option<String> {
+    core::key_hash_raw(b64_key)
+}
+
+/// Sign.keyHash として埋め込まれる値 (公開鍵 JSON の key フィールドの生バイトのハッシュ)
+#[wasm_bindgen]
+pub fn compute_sign_key_hash(public_key_json: &str) -> String {
+    core::compute_sign_key_hash(public_key_json)
+}
+
+#[wasm_bindgen]
+pub fn key_hash_urlsafe(input: &str) -> String {
+    core::key_hash_with_variant(input, core::Base64Variant::UrlSafe)
+}
+
+/// urlsafe が true なら URL-safe (パディングなし)、false なら標準 Base64 に書き直す
+#[wasm_bindgen]
+pub fn reencode_base64(b64: &str, urlsafe: bool) -> Option<String> {
+    let variant = if urlsafe { core::Base64Variant::UrlSafe } else { core::Base64Variant::Standard };
+    core::reencode_base64(b64, variant)
+}
+
+/// 鍵 JSON の Base64 フィールドを標準 Base64 の正規形に揃える (保存・索引の前に使う)
+#[wasm_bindgen]
+pub fn canonicalize_key_json(key_json: &str) -> Option<String> {
+    core::canonicalize_key_json(key_json)
+}
+#[wasm_bindgen]
+pub fn is_canonical_key_json(key_json: &str) -> bool {
+    core::is_canonical_key_json(key_json)
+}
+
+#[wasm_bindgen]
+pub fn encrypted_data_key_matches(encrypted_json: &str, key_json: &str) -> bool {
+    core::encrypted_data_key_matches(encrypted_json, key_json)
+}
+
+/// encrypted_jsons_json は EncryptedData JSON 文字列の配列 (JSON)。戻り値は [[i, j], ...] の JSON
+#[wasm_bindgen]
+pub fn find_iv_collisions(encrypted_jsons_json: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(encrypted_jsons_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    serde_json::to_string(&core::find_iv_collisions(&refs)).ok()
+}
+
+/// encrypted_jsons_json は EncryptedData JSON 文字列の配列 (JSON)。戻り値は { keyHash: [index, ...] } の JSON
+#[wasm_bindgen]
+pub fn group_by_key_hash(encrypted_jsons_json: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(encrypted_jsons_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    serde_json::to_string(&core::group_by_key_hash(&refs)).ok()
+}
+
+#[wasm_bindgen]
+pub fn to_armored(key_json: &str) -> Option<String> {
+    core::to_armored(key_json)
+}
+
+#[wasm_bindgen]
+pub fn from_armored(armored: &str) -> Option<String> {
+    core::from_armored(armored)
+}
+
+#[wasm_bindgen]
+pub fn canonicalize_json(s: &str) -> Option<String> {
+    core::canonicalize_json(s)
+}
+#[wasm_bindgen]
+pub fn key_fingerprint(key_json: &str) -> String {
+    core::key_fingerprint(key_json)
+}
+
 #[wasm_bindgen]
 pub fn is_valid_uuid_v7(input: &str) -> bool {
     core::is_valid_uuid_v7(input)
 }
 
+/// UUID v7 のタイムスタンプ (ミリ秒)。UUID v7 でなければ undefined
+#[wasm_bindgen]
+pub fn uuid_v7_timestamp(uuid: &str) -> Option<u64> {
+    core::uuid_v7_timestamp(uuid)
+}
+
+#[wasm_bindgen]
+pub fn is_valid_uuid_v7_bounded(uuid: &str, min_ms: u64, max_ms: u64) -> bool {
+    core::is_valid_uuid_v7_bounded(uuid, min_ms, max_ms)
+}
+
 // ---- 非対称暗号化・復号 ----
+/// @deprecated Use `encrypt_or_throw` instead, which throws `{ code, message }` on failure.
 #[wasm_bindgen]
 pub fn encrypt(data: &str, public_key: &str) -> JsValue {
-    JsValue::from_serde(&core::encrypt(data, public_key)).unwrap()
+    match core::encrypt(data, public_key) {
+        Ok(enc) => to_value(&enc).unwrap(),
+        Err(_) => JsValue::NULL,
+    }
+}
+/// KEM の封入のみ。戻り値は {cipherText, sharedSecret} の JSON (共有秘密は共通鍵として使える)
+#[wasm_bindgen]
+pub fn kem_encapsulate(public_key: &str) -> Option<String> {
+    let (cipher_text, shared_secret) = core::kem_encapsulate(public_key)?;
+    serde_json::to_string(&serde_json::json!({"cipherText": cipher_text, "sharedSecret": shared_secret})).ok()
+}
+#[wasm_bindgen]
+pub fn kem_decapsulate(private_key: &str, cipher_text: &str) -> Option<String> {
+    core::kem_decapsulate(private_key, cipher_text)
 }
+/// 失敗時 (平文が UTF-8 でない場合を含む) は `{ code, message }` を throw する
 #[wasm_bindgen]
-pub fn decrypt(encrypted_data: &str, cipher_text: &str, iv: &str, private_key: &str) -> String {
-    core::decrypt(encrypted_data, cipher_text, iv, private_key)
+pub fn decrypt(encrypted_data: &str, cipher_text: &str, iv: &str, private_key: &str) -> Result<String, JsValue> {
+    core::decrypt(encrypted_data, cipher_text, iv, private_key).map_err(|e| js_error(e.code(), &e.to_string()))
+}
+
+// ---- detached 署名 ----
+/// ML-DSA-65 の生署名 (Sign オブジェクトに包まない)
+#[wasm_bindgen]
+pub fn sign_detached_mlds65(private_key: &str, data: &str) -> Option<String> {
+    core::sign_with_mlds65(private_key, data.as_bytes()).ok()
+}
+#[wasm_bindgen]
+pub fn verify_detached_mlds65(public_key: &str, data: &str, signature: &str) -> bool {
+    core::verify_with_mlds65(public_key, data.as_bytes(), signature)
+}
+/// 事前ハッシュ署名用の SHA-256 ダイジェスト
+#[wasm_bindgen]
+pub fn prehash_sha256(data: &[u8]) -> Vec<u8> {
+    core::prehash_sha256(data)
+}
+/// digest は prehash_sha256 の 32 バイト。algorithm は "ML-DSA-65+SHA-256" で記録される
+#[wasm_bindgen]
+pub fn create_signature_object_prehash_mlds65(private_key: &str, digest: &[u8], key_hash: &str, key_type: &str) -> Option<String> {
+    core::create_signature_object_prehash_mlds65(private_key, digest, key_hash, key_type).ok()
+}
+#[wasm_bindgen]
+pub fn verify_prehash_mlds65(public_key: &str, digest: &[u8], signature: &str) -> bool {
+    core::verify_prehash_mlds65(public_key, digest, signature)
+}
+/// Sign.algorithm を見て ML-DSA-65 / ML-DSA-87 などを選んで検証する
+#[wasm_bindgen]
+pub fn verify_any(public_key: &str, sign: &str, data: &str) -> bool {
+    core::verify_any(public_key, sign, data.as_bytes())
+}
+#[wasm_bindgen] pub fn signature_raw_len_mlds65() -> usize { core::signature_raw_len_mlds65() }
+#[wasm_bindgen] pub fn signature_raw_len_mlds87() -> usize { core::signature_raw_len_mlds87() }
+#[wasm_bindgen] pub fn normalize_signature_mlds65(signature: &str) -> Option<String> { core::normalize_signature_mlds65(signature) }
+#[cfg(feature = "master-key")]
+#[wasm_bindgen] pub fn normalize_signature_mlds87(signature: &str) -> Option<String> { core::normalize_signature_mlds87(signature) }
+#[wasm_bindgen] pub fn extract_signature(sign_json: &str) -> Option<String> { core::extract_signature(sign_json) }
+#[wasm_bindgen]
+pub fn wrap_signature(signature: &str, key_hash: &str, key_type: &str, algorithm: &str) -> String {
+    core::wrap_signature(signature, key_hash, key_type, algorithm)
+}
+/// Sign.keyType に必須の署名アルゴリズム (未知の keyType は undefined)
+#[wasm_bindgen]
+pub fn sign_algorithm_for_key_type(key_type: &str) -> Option<String> {
+    core::sign_algorithm_for_key_type(key_type).map(String::from)
 }
 
 // ---- 対称暗号化・復号 ----
+/// @deprecated Use `encrypt_with_symmetric_key_or_throw` instead, which throws `{ code, message }` on failure.
 #[wasm_bindgen]
 pub fn encrypt_with_symmetric_key(data: &str, key: &str) -> JsValue {
-    JsValue::from_serde(&core::encrypt_with_symmetric_key(data, key)).unwrap()
+    to_value(&core::encrypt_with_symmetric_key(data, key)).unwrap()
+}
+/// 任意のバイト列を暗号化する。UTF-8 でないデータを decrypt_with_symmetric_key で復号すると invalidUtf8 を throw する
+///
+/// 鍵が不正な場合は { code: "invalidSymmetricKey", message } を throw する
+#[wasm_bindgen]
+pub fn encrypt_bytes_with_symmetric_key(data: &[u8], key: &str) -> Result<JsValue, JsValue> {
+    if !core::is_valid_symmetric_key(key) {
+        return Err(js_error("invalidSymmetricKey", "key must be a base64 256-bit key"));
+    }
+    to_js(&core::encrypt_bytes_with_symmetric_key(data, key))
+}
+/// IV・鍵の長さが不正な場合や復号に失敗した場合は { code, message } を throw する
+#[wasm_bindgen]
+pub fn decrypt_with_symmetric_key(encrypted_data: &str, iv: &str, key: &str) -> Result<String, JsValue> {
+    core::decrypt_with_symmetric_key(encrypted_data, iv, key).map_err(|e| js_error(e.code(), &e.to_string()))
+}
+/// 対称復号の出力先を使い回すバッファ (new DecryptBuffer() → decrypt → text / bytes)
+///
+/// 平文は wasm ヒープ上のこのバッファに復号され、複数のメッセージで確保を使い回す
+#[wasm_bindgen]
+pub struct DecryptBuffer {
+    buf: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl DecryptBuffer {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> DecryptBuffer {
+        DecryptBuffer { buf: Vec::new() }
+    }
+
+    /// 復号して平文のバイト長を返す。失敗時はバッファを空にして { code, message } を throw する
+    pub fn decrypt(&mut self, encrypted_data: &str, iv: &str, key: &str) -> Result<usize, JsValue> {
+        core::decrypt_into(encrypted_data, iv, key, &mut self.buf).map_err(|e| js_error(e.code(), &e.to_string()))?;
+        Ok(self.buf.len())
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.buf.clone()
+    }
+
+    /// UTF-8 でなければ undefined
+    pub fn text(&self) -> Option<String> {
+        std::str::from_utf8(&self.buf).ok().map(String::from)
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+impl Default for DecryptBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+/// 同じ共通鍵で使用済みの IV を記録する (new NonceGuard(keyHashRaw(key)) → register)
 #[wasm_bindgen]
-pub fn decrypt_with_symmetric_key(encrypted_data: &str, iv: &str, key: &str) -> String {
-    core::decrypt_with_symmetric_key(encrypted_data, iv, key)
+pub struct NonceGuard {
+    inner: core::NonceGuard,
+}
+
+#[wasm_bindgen]
+impl NonceGuard {
+    #[wasm_bindgen(constructor)]
+    pub fn new(key_hash: &str) -> NonceGuard {
+        NonceGuard { inner: core::NonceGuard::new(key_hash) }
+    }
+
+    /// 既に使用済みの IV なら false
+    pub fn register(&mut self, iv: &str) -> bool {
+        self.inner.register(iv)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+/// IV が guard に登録済みなら再生成し、使用済みの IV を出力しない
+///
+/// 鍵が不正な場合は { code: "invalidSymmetricKey" }、guard が別の鍵のものなら
+/// { code: "nonceGuardKeyMismatch" } を throw する
+#[wasm_bindgen]
+pub fn encrypt_with_symmetric_key_guarded(data: &str, key: &str, guard: &mut NonceGuard) -> Result<JsValue, JsValue> {
+    if !core::is_valid_symmetric_key(key) {
+        return Err(js_error("invalidSymmetricKey", "key must be a base64 256-bit key"));
+    }
+    let enc = core::encrypt_with_symmetric_key_guarded(data, key, &mut guard.inner)
+        .ok_or_else(|| js_error("nonceGuardKeyMismatch", "the nonce guard belongs to a different key"))?;
+    to_js(&enc)
 }
 
 // ---- keyutils ----
-#[wasm_bindgen] pub fn generate_kem_key_pair() -> JsValue { JsValue::from_serde(&core::generate_kem_key_pair().unwrap()).unwrap() }
-#[wasm_bindgen] pub fn generate_dsa65_key_pair() -> JsValue { JsValue::from_serde(&core::generate_dsa65_key_pair().unwrap()).unwrap() }
-#[wasm_bindgen] pub fn generate_dsa87_key_pair() -> JsValue { JsValue::from_serde(&core::generate_dsa87_key_pair().unwrap()).unwrap() }
+#[wasm_bindgen] pub fn generate_kem_key_pair() -> KeyTuple { to_value(&core::generate_kem_key_pair().unwrap()).unwrap().unchecked_into() }
+#[wasm_bindgen] pub fn generate_dsa65_key_pair() -> KeyTuple { to_value(&core::generate_dsa65_key_pair().unwrap()).unwrap().unchecked_into() }
+#[cfg(feature = "master-key")]
+#[wasm_bindgen] pub fn generate_dsa87_key_pair() -> KeyTuple { to_value(&core::generate_dsa87_key_pair().unwrap()).unwrap().unchecked_into() }
 #[wasm_bindgen] pub fn generate_symmetric_key() -> String { core::generate_symmetric_key() }
+/// seed (32 バイト) から決定的に生成する (テスト・再現用)。seed の長さが不正なら undefined
+#[wasm_bindgen]
+pub fn generate_kem_key_pair_seeded(seed: &[u8]) -> JsValue {
+    let seed: [u8; 32] = match seed.try_into() {
+        Ok(seed) => seed,
+        Err(_) => return JsValue::UNDEFINED,
+    };
+    to_value(&core::generate_kem_key_pair_from_rng(&mut core::seeded_rng(seed)).unwrap()).unwrap()
+}
+#[wasm_bindgen]
+pub fn generate_dsa65_key_pair_seeded(seed: &[u8]) -> JsValue {
+    let seed: [u8; 32] = match seed.try_into() {
+        Ok(seed) => seed,
+        Err(_) => return JsValue::UNDEFINED,
+    };
+    to_value(&core::generate_dsa65_key_pair_from_rng(&mut core::seeded_rng(seed)).unwrap()).unwrap()
+}
+#[wasm_bindgen]
+pub fn generate_symmetric_key_seeded(seed: &[u8]) -> Option<String> {
+    let seed: [u8; 32] = seed.try_into().ok()?;
+    Some(core::generate_symmetric_key_from_rng(&mut core::seeded_rng(seed)))
+}
 #[wasm_bindgen] pub fn is_valid_key_pair_sign(pub_json: &str, priv_json: &str) -> bool { core::is_valid_key_pair_sign(pub_json, priv_json) }
 #[wasm_bindgen] pub fn is_valid_key_pair_encrypt(pub_json: &str, priv_json: &str) -> bool { core::is_valid_key_pair_encrypt(pub_json, priv_json) }
 #[wasm_bindgen] pub fn is_valid_dsa65_key(key: &str, is_pub: bool) -> bool { core::is_valid_dsa65_key(key, is_pub) }
 #[wasm_bindgen] pub fn is_valid_dsa87_key(key: &str, is_pub: bool) -> bool { core::is_valid_dsa87_key(key, is_pub) }
 #[wasm_bindgen] pub fn is_valid_kem_key(key: &str, is_pub: bool) -> bool { core::is_valid_kem_key(key, is_pub) }
 #[wasm_bindgen] pub fn is_valid_symmetric_key(key: &str) -> bool { core::is_valid_symmetric_key(key) }
+#[wasm_bindgen] pub fn key_byte_len(key_type: &str, is_public: bool) -> Option<usize> { core::key_byte_len(key_type, is_public) }
+/// [{ name, algorithm, publicLen, privateLen, requiredFields }]
+#[wasm_bindgen] pub fn describe_key_types() -> JsValue { to_value(&core::describe_key_types()).unwrap() }
+/// 各 is_valid_* の検証ルール (JSON)。他言語のサーバーで同じ検証を実装するためのもの
+#[wasm_bindgen] pub fn export_validation_rules() -> String { core::export_validation_rules() }
 #[wasm_bindgen] pub fn generate_random_string(len: usize) -> String { core::generate_random_string(len) }
+/// seed は 32 バイト (テスト・再現用)。それ以外の長さは undefined
+#[wasm_bindgen]
+pub fn generate_random_string_seeded(len: usize, seed: &[u8]) -> Option<String> {
+    let seed: [u8; 32] = seed.try_into().ok()?;
+    Some(core::generate_random_string_from_rng(&mut core::seeded_rng(seed), len))
+}
 
 // ---- MasterKey ----
-#[wasm_bindgen] pub fn generate_master_key() -> JsValue { JsValue::from_serde(&core::generate_master_key()).unwrap() }
-#[wasm_bindgen] pub fn sign_master_key(key_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_master_key(key_json, data, hash) }
+/// @deprecated Use `generate_master_key_or_throw` instead, which throws `{ code, message }` on failure.
+#[cfg(feature = "master-key")]
+#[wasm_bindgen] pub fn generate_master_key() -> KeyTuple { to_value(&core::generate_master_key()).unwrap().unchecked_into() }
+/// @deprecated Use `sign_master_key_with_public` instead, which computes keyHash from the public key.
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+#[allow(deprecated)]
+pub fn sign_master_key(key_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_master_key(key_json, data, hash) }
+#[cfg(feature = "master-key")]
+#[wasm_bindgen] pub fn sign_master_key_with_public(key_json: &str, pub_json: &str, data: &str) -> Option<String> { core::sign_master_key_with_public(key_json, pub_json, data) }
 #[wasm_bindgen] pub fn verify_master_key(key: &str, sign: &str, data: &str) -> bool { core::verify_master_key(key, sign, data) }
 #[wasm_bindgen] pub fn is_valid_master_key_private(key_json: &str) -> bool { core::is_valid_master_key_private(key_json) }
 #[wasm_bindgen] pub fn is_valid_master_key_public(key_json: &str) -> bool { core::is_valid_master_key_public(key_json) }
+#[wasm_bindgen] pub fn master_key_pair_matches(pub_json: &str, priv_json: &str) -> bool { core::master_key_pair_matches(pub_json, priv_json) }
 #[wasm_bindgen] pub fn is_valid_sign_master_key(sign_json: &str) -> bool { core::is_valid_sign_master_key(sign_json) }
+#[cfg(feature = "master-key")]
+#[wasm_bindgen] pub fn resign_key(key_json: &str, new_priv: &str, new_pub: &str) -> Option<String> { core::resign_key(key_json, new_priv, new_pub) }
+#[wasm_bindgen] pub fn verify_key_signature(key_json: &str, sign: &str, master_pub: &str) -> bool { core::verify_key_signature(key_json, sign, master_pub) }
+/// 戻り値は { valid: [index], failed: [{index, keyType, reason}] } の JSON
+#[wasm_bindgen]
+pub fn validate_key_set(master_pub: &str, entries_json: &str) -> Option<String> {
+    serde_json::to_string(&core::validate_key_set(master_pub, entries_json)?).ok()
+}
 
 // ---- IdentityKey ----
+/// @deprecated Use `sign_identity_key_with_public` instead, which computes keyHash from the public key.
 #[wasm_bindgen]
+#[allow(deprecated)]
 pub fn sign_identity_key(key_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_identity_key(key_json, data, hash) }
 #[wasm_bindgen]
+pub fn sign_identity_key_with_public(key_json: &str, pub_json: &str, data: &str) -> Option<String> { core::sign_identity_key_with_public(key_json, pub_json, data) }
+#[wasm_bindgen]
 pub fn verify_identity_key(key_json: &str, sign: &str, data: &str) -> bool { core::verify_identity_key(key_json, sign, data) }
+/// key_jsons_json は IdentityKey 公開鍵 JSON 文字列の配列 (JSON)。検証できた鍵のインデックスを返す
 #[wasm_bindgen]
-pub fn generate_identity_key(uuid: &str, pubk: &str, privk: &str) -> JsValue {
-    match core::generate_identity_key(uuid, pubk, privk) {
-        Some((pk, sk, sign)) => {
-            JsValue::from_serde(&json!({
-                "publicKey": pk,
-                "privateKey": sk,
-                "sign": sign
-            })).unwrap()
-        }
+pub fn verify_identity_key_any(key_jsons_json: &str, sign: &str, data: &str) -> Option<usize> {
+    let list: Vec<String> = serde_json::from_str(key_jsons_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    core::verify_identity_key_any(&refs, sign, data)
+}
+/// IdentityKey 署名をチャンク単位で検証する (new MessageVerifier(pub, sign) → update → finalize)
+#[wasm_bindgen]
+pub struct MessageVerifier {
+    inner: core::MessageVerifier,
+}
+
+#[wasm_bindgen]
+impl MessageVerifier {
+    #[wasm_bindgen(constructor)]
+    pub fn new(identity_pub_json: &str, sign_json: &str) -> Result<MessageVerifier, JsValue> {
+        let inner = core::MessageVerifier::new(identity_pub_json, sign_json)
+            .ok_or_else(|| js_error("invalidIdentityKey", "identity public key is invalid"))?;
+        Ok(MessageVerifier { inner })
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.inner.update(chunk);
+    }
+
+    pub fn finalize(self) -> bool {
+        self.inner.finalize()
+    }
+}
+/// @deprecated Use `generate_identity_key_or_throw` instead, which throws `{ code, message }` on failure.
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_identity_key(uuid: &str, pubk: &str, privk: &str) -> OptionalKeyTriple {
+    key_triple_value(core::generate_identity_key(uuid, pubk, privk)).unchecked_into()
+}
+/// { publicKey, privateKey, sign, keyHash, fingerprint } を返す (失敗時は null)
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_identity_key_full(uuid: &str, pubk: &str, privk: &str) -> JsValue {
+    to_value(&core::generate_identity_key_full(uuid, pubk, privk)).unwrap()
+}
+/// uuids_json は sessionUuid 文字列の配列 (JSON)。{ publicKey, privateKey, sign } の配列を返す (失敗時は null)
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_identity_keys_batch(count: usize, pubk: &str, privk: &str, uuids_json: &str) -> JsValue {
+    let list: Vec<String> = match serde_json::from_str(uuids_json) {
+        Ok(v) => v,
+        Err(_) => return JsValue::NULL,
+    };
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    match core::generate_identity_keys_batch(count, pubk, privk, &refs) {
+        Some(keys) => keys.into_iter().map(|k| key_triple_value(Some(k))).collect::<js_sys::Array>().into(),
         None => JsValue::NULL,
     }
 }
 #[wasm_bindgen] pub fn is_valid_identity_key_private(key_json: &str) -> bool { core::is_valid_identity_key_private(key_json) }
 #[wasm_bindgen] pub fn is_valid_identity_key_public(key_json: &str) -> bool { core::is_valid_identity_key_public(key_json) }
+#[wasm_bindgen] pub fn identity_key_pair_matches(pub_json: &str, priv_json: &str) -> bool { core::identity_key_pair_matches(pub_json, priv_json) }
+/// 同じ sessionUuid 内で厳密に新しくない置き換え鍵は拒否すること
+#[wasm_bindgen] pub fn is_newer_identity_key(candidate_json: &str, current_json: &str) -> bool { core::is_newer_identity_key(candidate_json, current_json) }
 #[wasm_bindgen] pub fn is_valid_sign_identity_key(sign_json: &str) -> bool { core::is_valid_sign_identity_key(sign_json) }
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_identity_key_hybrid(uuid: &str, pubk: &str, privk: &str) -> OptionalKeyTriple {
+    key_triple_value(core::generate_identity_key_hybrid(uuid, pubk, privk)).unchecked_into()
+}
+/// @deprecated Use `sign_identity_key_with_public` instead, which computes keyHash from the public key.
+#[wasm_bindgen]
+#[allow(deprecated)]
+pub fn sign_identity_key_hybrid(key_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_identity_key_hybrid(key_json, data, hash) }
+#[wasm_bindgen]
+pub fn verify_identity_key_hybrid(key_json: &str, sign: &str, data: &str) -> bool { core::verify_identity_key_hybrid(key_json, sign, data) }
 
 // ---- AccountKey ----
+/// @deprecated Use `generate_account_key_or_throw` instead, which throws `{ code, message }` on failure.
+#[cfg(feature = "master-key")]
 #[wasm_bindgen]
-pub fn generate_account_key(pubk: &str, privk: &str) -> JsValue {
-    match core::generate_account_key(pubk, privk) {
-        Some((pk, sk, sign)) => {
-            JsValue::from_serde(&json!({
-                "publicKey": pk,
-                "privateKey": sk,
-                "sign": sign
-            })).unwrap()
-        }
-        None => JsValue::NULL,
-    }
+pub fn generate_account_key(pubk: &str, privk: &str) -> OptionalKeyTriple {
+    key_triple_value(core::generate_account_key(pubk, privk)).unchecked_into()
 }
 #[wasm_bindgen] pub fn is_valid_account_key_public(json: &str) -> bool { core::is_valid_account_key_public(json) }
 #[wasm_bindgen] pub fn is_valid_account_key_private(json: &str) -> bool { core::is_valid_account_key_private(json) }
+#[wasm_bindgen] pub fn account_key_pair_matches(pub_json: &str, priv_json: &str) -> bool { core::account_key_pair_matches(pub_json, priv_json) }
 #[wasm_bindgen] pub fn encrypt_data_account_key(key_json: &str, data: &str) -> Option<String> { core::encrypt_data_account_key(key_json, data) }
+#[wasm_bindgen] pub fn encrypt_data_account_key_unchecked(key_json: &str, data: &str) -> Option<String> { core::encrypt_data_account_key_unchecked(key_json, data) }
 #[wasm_bindgen] pub fn is_valid_encrypted_data_account_key(json: &str) -> bool { core::is_valid_encrypted_data_account_key(json) }
 #[wasm_bindgen] pub fn decrypt_data_account_key(key_json: &str, enc_json: &str) -> Option<String> { core::decrypt_data_account_key(key_json, enc_json) }
 #[wasm_bindgen] pub fn is_valid_encrypted_account_key(json: &str) -> bool { core::is_valid_encrypted_account_key(json) }
+/// recipient_pubkeys_json は AccountKey 公開鍵 JSON 文字列の配列 (JSON)。本体は一度だけ暗号化する
+#[wasm_bindgen]
+pub fn encrypt_to_recipients(recipient_pubkeys_json: &str, data: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(recipient_pubkeys_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    core::encrypt_to_recipients(&refs, data)
+}
+/// 受信者のアカウント鍵で暗号化し、送信者のアカウント鍵のハッシュと共に IdentityKey で署名した {message, sign}
+#[wasm_bindgen]
+pub fn encrypt_dm(their_account_pub: &str, my_account_pub: &str, my_identity_priv: &str, my_identity_pub: &str, data: &str) -> Option<String> {
+    core::encrypt_dm(their_account_pub, my_account_pub, my_identity_priv, my_identity_pub, data)
+}
+/// their_account_pubs_json は送信者のアカウント鍵候補 (公開鍵 JSON 文字列の配列, JSON)
+#[wasm_bindgen]
+pub fn decrypt_dm_try_all(my_account_priv: &str, their_account_pubs_json: &str, their_identity_pub: &str, envelope: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(their_account_pubs_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    core::decrypt_dm_try_all(my_account_priv, &refs, their_identity_pub, envelope)
+}
+#[wasm_bindgen] pub fn decrypt_from_envelope(priv_json: &str, envelope: &str, my_key_hash: &str) -> Option<String> { core::decrypt_from_envelope(priv_json, envelope, my_key_hash) }
+
+// ---- AccountPrekey ----
+/// 使い捨てプリキーを count 個生成 ({ publicKey, privateKey, sign } の配列)
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_account_prekeys(count: usize, pubk: &str, privk: &str) -> OptionalKeyTripleArray {
+    let value = match core::generate_account_prekeys(count, pubk, privk) {
+        Some(keys) => {
+            let arr: Vec<_> = keys
+                .into_iter()
+                .map(|(pk, sk, sign)| json!({ "publicKey": pk, "privateKey": sk, "sign": sign }))
+                .collect();
+            to_value(&arr).unwrap()
+        }
+        None => JsValue::NULL,
+    };
+    value.unchecked_into()
+}
+#[wasm_bindgen] pub fn is_valid_account_prekey_public(json: &str) -> bool { core::is_valid_account_prekey_public(json) }
+#[wasm_bindgen] pub fn is_valid_account_prekey_private(json: &str) -> bool { core::is_valid_account_prekey_private(json) }
+#[wasm_bindgen] pub fn encrypt_to_prekey(pub_json: &str, data: &str) -> Option<String> { core::encrypt_to_prekey(pub_json, data) }
+#[wasm_bindgen] pub fn decrypt_from_prekey(priv_json: &str, enc_json: &str) -> Option<String> { core::decrypt_from_prekey(priv_json, enc_json) }
+#[wasm_bindgen] pub fn is_valid_encrypted_data_prekey(json: &str) -> bool { core::is_valid_encrypted_data_prekey(json) }
 
 // ---- ServerKey ----
+/// @deprecated Use `generate_server_key_or_throw` instead, which throws `{ code, message }` on failure.
 #[wasm_bindgen]
-pub fn generate_server_key() -> JsValue {
+pub fn generate_server_key() -> KeyPair {
     // core::generate_server_key は (pub_json, priv_json) を返す
-    let (pub_json, priv_json) = core::generate_server_key();
-    JsValue::from_serde(&json!({
-        "publicKey": pub_json,
-        "privateKey": priv_json
-    })).unwrap()
+    let (pk, sk) = core::generate_server_key();
+    key_pair_value(pk, sk).unchecked_into()
 }
 #[wasm_bindgen] pub fn is_valid_server_key_public(json: &str) -> bool { core::is_valid_server_key_public(json) }
 #[wasm_bindgen] pub fn is_valid_server_key_private(json: &str) -> bool { core::is_valid_server_key_private(json) }
-#[wasm_bindgen] pub fn sign_data_server_key(priv_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_data_server_key(priv_json, data, hash) }
+/// @deprecated Use `sign_data_server_key_with_public` instead, which computes keyHash from the public key.
+#[wasm_bindgen]
+#[allow(deprecated)]
+pub fn sign_data_server_key(priv_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_data_server_key(priv_json, data, hash) }
+#[wasm_bindgen] pub fn sign_data_server_key_with_public(priv_json: &str, pub_json: &str, data: &str) -> Option<String> { core::sign_data_server_key_with_public(priv_json, pub_json, data) }
 #[wasm_bindgen] pub fn verify_data_server_key(pub_json: &str, sign: &str, data: &str) -> bool { core::verify_data_server_key(pub_json, sign, data) }
+#[wasm_bindgen] pub fn is_valid_sign_server_key(sign_json: &str) -> bool { core::is_valid_sign_server_key(sign_json) }
+/// merkle_path_json は [{hash, position: "left"|"right"}] の JSON。パスが merkle_root を再構成しなければ undefined
+#[wasm_bindgen]
+pub fn create_inclusion_proof(server_priv: &str, server_pub: &str, key_hash: &str, merkle_root: &str, merkle_path_json: &str, now_ms: u64) -> Option<String> {
+    core::create_inclusion_proof(server_priv, server_pub, key_hash, merkle_root, merkle_path_json, now_ms)
+}
+#[wasm_bindgen] pub fn verify_inclusion_proof(server_pub: &str, key_hash: &str, proof_json: &str) -> bool { core::verify_inclusion_proof(server_pub, key_hash, proof_json) }
 
 // ---- RoomKey ----
 #[wasm_bindgen] pub fn generate_room_key(uuid: &str) -> Option<String> { core::generate_room_key(uuid) }
 #[wasm_bindgen] pub fn is_valid_room_key(json: &str) -> bool { core::is_valid_room_key(json) }
-#[wasm_bindgen] pub fn encrypt_data_room_key(json: &str, data: &str) -> Option<String> { core::encrypt_data_room_key(json, data) }
+/// member_identity_pub_jsons_json は identityKey 公開鍵 JSON 文字列の配列 (JSON)
+#[wasm_bindgen]
+pub fn deterministic_room_uuid(member_identity_pub_jsons_json: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(member_identity_pub_jsons_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    Some(core::deterministic_room_uuid(&refs))
+}
+#[wasm_bindgen] pub fn encrypt_data_room_key(json: &str, data: &str, expected_uuid: Option<String>) -> Option<String> { core::encrypt_data_room_key(json, data, expected_uuid.as_deref()) }
+#[wasm_bindgen] pub fn encrypt_data_room_key_committing(json: &str, data: &str) -> Option<String> { core::encrypt_data_room_key_committing(json, data) }
+/// 暗号化前に deflate で圧縮する。秘密と攻撃者が制御できる値を混ぜた平文には使わない (CRIME 型攻撃)
+#[wasm_bindgen] pub fn encrypt_data_room_key_compressed(json: &str, data: &str) -> Option<String> { core::encrypt_data_room_key_compressed(json, data) }
 #[wasm_bindgen] pub fn decrypt_data_room_key(json: &str, enc_json: &str) -> Option<String> { core::decrypt_data_room_key(json, enc_json) }
+#[wasm_bindgen] pub fn rewrap_account_to_room(account_priv_json: &str, encrypted_account_json: &str, room_key_json: &str) -> Option<String> { core::rewrap_account_to_room(account_priv_json, encrypted_account_json, room_key_json) }
+#[wasm_bindgen] pub fn encrypt_bytes_room_key(json: &str, data: &[u8]) -> Option<String> { core::encrypt_bytes_room_key(json, data) }
+#[wasm_bindgen] pub fn decrypt_bytes_room_key(json: &str, enc_json: &str) -> Option<Vec<u8>> { core::decrypt_bytes_room_key(json, enc_json) }
 #[wasm_bindgen] pub fn is_valid_encrypted_data_room_key(json: &str) -> bool { core::is_valid_encrypted_data_room_key(json) }
+/// カウンタ方式の IV で RoomKey 暗号化を行う (prefix と counter を永続化して再開する)
+#[wasm_bindgen]
+pub struct RoomKeyCipher {
+    inner: core::RoomKeyCipher,
+}
 
-// ---- ShareKey / ShareSignKey ----
 #[wasm_bindgen]
-pub fn generate_share_key(privk: &str, uuid: &str) -> JsValue {
-    match core::generate_share_key(privk, uuid) {
-        Some((pk, sk, sign)) => JsValue::from_serde(&json!({
-            "publicKey": pk,
-            "privateKey": sk,
-            "sign": sign
-        })).unwrap(),
-        None => JsValue::NULL,
+impl RoomKeyCipher {
+    /// prefix は端末ごとに一意な 4 バイト。counter を省略すると 0 から始める
+    #[wasm_bindgen(constructor)]
+    pub fn new(room_key_json: &str, prefix: &[u8], counter: Option<u64>) -> Result<RoomKeyCipher, JsValue> {
+        let prefix: [u8; 4] = prefix.try_into().map_err(|_| js_error("invalidPrefix", "prefix must be 4 bytes"))?;
+        core::RoomKeyCipher::with_counter(room_key_json, prefix, counter.unwrap_or(0))
+            .map(|inner| RoomKeyCipher { inner })
+            .ok_or_else(|| js_error("invalidRoomKey", "invalid room key"))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn prefix(&self) -> Vec<u8> {
+        self.inner.prefix().to_vec()
+    }
+
+    /// 次に使用するカウンタ
+    #[wasm_bindgen(getter)]
+    pub fn counter(&self) -> u64 {
+        self.inner.counter()
+    }
+
+    /// EncryptedData JSON。カウンタを使い切った場合は { code: "nonceCounterExhausted" } を throw
+    pub fn encrypt(&mut self, data: &str) -> Result<String, JsValue> {
+        self.inner.encrypt(data).map_err(|e| js_error(e.code(), &e.to_string()))
     }
 }
+
+/// ルートシークレット (32 バイト以上, Base64) と room_uuid から HKDF で決定的に RoomKey を導出
+#[wasm_bindgen] pub fn derive_room_key(root_secret_b64: &str, room_uuid: &str) -> Option<String> { core::derive_room_key(root_secret_b64, room_uuid) }
+/// { roomKey, proof } の JSON (どちらも JSON 文字列)
 #[wasm_bindgen]
-pub fn generate_share_sign_key(privk: &str, uuid: &str) -> JsValue {
-    match core::generate_share_sign_key(privk, uuid) {
-        Some((pk, sk, sign)) => JsValue::from_serde(&json!({
-            "publicKey": pk,
-            "privateKey": sk,
-            "sign": sign
-        })).unwrap(),
-        None => JsValue::NULL,
-    }
+pub fn rotate_room_key(old_key_json: &str, room_uuid: &str) -> Option<String> {
+    let (room_key, proof) = core::rotate_room_key(old_key_json, room_uuid)?;
+    serde_json::to_string(&json!({ "roomKey": room_key, "proof": proof })).ok()
+}
+/// 管理者の IdentityKey で署名した、バージョン付きのルーム設定 ({settings, sign})
+#[wasm_bindgen]
+pub fn sign_room_settings(admin_identity_priv: &str, admin_identity_pub: &str, room_uuid: &str, settings_json: &str, version: u64, now_ms: u64) -> Option<String> {
+    core::sign_room_settings(admin_identity_priv, admin_identity_pub, room_uuid, settings_json, version, now_ms)
+}
+#[wasm_bindgen] pub fn verify_room_settings(admin_identity_pub: &str, signed_settings_json: &str, room_uuid: &str) -> bool { core::verify_room_settings(admin_identity_pub, signed_settings_json, room_uuid) }
+/// current_version より古い設定 (ロールバック) は false
+#[wasm_bindgen]
+pub fn verify_room_settings_with_version(admin_identity_pub: &str, signed_settings_json: &str, room_uuid: &str, current_version: u64) -> bool {
+    core::verify_room_settings_with_version(admin_identity_pub, signed_settings_json, room_uuid, current_version)
+}
+#[wasm_bindgen] pub fn reencrypt_data_room_key(old_key_json: &str, new_key_json: &str, encrypted_json: &str) -> Option<String> { core::reencrypt_data_room_key(old_key_json, new_key_json, encrypted_json) }
+
+// ---- ShareKey / ShareSignKey ----
+/// @deprecated Use `generate_share_key_or_throw` instead, which throws `{ code, message }` on failure.
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_share_key(pubk: &str, privk: &str, uuid: &str) -> OptionalKeyTriple {
+    key_triple_value(core::generate_share_key(pubk, privk, uuid)).unchecked_into()
+}
+/// @deprecated Use `generate_share_sign_key_or_throw` instead, which throws `{ code, message }` on failure.
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_share_sign_key(pubk: &str, privk: &str, uuid: &str) -> OptionalKeyTriple {
+    key_triple_value(core::generate_share_sign_key(pubk, privk, uuid)).unchecked_into()
 }
 #[wasm_bindgen] pub fn is_valid_share_key_public(json: &str) -> bool { core::is_valid_share_key_public(json) }
 #[wasm_bindgen] pub fn is_valid_share_key_private(json: &str) -> bool { core::is_valid_share_key_private(json) }
 #[wasm_bindgen] pub fn encrypt_data_share_key(pub_json: &str, data: &str) -> Option<String> { core::encrypt_data_share_key(pub_json, data) }
 #[wasm_bindgen] pub fn decrypt_data_share_key(priv_json: &str, json: &str) -> Option<String> { core::decrypt_data_share_key(priv_json, json) }
 #[wasm_bindgen] pub fn is_valid_encrypted_data_share_key(json: &str) -> bool { core::is_valid_encrypted_data_share_key(json) }
+#[wasm_bindgen] pub fn transfer_share_to_account(share_priv_json: &str, encrypted_share_json: &str, account_pub_json: &str) -> Option<String> { core::transfer_share_to_account(share_priv_json, encrypted_share_json, account_pub_json) }
 #[wasm_bindgen] pub fn is_valid_share_sign_key_public(json: &str) -> bool { core::is_valid_share_sign_key_public(json) }
 #[wasm_bindgen] pub fn is_valid_share_sign_key_private(json: &str) -> bool { core::is_valid_share_sign_key_private(json) }
-#[wasm_bindgen] pub fn sign_data_share_sign_key(priv_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_data_share_sign_key(priv_json, data, hash) }
+/// @deprecated Use `sign_data_share_sign_key_with_public` instead, which computes keyHash from the public key.
+#[wasm_bindgen]
+#[allow(deprecated)]
+pub fn sign_data_share_sign_key(priv_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_data_share_sign_key(priv_json, data, hash) }
+#[wasm_bindgen] pub fn sign_data_share_sign_key_with_public(priv_json: &str, pub_json: &str, data: &str) -> Option<String> { core::sign_data_share_sign_key_with_public(priv_json, pub_json, data) }
 #[wasm_bindgen] pub fn verify_data_share_sign_key(pub_json: &str, sign: &str, data: &str) -> bool { core::verify_data_share_sign_key(pub_json, sign, data) }
 #[wasm_bindgen] pub fn is_valid_sign_share_sign_key(json: &str) -> bool { core::is_valid_sign_share_sign_key(json) }
 
 // ---- MigrateKey / MigrateSignKey ----
+/// @deprecated Use `generate_migrate_key_or_throw` instead, which throws `{ code, message }` on failure.
 #[wasm_bindgen]
-pub fn generate_migrate_key() -> JsValue {
-    let (pk, sk) = core::generate_migrate_key();
-    JsValue::from_serde(&json!({
-        "publicKey": pk,
-        "privateKey": sk
-    })).unwrap()
+pub fn generate_migrate_key(ttl_ms: Option<u64>) -> KeyPair {
+    let (pk, sk) = core::generate_migrate_key_with_ttl(ttl_ms.unwrap_or(core::DEFAULT_MIGRATE_KEY_TTL_MS));
+    key_pair_value(pk, sk).unchecked_into()
 }
+/// @deprecated Use `generate_migrate_sign_key_or_throw` instead, which throws `{ code, message }` on failure.
 #[wasm_bindgen]
-pub fn generate_migrate_sign_key() -> JsValue {
-    let (pk, sk) = core::generate_migrate_sign_key();
-    JsValue::from_serde(&json!({
-        "publicKey": pk,
-        "privateKey": sk
-    })).unwrap()
+pub fn generate_migrate_sign_key(ttl_ms: Option<u64>) -> KeyPair {
+    let (pk, sk) = core::generate_migrate_sign_key_with_ttl(ttl_ms.unwrap_or(core::DEFAULT_MIGRATE_KEY_TTL_MS));
+    key_pair_value(pk, sk).unchecked_into()
 }
 #[wasm_bindgen] pub fn is_valid_migrate_key_public(json: &str) -> bool { core::is_valid_migrate_key_public(json) }
 #[wasm_bindgen] pub fn is_valid_migrate_key_private(json: &str) -> bool { core::is_valid_migrate_key_private(json) }
+#[wasm_bindgen] pub fn is_migrate_key_expired(json: &str, now_ms: u64) -> bool { core::is_migrate_key_expired(json, now_ms) }
 #[wasm_bindgen] pub fn encrypt_data_migrate_key(pub_json: &str, data: &str) -> Option<String> { core::encrypt_data_migrate_key(pub_json, data) }
 #[wasm_bindgen] pub fn decrypt_data_migrate_key(priv_json: &str, enc_json: &str) -> Option<String> { core::decrypt_data_migrate_key(priv_json, enc_json) }
 #[wasm_bindgen] pub fn is_valid_encrypted_data_migrate_key(json: &str) -> bool { core::is_valid_encrypted_data_migrate_key(json) }
-#[wasm_bindgen] pub fn sign_data_migrate_sign_key(priv_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_data_migrate_sign_key(priv_json, data, hash) }
+/// @deprecated Use `sign_data_migrate_sign_key_with_public` instead, which computes keyHash from the public key.
+#[wasm_bindgen]
+#[allow(deprecated)]
+pub fn sign_data_migrate_sign_key(priv_json: &str, data: &str, hash: &str) -> Option<String> { core::sign_data_migrate_sign_key(priv_json, data, hash) }
+#[wasm_bindgen] pub fn sign_data_migrate_sign_key_with_public(priv_json: &str, pub_json: &str, data: &str) -> Option<String> { core::sign_data_migrate_sign_key_with_public(priv_json, pub_json, data) }
 #[wasm_bindgen] pub fn verify_data_migrate_sign_key(pub_json: &str, sign: &str, data: &str) -> bool { core::verify_data_migrate_sign_key(pub_json, sign, data) }
+#[wasm_bindgen] pub fn is_valid_migrate_sign_key_public(json: &str) -> bool { core::is_valid_migrate_sign_key_public(json) }
+#[wasm_bindgen] pub fn is_valid_migrate_sign_key_private(json: &str) -> bool { core::is_valid_migrate_sign_key_private(json) }
 #[wasm_bindgen] pub fn is_valid_sign_migrate_sign_key(json: &str) -> bool { core::is_valid_sign_migrate_sign_key(json) }
 
 // ---- DeviceKey ----
@@ -191,17 +719,152 @@ pub fn generate_migrate_sign_key() -> JsValue {
 #[wasm_bindgen] pub fn encrypt_data_device_key(json: &str, data: &str) -> Option<String> { core::encrypt_data_device_key(json, data) }
 #[wasm_bindgen] pub fn decrypt_data_device_key(json: &str, enc_json: &str) -> Option<String> { core::decrypt_data_device_key(json, enc_json) }
 #[wasm_bindgen] pub fn is_valid_encrypted_data_device_key(json: &str) -> bool { core::is_valid_encrypted_data_device_key(json) }
+/// blobs_json は EncryptedData JSON 文字列の配列 (JSON)。戻り値も同じ形式
+#[wasm_bindgen]
+pub fn rotate_device_key(old_device_json: &str, new_device_json: &str, blobs_json: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(blobs_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    serde_json::to_string(&core::rotate_device_key(old_device_json, new_device_json, &refs)?).ok()
+}
 
 // ---- Message ----
 #[wasm_bindgen]
-pub fn encrypt_message(message: &str, metadata: &str, room_key: &str, identity_priv: &str, identity_pubhash: &str, roomid: &str) -> Option<String> {
-    core::encrypt_message(message, metadata, room_key, identity_priv, identity_pubhash, roomid)
+pub fn encrypt_message(message: &str, metadata: &str, room_key: &str, identity_priv: &str, identity_pub: &str, roomid: &str, original: Option<String>) -> Option<String> {
+    core::encrypt_message(message, metadata, room_key, identity_priv, identity_pub, roomid, original.as_deref())
+}
+/// channel (hide_original なら original も) を encryptedMetadata に暗号化して送る
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_message_private_metadata(message: &str, metadata: &str, room_key: &str, identity_priv: &str, identity_pub: &str, roomid: &str, original: Option<String>, hide_original: bool) -> Option<String> {
+    core::encrypt_message_private_metadata(message, metadata, room_key, identity_priv, identity_pub, roomid, original.as_deref(), hide_original)
+}
+#[wasm_bindgen]
+pub fn validate_message_original(message: &str, message_id: &str) -> bool {
+    core::validate_message_original(message, message_id)
 }
 #[wasm_bindgen]
-pub fn decrypt_message(message: &str, sign: &str, server_timestamp: u64, room_key: &str, identity_pub: &str, roomid: &str) -> Option<String> {
-    core::decrypt_message(message, sign, server_timestamp, room_key, identity_pub, roomid)
+pub fn send_text_message(text: &str, room_key: &str, identity_priv: &str, identity_pub: &str, roomid: &str, channel: &str, large_threshold: Option<usize>) -> Option<String> {
+    core::send_text_message(text, room_key, identity_priv, identity_pub, roomid, channel, large_threshold)
+}
+/// threshold を省略すると LARGE_THRESHOLD_BYTES (64KiB) を使う
+#[wasm_bindgen]
+pub fn should_be_large(content_json: &str, threshold: Option<usize>) -> bool {
+    core::should_be_large_with_threshold(content_json, threshold.unwrap_or(core::LARGE_THRESHOLD_BYTES))
+}
+/// メッセージを解析・検証して正規化した JSON を返す。失敗時は { code, message } を throw
+#[wasm_bindgen]
+pub fn parse_message(message: &str) -> Result<String, JsValue> {
+    let msg = core::parse_message(message).map_err(|e| js_error(e.code(), &e.to_string()))?;
+    serde_json::to_string(&msg).map_err(|e| js_error("serializationFailed", &e.to_string()))
+}
+#[wasm_bindgen]
+pub fn stamp_message(message: &str, server_priv: &str, server_pub: &str, server_timestamp: u64) -> Option<String> {
+    core::stamp_message(message, server_priv, server_pub, server_timestamp)
+}
+/// 検証できた刻印時刻を返す (失敗時は undefined)
+#[wasm_bindgen]
+pub fn verify_server_stamp(message: &str, stamp: &str, server_pub: &str) -> Option<u64> {
+    core::verify_server_stamp(message, stamp, server_pub)
+}
+#[wasm_bindgen]
+pub fn verify_server_stamp_matches(message: &str, stamp: &str, server_pub: &str, expected_timestamp: u64) -> bool {
+    core::verify_server_stamp_matches(message, stamp, server_pub, expected_timestamp)
+}
+/// サーバー鍵で検証した刻印時刻を server_timestamp として decrypt_message する
+#[wasm_bindgen]
+pub fn decrypt_stamped_message(message: &str, sign: &str, stamp: &str, server_pub: &str, room_key: &str, identity_pub: &str, roomid: &str) -> Option<String> {
+    core::decrypt_stamped_message(message, sign, stamp, server_pub, room_key, identity_pub, roomid)
+}
+#[wasm_bindgen]
+pub fn decrypt_message(message: &str, sign: &str, server_timestamp: u64, room_key: &str, identity_pub: &str, roomid: &str, expected_session_uuid: Option<String>) -> Option<String> {
+    core::decrypt_message(message, sign, server_timestamp, room_key, identity_pub, roomid, expected_session_uuid.as_deref())
+}
+
+/// サーバーが保存すべき正規形 (署名対象と同じバイト列)
+#[wasm_bindgen]
+pub fn normalize_stored_message(message: &str) -> Option<String> {
+    core::normalize_stored_message(message)
+}
+/// stored_bytes が正規形でなければ署名が正しくても false
+#[wasm_bindgen]
+pub fn verify_against_stored(identity_pub: &str, stored_bytes: &[u8], sign: &str) -> bool {
+    core::verify_against_stored(identity_pub, stored_bytes, sign)
+}
+
+/// 封筒 ({message, sign}) からインデックス用メタデータ { messageId, roomid, channel, timestamp, senderKeyHash } を抽出 (復号しない)
+#[wasm_bindgen]
+pub fn extract_index_metadata(envelope_json: &str) -> Option<String> {
+    core::extract_index_metadata(envelope_json)
+}
+
+/// encrypt_message の {message, sign} をバイナリ形式にする
+#[wasm_bindgen]
+pub fn encode_message_wire(message: &str, sign: &str) -> Option<Vec<u8>> {
+    core::encode_message_wire(message, sign)
+}
+
+/// バイナリ形式を {message, sign} の JSON に戻す
+#[wasm_bindgen]
+pub fn decode_message_wire(bytes: &[u8]) -> Option<String> {
+    let (message, sign) = core::decode_message_wire(bytes)?;
+    serde_json::to_string(&serde_json::json!({"message": message, "sign": sign})).ok()
+}
+
+/// envelopes_json は encrypt_message の出力文字列の配列 (JSON)。
+/// { decrypted: [{index, value}], failed: [{index, reason}] } を返す
+#[wasm_bindgen]
+pub fn decrypt_batch(envelopes_json: &str, room_keys_json: &str, identity_keys_json: &str, roomid: &str, server_timestamp: u64) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(envelopes_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    Some(core::decrypt_batch(&refs, room_keys_json, identity_keys_json, roomid, server_timestamp))
+}
+#[wasm_bindgen]
+pub fn verify_message_only(message: &str, sign: &str, identity_pub: &str) -> bool {
+    core::verify_message_only(message, sign, identity_pub)
+}
+/// 同じ署名のメッセージを 2 度受理しないための記録 (new ReplayGuard() → checkAndRecord)
+#[wasm_bindgen]
+pub struct ReplayGuard {
+    inner: core::ReplayGuard,
+}
+
+#[wasm_bindgen]
+impl ReplayGuard {
+    /// window_ms / capacity を省略すると 60 秒 / 100000 件
+    #[wasm_bindgen(constructor)]
+    pub fn new(window_ms: Option<u64>, capacity: Option<usize>) -> ReplayGuard {
+        ReplayGuard {
+            inner: core::ReplayGuard::with_window(
+                window_ms.unwrap_or(core::MESSAGE_TIMESTAMP_SKEW_MS),
+                capacity.unwrap_or(core::DEFAULT_REPLAY_GUARD_CAPACITY),
+            ),
+        }
+    }
+
+    pub fn check_and_record(&mut self, sign: &str, timestamp: u64) -> bool {
+        self.inner.check_and_record(sign, timestamp)
+    }
+
+    /// "accepted" / "replayed" / "saturated" / "malformed"
+    pub fn check(&mut self, sign: &str, timestamp: u64) -> String {
+        match self.inner.check(sign, timestamp) {
+            core::ReplayCheck::Accepted => "accepted",
+            core::ReplayCheck::Replayed => "replayed",
+            core::ReplayCheck::Saturated => "saturated",
+            core::ReplayCheck::Malformed => "malformed",
+        }
+        .to_string()
+    }
+}
+
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn decrypt_message_guarded(message: &str, sign: &str, server_timestamp: u64, room_key: &str, identity_pub: &str, roomid: &str, expected_session_uuid: Option<String>, guard: &mut ReplayGuard) -> Option<String> {
+    core::decrypt_message_guarded(message, sign, server_timestamp, room_key, identity_pub, roomid, expected_session_uuid.as_deref(), &mut guard.inner)
 }
 #[wasm_bindgen] pub fn is_valid_message(message: &str) -> bool { core::is_valid_message(message) }
+/// limits_json は {text, filename, uri, mimeType} の最大長 (文字数)。省略したものは既定値
+#[wasm_bindgen] pub fn validate_content_limits(content_json: &str, limits_json: &str) -> bool { core::validate_content_limits(content_json, limits_json) }
 #[wasm_bindgen]
 pub fn create_text_content(
     text: &str,
@@ -290,4 +953,308 @@ pub fn create_file_content(
         original_size,
     )
 }
+#[wasm_bindgen]
+pub fn create_thumbnail_content(
+    original_type: &str,
+    thumbnail: &str,
+    thumbnail_mime_type: Option<String>,
+    size: Option<u64>,
+) -> Option<String> {
+    core::create_thumbnail_content(original_type, thumbnail, thumbnail_mime_type.as_deref(), size)
+}
+#[wasm_bindgen] pub fn content_id(content_json: &str) -> String { core::content_id(content_json) }
+#[wasm_bindgen] pub fn validate_thumbnail_link(thumbnail_json: &str, original_json: &str) -> bool { core::validate_thumbnail_link(thumbnail_json, original_json) }
 #[wasm_bindgen] pub fn encrypt_room_key_with_account_keys(users_json: &str, room_key_json: &str) -> Option<String> { core::encrypt_room_key_with_account_keys(users_json, room_key_json) }
+#[wasm_bindgen] pub fn encrypt_room_key_with_account_keys_partial(users_json: &str, room_key_json: &str) -> Option<String> { core::encrypt_room_key_with_account_keys_partial(users_json, room_key_json) }
+#[wasm_bindgen] pub fn merge_distributions(a_json: &str, b_json: &str) -> Option<String> { core::merge_distributions(a_json, b_json) }
+/// room_keys_json は統合する側が保持する RoomKey JSON 文字列の配列 (JSON)。衝突はその鍵の timestamp で解決する
+#[wasm_bindgen]
+pub fn merge_distributions_with_room_keys(a_json: &str, b_json: &str, room_keys_json: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(room_keys_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    core::merge_distributions_with_room_keys(a_json, b_json, &refs)
+}
+#[wasm_bindgen] pub fn select_and_decrypt_room_key(wrapped_list_json: &str, account_priv_json: &str) -> Option<String> { core::select_and_decrypt_room_key(wrapped_list_json, account_priv_json) }
+/// share_pubkeys_json は ShareKey 公開鍵 JSON 文字列の配列 (JSON)
+#[wasm_bindgen]
+pub fn distribute_room_key_to_share_keys(share_pubkeys_json: &str, room_key_json: &str) -> Option<String> {
+    let list: Vec<String> = serde_json::from_str(share_pubkeys_json).ok()?;
+    let refs: Vec<&str> = list.iter().map(String::as_str).collect();
+    core::distribute_room_key_to_share_keys(&refs, room_key_json)
+}
+/// recipients_json は [{kind: "account" | "share", key, id}] (JSON)
+/// rooms_json は [{roomKey, roomUuid}]、remaining_recipients_json は wrap_room_key_for_recipients と同じ (JSON)
+#[wasm_bindgen] pub fn bulk_rotate_and_rewrap(rooms_json: &str, remaining_recipients_json: &str) -> Option<String> { core::bulk_rotate_and_rewrap(rooms_json, remaining_recipients_json) }
+#[wasm_bindgen] pub fn wrap_room_key_for_recipients(room_key_json: &str, recipients_json: &str) -> Option<String> { core::wrap_room_key_for_recipients(room_key_json, recipients_json) }
+
+// ---- エラーを throw する版 ----
+// 失敗時は { code, message } を JS の例外として投げる
+
+fn js_error(code: &str, message: &str) -> JsValue {
+    to_value(&json!({ "code": code, "message": message })).unwrap_or(JsValue::NULL)
+}
+
+fn to_js<T: serde::Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    to_value(value).map_err(|e| js_error("serializationFailed", &e.to_string()))
+}
+
+#[cfg(feature = "master-key")]
+fn key_triple(res: Option<(String, String, String)>, code: &str, message: &str) -> Result<KeyTriple, JsValue> {
+    let (pk, sk, sign) = res.ok_or_else(|| js_error(code, message))?;
+    Ok(to_js(&json!({ "publicKey": pk, "privateKey": sk, "sign": sign }))?.unchecked_into())
+}
+
+#[wasm_bindgen]
+pub fn encrypt_or_throw(data: &str, public_key: &str) -> Result<JsValue, JsValue> {
+    let enc = core::encrypt(data, public_key).map_err(|e| js_error(e.code(), &e.to_string()))?;
+    to_js(&enc)
+}
+
+/// 失敗時は理由 (invalidSignature / invalidRoomKey など) を code とする { code, message } を throw する
+#[wasm_bindgen]
+pub fn decrypt_message_or_throw(message: &str, sign: &str, server_timestamp: u64, room_key: &str, identity_pub: &str, roomid: &str, expected_session_uuid: Option<String>) -> Result<String, JsValue> {
+    core::decrypt_message_with_reason(message, sign, server_timestamp, room_key, identity_pub, roomid, expected_session_uuid.as_deref())
+        .map_err(|reason| js_error(reason, "message could not be verified or decrypted"))
+}
+
+#[wasm_bindgen]
+pub fn encrypt_with_symmetric_key_or_throw(data: &str, key: &str) -> Result<JsValue, JsValue> {
+    if !core::is_valid_symmetric_key(key) {
+        return Err(js_error("invalidSymmetricKey", "key must be a base64 256-bit key"));
+    }
+    to_js(&core::encrypt_with_symmetric_key(data, key))
+}
+
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_master_key_or_throw() -> Result<KeyTuple, JsValue> {
+    Ok(to_js(&core::generate_master_key())?.unchecked_into())
+}
+
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_identity_key_or_throw(uuid: &str, pubk: &str, privk: &str) -> Result<KeyTriple, JsValue> {
+    if !core::is_valid_uuid_v7(uuid) {
+        return Err(js_error("invalidUuid", "session uuid must be a UUID v7"));
+    }
+    key_triple(core::generate_identity_key(uuid, pubk, privk), "invalidMasterKey", "master key pair is invalid")
+}
+
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_account_key_or_throw(pubk: &str, privk: &str) -> Result<KeyTriple, JsValue> {
+    key_triple(core::generate_account_key(pubk, privk), "invalidMasterKey", "master key pair is invalid")
+}
+
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_share_key_or_throw(pubk: &str, privk: &str, uuid: &str) -> Result<KeyTriple, JsValue> {
+    if !core::is_valid_uuid_v7(uuid) {
+        return Err(js_error("invalidUuid", "session uuid must be a UUID v7"));
+    }
+    key_triple(core::generate_share_key(pubk, privk, uuid), "invalidMasterKey", "master key pair is invalid")
+}
+
+#[cfg(feature = "master-key")]
+#[wasm_bindgen]
+pub fn generate_share_sign_key_or_throw(pubk: &str, privk: &str, uuid: &str) -> Result<KeyTriple, JsValue> {
+    if !core::is_valid_uuid_v7(uuid) {
+        return Err(js_error("invalidUuid", "session uuid must be a UUID v7"));
+    }
+    key_triple(core::generate_share_sign_key(pubk, privk, uuid), "invalidMasterKey", "master key pair is invalid")
+}
+
+#[wasm_bindgen]
+pub fn generate_server_key_or_throw() -> Result<KeyPair, JsValue> {
+    let (pub_json, priv_json) = core::generate_server_key();
+    Ok(to_js(&json!({ "publicKey": pub_json, "privateKey": priv_json }))?.unchecked_into())
+}
+
+#[wasm_bindgen]
+pub fn generate_migrate_key_or_throw(ttl_ms: Option<u64>) -> Result<KeyPair, JsValue> {
+    let (pk, sk) = core::generate_migrate_key_with_ttl(ttl_ms.unwrap_or(core::DEFAULT_MIGRATE_KEY_TTL_MS));
+    Ok(to_js(&json!({ "publicKey": pk, "privateKey": sk }))?.unchecked_into())
+}
+
+#[wasm_bindgen]
+pub fn generate_migrate_sign_key_or_throw(ttl_ms: Option<u64>) -> Result<KeyPair, JsValue> {
+    let (pk, sk) = core::generate_migrate_sign_key_with_ttl(ttl_ms.unwrap_or(core::DEFAULT_MIGRATE_KEY_TTL_MS));
+    Ok(to_js(&json!({ "publicKey": pk, "privateKey": sk }))?.unchecked_into())
+}
+
+#[wasm_bindgen]
+pub fn generate_room_key_or_throw(uuid: &str) -> Result<String, JsValue> {
+    core::generate_room_key(uuid).ok_or_else(|| js_error("invalidUuid", "room uuid must be a UUID v7"))
+}
+
+#[wasm_bindgen]
+pub fn encrypt_data_account_key_or_throw(key_json: &str, data: &str) -> Result<String, JsValue> {
+    core::encrypt_data_account_key(key_json, data)
+        .ok_or_else(|| js_error("invalidAccountKey", "account public key is invalid"))
+}
+
+#[wasm_bindgen]
+pub fn encrypt_data_room_key_or_throw(json: &str, data: &str) -> Result<String, JsValue> {
+    core::encrypt_data_room_key(json, data, None)
+        .ok_or_else(|| js_error("invalidRoomKey", "room key is invalid"))
+}
+
+#[wasm_bindgen]
+pub fn encrypt_data_share_key_or_throw(pub_json: &str, data: &str) -> Result<String, JsValue> {
+    core::encrypt_data_share_key(pub_json, data)
+        .ok_or_else(|| js_error("invalidShareKey", "share public key is invalid"))
+}
+
+#[wasm_bindgen]
+pub fn encrypt_data_migrate_key_or_throw(pub_json: &str, data: &str) -> Result<String, JsValue> {
+    core::encrypt_data_migrate_key(pub_json, data)
+        .ok_or_else(|| js_error("invalidMigrateKey", "migrate public key is invalid"))
+}
+
+#[wasm_bindgen]
+pub fn encrypt_data_device_key_or_throw(json: &str, data: &str) -> Result<String, JsValue> {
+    if !core::is_valid_device_key(json) {
+        return Err(js_error("invalidDeviceKey", "device key is invalid"));
+    }
+    core::encrypt_data_device_key(json, data)
+        .ok_or_else(|| js_error("invalidDeviceKey", "device key is invalid"))
+}
+
+// ---- EncryptedData の生バイト列 ----
+/// EncryptedData の Base64 フィールドをデコードしたもの
+#[wasm_bindgen]
+pub struct EncryptedDataBytes {
+    key_hash: Vec<u8>,
+    encrypted_data: Vec<u8>,
+    iv: Vec<u8>,
+    cipher_text: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl EncryptedDataBytes {
+    #[wasm_bindgen(getter, js_name = keyHash)]
+    pub fn key_hash(&self) -> Vec<u8> {
+        self.key_hash.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = encryptedData)]
+    pub fn encrypted_data(&self) -> Vec<u8> {
+        self.encrypted_data.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn iv(&self) -> Vec<u8> {
+        self.iv.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = cipherText)]
+    pub fn cipher_text(&self) -> Option<Vec<u8>> {
+        self.cipher_text.clone()
+    }
+}
+
+/// いずれかのフィールドが Base64 としてデコードできなければ undefined
+#[wasm_bindgen]
+pub fn encrypted_data_bytes(json: &str) -> Option<EncryptedDataBytes> {
+    let ed: core::EncryptedData = serde_json::from_str(json).ok()?;
+    Some(EncryptedDataBytes {
+        key_hash: ed.key_hash_bytes()?,
+        encrypted_data: ed.encrypted_data_bytes()?,
+        iv: ed.iv_bytes()?,
+        cipher_text: ed.cipher_text_bytes()?,
+    })
+}
+/// 生バイト列から EncryptedData JSON を作る
+#[wasm_bindgen]
+pub fn encrypted_data_from_parts(key_type: &str, key_hash: &[u8], encrypted: &[u8], iv: &[u8], cipher_text: Option<Vec<u8>>, algorithm: Option<String>) -> Option<String> {
+    let ed = core::EncryptedData::from_parts(key_type, key_hash, encrypted, iv, cipher_text.as_deref(), algorithm.as_deref());
+    serde_json::to_string(&ed).ok()
+}
+
+// ---- CBOR ----
+/// kind は "masterKey" / "accountKey" / "encryptedData" / "encryptedMessage"。鍵などを生バイトで持つ CBOR にする
+#[wasm_bindgen]
+pub fn json_to_cbor(kind: &str, json: &str) -> Option<Vec<u8>> {
+    match kind {
+        "masterKey" => core::json_to_cbor::<core::MasterKey>(json),
+        "accountKey" => core::json_to_cbor::<core::AccountKey>(json),
+        "encryptedData" => core::json_to_cbor::<core::EncryptedData>(json),
+        "encryptedMessage" => core::json_to_cbor::<core::EncryptedMessage>(json),
+        _ => None,
+    }
+}
+#[wasm_bindgen]
+pub fn cbor_to_json(kind: &str, bytes: &[u8]) -> Option<String> {
+    match kind {
+        "masterKey" => core::cbor_to_json::<core::MasterKey>(bytes),
+        "accountKey" => core::cbor_to_json::<core::AccountKey>(bytes),
+        "encryptedData" => core::cbor_to_json::<core::EncryptedData>(bytes),
+        "encryptedMessage" => core::cbor_to_json::<core::EncryptedMessage>(bytes),
+        _ => None,
+    }
+}
+
+// ---- キーバンドル ----
+/// keys_json は鍵 JSON 文字列の配列 (JSON)。パスフレーズから Argon2id で導出した鍵で暗号化する
+#[wasm_bindgen] pub fn export_key_bundle(keys_json: &str, passphrase: &str) -> Option<String> { core::export_key_bundle(keys_json, passphrase) }
+/// パスフレーズ誤り・形式不正・妥当でない鍵を含む場合は undefined
+#[wasm_bindgen] pub fn import_key_bundle(bundle: &str, passphrase: &str) -> Option<String> { core::import_key_bundle(bundle, passphrase) }
+
+// ---- 診断 ----
+/// 鍵 JSON が無効な理由 (有効なら "ok")。鍵の値は含まない
+#[wasm_bindgen]
+pub fn diagnose_account_key(json: &str) -> String {
+    core::diagnose_account_key(json)
+}
+#[wasm_bindgen]
+pub fn diagnose_master_key(json: &str) -> String {
+    core::diagnose_master_key(json)
+}
+#[wasm_bindgen]
+pub fn diagnose_identity_key(json: &str) -> String {
+    core::diagnose_identity_key(json)
+}
+#[wasm_bindgen]
+pub fn diagnose_room_key(json: &str) -> String {
+    core::diagnose_room_key(json)
+}
+/// アカウントのエクスポート全体の鍵ごとの監査結果 (JSON)。now はミリ秒
+#[wasm_bindgen]
+pub fn audit_account_export(export_json: &str, now: u64) -> String {
+    core::audit_account_export_at(export_json, now).to_string()
+}
+
+// ---- 添付ファイル ----
+/// create_encrypted_attachment の結果 (uri はメッセージ本文に、blob はストレージに置く)
+#[wasm_bindgen]
+pub struct EncryptedAttachment {
+    uri: String,
+    blob: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl EncryptedAttachment {
+    #[wasm_bindgen(getter)]
+    pub fn uri(&self) -> String {
+        self.uri.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn blob(&self) -> Vec<u8> {
+        self.blob.clone()
+    }
+}
+
+#[wasm_bindgen]
+pub fn create_encrypted_attachment(data: &[u8]) -> Option<EncryptedAttachment> {
+    let (uri, blob) = core::create_encrypted_attachment(data)?;
+    Some(EncryptedAttachment { uri, blob })
+}
+#[wasm_bindgen]
+pub fn decrypt_attachment(uri: &str, encrypted_blob: &[u8]) -> Option<Vec<u8>> {
+    core::decrypt_attachment(uri, encrypted_blob)
+}
+#[wasm_bindgen]
+pub fn attachment_blob_hash(uri: &str) -> Option<String> {
+    core::attachment_blob_hash(uri)
+}
@@ -46,6 +46,8 @@ import {
   sign_data_migrate_sign_key as signDataMigrateSignKey,
   verify_data_migrate_sign_key as verifyDataMigrateSignKey,
   is_valid_sign_migrate_sign_key as isValidSignMigrateSignKey,
+  is_valid_migrate_sign_key_public as isValidMigrateSignKeyPublic,
+  is_valid_migrate_sign_key_private as isValidMigrateSignKeyPrivate,
   generate_device_key as generateDeviceKey,
   is_valid_device_key as isValidDeviceKey,
   encrypt_data_device_key as encryptDataDeviceKey,
@@ -60,6 +62,153 @@ import {
   create_text_content as createTextContent,
   encrypt_message as encryptMessage,
   decrypt_message as decryptMessage,
+  encrypt_or_throw as encryptOrThrow,
+  generate_account_key_or_throw as generateAccountKeyOrThrow,
+  resign_key as resignKey,
+  verify_key_signature as verifyKeySignature,
+  encrypt_data_room_key_committing as encryptDataRoomKeyCommitting,
+  is_migrate_key_expired as isMigrateKeyExpired,
+  parse_message as parseMessage,
+  encrypted_data_key_matches as encryptedDataKeyMatches,
+  generate_account_prekeys as generateAccountPrekeys,
+  is_valid_account_prekey_public as isValidAccountPrekeyPublic,
+  encrypt_to_prekey as encryptToPrekey,
+  decrypt_from_prekey as decryptFromPrekey,
+  is_valid_encrypted_data_prekey as isValidEncryptedDataPrekey,
+  MessageVerifier,
+  find_iv_collisions as findIvCollisions,
+  to_armored as toArmored,
+  from_armored as fromArmored,
+  send_text_message as sendTextMessage,
+  should_be_large as shouldBeLarge,
+  distribute_room_key_to_share_keys as distributeRoomKeyToShareKeys,
+  select_and_decrypt_room_key as selectAndDecryptRoomKey,
+  generate_identity_key_hybrid as generateIdentityKeyHybrid,
+  sign_identity_key_hybrid as signIdentityKeyHybrid,
+  verify_identity_key_hybrid as verifyIdentityKeyHybrid,
+  validate_key_set as validateKeySet,
+  generate_symmetric_key as generateSymmetricKey,
+  encrypt_with_symmetric_key as encryptWithSymmetricKey,
+  decrypt_with_symmetric_key as decryptWithSymmetricKey,
+  sign_detached_mlds65 as signDetachedMlds65,
+  verify_detached_mlds65 as verifyDetachedMlds65,
+  extract_signature as extractSignature,
+  wrap_signature as wrapSignature,
+  ReplayGuard,
+  decrypt_message_guarded as decryptMessageGuarded,
+  create_image_content as createImageContent,
+  create_thumbnail_content as createThumbnailContent,
+  content_id as contentId,
+  validate_thumbnail_link as validateThumbnailLink,
+  encrypt_data_account_key_unchecked as encryptDataAccountKeyUnchecked,
+  signature_raw_len_mlds65 as signatureRawLenMlds65,
+  signature_raw_len_mlds87 as signatureRawLenMlds87,
+  normalize_signature_mlds65 as normalizeSignatureMlds65,
+  normalize_signature_mlds87 as normalizeSignatureMlds87,
+  encrypt_bytes_room_key as encryptBytesRoomKey,
+  decrypt_bytes_room_key as decryptBytesRoomKey,
+  rewrap_account_to_room as rewrapAccountToRoom,
+  key_byte_len as keyByteLen,
+  wrap_room_key_for_recipients as wrapRoomKeyForRecipients,
+  canonicalize_json as canonicalizeJson,
+  bulk_rotate_and_rewrap as bulkRotateAndRewrap,
+  key_hash_urlsafe as keyHashUrlsafe,
+  reencode_base64 as reencodeBase64,
+  verify_message_only as verifyMessageOnly,
+  verify_any as verifyAny,
+  validate_message_original as validateMessageOriginal,
+  generate_random_string_seeded as generateRandomStringSeeded,
+  generate_random_string as generateRandomString,
+  diagnose_account_key as diagnoseAccountKey,
+  diagnose_master_key as diagnoseMasterKey,
+  diagnose_identity_key as diagnoseIdentityKey,
+  diagnose_room_key as diagnoseRoomKey,
+  verify_identity_key_any as verifyIdentityKeyAny,
+  encode_message_wire as encodeMessageWire,
+  decode_message_wire as decodeMessageWire,
+  is_valid_symmetric_key as isValidSymmetricKey,
+  kem_encapsulate as kemEncapsulate,
+  kem_decapsulate as kemDecapsulate,
+  encrypt_room_key_with_account_keys_partial as encryptRoomKeyWithAccountKeysPartial,
+  rotate_device_key as rotateDeviceKey,
+  encrypt_bytes_with_symmetric_key as encryptBytesWithSymmetricKey,
+  audit_account_export as auditAccountExport,
+  compute_sign_key_hash as computeSignKeyHash,
+  sign_master_key_with_public as signMasterKeyWithPublic,
+  sign_identity_key_with_public as signIdentityKeyWithPublic,
+  sign_data_server_key_with_public as signDataServerKeyWithPublic,
+  sign_data_share_sign_key_with_public as signDataShareSignKeyWithPublic,
+  sign_data_migrate_sign_key_with_public as signDataMigrateSignKeyWithPublic,
+  encrypt_data_room_key_compressed as encryptDataRoomKeyCompressed,
+  stamp_message as stampMessage,
+  verify_server_stamp as verifyServerStamp,
+  verify_server_stamp_matches as verifyServerStampMatches,
+  group_by_key_hash as groupByKeyHash,
+  generate_identity_key_full as generateIdentityKeyFull,
+  key_fingerprint as keyFingerprint,
+  decrypt_message_or_throw as decryptMessageOrThrow,
+  canonicalize_key_json as canonicalizeKeyJson,
+  is_canonical_key_json as isCanonicalKeyJson,
+  transfer_share_to_account as transferShareToAccount,
+  sign_algorithm_for_key_type as signAlgorithmForKeyType,
+  is_valid_sign_master_key as isValidSignMasterKey,
+  is_valid_sign_server_key as isValidSignServerKey,
+  create_encrypted_attachment as createEncryptedAttachment,
+  decrypt_attachment as decryptAttachment,
+  attachment_blob_hash as attachmentBlobHash,
+  describe_key_types as describeKeyTypes,
+  is_newer_identity_key as isNewerIdentityKey,
+  key_hash_raw as keyHashRaw,
+  uuid_v7_timestamp as uuidV7Timestamp,
+  is_valid_uuid_v7_bounded as isValidUuidV7Bounded,
+  generate_identity_keys_batch as generateIdentityKeysBatch,
+  account_key_pair_matches as accountKeyPairMatches,
+  identity_key_pair_matches as identityKeyPairMatches,
+  master_key_pair_matches as masterKeyPairMatches,
+  encrypt_message_private_metadata as encryptMessagePrivateMetadata,
+  NonceGuard,
+  encrypt_with_symmetric_key_guarded as encryptWithSymmetricKeyGuarded,
+  export_validation_rules as exportValidationRules,
+  generate_kem_key_pair_seeded as generateKemKeyPairSeeded,
+  generate_dsa65_key_pair_seeded as generateDsa65KeyPairSeeded,
+  generate_symmetric_key_seeded as generateSymmetricKeySeeded,
+  is_valid_kem_key as isValidKemKey,
+  is_valid_dsa65_key as isValidDsa65Key,
+  decrypt_batch as decryptBatch,
+  deterministic_room_uuid as deterministicRoomUuid,
+  generate_dsa65_key_pair as generateDsa65KeyPair,
+  prehash_sha256 as prehashSha256,
+  create_signature_object_prehash_mlds65 as createSignatureObjectPrehashMlds65,
+  verify_prehash_mlds65 as verifyPrehashMlds65,
+  normalize_stored_message as normalizeStoredMessage,
+  verify_against_stored as verifyAgainstStored,
+  rotate_room_key as rotateRoomKey,
+  reencrypt_data_room_key as reencryptDataRoomKey,
+  create_inclusion_proof as createInclusionProof,
+  verify_inclusion_proof as verifyInclusionProof,
+  export_key_bundle as exportKeyBundle,
+  import_key_bundle as importKeyBundle,
+  merge_distributions as mergeDistributions,
+  merge_distributions_with_room_keys as mergeDistributionsWithRoomKeys,
+  validate_content_limits as validateContentLimits,
+  encrypt_to_recipients as encryptToRecipients,
+  decrypt_from_envelope as decryptFromEnvelope,
+  sign_room_settings as signRoomSettings,
+  verify_room_settings as verifyRoomSettings,
+  verify_room_settings_with_version as verifyRoomSettingsWithVersion,
+  json_to_cbor as jsonToCbor,
+  cbor_to_json as cborToJson,
+  encrypt_dm as encryptDm,
+  decrypt_dm_try_all as decryptDmTryAll,
+  encrypted_data_bytes as encryptedDataBytes,
+  encrypted_data_from_parts as encryptedDataFromParts,
+  extract_index_metadata as extractIndexMetadata,
+  is_valid_dsa87_key as isValidDsa87Key,
+  generate_dsa87_key_pair as generateDsa87KeyPair,
+  decrypt_stamped_message as decryptStampedMessage,
+  derive_room_key as deriveRoomKey,
+  RoomKeyCipher,
+  DecryptBuffer,
 } from "takos_encrypt_ink_wasm";
 import { assert, assertEquals } from "https://deno.land/std@0.224.0/assert/mod.ts";
 // ts_regacy 互換性チェック用 import
@@ -98,6 +247,10 @@ Deno.test("Identity Key Tests", () => {
   assert(isValidIdentityKeyPublic(identityKey!.publicKey), "Identity public key should be valid");
   assert(isValidIdentityKeyPrivate(identityKey!.privateKey), "Identity private key should be valid");
   assert(verifyMasterKey(masterPub, identityKey!.sign, identityKey!.publicKey), "Identity key signature from master key should be valid");
+  const shortPub = JSON.stringify({ ...JSON.parse(identityKey!.publicKey), key: "AAAA" });
+  const shortPriv = JSON.stringify({ ...JSON.parse(identityKey!.privateKey), key: "AAAA" });
+  assert(!isValidIdentityKeyPublic(shortPub), "Identity public key must be 1952 bytes");
+  assert(!isValidIdentityKeyPrivate(shortPriv), "Identity private key must be 4032 bytes");
 });
 
 Deno.test("Account Key Tests", () => {
@@ -112,6 +265,9 @@ Deno.test("Account Key Tests", () => {
 Deno.test("Room Key Tests", () => {
   const rk = generateRoomKey(sessionUUID)!;
   assert(isValidRoomKey(rk), "Room key is valid");
+  const shortKey = JSON.stringify({ ...JSON.parse(rk), key: btoa("0123456789abcdef") });
+  assert(!isValidRoomKey(shortKey), "16-byte room key rejected");
+  assertEquals(encryptDataRoomKey(shortKey, testData), undefined, "Encrypting with a short key fails instead of panicking");
   const enc = encryptDataRoomKey(rk, testData)!;
   assert(isValidEncryptedDataRoomKey(enc), "Encrypted room data is valid");
   assertEquals(decryptDataRoomKey(rk, enc), testData, "Room key decryption matches");
@@ -132,7 +288,7 @@ Deno.test("Room Key Tests", () => {
 
 Deno.test("Share Key Tests", () => {
   const [mp, ms] = generateMasterKey();
-  const sk = generateShareKey(ms, sessionUUID)!;
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
   assert(isValidShareKeyPublic(sk.publicKey), "Share public key valid");
   assert(isValidShareKeyPrivate(sk.privateKey), "Share private key valid");
   const ed = encryptDataShareKey(sk.publicKey, testData)!;
@@ -142,7 +298,7 @@ Deno.test("Share Key Tests", () => {
 
 Deno.test("Share Sign Key Tests", () => {
   const [mp, ms] = generateMasterKey();
-  const ssk = generateShareSignKey(ms, sessionUUID)!;
+  const ssk = generateShareSignKey(mp, ms, sessionUUID)!;
   assert(isValidShareSignKeyPublic(ssk.publicKey), "ShareSign public key valid");
   assert(isValidShareSignKeyPrivate(ssk.privateKey), "ShareSign private key valid");
   const hash = keyHash(ssk.publicKey);
@@ -190,7 +346,7 @@ Deno.test("Message Encryption/Decryption Tests", () => {
   const rk = generateRoomKey(sessionUUID)!;
   const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
   const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
-  const msgJson = encryptMessage(text, meta, rk, ik.privateKey, keyHash(ik.publicKey), sessionUUID)!;
+  const msgJson = encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!;
 
   // 返却される JSON ラッパーをパース
   const wrapper = JSON.parse(msgJson);
@@ -207,4 +363,2159 @@ Deno.test("Message Encryption/Decryption Tests", () => {
   const result = JSON.parse(out);
   assert(!result.encrypted, "Decrypted not encrypted");
   assertEquals(result.roomid, sessionUUID);
-});
\ No newline at end of file
+
+  const now = BigInt(Date.now());
+  assert(decryptMessage(wrapper.message, wrapper.sign, now, rk, ik.publicKey, sessionUUID, sessionUUID), "Matching session accepted");
+  const otherSession = "01890a5d-ac96-7740-b86b-2f14e9b3c2a1";
+  assertEquals(decryptMessage(wrapper.message, wrapper.sign, now, rk, ik.publicKey, sessionUUID, otherSession), undefined, "Mismatched session rejected");
+  assert(decryptMessage(wrapper.message, wrapper.sign, now, rk, ik.publicKey, sessionUUID, undefined), "No expectation skips session check");
+});
+
+Deno.test("Throwing Variants Tests", () => {
+  try {
+    encryptOrThrow(testData, "invalid");
+    assert(false, "invalid public key should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "invalidPublicKey");
+  }
+  const [mp, ms] = generateMasterKey();
+  const ak = JSON.parse(generateAccountKey(mp, ms)!.publicKey);
+  const raw = Uint8Array.from(atob(ak.key), (c) => c.charCodeAt(0));
+  const short = btoa(String.fromCharCode(...raw.slice(0, raw.length - 1)));
+  try {
+    encryptOrThrow(testData, short);
+    assert(false, "1183-byte public key should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "wrongKeyLength");
+  }
+  try {
+    generateAccountKeyOrThrow("{}", "{}");
+    assert(false, "invalid master key should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "invalidMasterKey");
+  }
+});
+
+Deno.test("Master Key Rotation Tests", () => {
+  const [oldPub, oldPriv] = generateMasterKey();
+  const ak = generateAccountKey(oldPub, oldPriv)!;
+  assert(verifyKeySignature(ak.publicKey, ak.sign, oldPub), "Original sign verifies under old master");
+
+  const [newPub, newPriv] = generateMasterKey();
+  const resigned = resignKey(ak.publicKey, newPriv, newPub)!;
+  assert(verifyKeySignature(ak.publicKey, resigned, newPub), "Re-signed key verifies under new master");
+  assert(!verifyKeySignature(ak.publicKey, resigned, oldPub), "Re-signed key fails under old master");
+  assert(!verifyKeySignature(ak.publicKey, ak.sign, newPub), "Old sign fails under new master");
+});
+
+Deno.test("EncryptedData cipherText Shape Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const roomEnc = JSON.parse(encryptDataRoomKey(rk, testData)!);
+  assert(!isValidEncryptedDataRoomKey(JSON.stringify({ ...roomEnc, cipherText: "AAAA" })), "Room data with cipherText invalid");
+  assert(!isValidEncryptedDataRoomKey(JSON.stringify({ ...roomEnc, iv: "AAAA" })), "Room data with short IV invalid");
+
+  const dk = generateDeviceKey();
+  const devEnc = JSON.parse(encryptDataDeviceKey(dk, testData)!);
+  assert(isValidEncryptedDataDeviceKey(JSON.stringify(devEnc)), "Device data valid");
+  assert(!isValidEncryptedDataDeviceKey(JSON.stringify({ ...devEnc, cipherText: "AAAA" })), "Device data with cipherText invalid");
+
+  const [mp, ms] = generateMasterKey();
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const shareEnc = JSON.parse(encryptDataShareKey(sk.publicKey, testData)!);
+  delete shareEnc.cipherText;
+  assert(!isValidEncryptedDataShareKey(JSON.stringify(shareEnc)), "Share data without cipherText invalid");
+
+  const mk = generateMigrateKey();
+  const migEnc = JSON.parse(encryptDataMigrateKey(mk.publicKey, testData)!);
+  delete migEnc.cipherText;
+  assert(!isValidEncryptedDataMigrateKey(JSON.stringify(migEnc)), "Migrate data without cipherText invalid");
+
+  const ak = generateAccountKey(mp, ms)!;
+  const accEnc = JSON.parse(encryptDataAccountKey(ak.publicKey, testData)!);
+  delete accEnc.cipherText;
+  assert(!isValidEncryptedDataAccountKey(JSON.stringify(accEnc)), "Account data without cipherText invalid");
+});
+
+Deno.test("Key-Committing Room Encryption Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const enc = encryptDataRoomKeyCommitting(rk, testData)!;
+  const parsed = JSON.parse(enc);
+  assertEquals(parsed.algorithm, "AES-GCM-commit");
+  assert(isValidEncryptedDataRoomKey(enc), "Committed room data valid");
+  assertEquals(decryptDataRoomKey(rk, enc), testData, "Committed room data decrypts");
+
+  const other = generateRoomKey(sessionUUID)!;
+  const otherCommit = JSON.parse(encryptDataRoomKeyCommitting(other, testData)!).commitment;
+  const swapped = JSON.stringify({ ...parsed, commitment: otherCommit });
+  assertEquals(decryptDataRoomKey(rk, swapped), undefined, "Mismatched commitment rejected");
+  assert(!isValidEncryptedDataRoomKey(JSON.stringify({ ...parsed, commitment: undefined })), "Missing commitment invalid");
+});
+
+Deno.test("Migrate Key Expiry Tests", () => {
+  const mk = generateMigrateKey();
+  const pub = JSON.parse(mk.publicKey);
+  assertEquals(pub.expiresAt - pub.timestamp, 10 * 60 * 1000, "Default TTL is 10 minutes");
+  const now = BigInt(Date.now());
+  assert(!isMigrateKeyExpired(mk.privateKey, now), "Fresh key not expired");
+  assert(isMigrateKeyExpired(mk.privateKey, BigInt(pub.expiresAt)), "Key expired at expiresAt");
+
+  const expired = generateMigrateKey(0n);
+  const enc = encryptDataMigrateKey(expired.publicKey, testData)!;
+  assertEquals(decryptDataMigrateKey(expired.privateKey, enc), undefined, "Expired key refuses decrypt");
+
+  const legacy = JSON.stringify({ ...JSON.parse(mk.privateKey), timestamp: null, expiresAt: undefined });
+  assert(!isMigrateKeyExpired(legacy, now), "Legacy key without expiry never expires");
+});
+
+Deno.test("Parse Message Tests", () => {
+  const msg = {
+    encrypted: true,
+    value: "{}",
+    channel: "general",
+    original: null,
+    timestamp: Date.now(),
+    isLarge: false,
+    roomid: sessionUUID,
+  };
+  assertEquals(JSON.parse(parseMessage(JSON.stringify(msg))).channel, "general");
+  try {
+    parseMessage(JSON.stringify({ ...msg, value: { type: "text" } }));
+    assert(false, "encrypted message with object value should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "invalidSchema");
+  }
+  try {
+    parseMessage("not json");
+    assert(false, "non-JSON should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "malformedJson");
+  }
+});
+
+Deno.test("EncryptedData Key Hash Match Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const other = generateRoomKey(sessionUUID)!;
+  const enc = encryptDataRoomKey(rk, testData)!;
+  assert(encryptedDataKeyMatches(enc, rk), "Room key matches its own data");
+  assert(!encryptedDataKeyMatches(enc, other), "Other room key does not match");
+  assertEquals(decryptDataRoomKey(other, enc), undefined, "Wrong room key refused before decrypting");
+
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const ak2 = generateAccountKey(mp, ms)!;
+  const accEnc = encryptDataAccountKey(ak.publicKey, testData)!;
+  assert(encryptedDataKeyMatches(accEnc, ak.privateKey), "Account private key matches via derived public key");
+  assert(!encryptedDataKeyMatches(accEnc, ak2.privateKey), "Other account key does not match");
+  assertEquals(decryptDataAccountKey(ak2.privateKey, accEnc), undefined, "Wrong account key refused");
+});
+
+Deno.test("Account Prekey Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const prekeys = generateAccountPrekeys(3, mp, ms)!;
+  assertEquals(prekeys.length, 3);
+  for (const pk of prekeys) {
+    assert(isValidAccountPrekeyPublic(pk.publicKey), "Prekey public valid");
+    assert(verifyKeySignature(pk.publicKey, pk.sign, mp), "Prekey signed by master");
+  }
+  const enc = encryptToPrekey(prekeys[0].publicKey, testData)!;
+  assert(isValidEncryptedDataPrekey(enc), "Prekey encrypted data valid");
+  assertEquals(decryptFromPrekey(prekeys[0].privateKey, enc), testData);
+  assertEquals(decryptFromPrekey(prekeys[1].privateKey, enc), undefined, "Other prekey cannot decrypt");
+  assertEquals(generateAccountPrekeys(1, ms, mp), null, "Swapped master keys rejected");
+});
+
+Deno.test("Streaming Message Verification Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const message = "large message body ".repeat(1000);
+  const sign = signIdentityKey(ik.privateKey, message, keyHash(ik.publicKey))!;
+  const bytes = new TextEncoder().encode(message);
+
+  const verifier = new MessageVerifier(ik.publicKey, sign);
+  for (let i = 0; i < bytes.length; i += 1024) {
+    verifier.update(bytes.subarray(i, i + 1024));
+  }
+  assertEquals(verifier.finalize(), verifyIdentityKey(ik.publicKey, sign, message), "Chunked and one-shot agree");
+  assert(verifyIdentityKey(ik.publicKey, sign, message), "One-shot verification succeeds");
+
+  const tampered = new MessageVerifier(ik.publicKey, sign);
+  tampered.update(bytes.subarray(0, bytes.length - 1));
+  assert(!tampered.finalize(), "Truncated stream fails");
+
+  try {
+    new MessageVerifier(ik.privateKey, sign);
+    assert(false, "private key should be rejected");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "invalidIdentityKey");
+  }
+});
+
+Deno.test("IV Collision Detection Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const clean = [0, 1, 2, 3].map((i) => encryptDataRoomKey(rk, `${testData} ${i}`)!);
+  assertEquals(JSON.parse(findIvCollisions(JSON.stringify(clean))!), [], "Clean set has no collisions");
+
+  const reused = JSON.stringify({ ...JSON.parse(encryptDataRoomKey(rk, "other")!), iv: JSON.parse(clean[1]).iv });
+  const batch = [...clean, reused];
+  assertEquals(JSON.parse(findIvCollisions(JSON.stringify(batch))!), [[1, 4]], "Reused IV detected");
+
+  const otherKey = generateRoomKey(sessionUUID)!;
+  const sameIvOtherKey = JSON.stringify({ ...JSON.parse(encryptDataRoomKey(otherKey, "x")!), iv: JSON.parse(clean[0]).iv });
+  assertEquals(JSON.parse(findIvCollisions(JSON.stringify([clean[0], sameIvOtherKey]))!), [], "Same IV under different keys is not a collision");
+
+  const urlSafe = (b64: string) => b64.replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+  const c0 = JSON.parse(clean[0]);
+  const respelled = JSON.stringify({ ...JSON.parse(encryptDataRoomKey(rk, "y")!), keyHash: urlSafe(c0.keyHash), iv: urlSafe(c0.iv) });
+  assertEquals(JSON.parse(findIvCollisions(JSON.stringify([clean[0], respelled]))!), [[0, 1]], "Re-encoded keyHash / IV still collide");
+});
+
+Deno.test("Armored Key Export Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const ak = generateAccountKey(mp, ms)!;
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const ssk = generateShareSignKey(mp, ms, sessionUUID)!;
+  const srv = generateServerKey();
+  const mk = generateMigrateKey();
+  const keys = [
+    mp, ms,
+    ik.publicKey, ik.privateKey,
+    ak.publicKey, ak.privateKey,
+    sk.publicKey, sk.privateKey,
+    ssk.publicKey, ssk.privateKey,
+    srv.publicKey, srv.privateKey,
+    mk.publicKey, mk.privateKey,
+    generateRoomKey(sessionUUID)!,
+    generateDeviceKey(),
+  ];
+  for (const key of keys) {
+    const armored = toArmored(key)!;
+    assert(armored.includes(`Key-Type: ${JSON.parse(key).keyType}`), "Armor header carries keyType");
+    assertEquals(fromArmored(armored), key, "JSON -> armored -> JSON is identity");
+  }
+
+  const armored = toArmored(mp)!;
+  assert(armored.startsWith("-----BEGIN TAKOS MASTER KEY PUBLIC-----"));
+  const lines = armored.split("\n");
+  lines[3] = lines[3].slice(0, -1) + (lines[3].endsWith("A") ? "B" : "A");
+  assertEquals(fromArmored(lines.join("\n")), undefined, "Corrupted body fails checksum");
+  assertEquals(fromArmored(armored.replace("Key-Type: masterKeyPublic", "Key-Type: masterKeyPrivate")), undefined, "Mismatched label rejected");
+});
+
+Deno.test("Send Text Message Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const wrapper = JSON.parse(sendTextMessage("hello", rk, ik.privateKey, ik.publicKey, sessionUUID, "general")!);
+  const meta = JSON.parse(wrapper.message);
+  assertEquals(meta.channel, "general");
+  assertEquals(meta.isLarge, false);
+  assert(Math.abs(meta.timestamp - Date.now()) < 60000, "Timestamp is current");
+
+  const out = JSON.parse(decryptMessage(wrapper.message, wrapper.sign, BigInt(Date.now()), rk, ik.publicKey, sessionUUID)!);
+  assertEquals(JSON.parse(out.value.content).text, "hello");
+
+  const large = JSON.parse(sendTextMessage("x".repeat(70 * 1024), rk, ik.privateKey, ik.publicKey, sessionUUID, "general")!);
+  assertEquals(JSON.parse(large.message).isLarge, true, "Large text sets isLarge");
+});
+
+Deno.test("isLarge Threshold Tests", () => {
+  const threshold = 64 * 1024;
+  assert(!shouldBeLarge("a".repeat(threshold - 1)), "threshold - 1 is not large");
+  assert(!shouldBeLarge("a".repeat(threshold)), "threshold is not large");
+  assert(shouldBeLarge("a".repeat(threshold + 1)), "threshold + 1 is large");
+
+  assert(!shouldBeLarge("a".repeat(9), 10), "Custom threshold - 1");
+  assert(!shouldBeLarge("a".repeat(10), 10), "Custom threshold");
+  assert(shouldBeLarge("a".repeat(11), 10), "Custom threshold + 1");
+  // 4 文字だが UTF-8 では 12 バイト
+  assert(shouldBeLarge("ああああ", 10), "Multi-byte content measured in bytes");
+
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const small = JSON.parse(sendTextMessage("hello", rk, ik.privateKey, ik.publicKey, sessionUUID, "general", 8)!);
+  assertEquals(JSON.parse(small.message).isLarge, true, "Overridden threshold applies to send helper");
+});
+
+Deno.test("Share Key Room Key Distribution Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const a = generateShareKey(mp, ms, sessionUUID)!;
+  const b = generateShareKey(mp, ms, sessionUUID)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const out = JSON.parse(distributeRoomKeyToShareKeys(JSON.stringify([a.publicKey, a.privateKey, b.publicKey]), rk)!);
+  assertEquals(out.skipped, [1], "Invalid share key skipped");
+  assertEquals(out.distribution.length, 2);
+  assertEquals(out.distribution[0].keyHash, keyHashRaw(JSON.parse(a.publicKey).key));
+  assertEquals(decryptDataShareKey(a.privateKey, out.distribution[0].encryptedData), rk);
+  assertEquals(decryptDataShareKey(b.privateKey, out.distribution[1].encryptedData), rk);
+});
+
+Deno.test("Select And Decrypt Room Key Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const [alice, bob, carol] = [0, 1, 2].map(() => generateAccountKey(mp, ms)!);
+  const rk = generateRoomKey(sessionUUID)!;
+  const wrapped = encryptRoomKeyWithAccountKeys(JSON.stringify([
+    { userId: "alice", accountKey: alice.publicKey },
+    { userId: "bob", accountKey: bob.publicKey },
+    { userId: "carol", accountKey: carol.publicKey },
+  ]), rk)!;
+  // bob の分だけを残す
+  const onlyBob = JSON.stringify(JSON.parse(wrapped).filter((e: { userId: string }) => e.userId === "bob"));
+  const others = JSON.stringify(JSON.parse(wrapped).filter((e: { userId: string }) => e.userId !== "bob"));
+
+  assertEquals(selectAndDecryptRoomKey(wrapped, bob.privateKey), rk, "Middle recipient finds its entry");
+  assertEquals(selectAndDecryptRoomKey(onlyBob, bob.privateKey), rk);
+  assertEquals(selectAndDecryptRoomKey(others, bob.privateKey), undefined, "No matching entry");
+
+  const dave = generateAccountKey(mp, ms)!;
+  assertEquals(selectAndDecryptRoomKey(wrapped, dave.privateKey), undefined, "Non-recipient gets nothing");
+});
+
+Deno.test("Master Key Algorithm Field Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  assertEquals(JSON.parse(mp).algorithm, "ML-DSA-87", "New master keys record their algorithm");
+  assert(isValidMasterKeyPublic(mp) && isValidMasterKeyPrivate(ms));
+
+  const legacyPub = JSON.stringify({ ...JSON.parse(mp), algorithm: undefined });
+  const legacyPriv = JSON.stringify({ ...JSON.parse(ms), algorithm: undefined });
+  assert(isValidMasterKeyPublic(legacyPub), "Legacy public key without algorithm accepted");
+  assert(isValidMasterKeyPrivate(legacyPriv), "Legacy private key without algorithm accepted");
+  assert(generateAccountKey(legacyPub, legacyPriv), "Legacy master keys still sign");
+
+  assert(!isValidMasterKeyPublic(JSON.stringify({ ...JSON.parse(mp), algorithm: "ML-DSA-65" })), "Other algorithm rejected");
+});
+
+Deno.test("Hybrid Identity Key Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKeyHybrid(sessionUUID, mp, ms)!;
+  assert(isValidIdentityKeyPublic(ik.publicKey) && isValidIdentityKeyPrivate(ik.privateKey), "Hybrid keys valid");
+  assert(verifyMasterKey(mp, ik.sign, ik.publicKey), "Hybrid key signed by master");
+
+  const sign = signIdentityKeyHybrid(ik.privateKey, testData, keyHash(ik.publicKey))!;
+  assertEquals(JSON.parse(sign).algorithm, "Ed25519+ML-DSA-65");
+  assert(verifyIdentityKeyHybrid(ik.publicKey, sign, testData), "Hybrid signature verifies");
+  assert(verifyIdentityKey(ik.publicKey, sign, testData), "verify_identity_key handles hybrid keys");
+
+  const tamper = (index: number) => {
+    const obj = JSON.parse(sign);
+    const bytes = Uint8Array.from(atob(obj.signature), (c) => c.charCodeAt(0));
+    bytes[index] ^= 0x01;
+    return JSON.stringify({ ...obj, signature: btoa(String.fromCharCode(...bytes)) });
+  };
+  assert(!verifyIdentityKeyHybrid(ik.publicKey, tamper(0), testData), "Tampered Ed25519 half fails");
+  assert(!verifyIdentityKeyHybrid(ik.publicKey, tamper(100), testData), "Tampered ML-DSA-65 half fails");
+
+  // ML-DSA-65 単独の署名ではハイブリッド鍵の検証は通らない
+  const pqOnly = JSON.parse(sign);
+  const pqBytes = Uint8Array.from(atob(pqOnly.signature), (c) => c.charCodeAt(0)).slice(64);
+  const pqSign = JSON.stringify({ ...pqOnly, algorithm: "ML-DSA-65", signature: btoa(String.fromCharCode(...pqBytes)) });
+  assert(!verifyIdentityKey(ik.publicKey, pqSign, testData), "PQ-only signature rejected for hybrid key");
+
+  const classic = generateIdentityKey(sessionUUID, mp, ms)!;
+  assertEquals(signIdentityKeyHybrid(classic.privateKey, testData, keyHash(classic.publicKey)), undefined, "Non-hybrid key cannot hybrid-sign");
+});
+
+Deno.test("Key Set Validation Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const [otherPub, otherPriv] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const ak = generateAccountKey(mp, ms)!;
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const foreign = generateAccountKey(otherPub, otherPriv)!;
+  const entries = [
+    { keyType: "identityKeyPublic", keyPublic: ik.publicKey, sign: ik.sign },
+    { keyType: "accountKeyPublic", keyPublic: ak.publicKey, sign: ak.sign },
+    { keyType: "shareKeyPublic", keyPublic: sk.publicKey, sign: sk.sign },
+    { keyType: "accountKeyPublic", keyPublic: foreign.publicKey, sign: foreign.sign },
+    { keyType: "shareKeyPublic", keyPublic: ak.publicKey, sign: ak.sign },
+    { keyType: "accountKeyPrivate", keyPublic: ak.privateKey, sign: ak.sign },
+    { keyType: "accountKeyPublic" },
+  ];
+  const report = JSON.parse(validateKeySet(mp, JSON.stringify(entries))!);
+  assertEquals(report.valid, [0, 1, 2]);
+  assertEquals(
+    report.failed.map((f: { index: number; reason: string }) => [f.index, f.reason]),
+    [[3, "invalidSignature"], [4, "keyTypeMismatch"], [5, "notMasterSigned"], [6, "malformedEntry"]],
+  );
+  assertEquals(validateKeySet(ms, "[]"), undefined, "Private master key rejected");
+});
+
+Deno.test("Symmetric IV Length Guard Tests", () => {
+  const key = generateSymmetricKey();
+  const enc = encryptWithSymmetricKey(testData, key);
+  assertEquals(decryptWithSymmetricKey(enc.encrypted_data, enc.iv, key), testData);
+
+  const iv11 = btoa(String.fromCharCode(...new Uint8Array(11)));
+  const iv16 = btoa(String.fromCharCode(...new Uint8Array(16)));
+  for (const [iv, label] of [[iv11, "11-byte"], [iv16, "16-byte"], ["", "empty"]]) {
+    try {
+      decryptWithSymmetricKey(enc.encrypted_data, iv, key);
+      assert(false, `${label} IV should throw`);
+    } catch (e) {
+      assertEquals((e as { code: string }).code, "wrongIvLength", `${label} IV rejected cleanly`);
+    }
+  }
+  try {
+    decryptWithSymmetricKey(enc.encrypted_data, enc.iv, btoa("short key"));
+    assert(false, "short key should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "wrongKeyLength");
+  }
+
+  // EncryptedData 経由でも panic せず undefined
+  const dk = generateDeviceKey();
+  const devEnc = JSON.parse(encryptDataDeviceKey(dk, testData)!);
+  assertEquals(decryptDataDeviceKey(dk, JSON.stringify({ ...devEnc, iv: iv11 })), undefined);
+});
+
+Deno.test("Detached Signature Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rawPub = JSON.parse(ik.publicKey).key;
+  const rawPriv = JSON.parse(ik.privateKey).key;
+  const hash = keyHash(ik.publicKey);
+
+  // wrapped -> detached
+  const sign = signIdentityKey(ik.privateKey, testData, hash)!;
+  const detached = extractSignature(sign)!;
+  assert(verifyDetachedMlds65(rawPub, testData, detached), "Extracted signature verifies detached");
+  assertEquals(JSON.parse(wrapSignature(detached, hash, "identityKey", "ML-DSA-65")), JSON.parse(sign), "Wrap(extract(sign)) round-trips");
+
+  // detached -> wrapped
+  const raw = signDetachedMlds65(rawPriv, testData)!;
+  const wrapped = wrapSignature(raw, hash, "identityKey", "ML-DSA-65");
+  assert(verifyIdentityKey(ik.publicKey, wrapped, testData), "Wrapped detached signature verifies");
+  assertEquals(extractSignature(wrapped), raw, "Extract(wrap(raw)) round-trips");
+  assertEquals(extractSignature("not json"), undefined);
+});
+
+Deno.test("Replay Guard Tests", () => {
+  const guard = new ReplayGuard();
+  const t0 = 1_700_000_000_000n;
+  const [sigA, sigB] = [btoa("sig-a"), btoa("sig-b")];
+  assert(guard.check_and_record(sigA, t0), "First sighting accepted");
+  assert(!guard.check_and_record(sigA, t0 + 1000n), "Duplicate within window rejected");
+  assert(guard.check_and_record(sigB, t0 + 1000n), "Different signature accepted");
+  assert(guard.check_and_record(sigA, t0 + 120_001n), "Re-accepted once evicted after the window");
+  assertEquals(guard.check("not base64!", t0), "malformed", "Signatures that do not decode are rejected");
+
+  const small = new ReplayGuard(undefined, 2);
+  const [x, y, z] = [btoa("x"), btoa("y"), btoa("z")];
+  assertEquals(small.check(x, t0), "accepted");
+  assertEquals(small.check(y, t0), "accepted");
+  assertEquals(small.check(z, t0), "saturated", "New signatures rejected at capacity");
+  assertEquals(small.check(x, t0 + 1000n), "replayed", "Live entries are not evicted to make room");
+  assert(!small.check_and_record(z, t0 + 1000n), "Still saturated within the window");
+  assertEquals(small.check(x, t0 + 120_001n), "accepted", "Entries past the window free capacity");
+  assertEquals(small.check(x, t0 + 121_000n), "replayed", "Replay after eviction is still caught once re-recorded");
+
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const wrapper = JSON.parse(sendTextMessage("hi", rk, ik.privateKey, ik.publicKey, sessionUUID, "c")!);
+  const now = BigInt(Date.now());
+  const g = new ReplayGuard();
+  assert(decryptMessageGuarded(wrapper.message, wrapper.sign, now, rk, ik.publicKey, sessionUUID, undefined, g), "First delivery decrypts");
+  assertEquals(decryptMessageGuarded(wrapper.message, wrapper.sign, now + 5000n, rk, ik.publicKey, sessionUUID, undefined, g), undefined, "Replay rejected");
+
+  // 署名の Base64 表記を変えただけの再送も同じ署名として拒否する
+  const sign = JSON.parse(wrapper.sign);
+  const reencoded = JSON.stringify({ ...sign, signature: reencodeBase64(sign.signature, true) });
+  assert(reencoded !== wrapper.sign);
+  assertEquals(decryptMessageGuarded(wrapper.message, reencoded, now + 6000n, rk, ik.publicKey, sessionUUID, undefined, g), undefined, "Re-encoded replay rejected");
+  assertEquals(g.check(reencoded, now + 6000n), "replayed");
+});
+
+Deno.test("Thumbnail Link Validation Tests", () => {
+  const original = createImageContent("https://example.com/a.png", "a.png", "image/png", undefined, undefined, undefined)!;
+  const size = BigInt(new TextEncoder().encode(original).length);
+  const thumb = createImageContent("https://example.com/a_s.png", "a_s.png", "image/png", true, contentId(original), size)!;
+  assert(validateThumbnailLink(thumb, original), "Matching link accepted");
+
+  const other = createImageContent("https://example.com/b.png", "b.png", "image/png", undefined, undefined, undefined)!;
+  assert(!validateThumbnailLink(thumb, other), "Wrong thumbnailOf rejected");
+  const badSize = createImageContent("https://example.com/a_s.png", "a_s.png", "image/png", true, contentId(original), size + 1n)!;
+  assert(!validateThumbnailLink(badSize, original), "Wrong originalSize rejected");
+  const notThumb = createImageContent("https://example.com/a_s.png", "a_s.png", "image/png", undefined, contentId(original), size)!;
+  assert(!validateThumbnailLink(notThumb, original), "Content without isThumbnail rejected");
+
+  const t = JSON.parse(createThumbnailContent("image", "https://example.com/a_s.png", "image/png", size)!);
+  assertEquals(t.originalType, "image");
+  assertEquals(t.thumbnailMimeType, "image/png");
+  assertEquals(JSON.parse(createThumbnailContent("text", "hello", undefined, undefined)!).thumbnailText, "hello");
+  assertEquals(createThumbnailContent("image", "https://example.com/a_s.png", undefined, undefined), undefined, "Image thumbnail needs a MIME type");
+  assertEquals(createThumbnailContent("text", "hello", "text/plain", undefined), undefined, "Text thumbnail takes no MIME type");
+});
+
+Deno.test("Unchecked Account Key Encryption Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  assert(isValidAccountKeyPublic(ak.publicKey));
+  const enc = encryptDataAccountKeyUnchecked(ak.publicKey, testData)!;
+  assert(isValidEncryptedDataAccountKey(enc), "Unchecked output has the same shape");
+  assertEquals(decryptDataAccountKey(ak.privateKey, enc), testData);
+});
+
+Deno.test("ML-DSA Signature Normalization Tests", () => {
+  assertEquals(signatureRawLenMlds65(), 3309, "FIPS 204 ML-DSA-65 signature length");
+  assertEquals(signatureRawLenMlds87(), 4627, "FIPS 204 ML-DSA-87 signature length");
+
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rawPub = JSON.parse(ik.publicKey).key;
+  const sig = signDetachedMlds65(JSON.parse(ik.privateKey).key, testData)!;
+  const bytes = Uint8Array.from(atob(sig), (c) => c.charCodeAt(0));
+  assertEquals(bytes.length, signatureRawLenMlds65());
+  const normalized = normalizeSignatureMlds65(sig)!;
+  assertEquals(normalized, sig, "Canonical signature is unchanged");
+  assert(verifyDetachedMlds65(rawPub, testData, normalized));
+
+  assertEquals(normalizeSignatureMlds65(btoa(String.fromCharCode(...bytes.slice(1)))), undefined, "Wrong length rejected");
+  assertEquals(normalizeSignatureMlds87(sig), undefined, "ML-DSA-65 signature is not an ML-DSA-87 signature");
+  // ヒント部 (末尾 k バイトは累積インデックスで ω 以下) を壊すとデコードできない
+  const badHint = bytes.slice();
+  badHint.fill(0xff, badHint.length - 6);
+  assertEquals(normalizeSignatureMlds65(btoa(String.fromCharCode(...badHint))), undefined, "Malformed hint encoding rejected");
+
+  const masterSig = JSON.parse(signMasterKey(ms, testData, keyHash(mp))!).signature;
+  assertEquals(normalizeSignatureMlds87(masterSig), masterSig, "ML-DSA-87 signature round-trips");
+});
+
+// OpenSSL 3.5 で生成した ML-DSA-65 (FIPS 204, context なし, 決定的署名) のベクタ
+//   openssl genpkey -algorithm ML-DSA-65 -pkeyopt hexseed:000102...1f -out sk.pem
+//   openssl pkeyutl -sign -rawin -inkey sk.pem -in msg -pkeyopt deterministic:1
+// 公開鍵は SPKI DER の末尾 1952 バイト, msg は "hello world"
+const MLDSA65_KAT_PUBLIC_KEY =
+  "SGg9kZeOMes93biwRzSC0riKX2JZSf2PWKVh5pa9TCfQWzjbsu3wHmZO/YG+HqiTaIzmiqLVHFlY+LvG606J7mfSwDIJVNVyEsrHIp/x1urwOSi9UVEfjYjYR3NsfeJzDVl45UEHExYJeIZ3Eb9VOaC/xMNQwr5XK68O4uL7Fsz+oIAo2ZrEmuu3WTfdzhEc2rYv/zzqi6IjPR5W+8XFoecm3mP63SrwFrEZF3+j2XGi2Sdxc/zlW2d0WvC3wh1Zfb65Pmoy80HEmlqL6eglCI0fKqRRVdbIrhU2fk6wA7j994UQcZSXOfn/8JAj6vRRBNKoSkWQbu1GcaRNwo0nmHu1XfaenoVh9hqApyaZUDhl/tm37nKo4XoZxAgUT0spr+9wMcOm2FcWELQsn0ISRaiPGX4WgSsDEVm2W5aH5bPpNMUiWumKebpz0rOZ1zUQ7/rRnlO4RQ8LqPzhAS/ZjSYKdKqqE/riSaAGscNPW6C4gvJjeCIvs28ig8JD8P/rXxu0FKCnDVXj1ApWtsvIiuHwO3sogtmN7qKOFFyd7f2OrxzvLtlKiwUPiWT0bR6g0MKkPg3aYYKtv09u0XW2dCJXhZvyLzpBfs8fnYkxe15TnVh68WueExPgRRT/pkuos/8rgyH4gRyz+wIsj2ROcKS4Ci+/7mBKu3N5CR6o5sXHTfwCg2ZrQMB5OHACggShNr9dqVaOt5jTSQOL2wwR4DRF54R8tQacdc8orGAcd5nZWCEN28siblGv758d5HsHOHPW0/l0Vr7eCFCC50opiyzUj0swkxVfNmyPpgHGr4WN+jLAhJGyopiH+QM1lJpdbtqmeYgqOpXWv22XCiIfS509jL84SvgarJXisylOBHiayDcnpdwEVZ+Wr0HYoFNRb+7uvFJ0brarKBngkQhxDYNfAR+mMGWHKtM01c3/srIxBQfpL8mTrjF9qX9PMJza8PZ+2Z2QIVV2CDhJ+VOyRtf+2z/bZ2eYUKWtQE5kFH+3z09q7d0Fr7S4NJaNH+iAFJYNzl2UIjZSbhKkeNaeX75pcDELMIwGhFAYz8eyq0MKE6axrHuwLMy7PZEawvEQaGE/vgKb/c4Cz1zTiVDtcsg5RO37x1YVr4f4ZMBR88VUVsVBKGOkDAbR2rVivf8FcbjTw5F7vTAIgLul6Zgjm5X6kbfWQW1POYs6280wmD7TWStNnvfUI2/QD1DZiqU6I1rEFycg932WFyZymAz+j/elpwJ4PtwroxsiWQFaES/H9GipwvlGQDkALTDvZ4tMt5i8EWIWv3qafBi6A7e1j9B1FdMRUEnTYUvnoH50QwB1DfHSxYdTOJBZ6vw9eFzN0xwHZIvtwDpcO4rUbQZNWcE9VzdHKfxOKVNi4qUZEgRTBCi8FSKvoo/1/hZV4wTKW8jCetDgxqOd1N8olWwUs4zJNoLO/kArvV6C0pxGTkTrXTe0j8Vo3+DMbo4WuuoF5RNVkPGSlOc+g2ewIW27gVAwud5VkT8IA5xCNRxZ5VFd1a+OCJoV5iXo9t7mOThsRkl9eiYyiHdN5YGn3pYptBtEJBQfl4+4MxII797DxuDeObxXBj89zWxHA3PAiJHqKcvHzG1kg7iIkIOs6GqntRscLP5uKtGNl842+8VupC+ul+anrBFIZEeMNm3x67HnsRqQmFBP1Zdb3x9J3HAAK2PBc5qdJj+61Ac/ap9sK4r0tMMyoQOgz/pd7rLQYso8IV/TYAJr58UWT0pEJO90lIgE1m9GSHcyyCAseVR4ZHtOpx1ifAhgJMyjVKQfCHezjxmzd0rSCVyNpTsGniHHauLSAH4WcZ7UAIDTNPfaUun1pZkEOcrwg6lbgz8CrRCgjBptDyYMAHKFvUovR3A6Wu9GUofSU7GKwiUUMWIQ/1ZoFLEPh6KT1vGZ08OVmZDQwSaLT1DV+fzvu/I3vQwouAGC1mWXQfFPEL+7IbuhKrYgqiOW9WwGhrTqkBeZAiQhay/orXbEqRSO75qGo2Naaqd7wdz7b7pZp339qbdTDcDKhkjI2XNzjgG6uPCLSQXoSqRkG9YCQQzZdSAmXy8jHys14V6y+gTSvZTVp3q68eDhYQEKmQCH9bRuqYiyvAUS/aD6kj2t1sRcUwHQlINnMmW1qy4Q9LpSD2u61WSlw9Xie9sID30g4TKWoxgZVMOcZJyUPr4X31wfeq4Kj+EmxHdYWl1NZIoNAItq9ejNMb5pqSltTz/SXthvIh5Lk/ZfWSmWdTNiS5I1dQwwcHVQtYU20QmnExxaW75KVxVWfBJTSux2YHYe67n64okcd0WJuA5WatVX3e9zZxlrcifqmHDvCd3+x51rkxmmh5tSBddr96ulrPM6+1nRf8VOaDg9a+Wgjptm2lPc3gCLspS4WCvRMs3MSZWf28IeUnIYgMitA1LHnwOkO72ExM39xsUpAF4efNmjSacWijVWm6XeqBiWjVqRRmvW5k4gv2JBcZivxOgcKN137UAoIyOYtS+96GvIT0dbkBZxDOKqvBGga026yQHsFs82XKPy1TgTlIppOg+T55xGyl1abco9KMpQrRi9E/ylUFndmxhfefnEcZak6BshBLxGCgUeAvLoRE8=";
+const MLDSA65_KAT_SIGNATURE =
+  "zy7RR/fbz2LikG4iw2ZTFUcAADQhQ/L1D0ryEdEfKn4fONN2xcDN4TXleV+0Z1gkRgXqVU2Qp8GzAkShZAdeAc+mEdxnrghcdcaP8W1SBy8JOvVn18LcJQ1I6YSlkDue80BISPjm6hsoDzaWNPfMmC/FR8XbQPG3mZpWbnK3vRdqw65K+L9UkJSBRqZpp169a7QyfK4biCXzd0IOFOylxjKJOnSM2y3mVt5Zg2eRVJlGtkd0knkmZr5hJP0JRmAMgYOSFi7FhFvzTZPHjAQYWI1lgOLPepCaVY979YaN/EHp+VRaeaW5vh7T8+BL5pE9d9oyfK9Z2uHftoQE6uiltghjUhsTkOSWY3r4KsoVgYD7ktuHDpSbsUaqD+eju6ogSP+TmYR774rNbaLPRIJb93meMlZHMWuV9kGLC6HGW2szyUAB8heADc7tiEqZRIDb+H3jlUbuUw2UTsgwPVPwwAkZJ+pLXDOeQAz3gIqT10JCAliQH19F2i66aOI0ztLjsS4FrUhf0GnTcqlrbShXjv/BZAodntX0XPb5kk0z2ziWLihsmEHti9tRHvg4bsLL8Uv/nvejBMvMOgqigqANs/FEV8HYynXQ8m0W8le8LHawgVJ8GpaUvV8yq0bDoWXGMLVNqqI6uvHpqqtQCPkdOIy3QekMmEGEGIOfeUB880YHH2ky27F9yvXYrNoWlW/j4NDR2knYKeEq1p8hktHi5Gf41pORm2gb/VhK4KEVtCHuWHveeD+k6iigpp7d2zd9dXaxM8I9Bsu+Do6isvt36TV8chFXNdvpXSzcOmAjqgHP+ytlZUF05BH5sK/QO9C202whJY81gXXI4I16BfbTl+2w+QuoLYthoJdoRI9BF2rPR+8XNh4fb4hPEd5cWFpo93/l7b7wHMAxcelN0EX7nOfQjaXd9uHeew3gt77enqCJ19rRqQNuBg3ul/Hjq+Z7D4ND83uk4Cgfw2VeVd0jt3L8HFw8tgHDRLWS74Y+X8LZK0TQR8q/sfERxjDaAzDW4FHhnpDludpWRZrmdMUUDmdxgzUhgDyVGdrdhecKYzGa7bVo0/4fkLKP9AxbQFXwJKry3rHfpDOlXfsZKgMH7il8n3Ht1mvi0l+Ni2UoTLAs/XPS4I81fF50gYvLt0s8f96c3/zQO9y9nxxD56nXedTubcpYCccybaMqoM5Y1NFHx6Fmn0FAgAXf6+7DmZqthg/8WjtCjovqbnfvYreEeFjeIuyesKe2mJPzk2hKx2Pg+CGAxWCfoxLz+4mXGBtOdD5HSIH59kymNlZguI6shDmoe3DUNtij0hf+f0aU5F3OlYJJmyePvqHpmC7rDU1j5h8D3NR4FoFgWSdPQljWqCGDksVPhdfaZJ7OrYk/7ZUOgucwXazFQV5w3vk6l+efXxT6CuGv36DJbyeBKBETmniAbsUgPhtH2Dv+F2HGuNYI71ZqrWBWmtsCSd0Dz5s44cuHKcaHoZBhe4mmKa2rSZhcJeLK4BajRu1X+BaVpukLJF6ImJp4td5ikkjONHSq6NyDp2ZTTcFnDyW+JC97ZQhr65YUrOssIUiAH8Bx8bCr4nSywdHwPeTXjGTq6ks6qHxJrPU29/kOgAe2kfRCxkez5FNQuAnZJ2YsBPk+tJMINxUxEDGDPyz8mgM0twwxphANOoVi+BanB3SxNvq8j8wJDLPeUJQb2tAPmGljSFQpJ0dBxuW4Th50nQRV4+2lSUYKZNKqkDQg73pkxvY7zzJHGMjK9DIogvMq8TVDPI+fitRqs+PO2ghPOLnSMGzSm9wQoqpOW128nGEfX/RK5T5btFhz2VdJ3TYiBipk70Kr2+dQlTcrfnAqTbNMvM2Z2ZDn0GfzFQpO0gKexzBNrlxyPfkU8NCW+mBJVdjG5ZRHJ5WzfVpE3vhji0kGCy+z2XU5pWHU88gAVQnml04gc2Hniv7daxtyvKrB5e6tQ2W5XpnTCKHEyU9JtVmm2/ssVvxqBDerIjBxPzfcVUgeRNLDzYWhypKAAKjPLvJCyJG0iNNUfGOTAJlKJcEZQQM4ZLwbY65NG7ru1I1oG6DtOywqZmaAldWPF2Ro137n5ukuZkfOW5S/0M8Q9/dWWqZpMZlvidHMkqcx32e8oEWWfEoIXlfjGPTpw1DM3xWsb8r7pI20/NafYmLgxfRbierEPiPTYHMqMiYJlSO/QKGYeyfxclZBzf/x7zvfdmKsxEQdsCQ8WZL4a+lFEIeUyGIsJNrBbdE1gvE7Y7Uwp/zUh6Dpq8FSeLZ3a+QanQMvlQcvC1NTQx9z65pPnzv5wRV5Vd+TpD2QiI9N/H9AYsrL6YV+E9sG0wZpGgpEmlrAYv0KgxrYUsMEYP7LklbZUmVg1Da/632mWJq7yAaH8lRujmui6wNUObGzMd++LVO5C/lWcDzL/ysCubAde+td8TfFdhLcFDy6uZywZPKS9/4WBAlgeKKzalpPNBqgOkwoPosEFIFL/9dUlBoYP1jTlQAFHqO1HlyInY7OzQEGj6yNog3kaCE8ma/W3crT0zzDDo/pVp8PTNWUOew7rK+9CvWaQA8bgtm2HIHhkdbn4GLZwpakvM7mFxSY1ytdS1R0osLdddhNYly1s1rcxplK9Saau+LylfaCFD78Jf1FWNwenGUw7RorTEJgxoHiOKHZF+xk37vIp09EfnkjbD3aSoP7j08datLLv/ivdZvi+bcF3nybpxM47iwyrZm/TqD/XMjojvBJcoKs2MKgCChMSZ2Dk5C/XDnvH3Fl9QZJrUtDUQMbPsNvgCnKeE1k6NPrLLCNbAMW9HKqP0W/YJz48S5PUCdvCg20C6BBaYwEUTMBSdbevtztfewi3mur1WBslREiEHKpE7fBHa4ZGqEo7FwqgLTB818etr9ccUUUnetuY7UcQxyTeK8/56GVGzdk0uqsvSAi9dHI/qLLqHCOsR7B3XYPLCyZvpRgAcnp1A4pSwy9Iwzfxp26aKmtg4nlbKIuLbV9NASJEb8jqJZtzY+Nh/aGZJBKFcL62Jp2QwBsL1HQHwo65NyTSB3Xd/5g6PiuOysXk4BEl6GBMko/B3hHUt0Ea6hCxs2jUBt7wX2c2hmSVEdcn7q/V9xgqxrmpA9iQfuQQGu5Ea77jy2jOyvUMf2U66v3aBxVU5ayz5Ryp1xR3Ec8JMxB1yoPk0BVuD/TgxW8KoXLDyJHEjROSBmXCa5vUaKDLpbG6PZg3Jr7WwDIfUliwOL5pUHlQWYaNrHTmAuqLfSrX4K3jM/hDnRn5+K7xu0Wn7V8TJiuMKEU34OCtfm5MySF7aDh71R0QJmxBfLcCU+QYjA1QHKVv+d7Jtrju4Gv3noX/FPc/MkCg2d0Z7zsVlSaBXkuMn4B6Rl8+OH1TBjSNsgNeaYZ7SZ3iOnS+5V5VqyRHnZcho3cvepY4nGsPMXglrEy0WywNoVFHd/00psxQ+2vYeG/IH4GDtcV618I7U9aeT57RbqaDuzpVBDsUpPCbp4h4LdRijDHwK4icWRVX75o3pQypLXg8yFIDTVySJEmc9hiRpTRci6aJxWsJS6hOJzPACWpSs+eJOoUAqO9yVxUvQkdmx7rnUaBUsdABwJSUCqfVf5yKNCgaxm9NFrwTr4IUzQCjPCiuVOM2Ea4voqXsMeC1CPJAYLquAPfqNlMIlG4dLhHKhmJn0B/vILh0XGG5+uLC2g8ACRGXAGxyptd6gO83/pDnOYQFwbCbtxeGknjJh9oAGvSCFo6mVjiOxRx0yY6E/FvvfYb7f4HpLGzC86QLRlYXBO0iZmF5+WnuDwhEvgFVMKIXVJkBDdqSqgJTZTzgTvFI0C6bASi+hsU+8xW2haBUy5newCZzCJznKmFk3+CteaAec2/sTlNpjHUZRydCG+6550OJcesS/mXud+2XsBk6r3ZGCGSnxOwNudf3OyGbZazz1Gw5aImA/yYbIjd51Z6u2FTmhDi6ogNSF5QdMknJ3v/jfQ57bhqaw6MlpPGPIxTNTjyL3nFltB94IBH07L4J104xIgKvdDVOm48d0KxcLlWB+LSBYMVKIdiOL49Ac7RWcvxXiYyxaUktIHnaMfo2LixvzfOa4Nq6OtHtdW3jm0OkFvA/72z9jNec9NbU1pujld1JNaP/txhhU0ODbYWOppVJ/WdFQNaIGCniVgqD5E2KdARlJoASDexlcF42zvRYBk7dGkth9b51I9VseX7Pk3ant8CPFPu5BWqm3R/u0WllkOmOZh8w1AlsIfBZ0pRK6OIqdLH/MnB39NVT9AvWdHCbuJV1zGjR+3TWzUDGcomdrbisp6/cBi0C7j8UEem9ot1ZSqTnxCvWdO+pG8Vn6m8vcP4CA8hO4GhtIjF7is4Q1dgqtkLG1Rkpu02QMLGAAAAAAAAAAAAAAAAAAAAAAAAAAAABw4RGB4i";
+
+Deno.test("ML-DSA-65 Known Answer Tests", () => {
+  assertEquals(normalizeSignatureMlds65(MLDSA65_KAT_SIGNATURE), MLDSA65_KAT_SIGNATURE, "External signature is already canonical");
+  assert(verifyDetachedMlds65(MLDSA65_KAT_PUBLIC_KEY, testData, MLDSA65_KAT_SIGNATURE), "External signature verifies");
+  assert(!verifyDetachedMlds65(MLDSA65_KAT_PUBLIC_KEY, testData + "!", MLDSA65_KAT_SIGNATURE), "Different message rejected");
+});
+
+Deno.test("Binary Room Key Encryption Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const binary = new Uint8Array([0xff, 0xfe, 0x00, 0xc3, 0x28, 0x80]);
+  const enc = encryptBytesRoomKey(rk, binary)!;
+  assert(isValidEncryptedDataRoomKey(enc));
+  assertEquals(decryptBytesRoomKey(rk, enc), binary, "Non-UTF-8 payload round-trips");
+  assertEquals(decryptDataRoomKey(rk, enc), undefined, "String decrypt rejects non-UTF-8 without panicking");
+
+  const empty = encryptBytesRoomKey(rk, new Uint8Array(0))!;
+  assertEquals(decryptBytesRoomKey(rk, empty), new Uint8Array(0), "Empty payload round-trips");
+  const text = encryptDataRoomKey(rk, testData)!;
+  assertEquals(new TextDecoder().decode(decryptBytesRoomKey(rk, text)!), testData, "String ciphertext decrypts as bytes");
+  assertEquals(decryptBytesRoomKey(generateRoomKey(sessionUUID)!, enc), undefined, "Wrong key rejected");
+});
+
+Deno.test("Account To Room Rewrap Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const accEnc = encryptDataAccountKey(ak.publicKey, testData)!;
+  const roomEnc = rewrapAccountToRoom(ak.privateKey, accEnc, rk)!;
+  assert(isValidEncryptedDataRoomKey(roomEnc), "Result is room-key EncryptedData");
+  assertEquals(decryptDataRoomKey(rk, roomEnc), testData, "Round-trips through the room key");
+
+  const other = generateAccountKey(mp, ms)!;
+  assertEquals(rewrapAccountToRoom(other.privateKey, accEnc, rk), undefined, "Wrong account key rejected");
+  assertEquals(rewrapAccountToRoom(ak.privateKey, roomEnc, rk), undefined, "Non account-key input rejected");
+});
+
+Deno.test("Key Byte Length Tests", () => {
+  const expected: Record<string, [number, number]> = {
+    masterKey: [2592, 4896],
+    identityKey: [1952, 4032],
+    serverKey: [1952, 4032],
+    shareSignKey: [1952, 4032],
+    migrateSignKey: [1952, 4032],
+    accountKey: [1184, 2400],
+    accountPrekey: [1184, 2400],
+    shareKey: [1184, 2400],
+    migrateKey: [1184, 2400],
+    roomKey: [32, 32],
+    deviceKey: [32, 32],
+  };
+  for (const [keyType, [pub, priv]] of Object.entries(expected)) {
+    assertEquals(keyByteLen(keyType, true), pub, `${keyType} public length`);
+    assertEquals(keyByteLen(keyType, false), priv, `${keyType} private length`);
+  }
+  assertEquals(keyByteLen("unknownKey", true), undefined);
+
+  const decodedLen = (json: string) => atob(JSON.parse(json).key).length;
+  const [mp, ms] = generateMasterKey();
+  assertEquals(decodedLen(mp), keyByteLen("masterKey", true), "Generated master key matches");
+  assertEquals(decodedLen(ms), keyByteLen("masterKey", false));
+  const ak = generateAccountKey(mp, ms)!;
+  assertEquals(decodedLen(ak.publicKey), keyByteLen("accountKey", true), "Generated account key matches");
+  assertEquals(decodedLen(ak.privateKey), keyByteLen("accountKey", false));
+  assertEquals(decodedLen(generateRoomKey(sessionUUID)!), keyByteLen("roomKey", false), "Generated room key matches");
+});
+
+Deno.test("Mixed Recipient Room Key Wrapping Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const recipients = [
+    { kind: "account", key: ak.publicKey, id: "alice" },
+    { kind: "share", key: sk.publicKey, id: "my-phone" },
+    { kind: "share", key: ak.publicKey, id: "wrong-kind" },
+    { kind: "device", key: sk.publicKey, id: "unknown-kind" },
+    { kind: "account", key: ak.publicKey },
+  ];
+  const result = JSON.parse(wrapRoomKeyForRecipients(rk, JSON.stringify(recipients))!);
+  assertEquals(result.distribution.map((d: { id: string; kind: string }) => [d.id, d.kind]), [["alice", "account"], ["my-phone", "share"]]);
+  assertEquals(result.skipped, [2, 3, 4], "Invalid entries skipped by index");
+  assertEquals(decryptDataAccountKey(ak.privateKey, result.distribution[0].encryptedData), rk);
+  assertEquals(decryptDataShareKey(sk.privateKey, result.distribution[1].encryptedData), rk);
+  assertEquals(wrapRoomKeyForRecipients("invalid", JSON.stringify(recipients)), undefined);
+});
+
+Deno.test("Room Key Session Binding Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const otherRoom = "018fdb31-0798-78a2-b4c9-e145d5b5b88f";
+  assert(encryptDataRoomKey(rk, testData, sessionUUID), "Matching sessionUuid accepted");
+  assertEquals(encryptDataRoomKey(rk, testData, otherRoom), undefined, "Other room rejected");
+  assertEquals(encryptDataRoomKey(rk, testData, sessionUUID.toUpperCase()), undefined, "Different UUID format rejected");
+  assertEquals(encryptDataRoomKey(rk, testData, sessionUUID.replaceAll("-", "")), undefined, "Unhyphenated UUID rejected");
+
+  const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
+  assertEquals(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, otherRoom), undefined, "encrypt_message refuses another room's key");
+  assert(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID), "encrypt_message accepts the key's own room");
+});
+
+Deno.test("Canonical JSON Signing Tests", () => {
+  assertEquals(canonicalizeJson('{ "b": 1, "a": { "d": [1, 2], "c": null } }'), '{"a":{"c":null,"d":[1,2]},"b":1}');
+  assertEquals(canonicalizeJson("not json"), undefined);
+
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const wrapper = JSON.parse(sendTextMessage("hi", rk, ik.privateKey, ik.publicKey, sessionUUID, "c")!);
+  assertEquals(canonicalizeJson(wrapper.message), wrapper.message, "Transmitted message is already canonical");
+
+  // 同じ内容をキー順・空白を変えて再直列化
+  const parsed = JSON.parse(wrapper.message);
+  const reordered = JSON.stringify(Object.fromEntries(Object.entries(parsed).reverse()), null, 2);
+  assert(reordered !== wrapper.message);
+  const now = BigInt(Date.now());
+  const a = decryptMessage(wrapper.message, wrapper.sign, now, rk, ik.publicKey, sessionUUID, undefined);
+  const b = decryptMessage(reordered, wrapper.sign, now, rk, ik.publicKey, sessionUUID, undefined);
+  assert(a, "Original serialization verifies");
+  assertEquals(b, a, "Re-serialized message verifies identically");
+});
+
+Deno.test("Bulk Room Key Rotation Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const otherRoom = "018fdb31-0798-78a2-b4c9-e145d5b5b88f";
+  const rooms = [
+    { roomKey: generateRoomKey(sessionUUID)!, roomUuid: sessionUUID },
+    { roomKey: "invalid", roomUuid: sessionUUID },
+    { roomKey: generateRoomKey(otherRoom)!, roomUuid: otherRoom },
+  ];
+  const recipients = [
+    { kind: "account", key: ak.publicKey, id: "bob" },
+    { kind: "share", key: sk.publicKey, id: "my-laptop" },
+  ];
+  const result = JSON.parse(bulkRotateAndRewrap(JSON.stringify(rooms), JSON.stringify(recipients))!);
+  assertEquals(result.failed, [1], "Invalid room reported");
+  assertEquals(result.rooms.map((r: { roomUuid: string }) => r.roomUuid), [sessionUUID, otherRoom]);
+  for (const [i, room] of result.rooms.entries()) {
+    const original = rooms[i === 0 ? 0 : 2];
+    assertEquals(JSON.parse(room.rotation).previousKeyHash, keyHashRaw(JSON.parse(original.roomKey).key), "Rotation links to the old key");
+    assertEquals(room.skipped, []);
+    assertEquals(decryptDataAccountKey(ak.privateKey, room.distribution[0].encryptedData), room.roomKey);
+    assertEquals(decryptDataShareKey(sk.privateKey, room.distribution[1].encryptedData), room.roomKey);
+  }
+});
+
+Deno.test("URL-safe Base64 Tests", () => {
+  let input = "";
+  for (let i = 0; ; i++) {
+    input = `urlsafe-${i}`;
+    if (/[+/]/.test(keyHash(input))) break;
+  }
+  const standard = keyHash(input);
+  const urlsafe = keyHashUrlsafe(input);
+  assert(!/[+/=]/.test(urlsafe), "URL-safe hash has no + / =");
+  assertEquals(reencodeBase64(urlsafe, false), standard, "Round-trips to the standard form");
+  assertEquals(reencodeBase64(standard, true), urlsafe);
+  assertEquals(reencodeBase64("not base64!", true), undefined);
+
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const pub = JSON.parse(ak.publicKey);
+  pub.key = reencodeBase64(pub.key, true);
+  assert(isValidAccountKeyPublic(JSON.stringify(pub)), "Validator accepts a URL-safe key");
+
+  const rk = generateRoomKey(sessionUUID)!;
+  const enc = JSON.parse(encryptDataRoomKey(rk, testData)!);
+  for (const field of ["keyHash", "encryptedData", "iv"]) {
+    enc[field] = reencodeBase64(enc[field], true);
+  }
+  assert(isValidEncryptedDataRoomKey(JSON.stringify(enc)), "URL-safe EncryptedData is well-formed");
+  assertEquals(decryptDataRoomKey(rk, JSON.stringify(enc)), testData, "URL-safe EncryptedData decrypts");
+});
+
+Deno.test("Verify Message Only Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const other = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("reported", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
+  const wrapper = JSON.parse(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!);
+
+  // モデレーターは RoomKey を持たないが、送信者の真正性は確認できる
+  assert(verifyMessageOnly(wrapper.message, wrapper.sign, ik.publicKey), "Authentic message verifies without the room key");
+  assert(!verifyMessageOnly(wrapper.message, wrapper.sign, other.publicKey), "Another identity key is rejected");
+  const tampered = JSON.stringify({ ...JSON.parse(wrapper.message), channel: "other" });
+  assert(!verifyMessageOnly(tampered, wrapper.sign, ik.publicKey), "Tampered message is rejected");
+});
+
+Deno.test("Algorithm Dispatch Verification Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const masterKey = JSON.parse(mp).key;
+  const identityKey = JSON.parse(ik.publicKey).key;
+
+  const sign87 = signMasterKey(ms, testData, keyHash(mp))!;
+  assertEquals(JSON.parse(sign87).algorithm, "ML-DSA-87");
+  assert(verifyAny(masterKey, sign87, testData), "ML-DSA-87 signature verifies");
+  assert(!verifyAny(identityKey, sign87, testData), "Wrong key is rejected");
+
+  const sign65 = signIdentityKey(ik.privateKey, testData, keyHash(ik.publicKey))!;
+  assertEquals(JSON.parse(sign65).algorithm, "ML-DSA-65");
+  assert(verifyAny(identityKey, sign65, testData), "ML-DSA-65 signature verifies");
+  assert(!verifyAny(identityKey, sign65, testData + "x"), "Modified data is rejected");
+
+  const unknown = JSON.stringify({ ...JSON.parse(sign65), algorithm: "SLH-DSA-128s" });
+  assert(!verifyAny(identityKey, unknown, testData), "Unknown algorithm is rejected");
+});
+
+Deno.test("Message Original Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("edited", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
+
+  const first = JSON.parse(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!);
+  const firstId = keyHash(first.message);
+  const edit = JSON.parse(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID, firstId)!);
+  assertEquals(JSON.parse(edit.message).original, firstId, "original is set from the parameter");
+  assert(validateMessageOriginal(edit.message, keyHash(edit.message)), "Edit pointing at another message is valid");
+  assert(validateMessageOriginal(first.message, firstId), "Message without original is valid");
+
+  assert(!validateMessageOriginal(edit.message, firstId), "Self-referential original is rejected");
+  assertEquals(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID, ""), undefined, "Empty original is rejected");
+  const empty = JSON.stringify({ ...JSON.parse(first.message), original: "" });
+  assert(!isValidMessage(empty), "Empty original fails schema validation");
+});
+
+Deno.test("Device Key Length Guard Tests", () => {
+  const shortKey = JSON.stringify({ keyType: "deviceKey", key: btoa(String.fromCharCode(...new Uint8Array(16))) });
+  assert(!isValidDeviceKey(shortKey), "16-byte device key is invalid");
+  assertEquals(encryptDataDeviceKey(shortKey, testData), undefined, "Encrypting with a 16-byte key returns None");
+
+  const dk = generateDeviceKey();
+  const enc = JSON.parse(encryptDataDeviceKey(dk, testData)!);
+  enc.keyHash = keyHash(shortKey);
+  assertEquals(decryptDataDeviceKey(shortKey, JSON.stringify(enc)), undefined, "Decrypting with a 16-byte key returns None");
+});
+
+Deno.test("Random String Tests", () => {
+  const seed = new Uint8Array(32).fill(7);
+  const a = generateRandomStringSeeded(64, seed)!;
+  assertEquals(a.length, 64);
+  assertEquals(generateRandomStringSeeded(64, seed), a, "Same seed gives the same string");
+  assert(generateRandomStringSeeded(64, new Uint8Array(32).fill(8)) !== a, "Different seed gives a different string");
+  assertEquals(generateRandomStringSeeded(8, new Uint8Array(16)), undefined, "Seed must be 32 bytes");
+
+  // 62 文字がほぼ一様に出現すること (カイ二乗検定, 自由度 61, 有意水準 0.001 の臨界値 ≈ 100.9)
+  const perChar = 2000;
+  const sample = generateRandomString(62 * perChar);
+  assert(/^[A-Za-z0-9]+$/.test(sample), "Only alphanumeric characters");
+  const counts = new Map<string, number>();
+  for (const c of sample) counts.set(c, (counts.get(c) ?? 0) + 1);
+  assertEquals(counts.size, 62, "Every character appears");
+  let chi2 = 0;
+  for (const n of counts.values()) chi2 += (n - perChar) ** 2 / perChar;
+  assert(chi2 < 100.9, `Distribution is roughly uniform (chi2 = ${chi2})`);
+});
+
+Deno.test("Message AAD Binding Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("bound", undefined, undefined, undefined, undefined)!;
+  const now = Date.now();
+  const meta = JSON.stringify({ channel: "general", timestamp: now, isLarge: false });
+  const wrapper = JSON.parse(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!);
+  assert(decryptMessage(wrapper.message, wrapper.sign, BigInt(now), rk, ik.publicKey, sessionUUID), "Original context decrypts");
+  assertEquals(decryptDataRoomKey(rk, JSON.parse(wrapper.message).value), undefined, "Ciphertext needs its AAD");
+
+  // 署名をやり直しても、暗号文を別の channel に移すと復号できない
+  const moved = JSON.stringify({ ...JSON.parse(wrapper.message), channel: "admins" });
+  const movedSign = signIdentityKey(ik.privateKey, moved, keyHash(ik.publicKey))!;
+  assert(verifyMessageOnly(moved, movedSign, ik.publicKey), "Moved message carries a valid signature");
+  assertEquals(decryptMessage(moved, movedSign, BigInt(now), rk, ik.publicKey, sessionUUID), undefined, "Moved ciphertext fails to decrypt");
+});
+
+Deno.test("Key Diagnosis Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  assertEquals(diagnoseMasterKey(mp), "ok");
+  assertEquals(diagnoseMasterKey(ms), "ok");
+  assertEquals(diagnoseAccountKey(ak.publicKey), "ok");
+  assertEquals(diagnoseAccountKey(ak.privateKey), "ok");
+  assertEquals(diagnoseIdentityKey(ik.publicKey), "ok");
+  assertEquals(diagnoseRoomKey(rk), "ok");
+
+  const pub = JSON.parse(ak.publicKey);
+  assertEquals(diagnoseAccountKey("{"), "not valid JSON (line 1, column 1)");
+  assertEquals(diagnoseAccountKey(JSON.stringify({ ...pub, keyType: "shareKeyPublic" })), 'wrong keyType "shareKeyPublic" (expected accountKeyPublic or accountKeyPrivate)');
+  assertEquals(diagnoseAccountKey(JSON.stringify({ ...pub, key: "!!!" })), 'field "key" is not valid Base64');
+  assertEquals(diagnoseAccountKey(JSON.stringify({ ...pub, key: pub.key.slice(0, 64) })), 'field "key" decodes to 48 bytes (expected 1184)');
+  const { timestamp: _, ...noTimestamp } = pub;
+  assertEquals(diagnoseAccountKey(JSON.stringify(noTimestamp)), 'missing field "timestamp"');
+  assertEquals(diagnoseMasterKey(JSON.stringify({ ...JSON.parse(mp), algorithm: "ML-DSA-65" })), 'wrong algorithm "ML-DSA-65" (expected "ML-DSA-87")');
+  assertEquals(diagnoseIdentityKey(JSON.stringify({ ...JSON.parse(ik.publicKey), sessionUuid: "not-a-uuid" })), 'field "sessionUuid" is not a UUID v7');
+  assertEquals(diagnoseIdentityKey(JSON.stringify({ ...JSON.parse(ik.publicKey), key: "AAAA" })), 'field "key" decodes to 3 bytes (expected 1952)');
+  assertEquals(diagnoseIdentityKey(JSON.stringify({ ...JSON.parse(ik.privateKey), key: "not base64!" })), 'field "key" is not valid Base64');
+  assertEquals(diagnoseRoomKey(JSON.stringify({ ...JSON.parse(rk), algorithm: "AES-CBC" })), 'wrong algorithm "AES-CBC" (expected "AES-GCM")');
+  assertEquals(diagnoseRoomKey(JSON.stringify({ ...JSON.parse(rk), key: "not base64!" })), 'field "key" is not valid Base64');
+  assertEquals(diagnoseRoomKey(JSON.stringify({ ...JSON.parse(rk), key: btoa("0123456789abcdef") })), 'field "key" decodes to 16 bytes (expected 32)');
+
+  // 診断結果に鍵の値が含まれないこと
+  for (const bad of [{ ...pub, key: pub.key.slice(0, 64) }, { ...pub, keyType: 1 }]) {
+    assert(!diagnoseAccountKey(JSON.stringify(bad)).includes(pub.key.slice(0, 16)), "No key material in the message");
+  }
+});
+
+Deno.test("Verify Identity Key Any Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const oldKey = generateIdentityKey(sessionUUID, mp, ms)!;
+  const newKey = generateIdentityKey(sessionUUID, mp, ms)!;
+  const candidates = JSON.stringify([oldKey.publicKey, newKey.publicKey]);
+
+  const signedByNew = signIdentityKey(newKey.privateKey, testData, keyHash(newKey.publicKey))!;
+  assertEquals(verifyIdentityKeyAny(candidates, signedByNew, testData), 1, "New key validates");
+  const signedByOld = signIdentityKey(oldKey.privateKey, testData, keyHash(oldKey.publicKey))!;
+  assertEquals(verifyIdentityKeyAny(candidates, signedByOld, testData), 0, "Old key validates");
+
+  const other = generateIdentityKey(sessionUUID, mp, ms)!;
+  const signedByOther = signIdentityKey(other.privateKey, testData, keyHash(other.publicKey))!;
+  assertEquals(verifyIdentityKeyAny(candidates, signedByOther, testData), undefined, "No candidate validates");
+  assertEquals(verifyIdentityKeyAny(JSON.stringify(["invalid", newKey.publicKey]), signedByNew, testData), 1, "Broken candidates are skipped");
+});
+
+Deno.test("Message Wire Format Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("こんにちは、今日の会議は 15 時からです", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "general", timestamp: Date.now(), isLarge: false });
+  const json = encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!;
+  const wrapper = JSON.parse(json);
+
+  const wire = encodeMessageWire(wrapper.message, wrapper.sign)!;
+  const enc = new TextEncoder();
+  const jsonSize = enc.encode(json).length;
+  assertEquals(wire.length, 1 + 4 + enc.encode(wrapper.message).length + 4 + enc.encode(wrapper.sign).length, "Only the version and length prefixes are added");
+  assert(wire.length < jsonSize, "Wire format is smaller than the JSON envelope");
+  const decoded = JSON.parse(decodeMessageWire(wire)!);
+  assertEquals(decoded, wrapper, "Round-trips to the same envelope");
+  assert(decryptMessage(decoded.message, decoded.sign, BigInt(Date.now()), rk, ik.publicKey, sessionUUID), "Decoded message still verifies");
+
+  assertEquals(JSON.parse(decodeMessageWire(encodeMessageWire("", "")!)!), { message: "", sign: "" }, "Empty parts round-trip");
+  const huge = "x".repeat(4 * 1024 * 1024);
+  assertEquals(JSON.parse(decodeMessageWire(encodeMessageWire(huge, "s")!)!).message.length, huge.length, "Huge message round-trips");
+
+  assertEquals(decodeMessageWire(wire.slice(0, wire.length - 1)), undefined, "Truncated input is rejected");
+  assertEquals(decodeMessageWire(new Uint8Array([...wire, 0])), undefined, "Trailing bytes are rejected");
+  assertEquals(decodeMessageWire(new Uint8Array([2, ...wire.slice(1)])), undefined, "Unknown version is rejected");
+});
+
+Deno.test("KEM Encapsulation Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const pub = JSON.parse(ak.publicKey).key;
+  const priv = JSON.parse(ak.privateKey).key;
+
+  const { cipherText, sharedSecret } = JSON.parse(kemEncapsulate(pub)!);
+  assertEquals(kemDecapsulate(priv, cipherText), sharedSecret, "Both sides derive the same shared secret");
+  assert(isValidSymmetricKey(sharedSecret), "Shared secret is usable as a symmetric key");
+
+  // 一度の封入で複数のメッセージを送る
+  for (const text of ["first", "second", "third"]) {
+    const enc = encryptWithSymmetricKey(text, sharedSecret);
+    assertEquals(decryptWithSymmetricKey(enc.encrypted_data, enc.iv, kemDecapsulate(priv, cipherText)!), text);
+  }
+
+  const other = generateAccountKey(mp, ms)!;
+  assert(kemDecapsulate(JSON.parse(other.privateKey).key, cipherText) !== sharedSecret, "Another key derives a different secret");
+  assertEquals(kemEncapsulate("invalid"), undefined);
+});
+
+Deno.test("KEM Algorithm Binding Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const enc = encryptDataAccountKey(ak.publicKey, testData)!;
+  assertEquals(JSON.parse(enc).kem, "ML-KEM-768", "KEM algorithm is recorded");
+  assertEquals(decryptDataAccountKey(ak.privateKey, enc), testData);
+
+  const { kem: _, ...legacy } = JSON.parse(enc);
+  assert(isValidEncryptedDataAccountKey(JSON.stringify(legacy)), "Legacy ciphertext without kem is valid");
+  assertEquals(decryptDataAccountKey(ak.privateKey, JSON.stringify(legacy)), testData, "Legacy ciphertext assumes ML-KEM-768");
+
+  const downgraded = JSON.stringify({ ...JSON.parse(enc), kem: "ML-KEM-512" });
+  assert(!isValidEncryptedDataAccountKey(downgraded), "Unknown KEM fails validation");
+  assertEquals(decryptDataAccountKey(ak.privateKey, downgraded), undefined, "KEM mismatch is rejected");
+
+  const rk = generateRoomKey(sessionUUID)!;
+  const roomEnc = JSON.stringify({ ...JSON.parse(encryptDataRoomKey(rk, testData)!), kem: "ML-KEM-768" });
+  assert(!isValidEncryptedDataRoomKey(roomEnc), "Symmetric ciphertext must not carry kem");
+});
+
+Deno.test("Partial Room Key Wrapping Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const alice = generateAccountKey(mp, ms)!;
+  const bob = generateAccountKey(mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const users = [
+    { userId: "alice", accountKey: alice.publicKey },
+    { userId: "mallory", accountKey: "{\"keyType\":\"accountKeyPublic\"}" },
+    { accountKey: bob.publicKey },
+    { userId: "carol" },
+    { userId: "bob", accountKey: bob.publicKey },
+  ];
+  // 従来の関数は userId の無い要素があると全体が失敗する
+  assertEquals(encryptRoomKeyWithAccountKeys(JSON.stringify(users), rk), undefined);
+
+  const result = JSON.parse(encryptRoomKeyWithAccountKeysPartial(JSON.stringify(users), rk)!);
+  assertEquals(result.wrapped.map((w: { userId: string }) => w.userId), ["alice", "bob"], "Valid recipients are all wrapped");
+  assertEquals(decryptDataAccountKey(alice.privateKey, result.wrapped[0].encryptedData), rk);
+  assertEquals(decryptDataAccountKey(bob.privateKey, result.wrapped[1].encryptedData), rk);
+  assertEquals(result.failed, [
+    { index: 1, userId: "mallory", reason: "invalidAccountKey" },
+    { index: 2, userId: null, reason: "missingUserId" },
+    { index: 3, userId: "carol", reason: "missingAccountKey" },
+  ]);
+  assertEquals(encryptRoomKeyWithAccountKeysPartial("{}", rk), undefined, "Non-array input is rejected");
+});
+
+Deno.test("Device Key Rotation Tests", () => {
+  const oldKey = generateDeviceKey();
+  const newKey = generateDeviceKey();
+  const plains = ["first", "second", JSON.stringify({ nested: "blob" }), ""];
+  const blobs = plains.map((p) => encryptDataDeviceKey(oldKey, p)!);
+
+  const rotated: string[] = JSON.parse(rotateDeviceKey(oldKey, newKey, JSON.stringify(blobs))!);
+  assertEquals(rotated.length, plains.length);
+  rotated.forEach((blob, i) => {
+    assertEquals(decryptDataDeviceKey(newKey, blob), plains[i], "Re-encrypted blob decrypts with the new key in order");
+    assertEquals(decryptDataDeviceKey(oldKey, blob), undefined, "Old key no longer decrypts");
+  });
+
+  const foreign = encryptDataDeviceKey(generateDeviceKey(), "foreign")!;
+  assertEquals(rotateDeviceKey(oldKey, newKey, JSON.stringify([...blobs, foreign])), undefined, "A blob from another key fails the whole rotation");
+  assertEquals(JSON.parse(rotateDeviceKey(oldKey, newKey, "[]")!), [], "No blobs is fine");
+});
+
+Deno.test("Invalid UTF-8 Decrypt Tests", () => {
+  const key = generateSymmetricKey();
+  const bytes = encryptBytesWithSymmetricKey(new Uint8Array([0x61, 0xff, 0xfe, 0x80]), key);
+  try {
+    decryptWithSymmetricKey(bytes.encrypted_data, bytes.iv, key);
+    assert(false, "Non-UTF-8 plaintext should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "invalidUtf8", "Non-UTF-8 plaintext is reported, not a panic");
+  }
+
+  const text = encryptBytesWithSymmetricKey(new TextEncoder().encode(testData), key);
+  assertEquals(decryptWithSymmetricKey(text.encrypted_data, text.iv, key), testData, "Valid UTF-8 bytes still decrypt as a string");
+
+  try {
+    encryptBytesWithSymmetricKey(new Uint8Array([1, 2, 3]), btoa("short key"));
+    assert(false, "Invalid key should throw");
+  } catch (e) {
+    assertEquals((e as { code: string }).code, "invalidSymmetricKey", "Invalid key is reported, not a panic");
+  }
+});
+
+Deno.test("Account Export Audit Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const [otherMp, otherMs] = generateMasterKey();
+  const foreign = generateIdentityKey(sessionUUID, otherMp, otherMs)!;
+  const now = Date.now();
+
+  const report = JSON.parse(auditAccountExport(JSON.stringify({
+    masterKey: mp,
+    identityKeys: [
+      { publicKey: ik.publicKey, sign: ik.sign },
+      { publicKey: foreign.publicKey, sign: foreign.sign },
+    ],
+    accountKey: { publicKey: ak.publicKey, sign: ak.sign },
+    deviceKey: generateDeviceKey(),
+    shareKeys: [{ publicKey: sk.publicKey, sign: sk.sign }],
+  }), BigInt(now)));
+  assertEquals(report.masterKey.valid, true);
+  assertEquals(report.masterKey.algorithm, "ML-DSA-87");
+  assertEquals(report.masterKey.masterSignature, null);
+  assertEquals(report.accountKey.keyType, "accountKeyPublic");
+  assertEquals(report.accountKey.masterSignature, true);
+  assertEquals(report.accountKey.ageMs, now - JSON.parse(ak.publicKey).timestamp);
+  assertEquals(report.identityKeys.map((k: { masterSignature: boolean }) => k.masterSignature), [true, false], "Key signed by another master is flagged");
+  assertEquals(report.deviceKey.valid, true);
+  assertEquals(report.shareKeys[0].masterSignature, true);
+  assertEquals(report.signaturesVerified, false);
+  assertEquals(report.allValid, false);
+
+  const healthy = JSON.parse(auditAccountExport(JSON.stringify({
+    masterKey: mp,
+    identityKeys: [{ publicKey: ik.publicKey, sign: ik.sign }],
+    accountKey: { publicKey: ak.publicKey, sign: ak.sign },
+  }), BigInt(now)));
+  assertEquals(healthy.signaturesVerified, true);
+  assertEquals(healthy.allValid, true);
+
+  const partial = JSON.parse(auditAccountExport(JSON.stringify({ accountKey: { publicKey: ak.publicKey, sign: ak.sign } }), BigInt(now)));
+  assertEquals(partial.masterKey, null, "Missing sections are tolerated");
+  assertEquals(partial.identityKeys, []);
+  assertEquals(partial.accountKey.valid, true);
+  assertEquals(partial.accountKey.masterSignature, null, "No master key means the signature is not checked");
+  assertEquals(partial.signaturesVerified, false, "Unchecked signatures are not reported as verified");
+  assertEquals(partial.allValid, false);
+  const badMaster = JSON.parse(auditAccountExport(JSON.stringify({ masterKey: "{}", accountKey: { publicKey: ak.publicKey, sign: ak.sign } }), BigInt(now)));
+  assertEquals(badMaster.allValid, false, "An invalid master key cannot vouch for signed keys");
+
+  const garbage = JSON.parse(auditAccountExport("not json", BigInt(now)));
+  assertEquals(garbage.allValid, false, "Unparseable export is never reported as healthy");
+  assertEquals(garbage.accountKey, null);
+  assertEquals(JSON.parse(auditAccountExport(JSON.stringify({ accountKey: 42 }), BigInt(now))).accountKey.valid, false);
+});
+
+Deno.test("Sign Key Hash Consistency Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const masterHash = computeSignKeyHash(mp);
+  const embedded = (sign: string) => JSON.parse(sign).keyHash;
+
+  // マスター鍵で署名される鍵はすべて同じ keyHash を埋め込む
+  const ak = generateAccountKey(mp, ms)!;
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const hybrid = generateIdentityKeyHybrid(sessionUUID, mp, ms)!;
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const ssk = generateShareSignKey(mp, ms, sessionUUID)!;
+  const prekeys = generateAccountPrekeys(2, mp, ms)!;
+  for (const sign of [ak.sign, ik.sign, hybrid.sign, sk.sign, ssk.sign, ...prekeys.map((p: { sign: string }) => p.sign)]) {
+    assertEquals(embedded(sign), masterHash);
+  }
+  assertEquals(embedded(resignKey(ak.publicKey, ms, mp)!), masterHash);
+  const mSign = signMasterKeyWithPublic(ms, mp, testData)!;
+  assertEquals(embedded(mSign), masterHash);
+  assert(verifyMasterKey(mp, mSign, testData));
+
+  const iSign = signIdentityKeyWithPublic(ik.privateKey, ik.publicKey, testData)!;
+  assertEquals(embedded(iSign), computeSignKeyHash(ik.publicKey));
+  assert(verifyIdentityKey(ik.publicKey, iSign, testData));
+  assertEquals(embedded(signIdentityKeyWithPublic(hybrid.privateKey, hybrid.publicKey, testData)!), computeSignKeyHash(hybrid.publicKey));
+
+  const srv = generateServerKey();
+  const srvSign = signDataServerKeyWithPublic(srv.privateKey, srv.publicKey, testData)!;
+  assertEquals(embedded(srvSign), computeSignKeyHash(srv.publicKey));
+  assert(verifyDataServerKey(srv.publicKey, srvSign, testData));
+
+  assertEquals(embedded(signDataShareSignKeyWithPublic(ssk.privateKey, ssk.publicKey, testData)!), computeSignKeyHash(ssk.publicKey));
+  const msk = generateMigrateSignKey();
+  assertEquals(embedded(signDataMigrateSignKeyWithPublic(msk.privateKey, msk.publicKey, testData)!), computeSignKeyHash(msk.publicKey));
+
+  assertEquals(signMasterKeyWithPublic(ms, ak.publicKey, testData), undefined, "Public key of the wrong type is rejected");
+  assertEquals(computeSignKeyHash("{}"), "");
+});
+
+Deno.test("Compressed Room Key Encryption Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const compressible = JSON.stringify(Array.from({ length: 500 }, (_, i) => ({ id: i, type: "text", text: "hello takos" })));
+  const compressed = encryptDataRoomKeyCompressed(rk, compressible)!;
+  assertEquals(JSON.parse(compressed).compression, "deflate");
+  assert(isValidEncryptedDataRoomKey(compressed), "Compressed ciphertext is well formed");
+  assertEquals(decryptDataRoomKey(rk, compressed), compressible);
+  assertEquals(new TextDecoder().decode(decryptBytesRoomKey(rk, compressed)!), compressible);
+  const raw = encryptDataRoomKey(rk, compressible)!;
+  assert(JSON.parse(compressed).encryptedData.length < JSON.parse(raw).encryptedData.length / 4, "Compressible input shrinks");
+
+  const random = btoa(String.fromCharCode(...crypto.getRandomValues(new Uint8Array(4096))));
+  assertEquals(decryptDataRoomKey(rk, encryptDataRoomKeyCompressed(rk, random)!), random, "Incompressible input round-trips");
+  assertEquals(decryptDataRoomKey(rk, encryptDataRoomKeyCompressed(rk, "")!), "");
+
+  const unknown = JSON.stringify({ ...JSON.parse(compressed), compression: "zstd" });
+  assertEquals(isValidEncryptedDataRoomKey(unknown), false);
+  assertEquals(decryptDataRoomKey(rk, unknown), undefined, "Unknown compression is rejected");
+
+  // compression は認証タグに束縛されている
+  const { compression: _, ...stripped } = JSON.parse(compressed);
+  assertEquals(decryptDataRoomKey(rk, JSON.stringify(stripped)), undefined, "Stripping compression fails authentication");
+  assertEquals(decryptBytesRoomKey(rk, JSON.stringify(stripped)), undefined);
+  const added = JSON.stringify({ ...JSON.parse(raw), compression: "deflate" });
+  assertEquals(decryptDataRoomKey(rk, added), undefined, "Adding compression fails authentication");
+});
+
+Deno.test("Server Stamp Verification Tests", () => {
+  const srv = generateServerKey();
+  const message = JSON.stringify({ encrypted: false, channel: "general", timestamp: Date.now(), isLarge: false, roomid: "room", value: { type: "text" } });
+  const at = BigInt(Date.now());
+  const stamp = stampMessage(message, srv.privateKey, srv.publicKey, at)!;
+
+  assertEquals(verifyServerStamp(message, stamp, srv.publicKey), at);
+  assert(verifyServerStampMatches(message, stamp, srv.publicKey, at), "Valid stamp matches its timestamp");
+  assertEquals(verifyServerStampMatches(message, stamp, srv.publicKey, at + 1n), false, "Different expected timestamp is rejected");
+
+  const tampered = JSON.stringify({ ...JSON.parse(stamp), serverTimestamp: Number(at) + 60_000 });
+  assertEquals(verifyServerStampMatches(message, tampered, srv.publicKey, at + 60_000n), false, "Tampered timestamp fails the signature");
+  assertEquals(verifyServerStamp(message, tampered, srv.publicKey), undefined);
+  assertEquals(verifyServerStampMatches(message + " ", stamp, srv.publicKey, at), false, "Stamp is bound to the message");
+  assertEquals(verifyServerStampMatches(message, stamp, generateServerKey().publicKey, at), false, "Other server key is rejected");
+});
+
+Deno.test("Group By Key Hash Tests", () => {
+  const rk1 = generateRoomKey(sessionUUID)!;
+  const rk2 = generateRoomKey(sessionUUID)!;
+  const blobs = [
+    encryptDataRoomKey(rk1, "a")!,
+    encryptDataRoomKey(rk2, "b")!,
+    "not json",
+    encryptDataRoomKey(rk1, "c")!,
+    encryptDataRoomKey(rk2, "d")!,
+    encryptDataRoomKey(rk1, "e")!,
+  ];
+  const groups = JSON.parse(groupByKeyHash(JSON.stringify(blobs))!);
+  const hash = (rk: string) => keyHashRaw(JSON.parse(rk).key)!;
+  assertEquals(groups, { [hash(rk1)]: [0, 3, 5], [hash(rk2)]: [1, 4] }, "Malformed entries are skipped");
+  const urlSafe = (b64: string) => b64.replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+  const respelled = JSON.stringify({ ...JSON.parse(blobs[0]), keyHash: urlSafe(JSON.parse(blobs[0]).keyHash) });
+  assertEquals(JSON.parse(groupByKeyHash(JSON.stringify([blobs[0], respelled]))!), { [hash(rk1)]: [0, 1] }, "URL-safe keyHash joins the same group");
+  assertEquals(JSON.parse(groupByKeyHash("[]")!), {});
+  assertEquals(groupByKeyHash("{}"), undefined);
+});
+
+Deno.test("Identity Key Full Generation Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKeyFull(sessionUUID, mp, ms);
+  assert(isValidIdentityKeyPublic(ik.publicKey));
+  assert(isValidIdentityKeyPrivate(ik.privateKey));
+  assert(verifyMasterKey(mp, ik.sign, ik.publicKey), "Master signature is included");
+  assertEquals(ik.keyHash, computeSignKeyHash(ik.publicKey), "keyHash follows the Sign.keyHash convention");
+  assertEquals(ik.fingerprint, keyFingerprint(ik.publicKey));
+  assertEquals(JSON.parse(signIdentityKeyWithPublic(ik.privateKey, ik.publicKey, testData)!).keyHash, ik.keyHash);
+  assertEquals(generateIdentityKeyFull("not-a-uuid", mp, ms), null);
+});
+
+Deno.test("Verify Before Decrypt Ordering Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const now = Date.now();
+  // 暗号文は壊れており、RoomKey も不正 (復号まで進めばそちらで失敗する)
+  const message = JSON.stringify({ encrypted: true, channel: "general", timestamp: now, isLarge: false, roomid: "room", value: "garbage" });
+  const badRoomKey = "{}";
+  const reason = (sign: string) => {
+    try {
+      decryptMessageOrThrow(message, sign, BigInt(now), badRoomKey, ik.publicKey, "room");
+      return "ok";
+    } catch (e) {
+      return (e as { code: string }).code;
+    }
+  };
+
+  const wrongSign = signIdentityKey(ik.privateKey, JSON.stringify({ other: true }), keyHash(ik.publicKey))!;
+  assertEquals(reason(wrongSign), "invalidSignature", "Signature is checked before the room key or ciphertext");
+  assertEquals(decryptMessage(message, wrongSign, BigInt(now), badRoomKey, ik.publicKey, "room"), undefined);
+
+  const validSign = signIdentityKey(ik.privateKey, message, keyHash(ik.publicKey))!;
+  assertEquals(reason(validSign), "invalidRoomKey", "Same input with a valid signature reaches the room key check");
+});
+
+Deno.test("Canonical Base64 Key Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  assert(isCanonicalKeyJson(ak.publicKey), "Generated keys are already canonical");
+  assertEquals(canonicalizeKeyJson(ak.publicKey), ak.publicKey);
+
+  // 同じ鍵バイト列の別表現 (URL-safe・パディングなし) は正規化すると元の文字列に戻る
+  const pub = JSON.parse(ak.publicKey);
+  const variant = ak.publicKey.replace(pub.key, reencodeBase64(pub.key, true)!);
+  assert(variant !== ak.publicKey);
+  assert(isValidAccountKeyPublic(variant), "Validators accept the alternative form on input");
+  assertEquals(isCanonicalKeyJson(variant), false);
+  assertEquals(canonicalizeKeyJson(variant), ak.publicKey);
+  assertEquals(keyHash(canonicalizeKeyJson(variant)!), keyHash(ak.publicKey), "keyHash is stable after canonicalization");
+
+  // JSON のエスケープ (\/) を含む非正規な表記も、値を読んでから書き直すので取りこぼさない
+  const escaped = ak.publicKey.replace(pub.key, pub.key.replace(/=+$/, "").replaceAll("/", "\\/"));
+  assert(escaped.includes("\\/"));
+  assertEquals(isCanonicalKeyJson(escaped), false);
+  assertEquals(canonicalizeKeyJson(escaped), ak.publicKey, "Escaped, unpadded key is canonicalized");
+  const spaced = JSON.stringify(JSON.parse(ak.publicKey), null, 2);
+  assertEquals(canonicalizeKeyJson(spaced), ak.publicKey, "Whitespace is normalized too");
+  const imported = importKeyBundle(exportKeyBundle(JSON.stringify([variant]), "pass")!, "pass")!;
+  assertEquals(JSON.parse(imported), [ak.publicKey], "Imported keys come back canonical");
+
+  // 余りビットが 0 でない非正規な符号化は拒否される (1184 バイト → 末尾 2 ビットが余る)
+  const alphabet = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  const last = pub.key.length - 2;
+  const bumped = pub.key.slice(0, last) + alphabet[alphabet.indexOf(pub.key[last]) + 1] + "=";
+  const nonCanonical = ak.publicKey.replace(pub.key, bumped);
+  assertEquals(isValidAccountKeyPublic(nonCanonical), false, "Non-zero trailing bits are rejected");
+  assertEquals(canonicalizeKeyJson(nonCanonical), undefined);
+
+  const hybrid = generateIdentityKeyHybrid(sessionUUID, mp, ms)!;
+  const hp = JSON.parse(hybrid.publicKey);
+  const hybridVariant = hybrid.publicKey.replace(hp.classicalKey, reencodeBase64(hp.classicalKey, true)!);
+  assertEquals(canonicalizeKeyJson(hybridVariant), hybrid.publicKey, "classicalKey is canonicalized too");
+});
+
+Deno.test("Share To Account Transfer Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const sk = generateShareKey(mp, ms, sessionUUID)!;
+  const ak = generateAccountKey(mp, ms)!;
+  const shareEnc = encryptDataShareKey(sk.publicKey, testData)!;
+
+  const accountEnc = transferShareToAccount(sk.privateKey, shareEnc, ak.publicKey)!;
+  assertEquals(JSON.parse(accountEnc).keyType, "accountKey");
+  assert(isValidEncryptedDataAccountKey(accountEnc));
+  assertEquals(decryptDataAccountKey(ak.privateKey, accountEnc), testData, "Account key holder reads the share data");
+
+  const otherShare = generateShareKey(mp, ms, sessionUUID)!;
+  assertEquals(transferShareToAccount(otherShare.privateKey, shareEnc, ak.publicKey), undefined, "Wrong share key rejected");
+  assertEquals(transferShareToAccount(sk.privateKey, shareEnc, ak.privateKey), undefined, "Recipient must be an account public key");
+  assertEquals(transferShareToAccount(sk.privateKey, encryptDataAccountKey(ak.publicKey, testData)!, ak.publicKey), undefined, "Input must be share-key data");
+});
+
+Deno.test("Sign Algorithm Key Type Mapping Tests", () => {
+  const validators: Record<string, (s: string) => boolean> = {
+    masterKey: isValidSignMasterKey,
+    identityKey: isValidSignIdentityKey,
+    serverKey: isValidSignServerKey,
+    shareSignKey: isValidSignShareSignKey,
+    migrateSignKey: isValidSignMigrateSignKey,
+  };
+  const algorithms = ["ML-DSA-87", "ML-DSA-65", "Ed25519+ML-DSA-65", "ML-DSA-65+SHA-256"];
+  const sig = btoa("signature");
+  const hash = keyHash("signer");
+  for (const [keyType, isValid] of Object.entries(validators)) {
+    const expected = signAlgorithmForKeyType(keyType)!;
+    for (const algorithm of algorithms) {
+      const accepted = algorithm === expected || (keyType === "identityKey" && algorithm === "Ed25519+ML-DSA-65");
+      assertEquals(isValid(wrapSignature(sig, hash, keyType, algorithm)), accepted, `${keyType} with ${algorithm}`);
+    }
+    // keyType が違えば algorithm が正しくても拒否
+    assertEquals(isValid(wrapSignature(sig, hash, keyType === "masterKey" ? "identityKey" : "masterKey", expected)), false);
+  }
+  assertEquals(signAlgorithmForKeyType("masterKey"), "ML-DSA-87");
+  assertEquals(signAlgorithmForKeyType("roomKey"), undefined);
+
+  // 実際に生成された署名は受理される
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  assert(isValidSignMasterKey(ik.sign));
+  assert(isValidSignIdentityKey(signIdentityKeyWithPublic(ik.privateKey, ik.publicKey, testData)!));
+});
+
+Deno.test("Encrypted Attachment Tests", () => {
+  const data = crypto.getRandomValues(new Uint8Array(10_000));
+  const att = createEncryptedAttachment(data)!;
+  assert(att.uri.startsWith("takos://"), "Content-addressed URI");
+  assertEquals(decryptAttachment(att.uri, att.blob), data, "Round-trip");
+
+  const content = JSON.parse(createImageContent(att.uri, "photo.png", "image/png", undefined, undefined, undefined)!);
+  assertEquals(decryptAttachment(content.uri, att.blob), data, "URI survives the media content builder");
+
+  const hash = attachmentBlobHash(att.uri)!;
+  assert(!att.uri.split("#")[0].includes(att.uri.split("#")[1]), "Key is only in the fragment");
+  assertEquals(att.uri.split("#")[0], `takos://${hash}`);
+
+  const empty = createEncryptedAttachment(new Uint8Array())!;
+  assertEquals(decryptAttachment(empty.uri, empty.blob), new Uint8Array());
+
+  const tampered = att.blob.slice();
+  tampered[tampered.length - 1] ^= 1;
+  assertEquals(decryptAttachment(att.uri, tampered), undefined, "Blob that does not match the hash is rejected");
+  const other = createEncryptedAttachment(data)!;
+  assertEquals(decryptAttachment(other.uri, att.blob), undefined, "Other attachment's URI is rejected");
+  assertEquals(decryptAttachment("https://example.com/a.png", att.blob), undefined);
+  assertEquals(attachmentBlobHash("takos://abc#short"), undefined, "Key must be a 256-bit key");
+});
+
+Deno.test("Key Type Registry Tests", () => {
+  const registry = describeKeyTypes() as { name: string; algorithm: string; publicLen: number; privateLen: number; requiredFields: string[] }[];
+  const names = new Set(registry.map((t) => t.name));
+  assertEquals(names.size, registry.length, "Names are unique");
+  for (const t of registry) {
+    assertEquals(keyByteLen(t.name, true), t.publicLen, `${t.name} public length comes from the registry`);
+    assertEquals(keyByteLen(t.name, false), t.privateLen, `${t.name} private length comes from the registry`);
+    assert(t.requiredFields.includes("keyType") && t.requiredFields.includes("key"));
+  }
+
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const pairs = [
+    { publicKey: mp, privateKey: ms },
+    generateIdentityKey(sessionUUID, mp, ms)!,
+    ak,
+    generateAccountPrekeys(1, mp, ms)![0],
+    generateShareKey(mp, ms, sessionUUID)!,
+    generateShareSignKey(mp, ms, sessionUUID)!,
+    generateMigrateKey(),
+    generateMigrateSignKey(),
+    generateServerKey(),
+  ];
+  const used = new Set<string>();
+  for (const pair of pairs) {
+    used.add(JSON.parse(pair.publicKey).keyType.replace(/Public$/, ""));
+    used.add(JSON.parse(pair.privateKey).keyType.replace(/Private$/, ""));
+    if ("sign" in pair) used.add(JSON.parse(pair.sign).keyType);
+  }
+  used.add(JSON.parse(generateRoomKey(sessionUUID)!).keyType);
+  used.add(JSON.parse(generateDeviceKey()).keyType);
+  used.add(JSON.parse(encryptDataAccountKey(ak.publicKey, testData)!).keyType);
+  for (const keyType of used) {
+    assert(names.has(keyType), `${keyType} is in the registry`);
+  }
+  for (const t of registry) {
+    assert(used.has(t.name), `${t.name} is produced by a generator`);
+  }
+});
+
+Deno.test("Identity Key Monotonicity Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const current = generateIdentityKey(sessionUUID, mp, ms)!.publicKey;
+  const withTimestamp = (ts: number) => JSON.stringify({ ...JSON.parse(current), timestamp: ts });
+  const ts = JSON.parse(current).timestamp;
+
+  assert(isNewerIdentityKey(withTimestamp(ts + 1), current), "Newer candidate accepted");
+  assert(!isNewerIdentityKey(withTimestamp(ts), current), "Equal timestamp is not newer");
+  assert(!isNewerIdentityKey(withTimestamp(ts - 1), current), "Older candidate rejected");
+  assert(!isNewerIdentityKey(current, withTimestamp(ts + 1)), "Current key is not newer than its replacement");
+
+  const otherSession = JSON.stringify({ ...JSON.parse(withTimestamp(ts + 1)), sessionUuid: "01890a5d-ac96-774b-bcce-b302099a8057" });
+  assert(!isNewerIdentityKey(otherSession, current), "Different session is never newer");
+  assert(!isNewerIdentityKey("{}", current));
+});
+
+Deno.test("Raw Key Hash Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const pub = JSON.parse(ak.publicKey);
+  const reordered = JSON.stringify({ timestamp: pub.timestamp, key: pub.key, algorithm: pub.algorithm, keyType: pub.keyType }, null, 2);
+  assert(keyHash(reordered) !== keyHash(ak.publicKey), "JSON string hash depends on formatting");
+  assertEquals(keyHashRaw(JSON.parse(reordered).key), keyHashRaw(pub.key), "Raw hash depends only on the key");
+  assertEquals(keyHashRaw(reencodeBase64(pub.key, true)!), keyHashRaw(pub.key), "URL-safe form hashes the same");
+  assertEquals(keyHashRaw("not base64!"), undefined);
+
+  const enc = JSON.parse(encryptDataAccountKey(reordered, testData)!);
+  assertEquals(enc.keyHash, keyHashRaw(pub.key), "EncryptedData.keyHash uses the raw key bytes");
+  assertEquals(decryptDataAccountKey(ak.privateKey, JSON.stringify(enc)), testData);
+  assertEquals(enc.keyHash, computeSignKeyHash(ak.publicKey), "Same definition as Sign.keyHash");
+
+  // keyHash を JSON 文字列のハッシュで付けていた旧形式も復号できる
+  enc.keyHash = keyHash(ak.publicKey);
+  assertEquals(decryptDataAccountKey(ak.privateKey, JSON.stringify(enc)), testData, "Legacy keyHash still accepted");
+  enc.keyHash = keyHashRaw(btoa("other"));
+  assertEquals(decryptDataAccountKey(ak.privateKey, JSON.stringify(enc)), undefined, "Other key's hash rejected");
+
+  const rk = generateRoomKey(sessionUUID)!;
+  const roomEnc = JSON.parse(encryptDataRoomKey(rk, testData)!);
+  assertEquals(roomEnc.keyHash, keyHashRaw(JSON.parse(rk).key));
+  assertEquals(decryptDataRoomKey(rk, JSON.stringify(roomEnc)), testData);
+});
+
+Deno.test("Bounded UUID v7 Tests", () => {
+  const min = BigInt(Date.UTC(2020, 0, 1));
+  const max = BigInt(Date.UTC(2100, 0, 1));
+  const ts = uuidV7Timestamp(sessionUUID)!;
+  assertEquals(ts, BigInt(0x018fdb310798), "Timestamp is the first 48 bits");
+  assert(isValidUuidV7Bounded(sessionUUID, min, max), "Recent UUID accepted");
+
+  const farFuture = "ffffffff-ffff-7fff-bfff-ffffffffffff";
+  assert(isValidUUIDv7(farFuture), "Far-future UUID is well-formed");
+  assert(!isValidUuidV7Bounded(farFuture, min, max), "Far-future UUID rejected");
+  const epoch = "00000000-0000-7000-8000-000000000000";
+  assert(!isValidUuidV7Bounded(epoch, min, max), "1970 UUID rejected");
+
+  assert(isValidUuidV7Bounded(sessionUUID, ts, ts), "Bounds are inclusive");
+  assert(!isValidUuidV7Bounded(sessionUUID, ts + 1n, max));
+  assertEquals(uuidV7Timestamp("not-a-uuid"), undefined);
+  assert(!isValidUuidV7Bounded("01890a5d-ac96-474b-bcce-b302099a8057", 0n, max), "UUID v4 rejected");
+});
+
+Deno.test("Identity Key Batch Tests", () => {
+  const [masterPub, masterPriv] = generateMasterKey();
+  const uuids = [sessionUUID, "018fdb31-0798-78a2-b4c9-e145d5b5b88f", "018fdb31-0798-78a2-b4c9-e145d5b5b890"];
+  const keys = generateIdentityKeysBatch(uuids.length, masterPub, masterPriv, JSON.stringify(uuids));
+  assert(keys, "Batch generation should succeed");
+  assertEquals(keys.length, uuids.length);
+  keys.forEach((k: { publicKey: string; privateKey: string; sign: string }, i: number) => {
+    assert(isValidIdentityKeyPublic(k.publicKey), "Identity public key should be valid");
+    assert(isValidIdentityKeyPrivate(k.privateKey), "Identity private key should be valid");
+    assert(verifyMasterKey(masterPub, k.sign, k.publicKey), "Each key is signed by the master key");
+    assertEquals(JSON.parse(k.publicKey).sessionUuid, uuids[i]);
+  });
+  assertEquals(new Set(keys.map((k: { publicKey: string }) => k.publicKey)).size, uuids.length, "Keys are distinct");
+
+  assertEquals(generateIdentityKeysBatch(2, masterPub, masterPriv, JSON.stringify(uuids)), null, "count must match uuids");
+  assertEquals(generateIdentityKeysBatch(1, masterPub, masterPriv, JSON.stringify(["not-a-uuid"])), null);
+  assertEquals(generateIdentityKeysBatch(1, masterPriv, masterPub, JSON.stringify([sessionUUID])), null, "Swapped master keys rejected");
+});
+
+Deno.test("Key Pair Match Tests", () => {
+  const [mp1, ms1] = generateMasterKey();
+  const [mp2, ms2] = generateMasterKey();
+  assert(masterKeyPairMatches(mp1, ms1), "Master key pair matches");
+  assert(!masterKeyPairMatches(mp1, ms2), "Mismatched master keys rejected");
+  assert(!masterKeyPairMatches(ms1, mp1), "Swapped master keys rejected");
+
+  const ik1 = generateIdentityKey(sessionUUID, mp1, ms1)!;
+  const ik2 = generateIdentityKey(sessionUUID, mp1, ms1)!;
+  assert(identityKeyPairMatches(ik1.publicKey, ik1.privateKey), "Identity key pair matches");
+  assert(!identityKeyPairMatches(ik1.publicKey, ik2.privateKey), "Mismatched identity keys rejected");
+  assert(!identityKeyPairMatches(mp1, ms1), "Master keys are not identity keys");
+
+  const hy1 = generateIdentityKeyHybrid(sessionUUID, mp1, ms1)!;
+  const hy2 = generateIdentityKeyHybrid(sessionUUID, mp1, ms1)!;
+  assert(identityKeyPairMatches(hy1.publicKey, hy1.privateKey), "Hybrid identity key pair matches");
+  assert(!identityKeyPairMatches(hy1.publicKey, hy2.privateKey), "Mismatched hybrid identity keys rejected");
+  // ML-DSA 部分だけが一致していても Ed25519 部分が違えば拒否
+  const mixed = JSON.stringify({ ...JSON.parse(hy1.privateKey), classicalKey: JSON.parse(hy2.privateKey).classicalKey });
+  assert(!identityKeyPairMatches(hy1.publicKey, mixed), "Mismatched classical key rejected");
+
+  const ak1 = generateAccountKey(mp1, ms1)!;
+  const ak2 = generateAccountKey(mp1, ms1)!;
+  assert(accountKeyPairMatches(ak1.publicKey, ak1.privateKey), "Account key pair matches");
+  assert(!accountKeyPairMatches(ak1.publicKey, ak2.privateKey), "Mismatched account keys rejected");
+  assert(!accountKeyPairMatches(ak1.privateKey, ak1.publicKey), "Swapped account keys rejected");
+});
+
+Deno.test("Private Metadata Message Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
+  const now = Date.now();
+  const meta = JSON.stringify({ channel: "secret-channel-name", timestamp: now, isLarge: false });
+  const msgJson = encryptMessagePrivateMetadata(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID, "original-message-id", true)!;
+  assert(msgJson, "Encryption succeeds");
+  assert(!msgJson.includes("secret-channel-name"), "Channel name is not transmitted in clear");
+  assert(!msgJson.includes("original-message-id"), "Hidden original is not transmitted in clear");
+
+  const wrapper = JSON.parse(msgJson);
+  assert(isValidMessage(wrapper.message), "Message with encryptedMetadata is valid");
+  const sent = JSON.parse(wrapper.message);
+  assertEquals(sent.channel, "");
+  assertEquals(sent.roomid, sessionUUID, "roomid stays in clear");
+  assertEquals(sent.timestamp, now, "timestamp stays in clear");
+
+  const out = decryptMessage(wrapper.message, wrapper.sign, BigInt(now), rk, ik.publicKey, sessionUUID)!;
+  const result = JSON.parse(out);
+  assertEquals(result.channel, "secret-channel-name", "Channel restored");
+  assertEquals(result.original, "original-message-id", "Original restored");
+  assertEquals(JSON.parse(result.value.content).text, "hi");
+
+  // original を秘匿しない場合は平文のまま
+  const visible = JSON.parse(encryptMessagePrivateMetadata(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID, "original-message-id", false)!);
+  assert(!visible.message.includes("secret-channel-name"));
+  assertEquals(JSON.parse(visible.message).original, "original-message-id");
+  const visibleOut = JSON.parse(decryptMessage(visible.message, visible.sign, BigInt(now), rk, ik.publicKey, sessionUUID)!);
+  assertEquals(visibleOut.channel, "secret-channel-name");
+  assertEquals(visibleOut.original, "original-message-id");
+
+  // 別の RoomKey では復号できない
+  const otherRk = generateRoomKey(sessionUUID)!;
+  assertEquals(decryptMessage(wrapper.message, wrapper.sign, BigInt(now), otherRk, ik.publicKey, sessionUUID), undefined);
+});
+
+Deno.test("Nonce Guard Tests", () => {
+  const key = generateSymmetricKey();
+  const guard = new NonceGuard(keyHashRaw(key)!);
+  assert(guard.is_empty());
+  const iv = "AAAAAAAAAAAAAAAA";
+  assert(guard.register(iv), "First use of an IV accepted");
+  assert(!guard.register(iv), "Forced collision detected");
+
+  const seen = new Set<string>();
+  for (let i = 0; i < 500; i++) {
+    const enc = encryptWithSymmetricKeyGuarded(testData, key, guard);
+    assert(!seen.has(enc.iv), "Guarded encrypt never emits a duplicate IV");
+    seen.add(enc.iv);
+    assert(!guard.register(enc.iv), "Emitted IV is recorded in the guard");
+    assertEquals(decryptWithSymmetricKey(enc.encrypted_data, enc.iv, key), testData);
+  }
+  assertEquals(guard.len(), 501);
+
+  const errorCode = (f: () => unknown) => {
+    try {
+      f();
+    } catch (e) {
+      return (e as { code: string }).code;
+    }
+    return undefined;
+  };
+  assertEquals(errorCode(() => encryptWithSymmetricKeyGuarded(testData, generateSymmetricKey(), guard)), "nonceGuardKeyMismatch", "A guard is bound to its key");
+  assertEquals(errorCode(() => encryptWithSymmetricKeyGuarded(testData, "AAAA", guard)), "invalidSymmetricKey", "Invalid keys throw instead of panicking");
+  const legacy = new NonceGuard(keyHash(key));
+  assert(encryptWithSymmetricKeyGuarded(testData, key, legacy).iv, "Guards keyed by the legacy string hash still work");
+});
+
+Deno.test("Encrypt Message Silence Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
+
+  // 暗号化中にコンソール (stdout/stderr) へ何も出力しないこと
+  const methods = ["log", "info", "debug", "warn", "error"] as const;
+  const originals = methods.map((m) => console[m]);
+  let calls = 0;
+  methods.forEach((m) => { console[m] = () => { calls++; }; });
+  try {
+    for (let i = 0; i < 10; i++) {
+      assert(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID));
+    }
+  } finally {
+    methods.forEach((m, i) => { console[m] = originals[i]; });
+  }
+  assertEquals(calls, 0, "encrypt_message prints nothing");
+});
+
+Deno.test("Validation Rules Export Tests", () => {
+  const rules = JSON.parse(exportValidationRules());
+  assertEquals(rules.masterKeyPublic.key.length, 2592);
+  assertEquals(rules.masterKeyPrivate.key.length, 4896);
+  assertEquals(rules.accountKeyPublic.key.length, 1184);
+  assertEquals(rules.accountKeyPrivate.key.length, 2400);
+  assertEquals(rules.serverKeyPublic.key.length, 1952);
+  assertEquals(rules.identityKeyPublic.sessionUuid.format, "uuid-v7");
+  assertEquals(rules.identityKeyPrivate.sessionUuid.format, "uuid-v7");
+  assertEquals(rules.encryptedDataRoomKey.iv.length, 12);
+  assertEquals(rules.encryptedDataRoomKey.iv.decode, "base64");
+
+  // 鍵のルールはレジストリの必須フィールドを持つ
+  const registry = describeKeyTypes() as { name: string; requiredFields: string[] }[];
+  for (const t of registry) {
+    const ruleNames = t.name === "roomKey" || t.name === "deviceKey" ? [t.name] : [`${t.name}Public`, `${t.name}Private`];
+    for (const r of ruleNames) {
+      assertEquals(rules[r].requiredFields, t.requiredFields, `${r} requiredFields come from the registry`);
+      for (const f of t.requiredFields) assert(rules[r][f] !== undefined, `${r}.${f} has a rule`);
+    }
+  }
+  assertEquals(rules.shareSignKeyPublic.key.length, 1952);
+  assertEquals(rules.deviceKey.keyType.equals, "deviceKey");
+
+  const dk = JSON.parse(generateDeviceKey());
+  assert(!isValidDeviceKey(JSON.stringify({ ...dk, keyType: "roomKey" })), "Device key validator checks keyType");
+});
+
+Deno.test("Seeded Key Generation Tests", () => {
+  const seed = new Uint8Array(32).fill(7);
+  const other = new Uint8Array(32).fill(8);
+
+  const [kemPub, kemPriv] = generateKemKeyPairSeeded(seed);
+  assertEquals(generateKemKeyPairSeeded(seed), [kemPub, kemPriv], "Same seed yields the same KEM key pair");
+  assert(generateKemKeyPairSeeded(other)[0] !== kemPub, "Different seed yields a different KEM key pair");
+  assert(isValidKemKey(kemPub, true) && isValidKemKey(kemPriv, false));
+
+  const [dsaPub, dsaPriv] = generateDsa65KeyPairSeeded(seed);
+  assertEquals(generateDsa65KeyPairSeeded(seed), [dsaPub, dsaPriv], "Same seed yields the same ML-DSA-65 key pair");
+  assert(isValidDsa65Key(dsaPub, true) && isValidDsa65Key(dsaPriv, false));
+
+  const sym = generateSymmetricKeySeeded(seed)!;
+  assertEquals(generateSymmetricKeySeeded(seed), sym);
+  assert(isValidSymmetricKey(sym));
+  assertEquals(generateSymmetricKeySeeded(new Uint8Array(31)), undefined, "Seed must be 32 bytes");
+});
+
+Deno.test("Decrypt Batch Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const otherRk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
+  const valid = encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!;
+  const wrongKey = encryptMessage(text, meta, otherRk, ik.privateKey, ik.publicKey, sessionUUID)!;
+
+  const out = JSON.parse(decryptBatch(
+    JSON.stringify([valid, wrongKey, "not an envelope"]),
+    JSON.stringify([rk]),
+    JSON.stringify([ik.publicKey]),
+    sessionUUID,
+    BigInt(Date.now()),
+  )!);
+  assertEquals(out.decrypted.map((d: { index: number }) => d.index), [0]);
+  assertEquals(JSON.parse(out.decrypted[0].value).channel, "c");
+  assertEquals(out.failed, [
+    { index: 1, reason: "roomKeyNotFound" },
+    { index: 2, reason: "malformedEnvelope" },
+  ]);
+  assertEquals(decryptBatch("not json", "[]", "[]", sessionUUID, 0n), undefined);
+});
+
+Deno.test("Key Fingerprint Tests", () => {
+  const [mp] = generateMasterKey();
+  const fp = keyFingerprint(mp);
+  assert(/^([0-9a-f]{4} ){7}[0-9a-f]{4}$/.test(fp), "8 groups of 4 hex digits");
+  assertEquals(keyFingerprint(mp), fp, "Identical keys give identical fingerprints");
+  const parsed = JSON.parse(mp);
+  assertEquals(keyFingerprint(JSON.stringify({ key: parsed.key, keyType: parsed.keyType })), fp, "Derived from the key bytes, not the JSON layout");
+
+  const bytes = Uint8Array.from(atob(parsed.key), (c) => c.charCodeAt(0));
+  bytes[0] ^= 1;
+  const flipped = JSON.stringify({ ...parsed, key: btoa(String.fromCharCode(...bytes)) });
+  assert(keyFingerprint(flipped) !== fp, "A single flipped byte changes the fingerprint");
+});
+
+Deno.test("Deterministic Room UUID Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const [a, b, c] = [0, 1, 2].map(() => generateIdentityKey(sessionUUID, mp, ms)!.publicKey);
+
+  const ab = deterministicRoomUuid(JSON.stringify([a, b]))!;
+  assert(isValidUUIDv7(ab), "Result is a valid UUID v7");
+  assertEquals(deterministicRoomUuid(JSON.stringify([b, a])), ab, "Member order does not matter");
+  assertEquals(deterministicRoomUuid(JSON.stringify([a, b, a])), ab, "Duplicate members are ignored");
+  const aReordered = JSON.stringify(Object.fromEntries(Object.entries(JSON.parse(a)).reverse()));
+  assertEquals(deterministicRoomUuid(JSON.stringify([aReordered, b])), ab, "JSON formatting of a member key does not matter");
+  assert(deterministicRoomUuid(JSON.stringify([a, c])) !== ab, "Different member sets give different UUIDs");
+  assert(deterministicRoomUuid(JSON.stringify([a, b, c])) !== ab);
+  assertEquals(deterministicRoomUuid("not json"), undefined);
+});
+
+Deno.test("Prehash Signature Tests", () => {
+  const [pk, sk] = generateDsa65KeyPair();
+  const data = new TextEncoder().encode(testData);
+  const digest = prehashSha256(data);
+  assertEquals(digest.length, 32);
+
+  const sign = createSignatureObjectPrehashMlds65(sk, digest, "hash", "identityKey")!;
+  assertEquals(JSON.parse(sign).algorithm, "ML-DSA-65+SHA-256", "Prehash algorithm is recorded in the Sign object");
+  assert(verifyAny(pk, sign, testData), "verify_any routes prehash signatures by the recorded algorithm");
+  assert(!verifyAny(pk, sign, testData + "!"), "Different data fails");
+
+  const raw = extractSignature(sign)!;
+  assert(verifyPrehashMlds65(pk, digest, raw));
+  assert(!verifyDetachedMlds65(pk, testData, raw), "A prehash signature is not a plain signature over the data");
+  const plain = signDetachedMlds65(sk, testData)!;
+  assert(!verifyAny(pk, wrapSignature(plain, "hash", "identityKey", "ML-DSA-65+SHA-256"), testData), "A plain signature labelled as prehash fails");
+
+  assertEquals(createSignatureObjectPrehashMlds65(sk, digest.slice(0, 31), "hash", "identityKey"), undefined, "Digest must be 32 bytes");
+});
+
+Deno.test("Stored Message Normalization Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
+  const { message, sign } = JSON.parse(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!);
+  const enc = new TextEncoder();
+
+  assertEquals(normalizeStoredMessage(message), message, "encrypt_message already emits the canonical form");
+  assert(verifyAgainstStored(ik.publicKey, enc.encode(message), sign), "Storing the canonical form preserves verification");
+
+  const reformatted = JSON.stringify(JSON.parse(message), null, 2);
+  assert(!verifyAgainstStored(ik.publicKey, enc.encode(reformatted), sign), "A reformatted store breaks verification");
+  assertEquals(normalizeStoredMessage(reformatted), message, "Normalizing restores the signed bytes");
+  assert(verifyAgainstStored(ik.publicKey, enc.encode(normalizeStoredMessage(reformatted)!), sign));
+  assertEquals(normalizeStoredMessage("not json"), undefined);
+});
+
+Deno.test("Signature Key Type Routing Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const masterSign = signMasterKeyWithPublic(ms, mp, testData)!;
+  assert(verifyMasterKey(mp, masterSign, testData));
+
+  const parsed = JSON.parse(masterSign);
+  const asIdentity = wrapSignature(parsed.signature, parsed.keyHash, "identityKey", parsed.algorithm);
+  assert(!verifyMasterKey(mp, asIdentity, testData), "A masterKey signature relabelled as identityKey is rejected");
+  assert(!verifyIdentityKey(ik.publicKey, asIdentity, testData), "A masterKey signature does not verify as an identityKey signature");
+  assert(!verifyIdentityKey(ik.publicKey, masterSign, testData));
+  assert(!verifyDataServerKey(generateServerKey().publicKey, masterSign, testData));
+});
+
+Deno.test("Room Key Rotation Tests", () => {
+  const oldKey = generateRoomKey(sessionUUID)!;
+  const { roomKey: newKey, proof } = JSON.parse(rotateRoomKey(oldKey, sessionUUID)!);
+  assert(isValidRoomKey(newKey));
+  assert(JSON.parse(newKey).key !== JSON.parse(oldKey).key, "Rotation generates a fresh key");
+  const p = JSON.parse(proof);
+  assertEquals(p.previousKeyHash, keyHashRaw(JSON.parse(oldKey).key), "The proof links to the old key by key_material_hash");
+  assertEquals(JSON.parse(newKey).previousKeyHash, p.previousKeyHash);
+  assertEquals(p.newKeyHash, keyHashRaw(JSON.parse(newKey).key));
+  assertEquals(p.sessionUuid, sessionUUID);
+
+  const enc = encryptDataRoomKey(oldKey, testData, undefined)!;
+  const re = reencryptDataRoomKey(oldKey, newKey, enc)!;
+  assertEquals(decryptDataRoomKey(newKey, re), testData, "Re-encrypted data opens with the new key");
+  assertEquals(decryptDataRoomKey(oldKey, re), undefined, "The old key cannot open re-encrypted data");
+  assertEquals(reencryptDataRoomKey(generateRoomKey(sessionUUID)!, newKey, enc), undefined, "Wrong old key fails");
+  assertEquals(rotateRoomKey("{}", sessionUUID), undefined);
+});
+
+Deno.test("Inclusion Proof Tests", async () => {
+  const sha256 = async (...parts: Uint8Array[]) => {
+    const buf = new Uint8Array(parts.reduce((n, p) => n + p.length, 0));
+    let off = 0;
+    for (const p of parts) {
+      buf.set(p, off);
+      off += p.length;
+    }
+    return new Uint8Array(await crypto.subtle.digest("SHA-256", buf));
+  };
+  const b64 = (b: Uint8Array) => btoa(String.fromCharCode(...b));
+  const enc = new TextEncoder();
+  const sk = generateServerKey();
+  const [mp] = generateMasterKey();
+  const mine = keyHash(mp);
+  const other = keyHash(generateMasterKey()[0]);
+
+  // 2 葉の木: root = H(0x01 || H(0x00 || mine) || H(0x00 || other))
+  const leafMine = await sha256(new Uint8Array([0]), enc.encode(mine));
+  const leafOther = await sha256(new Uint8Array([0]), enc.encode(other));
+  const root = b64(await sha256(new Uint8Array([1]), leafMine, leafOther));
+  const path = JSON.stringify([{ hash: b64(leafOther), position: "right" }]);
+
+  const proof = createInclusionProof(sk.privateKey, sk.publicKey, mine, root, path, 1000n)!;
+  assert(verifyInclusionProof(sk.publicKey, mine, proof), "Valid inclusion proof verifies");
+  assert(!verifyInclusionProof(sk.publicKey, other, proof), "Proof is bound to the key hash");
+  assert(!verifyInclusionProof(generateServerKey().publicKey, mine, proof), "Another server's key fails");
+
+  const tampered = JSON.parse(proof);
+  tampered.merklePath[0].position = "left";
+  assert(!verifyInclusionProof(sk.publicKey, mine, JSON.stringify(tampered)), "Tampered Merkle path fails");
+  const swapped = JSON.parse(proof);
+  swapped.merklePath[0].hash = b64(leafMine);
+  assert(!verifyInclusionProof(sk.publicKey, mine, JSON.stringify(swapped)), "Replaced sibling hash fails");
+
+  const wrongPath = JSON.stringify([{ hash: b64(leafMine), position: "right" }]);
+  assertEquals(createInclusionProof(sk.privateKey, sk.publicKey, mine, root, wrongPath, 1000n), undefined, "Path must reconstruct the root");
+});
+
+Deno.test("Key Bundle Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const rk = generateRoomKey(sessionUUID)!;
+  const migrateSign = generateMigrateSignKey(undefined);
+  assert(isValidMigrateSignKeyPublic(migrateSign.publicKey) && isValidMigrateSignKeyPrivate(migrateSign.privateKey));
+  assert(!isValidMigrateSignKeyPublic(migrateSign.privateKey), "keyType must match");
+  const keys = JSON.stringify([mp, ms, rk, migrateSign.publicKey, migrateSign.privateKey]);
+
+  const bundle = exportKeyBundle(keys, "correct horse")!;
+  const parsed = JSON.parse(bundle);
+  assertEquals(parsed.magic !== undefined && parsed.version !== undefined, true, "Bundle carries a magic/version header");
+  assert(!bundle.includes(JSON.parse(ms).key), "Private key material is not in the clear");
+  assertEquals(importKeyBundle(bundle, "correct horse"), keys, "Round trip restores the keys");
+
+  assertEquals(importKeyBundle(bundle, "wrong horse"), undefined, "Wrong passphrase fails cleanly");
+  assertEquals(importKeyBundle(JSON.stringify({ ...parsed, magic: "other" }), "correct horse"), undefined, "Unknown magic is rejected");
+  assertEquals(parsed.kdf, "Argon2id");
+  assert(parsed.kdfParams.memoryCost > 0 && parsed.kdfParams.timeCost > 0 && parsed.kdfParams.parallelism > 0, "Argon2 parameters are recorded");
+  assertEquals(importKeyBundle(JSON.stringify({ ...parsed, kdf: "scrypt" }), "correct horse"), undefined, "Unknown kdf is rejected");
+  assertEquals(importKeyBundle(JSON.stringify({ ...parsed, algorithm: "AES-CBC" }), "correct horse"), undefined, "Unknown algorithm is rejected");
+  const altered = { ...parsed, kdfParams: { ...parsed.kdfParams, timeCost: parsed.kdfParams.timeCost + 1 } };
+  assertEquals(importKeyBundle(JSON.stringify(altered), "correct horse"), undefined, "The recorded parameters are used for derivation");
+  const { kdfParams: _, ...legacy } = parsed;
+  assertEquals(importKeyBundle(JSON.stringify(legacy), "correct horse"), keys, "Bundles without kdfParams use the defaults");
+  assertEquals(importKeyBundle("not json", "correct horse"), undefined);
+  assertEquals(exportKeyBundle(JSON.stringify(["{}"]), "correct horse"), undefined, "Invalid keys are not exported");
+});
+
+Deno.test("Merge Distributions Tests", async () => {
+  const [mp, ms] = generateMasterKey();
+  const [alice, bob, aliceOther] = [0, 1, 2].map(() => generateAccountKey(mp, ms)!);
+  const oldKey = generateRoomKey(sessionUUID)!;
+  await new Promise((r) => setTimeout(r, 5));
+  const newKey = generateRoomKey(sessionUUID)!;
+  const wrap = (users: { userId: string; accountKey: string }[], rk: string) =>
+    encryptRoomKeyWithAccountKeys(JSON.stringify(users), rk)!;
+  const a = wrap([{ userId: "alice", accountKey: alice.publicKey }], oldKey);
+  const b = wrap([{ userId: "bob", accountKey: bob.publicKey }], oldKey);
+
+  const clean = JSON.parse(mergeDistributions(a, b)!);
+  assertEquals(clean.distribution.map((e: { userId: string }) => e.userId), ["alice", "bob"], "Clean union keeps both users");
+  assertEquals(clean.conflicts, []);
+
+  const dup = JSON.parse(mergeDistributions(a, a)!);
+  assertEquals(dup.distribution.length, 1, "The same entry twice is deduplicated");
+  assertEquals(dup.conflicts, []);
+
+  // 同じ RoomKey を同じユーザーの別の AccountKey に配ったエントリは衝突ではない
+  const otherDevice = wrap([{ userId: "alice", accountKey: aliceOther.publicKey }], oldKey);
+  const devices = JSON.parse(mergeDistributions(a, otherDevice)!);
+  assertEquals(devices.conflicts, [], "Same room key for another recipient is not a conflict");
+  assertEquals(devices.distribution.length, 2, "Both recipient entries are kept");
+  const stripHash = (d: string) => JSON.stringify(JSON.parse(d).map(({ roomKeyHash: _, ...e }: { roomKeyHash: string }) => e));
+  const noHash = JSON.parse(mergeDistributions(stripHash(a), stripHash(otherDevice))!);
+  assertEquals(noHash.conflicts, [], "Entries without roomKeyHash are never reported as conflicts");
+  assertEquals(noHash.distribution.length, 2);
+
+  // 新しい鍵の配布に署名されていない timestamp を古く書いても、鍵自身の timestamp で新しい方が勝つ
+  const newer = JSON.parse(wrap([{ userId: "alice", accountKey: alice.publicKey }], newKey));
+  newer[0].timestamp = 0;
+  const stale = JSON.parse(a);
+  stale[0].timestamp = Number.MAX_SAFE_INTEGER;
+  const conflict = JSON.parse(mergeDistributionsWithRoomKeys(JSON.stringify(newer), JSON.stringify(stale), JSON.stringify([oldKey, newKey]))!);
+  assertEquals(conflict.conflicts.length, 1, "Different room keys for one user are reported");
+  assert(conflict.conflicts[0].resolved);
+  assertEquals(decryptDataAccountKey(alice.privateKey, conflict.distribution[0].encryptedData), newKey, "The room key's own timestamp decides");
+
+  const unknown = JSON.parse(mergeDistributions(a, JSON.stringify(newer))!);
+  assertEquals(unknown.conflicts[0].resolved, false, "Unresolvable without the room keys");
+  assertEquals(decryptDataAccountKey(alice.privateKey, unknown.distribution[0].encryptedData), oldKey, "The first entry is kept");
+
+  const bad = JSON.stringify([{ userId: "carol" }, ...JSON.parse(b)]);
+  const withMalformed = JSON.parse(mergeDistributions(a, bad)!);
+  assertEquals(withMalformed.distribution.map((e: { userId: string }) => e.userId), ["alice", "bob"], "Malformed entries do not abort the merge");
+  assertEquals(withMalformed.malformed, [{ source: "b", index: 0 }]);
+});
+
+Deno.test("Content Limits Tests", () => {
+  const text = createTextContent("hello", undefined, undefined, undefined, undefined)!;
+  assert(validateContentLimits(text, "{}"), "Normal content passes the defaults");
+  assert(!validateContentLimits(text, JSON.stringify({ text: 4 })), "Configured limits apply");
+  assertEquals(createTextContent("a".repeat(100_001), undefined, undefined, undefined, undefined), undefined, "Over-long text is rejected");
+
+  const image = createImageContent("https://example.com/a.png", "a.png", "image/png", undefined, undefined, undefined)!;
+  assert(validateContentLimits(image, "{}"));
+  assertEquals(createImageContent("https://example.com/a.png", "a".repeat(256), "image/png", undefined, undefined, undefined), undefined, "Over-long filename is rejected");
+  assert(createImageContent("https://example.com/a.png", "a".repeat(255), "image/png", undefined, undefined, undefined), "A filename at the limit passes");
+  assertEquals(createImageContent("https://example.com/" + "a".repeat(2048), "a.png", "image/png", undefined, undefined, undefined), undefined, "Over-long uri is rejected");
+  assert(!validateContentLimits("not json", "{}"));
+});
+
+Deno.test("Multi-Recipient Envelope Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const recipients = [0, 1, 2].map(() => generateAccountKey(mp, ms)!);
+  const outsider = generateAccountKey(mp, ms)!;
+  const payload = "invite:" + "x".repeat(1000);
+
+  const envelope = encryptToRecipients(JSON.stringify(recipients.map((r) => r.publicKey)), payload)!;
+  const parsed = JSON.parse(envelope);
+  assertEquals(parsed.recipients.length, 3, "One wrapped key per recipient");
+  assert(typeof parsed.encryptedData === "string", "The payload is encrypted once");
+  const materialHash = (j: string) => keyHashRaw(JSON.parse(j).key)!;
+  assertEquals(parsed.recipients[0].keyHash, materialHash(recipients[0].publicKey), "keyHash is the key_material_hash");
+  for (const r of recipients) {
+    assertEquals(decryptFromEnvelope(r.privateKey, envelope, materialHash(r.publicKey)), payload);
+  }
+  assertEquals(decryptFromEnvelope(outsider.privateKey, envelope, materialHash(outsider.publicKey)), undefined, "Non-recipients have no wrapped key");
+  assertEquals(decryptFromEnvelope(outsider.privateKey, envelope, materialHash(recipients[0].publicKey)), undefined, "Another recipient's wrapped key does not open");
+
+  // 旧形式 (JSON 文字列のハッシュ) の keyHash を持つエンベロープも開ける
+  const legacy = { ...parsed, recipients: parsed.recipients.map((r: { keyHash: string }, i: number) => ({ ...r, keyHash: keyHash(recipients[i].publicKey) })) };
+  assertEquals(decryptFromEnvelope(recipients[1].privateKey, JSON.stringify(legacy), materialHash(recipients[1].publicKey)), payload, "Legacy keyHash is accepted");
+  assertEquals(decryptFromEnvelope(recipients[1].privateKey, JSON.stringify(legacy), keyHash(recipients[1].publicKey)), payload);
+  assertEquals(encryptToRecipients("[]", payload), undefined, "At least one recipient is required");
+  assertEquals(encryptToRecipients(JSON.stringify([recipients[0].publicKey, "{}"]), payload), undefined, "Invalid recipient keys are rejected");
+});
+
+Deno.test("Room Settings Signature Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const admin = generateIdentityKey(sessionUUID, mp, ms)!;
+  const settings = JSON.stringify({ name: "room", topic: "hello" });
+  const v2 = signRoomSettings(admin.privateKey, admin.publicKey, sessionUUID, settings, 2n, 1000n)!;
+  assert(verifyRoomSettings(admin.publicKey, v2, sessionUUID), "Valid settings verify");
+  assert(!verifyRoomSettings(admin.publicKey, v2, "018fdb31-0798-78a2-b4c9-e145d5b5b88f"), "Bound to the room");
+  assert(!verifyRoomSettings(generateIdentityKey(sessionUUID, mp, ms)!.publicKey, v2, sessionUUID), "Another key fails");
+
+  const tampered = JSON.parse(v2);
+  tampered.settings = tampered.settings.replace("hello", "pwned");
+  assert(!verifyRoomSettings(admin.publicKey, JSON.stringify(tampered), sessionUUID), "A tampered settings field fails");
+
+  const v1 = signRoomSettings(admin.privateKey, admin.publicKey, sessionUUID, settings, 1n, 900n)!;
+  assert(verifyRoomSettings(admin.publicKey, v1, sessionUUID), "Older settings are still validly signed");
+  assert(!verifyRoomSettingsWithVersion(admin.publicKey, v1, sessionUUID, 2n), "Rollback below the current version is rejected");
+  assert(verifyRoomSettingsWithVersion(admin.publicKey, v2, sessionUUID, 2n));
+  assert(verifyRoomSettingsWithVersion(admin.publicKey, v2, sessionUUID, 1n));
+});
+
+Deno.test("CBOR Round Trip Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
+  const meta = JSON.stringify({ channel: "c", timestamp: Date.now(), isLarge: false });
+  const { message } = JSON.parse(encryptMessage(text, meta, rk, ik.privateKey, ik.publicKey, sessionUUID)!);
+  const cases: [string, string][] = [
+    ["masterKey", mp],
+    ["accountKey", ak.publicKey],
+    ["encryptedData", encryptDataRoomKey(rk, testData, undefined)!],
+    ["encryptedMessage", message],
+  ];
+  for (const [kind, json] of cases) {
+    const cbor = jsonToCbor(kind, json)!;
+    assert(cbor.length < new TextEncoder().encode(json).length, `${kind} CBOR is smaller than JSON`);
+    assertEquals(JSON.parse(cborToJson(kind, cbor)!), JSON.parse(json), `${kind} JSON and CBOR decode to the same value`);
+  }
+  assertEquals(jsonToCbor("roomKey", rk), undefined, "Unsupported kinds are rejected");
+  assertEquals(cborToJson("masterKey", new Uint8Array([0xff, 0x00])), undefined, "Garbage CBOR fails cleanly");
+});
+
+Deno.test("DM Try-All Decryption Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const sender = generateIdentityKey(sessionUUID, mp, ms)!;
+  const senderAccounts = [0, 1, 2].map(() => generateAccountKey(mp, ms)!.publicKey);
+  const me = generateAccountKey(mp, ms)!;
+
+  const envelope = encryptDm(me.publicKey, senderAccounts[1], sender.privateKey, sender.publicKey, testData)!;
+  assertEquals(decryptDmTryAll(me.privateKey, JSON.stringify(senderAccounts), sender.publicKey, envelope), testData, "Only the correct candidate yields the verified plaintext");
+  assertEquals(decryptDmTryAll(me.privateKey, JSON.stringify([senderAccounts[0], senderAccounts[2]]), sender.publicKey, envelope), undefined, "No matching candidate");
+  assertEquals(decryptDmTryAll(me.privateKey, JSON.stringify(senderAccounts), generateIdentityKey(sessionUUID, mp, ms)!.publicKey, envelope), undefined, "Signature must verify");
+  assertEquals(decryptDmTryAll(generateAccountKey(mp, ms)!.privateKey, JSON.stringify(senderAccounts), sender.publicKey, envelope), undefined, "Wrong recipient key");
+  assertEquals(JSON.parse(JSON.parse(envelope).message).senderAccountKeyHash, keyHashRaw(JSON.parse(senderAccounts[1]).key), "senderAccountKeyHash is the key_material_hash");
+
+  // 旧形式 (JSON 文字列のハッシュ) の senderAccountKeyHash も受け付ける
+  const legacyMessage = JSON.stringify({ encryptedData: encryptDataAccountKey(me.publicKey, testData)!, senderAccountKeyHash: keyHash(senderAccounts[1]) });
+  const legacySign = signIdentityKeyWithPublic(sender.privateKey, sender.publicKey, legacyMessage)!;
+  const legacy = JSON.stringify({ message: legacyMessage, sign: legacySign });
+  assertEquals(decryptDmTryAll(me.privateKey, JSON.stringify(senderAccounts), sender.publicKey, legacy), testData, "Legacy senderAccountKeyHash is accepted");
+});
+
+Deno.test("EncryptedData Raw Bytes Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const json = encryptDataRoomKey(rk, testData, undefined)!;
+  const parsed = JSON.parse(json);
+  const parts = encryptedDataBytes(json)!;
+  assertEquals(parts.iv.length, 12);
+  assertEquals(parts.keyHash.length, 32);
+  assertEquals(parts.cipherText, undefined, "Symmetric EncryptedData has no cipherText");
+
+  const rebuilt = encryptedDataFromParts(parsed.keyType, parts.keyHash, parts.encryptedData, parts.iv, undefined, parsed.algorithm)!;
+  assertEquals(JSON.parse(rebuilt), parsed, "from_parts reproduces the unchanged JSON representation");
+  assertEquals(decryptDataRoomKey(rk, rebuilt), testData);
+
+  const [mp, ms] = generateMasterKey();
+  const ak = generateAccountKey(mp, ms)!;
+  const asym = encryptDataAccountKey(ak.publicKey, testData)!;
+  assertEquals(encryptedDataBytes(asym)!.cipherText!.length > 0, true, "KEM ciphertext is exposed as bytes");
+  assertEquals(encryptedDataBytes(JSON.stringify({ ...parsed, iv: "%%%" })), undefined, "Invalid base64 fails");
+});
+
+Deno.test("Index Metadata Extraction Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const secret = "top secret body";
+  const text = createTextContent(secret, undefined, undefined, undefined, undefined)!;
+  const timestamp = Date.now();
+  const envelope = encryptMessage(text, JSON.stringify({ channel: "general", timestamp, isLarge: false }), rk, ik.privateKey, ik.publicKey, sessionUUID)!;
+  const { message, sign } = JSON.parse(envelope);
+
+  const meta = JSON.parse(extractIndexMetadata(envelope)!);
+  assertEquals(meta, {
+    messageId: keyHash(message),
+    roomid: sessionUUID,
+    channel: "general",
+    timestamp,
+    senderKeyHash: JSON.parse(sign).keyHash,
+  }, "Exactly the index fields are extracted");
+  assert(!extractIndexMetadata(envelope)!.includes(secret), "No plaintext is exposed");
+
+  assertEquals(extractIndexMetadata("not json"), undefined);
+  assertEquals(extractIndexMetadata(JSON.stringify({ message })), undefined, "Missing sign");
+  assertEquals(extractIndexMetadata(JSON.stringify({ message: "{}", sign })), undefined, "Malformed message");
+});
+
+Deno.test("DSA Private Key Length Validation Tests", () => {
+  const b64 = (b: Uint8Array) => {
+    let s = "";
+    for (const x of b) s += String.fromCharCode(x);
+    return btoa(s);
+  };
+  const garbage = (n: number) => {
+    const b = new Uint8Array(n);
+    for (let i = 0; i < n; i += 65536) crypto.getRandomValues(b.subarray(i, Math.min(n, i + 65536)));
+    return b64(b);
+  };
+  for (const [gen, valid] of [[generateDsa65KeyPair, isValidDsa65Key], [generateDsa87KeyPair, isValidDsa87Key]] as const) {
+    const [pk, sk] = gen();
+    const len = atob(sk).length;
+    assert(valid(sk, false), "A generated private key is valid");
+    assert(valid(garbage(len), false), "A correct-length garbage key passes the length check without a test signature");
+    assert(!valid(garbage(len - 1), false), "One byte short is rejected");
+    assert(!valid(garbage(len + 1), false), "One byte long is rejected");
+    assert(!valid(pk, false), "A public key is not a private key");
+    assert(!valid("%%%", false), "Invalid base64 is rejected");
+  }
+});
+
+Deno.test("Stamped Message Decryption Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const rk = generateRoomKey(sessionUUID)!;
+  const srv = generateServerKey();
+  const text = createTextContent("hi", undefined, undefined, undefined, undefined)!;
+  const now = Date.now();
+  const { message, sign } = JSON.parse(encryptMessage(text, JSON.stringify({ channel: "c", timestamp: now, isLarge: false }), rk, ik.privateKey, ik.publicKey, sessionUUID)!);
+  const stamp = stampMessage(message, srv.privateKey, srv.publicKey, BigInt(now))!;
+
+  assert(decryptStampedMessage(message, sign, stamp, srv.publicKey, rk, ik.publicKey, sessionUUID), "A verified server stamp supplies the timestamp");
+  assertEquals(decryptStampedMessage(message, sign, stamp, generateServerKey().publicKey, rk, ik.publicKey, sessionUUID), undefined, "Another server's key is rejected");
+  const forged = JSON.stringify({ ...JSON.parse(stamp), serverTimestamp: now + 3_600_000 });
+  assertEquals(decryptStampedMessage(message, sign, forged, srv.publicKey, rk, ik.publicKey, sessionUUID), undefined, "A forged stamp time is rejected");
+});
+
+Deno.test("Asymmetric Key Pair Trait Tests", () => {
+  const [mp, ms] = generateMasterKey();
+  const account = generateAccountKey(mp, ms)!;
+  const share = generateShareKey(mp, ms, sessionUUID)!;
+  const migrate = generateMigrateKey(undefined);
+  // AccountKey / ShareKey / MigrateKey は AsymmetricKeyPair の共通実装を使う
+  const kinds = [
+    { name: "account", pair: account, isPub: isValidAccountKeyPublic, isPriv: isValidAccountKeyPrivate, enc: encryptDataAccountKey, dec: decryptDataAccountKey, isEnc: isValidEncryptedDataAccountKey },
+    { name: "share", pair: share, isPub: isValidShareKeyPublic, isPriv: isValidShareKeyPrivate, enc: encryptDataShareKey, dec: decryptDataShareKey, isEnc: isValidEncryptedDataShareKey },
+    { name: "migrate", pair: migrate, isPub: isValidMigrateKeyPublic, isPriv: isValidMigrateKeyPrivate, enc: encryptDataMigrateKey, dec: decryptDataMigrateKey, isEnc: isValidEncryptedDataMigrateKey },
+  ];
+  for (const k of kinds) {
+    assert(k.isPub(k.pair.publicKey) && k.isPriv(k.pair.privateKey), `${k.name} keys validate`);
+    assert(!k.isPub(k.pair.privateKey) && !k.isPriv(k.pair.publicKey), `${k.name} public/private are not interchangeable`);
+    const enc = k.enc(k.pair.publicKey, testData)!;
+    assert(k.isEnc(enc), `${k.name} EncryptedData validates`);
+    assertEquals(k.dec(k.pair.privateKey, enc), testData, `${k.name} round trip`);
+    for (const other of kinds.filter((o) => o !== k)) {
+      assert(!other.isPub(k.pair.publicKey), `${k.name} public key is not a ${other.name} key`);
+      assert(!other.isEnc(enc), `${k.name} EncryptedData is not ${other.name} EncryptedData`);
+      assertEquals(other.dec(other.pair.privateKey, enc), undefined, `${other.name} key cannot open ${k.name} data`);
+    }
+  }
+});
+
+Deno.test("Sign keyHash Convention Tests", async () => {
+  // Sign.keyHash = Base64(SHA-256(署名鍵の key フィールドを Base64 デコードしたバイト列))
+  const rawKeyHash = async (keyJson: string) => {
+    const bytes = Uint8Array.from(atob(JSON.parse(keyJson).key), (c) => c.charCodeAt(0));
+    const digest = new Uint8Array(await crypto.subtle.digest("SHA-256", bytes));
+    return btoa(String.fromCharCode(...digest));
+  };
+  const [mp, ms] = generateMasterKey();
+  const ik = generateIdentityKey(sessionUUID, mp, ms)!;
+  const ak = generateAccountKey(mp, ms)!;
+  const share = generateShareKey(mp, ms, sessionUUID)!;
+  const shareSign = generateShareSignKey(mp, ms, sessionUUID)!;
+  const [prekey] = generateAccountPrekeys(1, mp, ms)!;
+  const srv = generateServerKey();
+  const migrateSign = generateMigrateSignKey(undefined);
+  const rk = generateRoomKey(sessionUUID)!;
+  const message = JSON.parse(sendTextMessage("hi", rk, ik.privateKey, ik.publicKey, sessionUUID, "c")!);
+  const dm = JSON.parse(encryptDm(ak.publicKey, ak.publicKey, ik.privateKey, ik.publicKey, testData)!);
+  const settings = JSON.parse(signRoomSettings(ik.privateKey, ik.publicKey, sessionUUID, "{}", 1n, 1000n)!);
+
+  const cases: [string, string, string][] = [
+    ["identityKey", ik.sign, mp],
+    ["accountKey", ak.sign, mp],
+    ["shareKey", share.sign, mp],
+    ["shareSignKey", shareSign.sign, mp],
+    ["accountPrekey", prekey.sign, mp],
+    ["masterKey data", signMasterKeyWithPublic(ms, mp, testData)!, mp],
+    ["identityKey data", signIdentityKeyWithPublic(ik.privateKey, ik.publicKey, testData)!, ik.publicKey],
+    ["serverKey data", signDataServerKeyWithPublic(srv.privateKey, srv.publicKey, testData)!, srv.publicKey],
+    ["shareSignKey data", signDataShareSignKeyWithPublic(shareSign.privateKey, shareSign.publicKey, testData)!, shareSign.publicKey],
+    ["migrateSignKey data", signDataMigrateSignKeyWithPublic(migrateSign.privateKey, migrateSign.publicKey, testData)!, migrateSign.publicKey],
+    ["message", message.sign, ik.publicKey],
+    ["DM envelope", dm.sign, ik.publicKey],
+    ["room settings", settings.sign, ik.publicKey],
+  ];
+  for (const [name, sign, signer] of cases) {
+    assertEquals(JSON.parse(sign).keyHash, await rawKeyHash(signer), `${name}: keyHash is the hash of the decoded signing key`);
+  }
+});
+
+Deno.test("Derived Room Key Tests", () => {
+  const secret = btoa(String.fromCharCode(...crypto.getRandomValues(new Uint8Array(32))));
+  const rk = deriveRoomKey(secret, sessionUUID)!;
+  assert(isValidRoomKey(rk), "Derived key passes is_valid_room_key");
+  assertEquals(atob(JSON.parse(rk).key).length, 32);
+  assertEquals(deriveRoomKey(secret, sessionUUID), rk, "Same inputs give a byte-identical key");
+
+  const otherRoom = "018fdb31-0798-78a2-b4c9-e145d5b5b88f";
+  assert(JSON.parse(deriveRoomKey(secret, otherRoom)!).key !== JSON.parse(rk).key, "The room UUID separates keys");
+  const otherSecret = btoa(String.fromCharCode(...crypto.getRandomValues(new Uint8Array(32))));
+  assert(JSON.parse(deriveRoomKey(otherSecret, sessionUUID)!).key !== JSON.parse(rk).key);
+
+  const enc = encryptDataRoomKey(rk, testData, undefined)!;
+  assertEquals(decryptDataRoomKey(deriveRoomKey(secret, sessionUUID)!, enc), testData, "Another device recomputes the same key");
+  assertEquals(deriveRoomKey(btoa("short"), sessionUUID), undefined, "Secrets under 32 bytes are rejected");
+  assertEquals(deriveRoomKey(secret, "not-a-uuid"), undefined);
+});
+
+Deno.test("Room Key Cipher Counter Tests", () => {
+  const rk = generateRoomKey(sessionUUID)!;
+  const ivOf = (enc: string) => Array.from(atob(JSON.parse(enc).iv), (c) => c.charCodeAt(0));
+  const prefix = new Uint8Array([1, 2, 3, 4]);
+  const errorCode = (f: () => unknown) => {
+    try {
+      f();
+    } catch (e) {
+      return (e as { code: string }).code;
+    }
+    return undefined;
+  };
+
+  const cipher = new RoomKeyCipher(rk, prefix, undefined);
+  const first = cipher.encrypt(testData);
+  assertEquals(ivOf(first), [1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0], "IV is prefix || counter");
+  assertEquals(cipher.counter, 1n);
+  assertEquals(decryptDataRoomKey(rk, first), testData);
+
+  // 永続化した prefix / counter から再開すると続きの IV を使う
+  const resumed = new RoomKeyCipher(rk, cipher.prefix, cipher.counter);
+  assertEquals(ivOf(resumed.encrypt(testData)), [1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 1]);
+  const otherDevice = new RoomKeyCipher(rk, new Uint8Array([9, 9, 9, 9]), undefined);
+  assert(ivOf(otherDevice.encrypt(testData)).join() !== ivOf(first).join(), "Per-device prefixes keep IVs apart at the same counter");
+
+  // カウンタの境界: u64::MAX - 1 は使えるが、u64::MAX に達すると拒否する
+  const nearEnd = new RoomKeyCipher(rk, prefix, 0xffff_ffff_ffff_fffen);
+  const last = nearEnd.encrypt(testData);
+  assertEquals(ivOf(last), [1, 2, 3, 4, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]);
+  assertEquals(decryptDataRoomKey(rk, last), testData);
+  assertEquals(nearEnd.counter, 0xffff_ffff_ffff_ffffn);
+  assertEquals(errorCode(() => nearEnd.encrypt(testData)), "nonceCounterExhausted");
+  assertEquals(errorCode(() => nearEnd.encrypt(testData)), "nonceCounterExhausted", "Stays exhausted");
+
+  assertEquals(errorCode(() => new RoomKeyCipher(rk, new Uint8Array(3), undefined)), "invalidPrefix");
+  assertEquals(errorCode(() => new RoomKeyCipher("{}", prefix, undefined)), "invalidRoomKey");
+});
+
+Deno.test("Decrypt Buffer Tests", () => {
+  const key = generateSymmetricKey();
+  const buf = new DecryptBuffer();
+  const a = encryptWithSymmetricKey("first message", key);
+  const b = encryptWithSymmetricKey("second", key);
+
+  assertEquals(buf.decrypt(a.encrypted_data, a.iv, key), "first message".length);
+  assertEquals(buf.text(), "first message");
+  assertEquals(buf.decrypt(b.encrypted_data, b.iv, key), "second".length, "The buffer is reused for the next message");
+  assertEquals(buf.text(), "second");
+
+  try {
+    buf.decrypt(a.encrypted_data, a.iv, generateSymmetricKey());
+    assert(false, "wrong key should throw");
+  } catch (e) {
+    assert(typeof (e as { code: string }).code === "string");
+  }
+  assert(buf.is_empty(), "No plaintext is left behind after a failure");
+});
@@ -0,0 +1,81 @@
+#![cfg(target_arch = "wasm32")]
+
+use js_sys::{Array, Reflect, JSON};
+use takos_encrypt_ink_wasm::generate_server_key;
+#[cfg(feature = "master-key")]
+use takos_encrypt_ink_wasm::generate_master_key;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::*;
+
+fn get(obj: &JsValue, key: &str) -> JsValue {
+    Reflect::get(obj, &JsValue::from_str(key)).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn generate_server_key_shape() {
+    let value: JsValue = generate_server_key().into();
+    // Map ではなくプレーンオブジェクトで返ること
+    assert!(value.is_object());
+    assert!(!value.is_instance_of::<js_sys::Map>());
+    let public_key = get(&value, "publicKey");
+    let private_key = get(&value, "privateKey");
+    assert!(public_key.is_string());
+    assert!(private_key.is_string());
+
+    let parsed = JSON::parse(&public_key.as_string().unwrap()).unwrap();
+    assert_eq!(get(&parsed, "keyType").as_string().as_deref(), Some("serverKeyPublic"));
+    // timestamp は文字列や bigint ではなく number
+    assert!(get(&parsed, "timestamp").as_f64().is_some());
+}
+
+#[cfg(feature = "master-key")]
+#[wasm_bindgen_test]
+fn generate_master_key_is_array() {
+    let value: JsValue = generate_master_key().into();
+    let arr = value.dyn_into::<Array>().unwrap();
+    assert_eq!(arr.length(), 2);
+    assert!(arr.get(0).is_string());
+    assert!(arr.get(1).is_string());
+}
+
+#[cfg(feature = "logging")]
+mod logging {
+    use super::get;
+    use std::sync::Mutex;
+    use takos_encrypt_ink_wasm::{generate_server_key, sign_data_server_key_with_public, verify_data_server_key};
+    use wasm_bindgen::JsValue;
+    use wasm_bindgen_test::*;
+
+    struct Capture(Mutex<Vec<(log::Level, String)>>);
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool { true }
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+    #[wasm_bindgen_test]
+    fn failed_verification_emits_one_warn() {
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let value: JsValue = generate_server_key().into();
+        let public_key = get(&value, "publicKey").as_string().unwrap();
+        let private_key = get(&value, "privateKey").as_string().unwrap();
+        let sign = sign_data_server_key_with_public(&private_key, &public_key, "hello").unwrap();
+        assert!(verify_data_server_key(&public_key, &sign, "hello"));
+        assert!(CAPTURE.0.lock().unwrap().is_empty());
+
+        assert!(!verify_data_server_key(&public_key, &sign, "tampered"));
+        let records = CAPTURE.0.lock().unwrap();
+        let warns: Vec<_> = records.iter().filter(|(level, _)| *level == log::Level::Warn).collect();
+        assert_eq!(warns.len(), 1);
+        // 署名の値は出力しない
+        let signature = get(&js_sys::JSON::parse(&sign).unwrap(), "signature").as_string().unwrap();
+        assert!(!warns[0].1.contains(&signature));
+    }
+}